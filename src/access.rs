@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::{Result, UniversalStorage};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AccessStats {
+    pub read_count: u64,
+    pub last_access: Option<DateTime<Utc>>,
+}
+
+impl AccessStats {
+    fn merge(&mut self, other: &AccessStats) {
+        self.read_count += other.read_count;
+        self.last_access = self.last_access.max(other.last_access);
+    }
+}
+
+impl UniversalStorage {
+    /// Enables or disables read tracking for this handle. Reads are counted
+    /// in memory and persisted with the next metadata commit, so tracking
+    /// never adds a write to the read path.
+    pub fn set_access_tracking(&mut self, enabled: bool) {
+        self.access_tracking = enabled;
+    }
+
+    pub fn access_tracking(&self) -> bool {
+        self.access_tracking
+    }
+
+    /// Per-key read counts and last-access times, including reads not yet
+    /// persisted.
+    pub fn access_stats(&self) -> HashMap<String, AccessStats> {
+        let mut stats: HashMap<_, _> = self.metadata.access.clone().into_iter().collect();
+        for (key, pending) in &self.pending_access {
+            stats.entry(key.clone()).or_default().merge(pending);
+        }
+        stats
+    }
+
+    /// Persists pending access counts without waiting for the next store.
+    pub fn flush_access_stats(&mut self) -> Result<()> {
+        if self.pending_access.is_empty() {
+            return Ok(());
+        }
+        self.update_metadata()
+    }
+
+    pub(crate) fn record_access(&mut self, key: &str) {
+        let now = self.now();
+        let stats = self.pending_access.entry(key.to_string()).or_default();
+        stats.read_count += 1;
+        stats.last_access = Some(now);
+    }
+
+    // Drops the stats of a key that is gone for good, so a later value
+    // stored under it starts cold
+    pub(crate) fn forget_access(&mut self, key: &str) {
+        self.metadata.access.remove(key);
+        self.pending_access.remove(key);
+    }
+
+    pub(crate) fn merge_pending_access(&mut self) {
+        for (key, pending) in self.pending_access.drain() {
+            self.metadata.access.entry(key).or_default().merge(&pending);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_access_stats_are_batched_and_persisted() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("access.usf");
+        let mut storage = UniversalStorage::create(&file_path)?;
+        storage.store("hot", b"hot", DataType::Text)?;
+        storage.store("cold", b"cold", DataType::Text)?;
+
+        // Untracked reads are not counted
+        storage.retrieve("hot")?;
+        assert!(storage.access_stats().is_empty());
+
+        storage.set_access_tracking(true);
+        for _ in 0..3 {
+            storage.retrieve("hot")?;
+        }
+        storage.retrieve("cold")?;
+
+        let stats = storage.access_stats();
+        assert_eq!(stats["hot"].read_count, 3);
+        assert_eq!(stats["cold"].read_count, 1);
+        assert!(stats["hot"].last_access.is_some());
+
+        storage.flush_access_stats()?;
+        let reopened = UniversalStorage::open(&file_path)?;
+        assert_eq!(reopened.access_stats()["hot"].read_count, 3);
+
+        Ok(())
+    }
+}
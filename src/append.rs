@@ -0,0 +1,99 @@
+use std::io::Read;
+use xxhash_rust::xxh3::xxh3_128;
+use crate::limits::check_value_size;
+use crate::stream::ValueReader;
+use crate::{EntryOptions, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Appends `data` to the value stored under `key`, keeping its data
+    /// type, hint and attributes. The new bytes go into fresh blocks added
+    /// to the end of the entry's chain; the existing blocks are neither
+    /// read nor rewritten.
+    ///
+    /// A value sharing its blocks with other keys, through a link or a
+    /// solid group, is rewritten once instead, which gives it blocks of its
+    /// own; later appends to it are cheap again. So is a text delta log,
+    /// which the rewrite turns into a plain value.
+    pub fn append(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+        check_value_size(&key, entry.size + data.len() as u64, self.metadata.max_value_size)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let encoding = self.encoding_for(&key, &entry.data_type);
+        let options = EntryOptions { hint: entry.hint, attributes: entry.attributes.clone(), ..EntryOptions::default() };
+        if entry.solid_offset.is_some() || entry.text_deltas.is_some() || self.ref_count(&key)? > 1 {
+            let mut value = Vec::with_capacity(entry.size as usize + data.len());
+            ValueReader::new(self, &entry).read_to_end(&mut value)?;
+            value.extend_from_slice(data);
+            let blocks = Self::prepare_blocks(&value, entry.data_type.clone(), &encoding)?;
+            let options = EntryOptions { content_hash: Some(xxh3_128(&value)), ..options };
+            return self.write_entry(key, blocks, entry.data_type, options);
+        }
+
+        let blocks = Self::prepare_chunks(data, entry.block_size as usize, entry.data_type.clone(), &encoding)?;
+        let (locations, size) = self.write_blocks(&blocks)?;
+        self.metadata.total_blocks += locations.len() as u64;
+        let now = self.now();
+        let entry = self.metadata.index.get_mut(&key).expect("checked above");
+        entry.ragged |= !entry.size.is_multiple_of(entry.block_size);
+        entry.blocks.extend(locations);
+        entry.size += size;
+        entry.stored_at = now;
+        // The hash covered the old value only
+        entry.content_hash = None;
+
+        self.record_store(size);
+        self.metadata.modified = now;
+        self.update_metadata()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, BLOCK_SIZE};
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_extends_chain_without_rewriting() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("append.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let first = vec![b'a'; BLOCK_SIZE + 10];
+        storage.store("telemetry", &first, DataType::Text)?;
+        storage.set_attribute("telemetry", "source", "sensor-1")?;
+        let original: Vec<u64> = storage.metadata.index["telemetry"].blocks.iter().map(|loc| loc.offset).collect();
+
+        storage.append("telemetry", b"line 2\n")?;
+        storage.append("telemetry", b"line 3\n")?;
+        let entry = &storage.metadata.index["telemetry"];
+        assert_eq!(entry.blocks.len(), 4);
+        assert_eq!(entry.blocks[..2].iter().map(|loc| loc.offset).collect::<Vec<_>>(), original);
+        assert_eq!(entry.attributes["source"], "sensor-1");
+
+        let mut expected = first;
+        expected.extend_from_slice(b"line 2\nline 3\n");
+        assert_eq!(storage.retrieve("telemetry")?, expected);
+        assert!(!storage.store_if_changed("telemetry", &expected, DataType::Text)?);
+        assert!(matches!(storage.append("missing", b"x"), Err(UsfError::KeyNotFound(_))));
+
+        // A linked value is detached, leaving the alias as it was
+        storage.link("telemetry", "snapshot")?;
+        storage.append("telemetry", b"line 4\n")?;
+        assert_eq!(storage.ref_count("snapshot")?, 1);
+
+        drop(storage);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.retrieve("snapshot")?, expected);
+        expected.extend_from_slice(b"line 4\n");
+        assert_eq!(storage.retrieve("telemetry")?, expected);
+
+        Ok(())
+    }
+}
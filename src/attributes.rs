@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+use crate::{Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Free-form name/value pairs recorded on the entry under `key`. They
+    /// are cleared whenever the key is stored again.
+    pub fn attributes(&self, key: &str) -> Result<&BTreeMap<String, String>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.metadata.index.get(&key)
+            .map(|entry| &entry.attributes)
+            .ok_or(UsfError::KeyNotFound(key))
+    }
+
+    pub fn set_attribute(&mut self, key: &str, name: &str, value: &str) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get_mut(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+        entry.attributes.insert(name.to_string(), value.to_string());
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Removes an attribute, returning its previous value.
+    pub fn remove_attribute(&mut self, key: &str, name: &str) -> Result<Option<String>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get_mut(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+        let previous = entry.attributes.remove(name);
+        if previous.is_some() {
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+        }
+        Ok(previous)
+    }
+}
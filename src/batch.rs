@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::xxh3_128;
+use crate::limits::{check_limit, check_value_size};
+use crate::{chunk_size, clamp, textdelta, BlockLocation, DataType, EntryOptions, IndexEntry, ProgressPhase, Result, UniversalStorage, UsfError};
+
+// Members of one solid group: prefix, data type and (key, value) pairs
+type SolidGroup<'a> = (String, &'a DataType, Vec<(String, &'a [u8])>);
+
+// Slot, piece index and range within a block, for one value read by
+// retrieve_many
+type PieceRef = (usize, usize, Range<usize>);
+
+// A value whose blocks are written but not yet in the index
+enum Written<'a> {
+    Value { key: String, locations: Vec<BlockLocation>, size: u64, block_size: u64, data_type: DataType, content_hash: u128 },
+    Solid { members: Vec<(String, &'a [u8])>, locations: Vec<BlockLocation>, block_size: u64, data_type: DataType },
+}
+
+impl UniversalStorage {
+    /// Stores every item with a single metadata commit. All blocks are
+    /// written before the index changes, so if the process dies partway
+    /// none of the batch is visible and the archive is left as it was,
+    /// short of some unreferenced blocks. Keys are checked before anything
+    /// is written. Values under a solid prefix are packed per prefix and
+    /// data type; when a key appears more than once, the last item wins.
+    pub fn store_batch(&mut self, items: &[(&str, &[u8], DataType)]) -> Result<()> {
+        self.apply_batch(items, &[])
+    }
+
+    /// Like [`UniversalStorage::store_batch`], also deleting every key in
+    /// `deletes` in the same commit, so readers see either none of the
+    /// changes or all of them. Deletes apply before the stores, and keys
+    /// that are not in the archive are skipped.
+    pub fn apply_batch(&mut self, items: &[(&str, &[u8], DataType)], deletes: &[&str]) -> Result<()> {
+        let mut canonical = Vec::with_capacity(items.len());
+        for (key, data, data_type) in items {
+            let key = self.metadata.key_policy.canonicalize(key)?;
+            check_value_size(&key, data.len() as u64, self.metadata.max_value_size)?;
+            canonical.push((key, *data, data_type));
+        }
+        let deletes = deletes.iter()
+            .map(|key| self.metadata.key_policy.canonicalize(key))
+            .collect::<Result<Vec<_>>>()?;
+        if canonical.is_empty() && !deletes.iter().any(|key| self.metadata.index.contains_key(key)) {
+            return Ok(());
+        }
+        let last: HashMap<&str, usize> = canonical.iter().enumerate().map(|(i, (key, ..))| (key.as_str(), i)).collect();
+
+        let mut singles = Vec::new();
+        let mut groups: Vec<SolidGroup> = Vec::new();
+        for (i, (key, data, data_type)) in canonical.iter().enumerate() {
+            if last[key.as_str()] != i {
+                continue;
+            }
+            match self.solid_prefix_of(key) {
+                Some(prefix) => match groups.iter_mut().find(|(p, t, _)| *p == prefix && t == data_type) {
+                    Some((.., members)) => members.push((key.clone(), *data)),
+                    None => groups.push((prefix, *data_type, vec![(key.clone(), *data)])),
+                },
+                None => singles.push((key, *data, *data_type)),
+            }
+        }
+
+        let mut written = Vec::with_capacity(singles.len() + groups.len());
+        for (key, data, data_type) in singles {
+            let encoding = self.encoding_for(key, data_type);
+            let blocks = Self::prepare_blocks(data, data_type.clone(), &encoding)?;
+            let (locations, size) = self.write_blocks(&blocks)?;
+            let content_hash = xxh3_128(data);
+            let block_size = chunk_size(&blocks);
+            written.push(Written::Value { key: key.clone(), locations, size, block_size, data_type: data_type.clone(), content_hash });
+        }
+        for (prefix, data_type, members) in groups {
+            let (locations, block_size) = self.write_solid_group(&prefix, &members, data_type)?;
+            written.push(Written::Solid { members, locations, block_size, data_type: data_type.clone() });
+        }
+
+        for key in deletes {
+            if self.metadata.index.contains_key(&key) {
+                self.remove_entry(key)?;
+            }
+        }
+        for value in written {
+            match value {
+                Written::Value { key, locations, size, block_size, data_type, content_hash } => {
+                    let options = EntryOptions { content_hash: Some(content_hash), block_size: Some(block_size), ..EntryOptions::default() };
+                    self.index_entry(key, locations, size, data_type, options);
+                },
+                Written::Solid { members, locations, block_size, data_type } => self.index_solid_group(members, locations, block_size, data_type),
+            }
+        }
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Retrieves every key in `keys`, returning one result per key in the
+    /// same order. A missing or corrupt entry fails only its own slot, so
+    /// bulk readers can skip damaged values and carry on.
+    ///
+    /// Blocks are read in file offset order in a single forward pass,
+    /// rather than key by key, and a block shared by several keys (solid
+    /// groups, links, repeated keys) is read once.
+    pub fn retrieve_many<S: AsRef<str>>(&mut self, keys: &[S]) -> Vec<Result<Vec<u8>>> {
+        let mut slots: Vec<Result<(String, IndexEntry)>> = keys.iter()
+            .map(|key| {
+                let key = self.metadata.key_policy.canonicalize(key.as_ref())?;
+                let entry = self.metadata.index.get(&key).ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+                check_limit(self.limits.as_ref(), "entry size", entry.size, |l| l.max_entry_size)?;
+                Ok((key, entry.clone()))
+            })
+            .collect();
+
+        // Every block to read, by offset, with the pieces it supplies
+        let mut reads: BTreeMap<u64, (BlockLocation, Vec<PieceRef>)> = BTreeMap::new();
+        let mut pieces: Vec<Vec<Vec<u8>>> = Vec::with_capacity(slots.len());
+        let mut total = 0;
+        for (slot, resolved) in slots.iter().enumerate() {
+            let ranges = resolved.as_ref().map(|(_, entry)| entry.block_ranges()).unwrap_or_default();
+            pieces.push(vec![Vec::new(); ranges.len()]);
+            total += resolved.as_ref().map_or(0, |(_, entry)| entry.size);
+            for (piece, (location, range)) in ranges.into_iter().enumerate() {
+                reads.entry(location.offset).or_insert_with(|| (location, Vec::new())).1.push((slot, piece, range));
+            }
+        }
+
+        let permits = self.admit(slots.iter().flatten().map(|(key, _)| key.as_str()));
+        let mut elapsed = vec![Duration::ZERO; slots.len()];
+        let mut done = 0;
+        for (location, users) in reads.into_values() {
+            let started = Instant::now();
+            let (data, mut error) = match self.load_block(&location) {
+                Ok(data) => (Some(data), None),
+                Err(e) => (None, Some(e)),
+            };
+            let took = started.elapsed();
+
+            for (slot, piece, range) in users {
+                elapsed[slot] += took;
+                if slots[slot].is_err() {
+                    continue;
+                }
+                match &data {
+                    Some(data) => {
+                        pieces[slot][piece] = data[clamp(&range, data.len())].to_vec();
+                        let len = pieces[slot][piece].len() as u64;
+                        if let Ok((key, _)) = &slots[slot] {
+                            permits.charge(key, len);
+                        }
+                        done += len;
+                    },
+                    // Later keys sharing the failed block read it again for
+                    // an error of their own
+                    None => slots[slot] = Err(error.take().unwrap_or_else(|| {
+                        self.load_block(&location).err().unwrap_or_else(|| {
+                            UsfError::Corruption(format!("block at offset {} read inconsistently", location.offset))
+                        })
+                    })),
+                }
+            }
+            self.report_progress(done, total, ProgressPhase::Retrieve);
+        }
+
+        slots.into_iter().zip(pieces).zip(elapsed)
+            .map(|((resolved, pieces), took)| {
+                let (key, entry) = resolved?;
+                let value = pieces.concat();
+                if self.access_tracking {
+                    self.record_access(&key);
+                }
+                self.record_retrieval(&key, value.len() as u64, took);
+
+                if entry.text_deltas.is_some() {
+                    return textdelta::replay(&value);
+                }
+                if entry.data_type == DataType::Reference {
+                    return self.resolve_reference(value);
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::{self, Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_retrieve_many_reports_per_key() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("batch.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("good", b"fine", DataType::Binary)?;
+        storage.store("damaged", b"will be overwritten", DataType::Binary)?;
+
+        // Flip the last data byte of the "damaged" block
+        let offset = storage.metadata.index["damaged"].blocks[0].offset
+            + storage.metadata.index["damaged"].blocks[0].disk_size() - 1;
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(b"!")?;
+
+        let results = storage.retrieve_many(&["good", "missing", "damaged"]);
+        assert_eq!(results[0].as_ref().ok(), Some(&b"fine".to_vec()));
+        assert!(matches!(results[1], Err(UsfError::KeyNotFound(_))));
+        assert!(matches!(results[2], Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieve_many_reads_shared_blocks_once() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("retrieve_many.usf"))?;
+        let large: Vec<u8> = (0..crate::BLOCK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        storage.store("large", &large, DataType::Binary)?;
+        storage.add_solid_prefix("s/")?;
+        storage.store_many(&[("s/a", b"alpha"), ("s/b", b"beta")], DataType::Text)?;
+        storage.store("empty", b"", DataType::Binary)?;
+        storage.link("s/b", "alias")?;
+        storage.set_access_tracking(true);
+
+        let results = storage.retrieve_many(&["alias", "large", "s/a", "missing", "empty", "s/b", "large"]);
+        let values: Vec<Option<Vec<u8>>> = results.into_iter().map(|r| r.ok()).collect();
+        assert_eq!(values, [
+            Some(b"beta".to_vec()),
+            Some(large.clone()),
+            Some(b"alpha".to_vec()),
+            None,
+            Some(Vec::new()),
+            Some(b"beta".to_vec()),
+            Some(large),
+        ]);
+        assert_eq!(storage.metrics().retrieves, 6);
+        assert_eq!(storage.access_stats()["large"].read_count, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_batch_commits_once() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("store_batch.usf"))?;
+        storage.add_solid_prefix("small/")?;
+        storage.store("existing", b"old", DataType::Text)?;
+        let generation = storage.metadata.generation;
+
+        storage.store_batch(&[
+            ("existing", b"new", DataType::Text),
+            ("doc.json", b"{}", DataType::Json),
+            ("small/a", b"aaa", DataType::Text),
+            ("small/b", b"bbb", DataType::Text),
+            ("doc.json", b"{\"v\":2}", DataType::Json),
+        ])?;
+        assert_eq!(storage.metadata.generation, generation + 1);
+        assert_eq!(storage.retrieve("existing")?, b"new");
+        assert_eq!(storage.retrieve("doc.json")?, b"{\"v\":2}");
+        assert_eq!(storage.retrieve("small/b")?, b"bbb");
+        assert_eq!(storage.ref_count("small/a")?, 2);
+
+        // Deletes share the commit, and missing keys are skipped
+        storage.apply_batch(&[("doc.json", b"{}", DataType::Json)], &["existing", "never stored"])?;
+        assert_eq!(storage.metadata.generation, generation + 2);
+        assert!(!storage.contains_key("existing"));
+        assert_eq!(storage.retrieve("doc.json")?, b"{}");
+
+        // A rejected value fails the batch before anything is written
+        storage.set_max_value_size(Some(8))?;
+        let size = std::fs::metadata(dir.path().join("store_batch.usf"))?.len();
+        let result = storage.store_batch(&[("fine", b"x", DataType::Text), ("big", b"too large", DataType::Text)]);
+        assert!(matches!(result, Err(UsfError::ValueTooLarge { .. })));
+        assert!(!storage.contains_key("fine"));
+        assert_eq!(std::fs::metadata(dir.path().join("store_batch.usf"))?.len(), size);
+
+        Ok(())
+    }
+}
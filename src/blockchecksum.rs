@@ -0,0 +1,98 @@
+use std::io;
+use serde::{Serialize, Deserialize};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::format::VERSION_2;
+use crate::{Result, UniversalStorage};
+
+/// Algorithm of the checksum over each block's stored data. Every block
+/// header records the one it was written with, so blocks written before a
+/// change keep theirs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum BlockChecksum {
+    /// xxh3-64: the fastest, but not cryptographic
+    #[default]
+    Xxh3,
+    /// CRC-32C, computed in hardware on most CPUs
+    Crc32c,
+    /// BLAKE3, with the full 256-bit digest kept in the block header, for
+    /// cryptographic integrity
+    Blake3,
+}
+
+impl BlockChecksum {
+    /// The checksum of `data`, and the full digest when it is wider than
+    /// 64 bits. A BLAKE3 checksum is the first 8 bytes of its digest.
+    pub fn compute(self, data: &[u8]) -> (u64, Option<[u8; 32]>) {
+        match self {
+            BlockChecksum::Xxh3 => (xxh3_64(data), None),
+            BlockChecksum::Crc32c => (crc32c::crc32c(data) as u64, None),
+            BlockChecksum::Blake3 => {
+                let digest = *blake3::hash(data).as_bytes();
+                (u64::from_le_bytes(digest[..8].try_into().expect("8 bytes")), Some(digest))
+            },
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Checksums blocks written from now on with `algorithm`; blocks
+    /// already stored keep the one they were written with until compaction
+    /// copies them as they are. The default is [`BlockChecksum::Xxh3`].
+    /// Commits and the journal are still checksummed with xxh3. Archives
+    /// with bincode block headers, version 2 and earlier, can only record
+    /// xxh3; migrate them first.
+    pub fn set_block_checksum(&mut self, algorithm: BlockChecksum) -> Result<()> {
+        if self.version <= VERSION_2 && algorithm != BlockChecksum::Xxh3 {
+            let message = format!("version {} block headers only record xxh3 checksums; migrate the archive first", self.version);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        self.metadata.block_checksum = algorithm;
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn block_checksum(&self) -> BlockChecksum {
+        self.metadata.block_checksum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{platform, DataType, UsfError, UsfOptions, VerifyIssue};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_block_checksum_algorithms() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("checksums.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.store("xxh3", b"checked with xxh3", DataType::Text)?;
+        storage.set_block_checksum(BlockChecksum::Crc32c)?;
+        storage.store("crc32c", b"checked with crc32c", DataType::Text)?;
+        storage.set_block_checksum(BlockChecksum::Blake3)?;
+        storage.store("blake3", b"checked with blake3", DataType::Text)?;
+        drop(storage);
+
+        // The setting persists, and each block is read with its own
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        assert_eq!(storage.block_checksum(), BlockChecksum::Blake3);
+        for (key, algorithm) in [("xxh3", BlockChecksum::Xxh3), ("crc32c", BlockChecksum::Crc32c), ("blake3", BlockChecksum::Blake3)] {
+            let location = storage.metadata.index[key].blocks[0].clone();
+            let header = storage.read_header(&location)?;
+            assert_eq!(header.checksum_algorithm, algorithm);
+            assert_eq!(storage.retrieve(key)?, format!("checked with {}", key).as_bytes());
+        }
+        let location = storage.metadata.index["blake3"].blocks[0].clone();
+        assert_eq!(storage.read_header(&location)?.digest, Some(*blake3::hash(b"checked with blake3").as_bytes()));
+        assert!(storage.verify()?.is_clean());
+
+        // Damage to a BLAKE3 block is caught by the full digest
+        let data_offset = location.offset + crate::format::BLOCK_HEADER_PREFIX_SIZE + location.header_size as u64;
+        platform::write_all_at(&storage.file, b"C", data_offset)?;
+        assert!(matches!(storage.retrieve("blake3"), Err(UsfError::Corruption(_))));
+        assert!(matches!(storage.verify()?.issues[..], [VerifyIssue::ChecksumMismatch { offset, .. }] if offset == location.offset));
+
+        Ok(())
+    }
+}
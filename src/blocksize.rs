@@ -0,0 +1,117 @@
+use std::io;
+use serde::{Serialize, Deserialize};
+use crate::format::{BLOCK_SIZE, MAX_BLOCK_SIZE};
+use crate::{Result, UniversalStorage};
+
+// Chains are kept to about this many blocks by growing the block size
+const TARGET_CHAIN_BLOCKS: u64 = 1024;
+
+// Bounds on the amount of uncompressed data per block, kept in the
+// archive metadata
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockSizing {
+    min: u64,
+    max: u64,
+}
+
+impl Default for BlockSizing {
+    fn default() -> Self {
+        Self { min: BLOCK_SIZE as u64, max: MAX_BLOCK_SIZE as u64 }
+    }
+}
+
+impl BlockSizing {
+    // The smallest power of two that keeps a value of `size` bytes within
+    // TARGET_CHAIN_BLOCKS blocks, clamped to the bounds
+    pub(crate) fn block_size_for(self, size: u64) -> usize {
+        size.div_ceil(TARGET_CHAIN_BLOCKS).next_power_of_two().clamp(self.min, self.max) as usize
+    }
+}
+
+impl UniversalStorage {
+    /// Bounds the amount of uncompressed data per block for values stored
+    /// from now on. Values are chunked at `min` bytes until that would
+    /// take more than about a thousand blocks; larger values get larger
+    /// blocks, up to `max`, to cut per-block overhead and chain length.
+    /// The defaults are [`BLOCK_SIZE`] and [`MAX_BLOCK_SIZE`], so values
+    /// under 64 MiB are chunked exactly as before. Values already stored
+    /// keep their block size.
+    pub fn set_block_size_bounds(&mut self, min: u64, max: u64) -> Result<()> {
+        if min == 0 || min > max || max > MAX_BLOCK_SIZE as u64 {
+            let message = format!("block size bounds must satisfy 0 < min <= max <= {}", MAX_BLOCK_SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        self.metadata.block_sizing = BlockSizing { min, max };
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn block_size_bounds(&self) -> (u64, u64) {
+        (self.metadata.block_sizing.min, self.metadata.block_sizing.max)
+    }
+
+    /// Uncompressed bytes per block for a value of `size` bytes stored now.
+    pub fn block_size_for(&self, size: u64) -> usize {
+        self.metadata.block_sizing.block_size_for(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfOptions};
+    use tempfile::tempdir;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_block_size_grows_with_value_size() {
+        let sizing = BlockSizing::default();
+        assert_eq!(sizing.block_size_for(0), BLOCK_SIZE);
+        assert_eq!(sizing.block_size_for(64 * MIB), BLOCK_SIZE);
+        assert_eq!(sizing.block_size_for(64 * MIB + 1), 2 * BLOCK_SIZE);
+        assert_eq!(sizing.block_size_for(1024 * MIB), MIB as usize);
+        assert_eq!(sizing.block_size_for(100 * 1024 * MIB), MAX_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_block_size_bounds() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("blocks.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        let before: Vec<u8> = (0..BLOCK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        storage.store("before", &before, DataType::Binary)?;
+        assert!(storage.set_block_size_bounds(0, 1).is_err());
+        assert!(storage.set_block_size_bounds(2, 1).is_err());
+        assert!(storage.set_block_size_bounds(1, MAX_BLOCK_SIZE as u64 + 1).is_err());
+
+        // A raised minimum makes fewer, larger blocks
+        storage.set_block_size_bounds(4 * BLOCK_SIZE as u64, MAX_BLOCK_SIZE as u64)?;
+        let value: Vec<u8> = (0..BLOCK_SIZE * 5 + 9).map(|i| (i % 253) as u8).collect();
+        storage.store("large", &value, DataType::Binary)?;
+        let info = storage.entry_info("large")?;
+        assert_eq!((info.block_size, info.blocks.len()), (4 * BLOCK_SIZE as u64, 2));
+        assert_eq!(storage.entry_info("before")?.blocks.len(), 3);
+        drop(storage);
+
+        // The bounds persist, and reads find positions by each entry's own
+        // block size
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        assert_eq!(storage.block_size_bounds(), (4 * BLOCK_SIZE as u64, MAX_BLOCK_SIZE as u64));
+        let start = BLOCK_SIZE as u64 * 4 - 3;
+        assert_eq!(storage.retrieve_range("large", start, 10)?, &value[start as usize..start as usize + 10]);
+        assert_eq!(storage.retrieve_range("before", start - BLOCK_SIZE as u64, 3)?, &before[BLOCK_SIZE * 3 - 3..BLOCK_SIZE * 3]);
+
+        // Appends continue the value's chain at its block size
+        storage.append("large", &vec![7u8; BLOCK_SIZE * 4 - 9])?;
+        storage.append("large", b"tail")?;
+        let mut expected = value.clone();
+        expected.extend(vec![7u8; BLOCK_SIZE * 4 - 9]);
+        expected.extend(b"tail");
+        assert_eq!(storage.retrieve("large")?, expected);
+        let start = BLOCK_SIZE as u64 * 8 - 2;
+        assert_eq!(storage.retrieve_range("large", start, 4)?, &expected[start as usize..start as usize + 4]);
+
+        Ok(())
+    }
+}
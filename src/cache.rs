@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use crate::{DataType, OperationMetrics, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Returns the value under `key`, or computes, stores and returns it if
+    /// the key is missing.
+    pub fn get_or_store_with<F>(&mut self, key: &str, data_type: DataType, compute: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        match self.retrieve(key) {
+            Err(UsfError::KeyNotFound(_)) => {
+                self.metrics.cache_misses += 1;
+                let data = compute();
+                self.store(key, &data, data_type)?;
+                Ok(data)
+            },
+            Ok(data) => {
+                self.metrics.cache_hits += 1;
+                Ok(data)
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A cloneable, thread-safe handle to one archive. Operations are
+/// serialized on an internal lock.
+#[derive(Clone)]
+pub struct SharedStorage {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    storage: Mutex<UniversalStorage>,
+    // Keys whose value is being computed by a get_or_store_with caller
+    in_flight: Mutex<HashSet<String>>,
+    landed: Condvar,
+}
+
+impl SharedStorage {
+    pub fn new(storage: UniversalStorage) -> Self {
+        Self {
+            inner: Arc::new(Shared {
+                storage: Mutex::new(storage),
+                in_flight: Mutex::new(HashSet::new()),
+                landed: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn store(&self, key: &str, data: &[u8], data_type: DataType) -> Result<()> {
+        self.storage().store(key, data, data_type)
+    }
+
+    pub fn retrieve(&self, key: &str) -> Result<Vec<u8>> {
+        self.storage().retrieve(key)
+    }
+
+    /// Like [`UniversalStorage::get_or_store_with`], but when several
+    /// threads miss on the same key only one runs `compute`; the others
+    /// wait for its result. The lock is not held while computing, so other
+    /// keys stay available.
+    pub fn get_or_store_with<F>(&self, key: &str, data_type: DataType, compute: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let key = self.storage().key_policy().canonicalize(key)?;
+
+        loop {
+            if let Some(data) = self.cached(&key)? {
+                self.storage().metrics.cache_hits += 1;
+                return Ok(data);
+            }
+
+            let mut in_flight = lock(&self.inner.in_flight);
+            if in_flight.insert(key.clone()) {
+                break;
+            }
+            while in_flight.contains(&key) {
+                in_flight = self.inner.landed.wait(in_flight).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+
+        let _flight = Flight { shared: &self.inner, key: &key };
+        // Another caller may have landed the value before this one took the flight
+        if let Some(data) = self.cached(&key)? {
+            self.storage().metrics.cache_hits += 1;
+            return Ok(data);
+        }
+        self.storage().metrics.cache_misses += 1;
+        let data = compute();
+        self.store(&key, &data, data_type)?;
+        Ok(data)
+    }
+
+    pub fn metrics(&self) -> OperationMetrics {
+        self.storage().metrics().clone()
+    }
+
+    /// Returns the storage if this is the last handle.
+    pub fn into_inner(self) -> Option<UniversalStorage> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|shared| shared.storage.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn storage(&self) -> MutexGuard<'_, UniversalStorage> {
+        lock(&self.inner.storage)
+    }
+
+    fn cached(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.retrieve(key) {
+            Ok(data) => Ok(Some(data)),
+            Err(UsfError::KeyNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Releases a key's flight and wakes waiters, even if `compute` panics
+struct Flight<'a> {
+    shared: &'a Shared,
+    key: &'a str,
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        lock(&self.shared.in_flight).remove(self.key);
+        self.shared.landed.notify_all();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_get_or_store_with_single_flight() -> io::Result<()> {
+        let dir = tempdir()?;
+        let shared = SharedStorage::new(UniversalStorage::create(dir.path().join("cache.usf"))?);
+        let computed = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let shared = shared.clone();
+            let computed = Arc::clone(&computed);
+            thread::spawn(move || shared.get_or_store_with("expensive", DataType::Text, || {
+                computed.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                b"result".to_vec()
+            }))
+        }).collect();
+
+        for thread in threads {
+            assert_eq!(thread.join().expect("worker panicked")?, b"result");
+        }
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+
+        let mut storage = shared.into_inner().expect("last handle");
+        assert_eq!(storage.get_or_store_with("expensive", DataType::Text, || unreachable!())?, b"result");
+
+        Ok(())
+    }
+}
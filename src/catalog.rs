@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::Path;
+use rusqlite::{params, Connection};
+use crate::{platform, Result, UniversalStorage};
+
+const SCHEMA: &str = "
+    CREATE TABLE entries (
+        key TEXT PRIMARY KEY,
+        data_type TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        stored_size INTEGER NOT NULL,
+        block_count INTEGER NOT NULL,
+        stored_at TEXT NOT NULL,
+        content_hash TEXT
+    );
+    CREATE TABLE attributes (
+        key TEXT NOT NULL REFERENCES entries(key),
+        name TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (key, name)
+    );
+    CREATE TABLE blocks (
+        key TEXT NOT NULL REFERENCES entries(key),
+        position INTEGER NOT NULL,
+        offset INTEGER NOT NULL,
+        original_size INTEGER NOT NULL,
+        compressed_size INTEGER NOT NULL,
+        compression TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        PRIMARY KEY (key, position)
+    );
+";
+
+impl UniversalStorage {
+    /// Writes an inventory of the archive to a new SQLite database at
+    /// `path`, replacing any file there, and returns the number of entries
+    /// written. Tables:
+    ///
+    /// - `entries`: key, data type, value size, compressed size, block
+    ///   count, store time (RFC 3339) and xxh3-128 content hash, if known
+    /// - `attributes`: one row per key and attribute name
+    /// - `blocks`: each block of each key in order, with its offset,
+    ///   sizes, compression method and xxh3-64 checksum
+    ///
+    /// Hashes and checksums are lowercase hex. Only block headers are
+    /// read, not values.
+    pub fn export_catalog_sqlite<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let path = platform::native_path(path.as_ref());
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let mut connection = Connection::open(&path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        let transaction = connection.transaction()?;
+        {
+            let mut insert_entry = transaction.prepare("INSERT INTO entries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            let mut insert_attribute = transaction.prepare("INSERT INTO attributes VALUES (?1, ?2, ?3)")?;
+            let mut insert_block = transaction.prepare("INSERT INTO blocks VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            for (key, entry) in &self.metadata.index {
+                let info = self.entry_info(key)?;
+                insert_entry.execute(params![
+                    key,
+                    entry.data_type.to_string(),
+                    entry.size as i64,
+                    info.compressed_size as i64,
+                    info.blocks.len() as i64,
+                    entry.stored_at.to_rfc3339(),
+                    entry.content_hash.map(|hash| format!("{:032x}", hash)),
+                ])?;
+                for (name, value) in &entry.attributes {
+                    insert_attribute.execute(params![key, name, value])?;
+                }
+                for (position, block) in info.blocks.iter().enumerate() {
+                    insert_block.execute(params![
+                        key,
+                        position as i64,
+                        block.offset as i64,
+                        block.original_size as i64,
+                        block.compressed_size as i64,
+                        format!("{:?}", block.compression_method),
+                        format!("{:016x}", block.checksum),
+                    ])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(self.metadata.index.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::BLOCK_SIZE;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_catalog_sqlite() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("catalog.usf"))?;
+        storage.store("docs/readme", b"read me", DataType::Text)?;
+        storage.store("data/large", &vec![3u8; BLOCK_SIZE + 10], DataType::Binary)?;
+        storage.set_attribute("docs/readme", "owner", "ops")?;
+
+        let catalog = dir.path().join("catalog.sqlite");
+        fs::write(&catalog, b"stale")?;
+        assert_eq!(storage.export_catalog_sqlite(&catalog)?, 2);
+
+        let connection = Connection::open(&catalog).map_err(io::Error::other)?;
+        let query = |sql: &str| connection.query_row(sql, [], |row| row.get::<_, String>(0)).map_err(io::Error::other);
+        assert_eq!(query("SELECT data_type || ':' || size FROM entries WHERE key = 'docs/readme'")?, "Text:7");
+        assert_eq!(query("SELECT value FROM attributes WHERE key = 'docs/readme' AND name = 'owner'")?, "ops");
+        assert_eq!(query("SELECT group_concat(original_size) FROM blocks WHERE key = 'data/large' ORDER BY position")?, format!("{},10", BLOCK_SIZE));
+        assert_eq!(query("SELECT CAST(block_count AS TEXT) FROM entries WHERE key = 'data/large'")?, "2");
+        assert_eq!(query("SELECT CAST(length(content_hash) AS TEXT) FROM entries WHERE key = 'docs/readme'")?, "32");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use sha2::{Digest, Sha256};
+use crate::stream::ValueReader;
+use crate::{Result, UniversalStorage, UsfError};
+
+/// Digest used by [`UniversalStorage::export_checksums`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// Checked with `sha256sum -c`
+    Sha256,
+    /// Checked with `b3sum -c`
+    Blake3,
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        })
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = UsfError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(UsfError::Serialization(format!("unknown checksum algorithm: {}", s))),
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Writes a `sha256sum`/`b3sum` style manifest of every value, in key
+    /// order: the hex digest of the decompressed content, two spaces and
+    /// the key. Keys containing a newline or backslash are escaped the way
+    /// coreutils does, with a leading `\` on the line.
+    pub fn export_checksums<W: Write>(&mut self, mut writer: W, algorithm: ChecksumAlgorithm) -> Result<()> {
+        let entries: Vec<_> = self.metadata.index.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+
+        for (key, entry) in entries {
+            let mut reader = ValueReader::new(self, &entry);
+            let digest = match algorithm {
+                ChecksumAlgorithm::Sha256 => {
+                    let mut hasher = Sha256::new();
+                    io::copy(&mut reader, &mut hasher)?;
+                    hex(&hasher.finalize())
+                },
+                ChecksumAlgorithm::Blake3 => {
+                    let mut hasher = blake3::Hasher::new();
+                    io::copy(&mut reader, &mut hasher)?;
+                    hasher.finalize().to_hex().to_string()
+                },
+            };
+
+            match key.contains(['\n', '\\']) {
+                true => writeln!(writer, "\\{}  {}", digest, key.replace('\\', "\\\\").replace('\n', "\\n"))?,
+                false => writeln!(writer, "{}  {}", digest, key)?,
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_checksums_matches_coreutils_format() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("checksums.usf"))?;
+        storage.store("b/abc.txt", b"abc", DataType::Text)?;
+        storage.store("a/large.bin", &vec![9u8; 200_000], DataType::Binary)?;
+        storage.store("odd\\name", b"", DataType::Binary)?;
+
+        let mut manifest = Vec::new();
+        storage.export_checksums(&mut manifest, ChecksumAlgorithm::Sha256)?;
+        let manifest = String::from_utf8(manifest).expect("utf-8 manifest");
+        let lines: Vec<&str> = manifest.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("{}  a/large.bin", hex(&Sha256::digest(vec![9u8; 200_000]))));
+        assert_eq!(lines[1], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  b/abc.txt");
+        assert_eq!(lines[2], "\\e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  odd\\\\name");
+
+        let mut manifest = Vec::new();
+        storage.export_checksums(&mut manifest, "blake3".parse()?)?;
+        let expected = format!("{}  b/abc.txt", blake3::hash(b"abc").to_hex());
+        assert!(String::from_utf8_lossy(&manifest).lines().any(|line| line == expected));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Serialize, Deserialize};
+use crate::{DataType, Result, UniversalStorage};
+
+/// Maps keys matching a glob pattern to a default data type. `*` and `?`
+/// stay within one path segment and `**` spans segments. A pattern without
+/// `/` is matched against the key's last segment, so `*.json` matches
+/// `a/b/c.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TypeRule {
+    pub pattern: String,
+    pub data_type: DataType,
+}
+
+impl TypeRule {
+    pub fn matches(&self, key: &str) -> bool {
+        glob_matches(&self.pattern, key)
+    }
+}
+
+// Matches `key` against a glob; patterns without '/' match its last segment
+pub(crate) fn glob_matches(pattern: &str, key: &str) -> bool {
+    let target = if pattern.contains('/') {
+        key
+    } else {
+        key.rsplit('/').next().unwrap_or(key)
+    };
+    glob_match(pattern.as_bytes(), target.as_bytes())
+}
+
+impl UniversalStorage {
+    /// Appends a rule; earlier rules take precedence.
+    pub fn add_type_rule(&mut self, pattern: &str, data_type: DataType) -> Result<()> {
+        self.metadata.type_rules.push(TypeRule { pattern: pattern.to_string(), data_type });
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn type_rules(&self) -> &[TypeRule] {
+        &self.metadata.type_rules
+    }
+
+    pub fn clear_type_rules(&mut self) -> Result<()> {
+        self.metadata.type_rules.clear();
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// The data type the first matching rule assigns to `key`, if any.
+    pub fn classify(&self, key: &str) -> Option<DataType> {
+        self.metadata.type_rules.iter()
+            .find(|rule| rule.matches(key))
+            .map(|rule| rule.data_type.clone())
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` may also match no directories at all
+            let rest_after_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            glob_match(rest_after_slash, text)
+                || (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        },
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_match(rest, &text[i..]))
+        },
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && glob_match(rest, tail)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.json", b"config.json"));
+        assert!(!glob_match(b"*.json", b"dir/config.json"));
+        assert!(glob_match(b"images/**", b"images/2024/cat.png"));
+        assert!(glob_match(b"logs/**/*.txt", b"logs/app.txt"));
+        assert!(glob_match(b"logs/**/*.txt", b"logs/a/b/app.txt"));
+        assert!(glob_match(b"v?.bin", b"v1.bin"));
+        assert!(!glob_match(b"v?.bin", b"v10.bin"));
+    }
+
+    #[test]
+    fn test_type_rules_classify_keys() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("rules.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.add_type_rule("images/**", DataType::Image)?;
+        storage.add_type_rule("*.json", DataType::Json)?;
+        storage.add_type_rule("*.bin", DataType::Binary)?;
+
+        let storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.classify("images/icons/logo.json"), Some(DataType::Image));
+        assert_eq!(storage.classify("configs/app.json"), Some(DataType::Json));
+        assert_eq!(storage.classify("model.bin"), Some(DataType::Binary));
+        assert_eq!(storage.classify("README"), None);
+
+        Ok(())
+    }
+}
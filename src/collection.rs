@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use xxhash_rust::xxh3::xxh3_128;
+use crate::limits::check_value_size;
+use crate::scope::scope_prefix;
+use crate::typed::ValueFormat;
+use crate::{EntryOptions, Result, UniversalStorage, UsfError};
+
+/// Attribute recording the schema version a document was written with
+pub const SCHEMA_VERSION_ATTRIBUTE: &str = "schema_version";
+
+/// Documents of one type kept under `<name>/<id>` keys, serialized with a
+/// [`ValueFormat`] and stamped with a schema version, so readers can tell
+/// documents written before a change to `T` from those written after.
+pub struct Collection<'a, T> {
+    storage: &'a mut UniversalStorage,
+    // Canonical, and ends with '/'
+    prefix: String,
+    schema_version: u32,
+    format: ValueFormat,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl UniversalStorage {
+    /// The collection named `name`, at schema version 1 and stored as
+    /// bincode until changed with [`Collection::schema_version`] and
+    /// [`Collection::format`]. Like [`UniversalStorage::scoped`], `tasks`
+    /// covers `tasks/...` but not `tasks-archive/...`.
+    pub fn collection<T>(&mut self, name: &str) -> Result<Collection<'_, T>> {
+        let prefix = scope_prefix(self, "", name)?;
+        Ok(Collection { storage: self, prefix, schema_version: 1, format: ValueFormat::default(), marker: PhantomData })
+    }
+}
+
+impl<T> Collection<'_, T> {
+    /// The schema version stamped on documents inserted from now on.
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.schema_version = version;
+        self
+    }
+
+    /// How documents inserted from now on are serialized. Documents are
+    /// read back in whatever format they were written in.
+    pub fn format(mut self, format: ValueFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.prefix.trim_end_matches('/')
+    }
+
+    /// Document ids, sorted.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.storage.keys_with_prefix(&self.prefix)
+            .map(|key| &key[self.prefix.len()..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids().next().is_none()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.resolve(id).is_ok_and(|key| self.storage.contains_key(&key))
+    }
+
+    /// Stores `value` under `id`, replacing any document already there,
+    /// with its schema version in the same commit.
+    pub fn insert(&mut self, id: &str, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let key = self.resolve(id)?;
+        let data = self.format.encode(value)?;
+        let data_type = self.format.data_type();
+        check_value_size(&key, data.len() as u64, self.storage.metadata.max_value_size)?;
+
+        let encoding = self.storage.encoding_for(&key, &data_type);
+        let blocks = UniversalStorage::prepare_blocks(&data, data_type.clone(), &encoding)?;
+        let attributes = BTreeMap::from([(SCHEMA_VERSION_ATTRIBUTE.to_string(), self.schema_version.to_string())]);
+        let options = EntryOptions { attributes, content_hash: Some(xxh3_128(&data)), ..EntryOptions::default() };
+        self.storage.write_entry(key, blocks, data_type, options)
+    }
+
+    /// The document under `id`, or `None` if there is none.
+    pub fn get(&mut self, id: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let key = self.resolve(id)?;
+        match self.storage.retrieve_value(&key) {
+            Ok(value) => Ok(Some(value)),
+            Err(UsfError::KeyNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The schema version `id` was inserted with. Documents stored under
+    /// the collection's prefix by other means have none.
+    pub fn schema_version_of(&self, id: &str) -> Result<Option<u32>> {
+        let key = self.resolve(id)?;
+        let attributes = self.storage.attributes(&key).map_err(|e| self.relative(e))?;
+        attributes.get(SCHEMA_VERSION_ATTRIBUTE)
+            .map(|version| version.parse().map_err(|_| UsfError::Corruption(format!("{:?} has schema version {:?}", key, version))))
+            .transpose()
+    }
+
+    /// Deletes the document under `id`, returning whether there was one.
+    pub fn remove(&mut self, id: &str) -> Result<bool> {
+        let key = self.resolve(id)?;
+        match self.storage.delete(&key) {
+            Ok(()) => Ok(true),
+            Err(UsfError::KeyNotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every document with its id, in id order. A document that fails to
+    /// decode fails only its own item.
+    pub fn scan(&mut self) -> impl Iterator<Item = Result<(String, T)>> + '_
+    where
+        T: DeserializeOwned,
+    {
+        let ids: Vec<String> = self.ids().map(str::to_string).collect();
+        let (storage, prefix) = (&mut *self.storage, &self.prefix);
+        ids.into_iter().map(move |id| {
+            let value = storage.retrieve_value(&format!("{}{}", prefix, id))?;
+            Ok((id, value))
+        })
+    }
+
+    // The canonical key for `id`, refusing anything that canonicalizes
+    // outside the collection
+    fn resolve(&self, id: &str) -> Result<String> {
+        let key = self.storage.metadata.key_policy.canonicalize(&format!("{}{}", self.prefix, id))?;
+        if !key.starts_with(self.prefix.as_str()) || key.len() == self.prefix.len() {
+            return Err(UsfError::InvalidKey { key: id.to_string(), reason: "outside the collection".to_string() });
+        }
+        Ok(key)
+    }
+
+    // Reports keys relative to the collection, as they were passed in
+    fn relative(&self, error: UsfError) -> UsfError {
+        match error {
+            UsfError::KeyNotFound(key) => UsfError::KeyNotFound(key.strip_prefix(self.prefix.as_str()).unwrap_or(&key).to_string()),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use serde::Deserialize;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Task {
+        title: String,
+        done: bool,
+    }
+
+    #[test]
+    fn test_collection_insert_get_scan() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("collection.usf"))?;
+        storage.store("tasks-archive/1", b"sibling", DataType::Text)?;
+        let write = Task { title: "write docs".to_string(), done: false };
+        let ship = Task { title: "ship".to_string(), done: true };
+
+        let mut tasks = storage.collection::<Task>("tasks")?;
+        assert!(tasks.is_empty());
+        tasks.insert("2", &ship)?;
+        tasks.insert("1", &write)?;
+        assert_eq!(tasks.get("1")?, Some(write.clone()));
+        assert_eq!(tasks.get("missing")?, None);
+        assert_eq!(tasks.ids().collect::<Vec<_>>(), ["1", "2"]);
+        assert_eq!(tasks.schema_version_of("1")?, Some(1));
+        assert!(matches!(tasks.insert("", &write), Err(UsfError::InvalidKey { .. })));
+
+        // Later documents carry the new schema version and format, and
+        // earlier ones still read
+        let mut tasks = storage.collection::<Task>("tasks")?.schema_version(2).format(ValueFormat::Json);
+        let done = Task { done: true, ..write.clone() };
+        tasks.insert("1", &done)?;
+        assert_eq!(tasks.schema_version_of("1")?, Some(2));
+        assert_eq!(tasks.schema_version_of("2")?, Some(1));
+        let scanned = tasks.scan().collect::<Result<Vec<_>>>()?;
+        assert_eq!(scanned, [("1".to_string(), done), ("2".to_string(), ship)]);
+
+        assert!(tasks.remove("2")?);
+        assert!(!tasks.remove("2")?);
+        assert_eq!(tasks.len(), 1);
+        assert!(matches!(tasks.schema_version_of("2"), Err(UsfError::KeyNotFound(id)) if id == "2"));
+        assert_eq!(storage.data_type_of("tasks/1")?, DataType::Json);
+        assert_eq!(storage.retrieve("tasks-archive/1")?, b"sibling");
+
+        Ok(())
+    }
+}
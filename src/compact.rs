@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use crate::format::{BlockHeader, BLOCK_HEADER_PREFIX_SIZE, COLD_TIER_BASE, VERSION_2};
+use crate::placement::placement_rank;
+use crate::tier::TierPolicy;
+use crate::{BlockLocation, ProgressPhase, Result, UniversalStorage};
+
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Size of the cold file, included in `bytes_after`, after
+    /// [`UniversalStorage::compact_tiered`]
+    pub cold_bytes_after: u64,
+    /// Keys deleted by retention rules
+    pub retention_deleted: Vec<String>,
+    /// Trashed keys whose retention had lapsed and were dropped
+    pub purged_keys: Vec<String>,
+}
+
+impl UniversalStorage {
+    /// Applies retention rules, then rewrites the archive with only
+    /// reachable blocks, purging expired trash entries. The new file
+    /// replaces the old one atomically. A cold tier left by
+    /// [`UniversalStorage::compact_tiered`] is folded back in.
+    pub fn compact(&mut self) -> Result<CompactionReport> {
+        self.compact_into(None)
+    }
+
+    pub(crate) fn compact_into(&mut self, tiered: Option<(&Path, &TierPolicy)>) -> Result<CompactionReport> {
+        self.check_writable()?;
+        self.check_not_frozen()?;
+        let retention_deleted = self.apply_retention()?;
+
+        let now = Utc::now();
+        let mut purged_keys: Vec<String> = self.metadata.trash.iter()
+            .filter(|(_, trashed)| self.trash_expired(trashed, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        purged_keys.sort();
+        for key in &purged_keys {
+            if let Some(trashed) = self.metadata.trash.remove(key) {
+                self.release_chain(&trashed.entry.blocks);
+            }
+            if !self.metadata.index.contains_key(key) {
+                self.forget_access(key);
+            }
+        }
+
+        let report = self.rewrite(tiered)?;
+        Ok(CompactionReport { retention_deleted, purged_keys, ..report })
+    }
+
+    // Copies every reachable block into a fresh file in the current format
+    // version and swaps it in
+    pub(crate) fn rewrite(&mut self, tiered: Option<(&Path, &TierPolicy)>) -> Result<CompactionReport> {
+        self.check_writable()?;
+        self.check_not_frozen()?;
+        self.merge_pending_access();
+        let bytes_before = self.file.metadata()?.len() + self.cold_tier_len()?;
+        let parity = self.parity_geometry();
+        let now = Utc::now();
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".compact");
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut metadata = self.metadata.clone();
+        metadata.index.clear();
+        metadata.trash.clear();
+        metadata.chain_refs.clear();
+        metadata.freed.clear();
+        metadata.ingests.clear();
+        metadata.dictionaries.clear();
+        // Blocks move, so parity written for the old layout no longer
+        // applies; it is written afresh below
+        metadata.parity.clear();
+        metadata.total_blocks = 0;
+        // The sidecar is rewritten once the compacted archive is in place
+        let index_sidecar = std::mem::take(&mut metadata.index_sidecar);
+        // So is the journal, which names the archive rather than the target
+        let write_ahead_log = std::mem::take(&mut metadata.write_ahead_log);
+        let previous_tier = metadata.cold_tier.take();
+        let mut cold = match tiered {
+            Some((cold_path, _)) => {
+                let (tier, file) = self.create_cold_tier(cold_path)?;
+                metadata.cold_tier = Some(tier);
+                Some(file)
+            },
+            None => None,
+        };
+        let mut target = Self::initialize(&temp_path, metadata)?;
+        // The target is only opened once its last commit is written, so the
+        // empty first commit is dropped rather than left as dead space
+        target.file.set_len(target.data_offset())?;
+        target.commit = None;
+        target.progress = self.progress.clone();
+        let live_bytes = self.live_block_bytes();
+        let mut copied = 0;
+
+        // Chains already copied, keyed by their old first block offset
+        let mut moved: HashMap<u64, Vec<BlockLocation>> = HashMap::new();
+
+        // Laid out grouped by placement hint, in key order within a group
+        let mut index: Vec<_> = self.metadata.index.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        index.sort_by_key(|(_, entry)| placement_rank(entry.hint));
+        for (key, mut entry) in index {
+            let goes_cold = tiered.is_some_and(|(_, policy)| self.goes_cold(&key, &entry, policy, now));
+            let cold = cold.as_mut().filter(|_| goes_cold);
+            entry.blocks = self.copy_chain(&entry.blocks, &mut target, cold, &mut moved, &mut copied, live_bytes)?;
+            target.metadata.index.insert(key, entry);
+        }
+
+        let trash: Vec<_> = self.metadata.trash.iter()
+            .map(|(key, trashed)| (key.clone(), trashed.clone()))
+            .collect();
+        for (key, mut trashed) in trash {
+            let cold = cold.as_mut();
+            trashed.entry.blocks = self.copy_chain(&trashed.entry.blocks, &mut target, cold, &mut moved, &mut copied, live_bytes)?;
+            target.metadata.trash.insert(key, trashed);
+        }
+
+        let ingests: Vec<_> = self.metadata.ingests.iter()
+            .map(|(key, checkpoint)| (key.clone(), checkpoint.clone()))
+            .collect();
+        for (key, mut checkpoint) in ingests {
+            checkpoint.blocks = self.copy_chain(&checkpoint.blocks, &mut target, None, &mut moved, &mut copied, live_bytes)?;
+            target.metadata.ingests.insert(key, checkpoint);
+        }
+
+        let dictionaries: Vec<_> = self.metadata.dictionaries.iter()
+            .map(|(name, stored)| (name.clone(), stored.clone()))
+            .collect();
+        for (name, mut stored) in dictionaries {
+            stored.blocks = self.copy_chain(&stored.blocks, &mut target, None, &mut moved, &mut copied, live_bytes)?;
+            target.metadata.dictionaries.insert(name, stored);
+        }
+
+        for (old_offset, count) in &self.metadata.chain_refs {
+            if let Some(first) = moved.get(old_offset).and_then(|chain| chain.first()) {
+                target.metadata.chain_refs.insert(first.offset, *count);
+            }
+        }
+
+        // Parity is written afresh for the moved blocks, cold ones included
+        if let Some((group_size, parity_shards)) = parity {
+            target.cold = cold.as_ref().map(File::try_clone).transpose()?;
+            target.write_parity(group_size, parity_shards)?;
+        }
+
+        let cold_bytes_after = match &cold {
+            Some(file) => {
+                file.sync_all()?;
+                file.metadata()?.len()
+            },
+            None => 0,
+        };
+        target.metadata.modified = target.now();
+        target.update_metadata()?;
+        target.file.sync_all()?;
+        let bytes_after = target.file.metadata()?.len() + cold_bytes_after;
+
+        // The rename commits; a cold file not yet in place is picked up by
+        // the next open
+        fs::rename(&temp_path, &self.path)?;
+        self.reopen_for_writing()?;
+        self.metadata = target.metadata;
+        self.version = target.version;
+        self.commit = target.commit;
+        self.install_cold_tier(previous_tier)?;
+        if index_sidecar || write_ahead_log {
+            self.metadata.index_sidecar = index_sidecar;
+            self.metadata.write_ahead_log = write_ahead_log;
+            self.update_metadata()?;
+        }
+        self.metrics.compactions += 1;
+
+        Ok(CompactionReport { bytes_before, bytes_after, cold_bytes_after, ..CompactionReport::default() })
+    }
+
+    // On-disk bytes of every distinct block still referenced
+    pub(crate) fn live_block_bytes(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.metadata.index.values()
+            .map(|entry| entry.blocks.as_slice())
+            .chain(self.pinned_chains().map(|(_, locations)| locations))
+            .flatten()
+            .filter(|loc| seen.insert(loc.offset))
+            .map(|loc| loc.disk_size())
+            .sum()
+    }
+
+    // Copies a block chain into `target`, or into the `cold` tier file when
+    // given, once, reusing the copy for chains shared between linked keys
+    pub(crate) fn copy_chain(
+        &mut self,
+        locations: &[BlockLocation],
+        target: &mut UniversalStorage,
+        mut cold: Option<&mut File>,
+        moved: &mut HashMap<u64, Vec<BlockLocation>>,
+        copied: &mut u64,
+        live_bytes: u64,
+    ) -> Result<Vec<BlockLocation>> {
+        let Some(first) = locations.first() else {
+            return Ok(Vec::new());
+        };
+        if let Some(chain) = moved.get(&first.offset) {
+            return Ok(chain.clone());
+        }
+
+        let mut chain = Vec::with_capacity(locations.len());
+        for loc in locations {
+            let mut raw = vec![0u8; loc.disk_size() as usize];
+            self.read_at(&mut raw, loc.offset)?;
+            let mut header_size = loc.header_size;
+            // Bincode headers are rewritten in the fixed layout; the
+            // checksum covers the data alone, so it still holds
+            if self.version <= VERSION_2 {
+                let start = BLOCK_HEADER_PREFIX_SIZE as usize;
+                let data = raw.split_off(start + header_size as usize);
+                let header = BlockHeader::decode(&raw[start..], loc.offset)?.encode(target.version)?;
+                header_size = header.len() as u32;
+                raw = [header_size.to_le_bytes().as_slice(), &header, &data].concat();
+            }
+
+            let offset = match cold.as_deref_mut() {
+                Some(file) => {
+                    let at = file.seek(SeekFrom::End(0))?;
+                    file.write_all(&raw)?;
+                    COLD_TIER_BASE + at
+                },
+                None => {
+                    let at = target.file.seek(SeekFrom::End(0))?;
+                    target.file.write_all(&raw)?;
+                    at
+                },
+            };
+            chain.push(BlockLocation { offset, header_size, ..loc.clone() });
+            *copied += raw.len() as u64;
+            self.report_progress(*copied, live_bytes, ProgressPhase::Compact);
+        }
+
+        target.metadata.total_blocks += chain.len() as u64;
+        moved.insert(first.offset, chain.clone());
+        Ok(chain)
+    }
+}
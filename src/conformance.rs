@@ -0,0 +1,313 @@
+//! `usf gen-conformance`: reference archives for other implementations.
+//!
+//! Each case is built with [`UniversalStorage::create_reproducible`] at the
+//! Unix epoch and described in `manifest.json`: every key with its data
+//! type, length, SHA-256 and block layout. A reader passes a case when it
+//! lists the same keys and reproduces every digest. Block bytes are not
+//! compared, since Zstd output may differ between library versions.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use usf::format::{BLOCK_SIZE, MIN_COMPRESS_SIZE, VERSION};
+use usf::{
+    CompressionPolicy, CustomType, DataType, Hint, KeyPolicy, Reference, Result, TierPolicy, Transform,
+    UniversalStorage,
+};
+
+// Values up to this length are also written out in hex
+const INLINE_VALUE_LIMIT: usize = 64;
+const XOR_TRANSFORM: &str = "conformance-xor-5a";
+const XOR_PREFIX: &str = "sealed/";
+
+type Build = fn(&mut UniversalStorage) -> Result<()>;
+
+const CASES: &[(&str, &str, Build)] = &[
+    ("empty", "An archive with no entries", |_| Ok(())),
+    ("codecs", "One value per compression method: none, zstd and delta encoding", build_codecs),
+    ("chained", "Values spanning several blocks, including exact block multiples", build_chained),
+    ("edge-values", "An empty value and values on either side of the compression threshold", build_edge_values),
+    ("unicode-keys", "Keys outside ASCII, canonicalized to NFC by the default key policy", build_unicode_keys),
+    ("data-types", "One value of each data type, including a registered custom type and a reference", build_data_types),
+    ("solid", "Small values packed into a solid group, and a link sharing another value's blocks", build_solid),
+    ("transformed", "Values under `sealed/` passed through the XOR transform described below", build_transformed),
+    ("overwritten", "Overwritten, deleted and trashed keys, leaving dead blocks behind", build_overwritten),
+    ("parity", "Values protected by Reed-Solomon parity blocks", build_parity),
+    ("tiered", "Cold entries moved to `tiered.cold` by a tiered compaction", build_tiered),
+];
+
+// Features the format reserves but this implementation does not provide
+const UNSUPPORTED: &[(&str, &str)] = &[
+    ("encryption", "The `encryption` feature flag exists but no cipher is implemented"),
+    ("sealing", "There is no sealed archive mode; `transformed` covers the block transform pipeline"),
+];
+
+#[derive(Serialize)]
+struct Manifest {
+    format_version: u8,
+    generator: String,
+    timestamp: DateTime<Utc>,
+    block_size: usize,
+    min_compress_size: usize,
+    transforms: Vec<Note>,
+    unsupported: Vec<Note>,
+    archives: Vec<ArchiveDescription>,
+}
+
+#[derive(Serialize)]
+struct Note {
+    name: String,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct ArchiveDescription {
+    file: String,
+    description: String,
+    /// The cold file of a tiered archive
+    cold_file: Option<String>,
+    entries: Vec<EntryDescription>,
+}
+
+#[derive(Serialize)]
+struct EntryDescription {
+    key: String,
+    data_type: String,
+    size: u64,
+    sha256: String,
+    value_hex: Option<String>,
+    solid: bool,
+    blocks: Vec<BlockDescription>,
+}
+
+#[derive(Serialize)]
+struct BlockDescription {
+    offset: u64,
+    original_size: u64,
+    compressed_size: u64,
+    compression: String,
+    transforms: Vec<String>,
+}
+
+// XORs every byte with 0x5a; its own inverse
+struct XorTransform;
+
+impl Transform for XorTransform {
+    fn name(&self) -> &str {
+        XOR_TRANSFORM
+    }
+
+    fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.iter().map(|byte| byte ^ 0x5a).collect())
+    }
+
+    fn reverse(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.apply(data)
+    }
+}
+
+pub fn generate(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let epoch = DateTime::from_timestamp(0, 0).unwrap_or_default();
+
+    let mut archives = Vec::with_capacity(CASES.len());
+    for (name, description, build) in CASES {
+        let file = format!("{}.usf", name);
+        let mut storage = UniversalStorage::create_reproducible(dir.join(&file), KeyPolicy::default(), epoch)?;
+        storage.add_transform(XOR_PREFIX, Arc::new(XorTransform));
+        build(&mut storage)?;
+
+        archives.push(ArchiveDescription {
+            file,
+            description: description.to_string(),
+            cold_file: storage.cold_tier_path()
+                .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned())),
+            entries: describe_entries(&mut storage)?,
+        });
+    }
+
+    let manifest = Manifest {
+        format_version: VERSION,
+        generator: format!("usf {}", env!("CARGO_PKG_VERSION")),
+        timestamp: epoch,
+        block_size: BLOCK_SIZE,
+        min_compress_size: MIN_COMPRESS_SIZE,
+        transforms: vec![Note {
+            name: XOR_TRANSFORM.to_string(),
+            description: "Every byte XORed with 0x5a, applied before compression".to_string(),
+        }],
+        unsupported: UNSUPPORTED.iter()
+            .map(|(name, description)| Note { name: name.to_string(), description: description.to_string() })
+            .collect(),
+        archives,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+    fs::write(dir.join("manifest.json"), json + "\n")?;
+    Ok(())
+}
+
+fn describe_entries(storage: &mut UniversalStorage) -> Result<Vec<EntryDescription>> {
+    let keys: Vec<String> = storage.keys().map(str::to_string).collect();
+    keys.into_iter()
+        .map(|key| {
+            let info = storage.entry_info(&key)?;
+            let value = storage.retrieve(&key)?;
+            Ok(EntryDescription {
+                data_type: info.data_type.to_string(),
+                size: info.original_size,
+                sha256: hex(&Sha256::digest(&value)),
+                value_hex: (value.len() <= INLINE_VALUE_LIMIT).then(|| hex(&value)),
+                solid: info.solid,
+                blocks: info.blocks.into_iter()
+                    .map(|block| BlockDescription {
+                        offset: block.offset,
+                        original_size: block.original_size,
+                        compressed_size: block.compressed_size,
+                        compression: format!("{:?}", block.compression_method),
+                        transforms: block.transforms,
+                    })
+                    .collect(),
+                key,
+            })
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Deterministic bytes that do not compress
+fn noise(len: usize) -> Vec<u8> {
+    (0..len as u64).map(|i| xxhash_rust::xxh3::xxh3_64(&i.to_le_bytes()) as u8).collect()
+}
+
+fn build_codecs(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("none", b"stored as-is: below the compression threshold", DataType::Text)?;
+    storage.store("zstd", "the quick brown fox jumps over the lazy dog\n".repeat(100).as_bytes(), DataType::Text)?;
+    let series: Vec<i64> = (0..512).map(|i| 1_700_000_000 + i * 60).collect();
+    storage.store("delta", &bincode::serialize(&series)?, DataType::Structured)
+}
+
+fn build_chained(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("three-blocks", &noise(BLOCK_SIZE * 2 + 123), DataType::Binary)?;
+    storage.store("exact-two-blocks", &vec![7u8; BLOCK_SIZE * 2], DataType::Binary)?;
+    storage.store("compressed-chain", "log line\n".repeat(BLOCK_SIZE / 4).as_bytes(), DataType::Text)
+}
+
+fn build_edge_values(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("empty", b"", DataType::Binary)?;
+    storage.store("one-byte", b"x", DataType::Binary)?;
+    storage.store("below-threshold", &vec![b'a'; MIN_COMPRESS_SIZE - 1], DataType::Text)?;
+    storage.store("at-threshold", &vec![b'a'; MIN_COMPRESS_SIZE], DataType::Text)
+}
+
+fn build_unicode_keys(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("café/naïve.txt", "decomposed input: cafe\u{301}".as_bytes(), DataType::Text)?;
+    // Stored under the precomposed form of the key
+    storage.store("re\u{301}sume\u{301}.txt", b"canonicalized key", DataType::Text)?;
+    storage.store("日本語/キー", "値".as_bytes(), DataType::Text)?;
+    storage.store("emoji/🦀", "🦀".as_bytes(), DataType::Text)?;
+    storage.store("space and\ttab", b"whitespace", DataType::Text)
+}
+
+fn build_data_types(storage: &mut UniversalStorage) -> Result<()> {
+    storage.register_data_type(7, CustomType {
+        name: "conformance/raw".to_string(),
+        compression: CompressionPolicy::None,
+        attributes: Vec::new(),
+    })?;
+    storage.store("text", b"plain text", DataType::Text)?;
+    storage.store("binary", &noise(2000), DataType::Binary)?;
+    storage.store("json", br#"{"name":"usf","features":["codecs","solid","tiers"]}"#, DataType::Json)?;
+    storage.store("structured", &bincode::serialize(&("not a list of i64", 42u32))?, DataType::Structured)?;
+    storage.store("custom", &vec![3u8; 4096], DataType::Custom(7))?;
+    storage.store_reference("reference", &Reference::new("other.usf", "text"))
+}
+
+fn build_solid(storage: &mut UniversalStorage) -> Result<()> {
+    storage.add_solid_prefix("packed/")?;
+    let members: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|i| (format!("packed/{:02}", i), format!("small value {}", i).into_bytes()))
+        .collect();
+    let members: Vec<(&str, &[u8])> = members.iter().map(|(key, value)| (key.as_str(), value.as_slice())).collect();
+    storage.store_many(&members, DataType::Text)?;
+    storage.store("original", "shared by a link\n".repeat(200).as_bytes(), DataType::Text)?;
+    storage.link("original", "alias")
+}
+
+fn build_transformed(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("sealed/small", b"xor only", DataType::Text)?;
+    storage.store("sealed/large", "xor then zstd\n".repeat(500).as_bytes(), DataType::Text)?;
+    storage.store("plain", b"not transformed", DataType::Text)
+}
+
+fn build_overwritten(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store("kept", b"first version", DataType::Text)?;
+    storage.store("kept", b"second version", DataType::Text)?;
+    storage.store("deleted", &noise(3000), DataType::Binary)?;
+    storage.delete("deleted")?;
+    storage.store("live", b"untouched", DataType::Text)
+}
+
+fn build_parity(storage: &mut UniversalStorage) -> Result<()> {
+    for i in 0..6 {
+        storage.store(&format!("protected/{}", i), &noise(1500 + i * 100), DataType::Binary)?;
+    }
+    storage.add_parity(4, 2)?;
+    Ok(())
+}
+
+fn build_tiered(storage: &mut UniversalStorage) -> Result<()> {
+    storage.store_with_hint("archive/2019", &noise(5000), DataType::Binary, Hint::Cold)?;
+    storage.store_with_hint("archive/2020", "old report\n".repeat(300).as_bytes(), DataType::Text, Hint::Cold)?;
+    storage.store("current", b"stays in the main file", DataType::Text)?;
+    storage.store_with_hint("pinned", b"hot despite its age", DataType::Text, Hint::Hot)?;
+    // Entries written at the epoch are old enough by any measure
+    let policy = TierPolicy { cold_after: Some(Duration::days(1)), ..TierPolicy::default() };
+    storage.compact_tiered("tiered.cold", &policy)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_conformance_archives_match_manifest() -> io::Result<()> {
+        let dir = tempdir()?;
+        generate(dir.path())?;
+
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(dir.path().join("manifest.json"))?)?;
+        let archives = manifest["archives"].as_array().expect("archive list");
+        assert_eq!(archives.len(), CASES.len());
+
+        // Reopened from disk, every archive reproduces its description
+        for archive in archives {
+            let mut storage = UniversalStorage::open(dir.path().join(archive["file"].as_str().expect("file name")))?;
+            storage.add_transform(XOR_PREFIX, Arc::new(XorTransform));
+            let entries = archive["entries"].as_array().expect("entry list");
+            assert_eq!(storage.keys().count(), entries.len());
+            for entry in entries {
+                let value = storage.retrieve(entry["key"].as_str().expect("key"))?;
+                assert_eq!(entry["sha256"], hex(&Sha256::digest(&value)));
+            }
+        }
+
+        let codecs = archives.iter().find(|a| a["file"] == "codecs.usf").expect("codecs case");
+        let compression: Vec<&str> = codecs["entries"].as_array().expect("entry list").iter()
+            .map(|entry| entry["blocks"][0]["compression"].as_str().expect("method"))
+            .collect();
+        assert_eq!(compression, ["DeltaEncoding", "None", "Zstd"]);
+        let tiered = archives.iter().find(|a| a["file"] == "tiered.usf").expect("tiered case");
+        assert_eq!(tiered["cold_file"], "tiered.cold");
+        assert!(dir.path().join("tiered.cold").exists());
+
+        Ok(())
+    }
+}
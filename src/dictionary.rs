@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::format::CompressionMethod;
+use crate::limits::read_capped;
+use crate::transform::Encoding;
+use crate::{BlockLocation, CompressionPolicy, DataType, ParseLimits, Result, UniversalStorage, UsfError};
+
+// Level for dictionary compression under CompressionPolicy::Auto, matching
+// the built-in text compression
+const AUTO_LEVEL: i32 = 21;
+
+/// A trained zstd dictionary kept in the archive, as listed by
+/// [`UniversalStorage::dictionaries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryInfo {
+    pub id: u32,
+    pub name: String,
+    /// Length of the dictionary itself
+    pub size: u64,
+    pub created: DateTime<Utc>,
+    /// Key prefixes whose new values are compressed with it
+    pub prefixes: Vec<String>,
+}
+
+// A dictionary's blocks, stored uncompressed like any other chain
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct StoredDictionary {
+    pub(crate) id: u32,
+    pub(crate) blocks: Vec<BlockLocation>,
+    pub(crate) size: u64,
+    pub(crate) created: DateTime<Utc>,
+}
+
+// Dictionary bytes and prefix associations, loaded when a handle is opened
+// so encodings can be resolved away from it (as by the background writer)
+#[derive(Clone, Default)]
+pub(crate) struct Dictionaries {
+    data: BTreeMap<u32, Arc<Vec<u8>>>,
+    prefixes: BTreeMap<String, u32>,
+}
+
+impl Dictionaries {
+    // The dictionary for `key`'s longest associated prefix
+    pub(crate) fn for_key(&self, key: &str) -> Option<(u32, Arc<Vec<u8>>)> {
+        let (_, id) = self.prefixes.iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())?;
+        self.data.get(id).map(|data| (*id, Arc::clone(data)))
+    }
+
+    pub(crate) fn decompress(&self, id: u32, data: &[u8], limits: Option<&ParseLimits>) -> Result<Vec<u8>> {
+        let dictionary = self.data.get(&id).ok_or(UsfError::UnknownDictionary(id))?;
+        read_capped(zstd::stream::Decoder::with_dictionary(data, dictionary)?, limits)
+    }
+}
+
+impl Encoding {
+    // Compresses with the encoding's dictionary, if it has one and the
+    // policy compresses at all
+    pub(crate) fn compress_with_dictionary(&self, data: &[u8]) -> Option<io::Result<(Vec<u8>, CompressionMethod)>> {
+        let (id, dictionary) = self.dictionary.as_ref()?;
+        let level = match self.compression {
+            CompressionPolicy::None => return None,
+            CompressionPolicy::Zstd { level } => level,
+            CompressionPolicy::Auto => AUTO_LEVEL,
+        };
+        let compressed = zstd::bulk::Compressor::with_dictionary(level, dictionary)
+            .and_then(|mut compressor| compressor.compress(data));
+        Some(compressed.map(|compressed| (compressed, CompressionMethod::ZstdDictionary(*id))))
+    }
+}
+
+impl UniversalStorage {
+    /// Trains a zstd dictionary of at most `max_size` bytes on `samples`
+    /// and keeps it in the archive under `name`. Returns its id, which
+    /// is never reused within the archive.
+    pub fn create_dictionary<S: AsRef<[u8]>>(&mut self, name: &str, samples: &[S], max_size: usize) -> Result<u32> {
+        if self.metadata.dictionaries.contains_key(name) {
+            return Err(UsfError::DictionaryExists(name.to_string()));
+        }
+        let data = zstd::dict::from_samples(samples, max_size)?;
+
+        let encoding = Encoding {
+            compression: CompressionPolicy::None,
+            transforms: Vec::new(),
+            dictionary: None,
+            block_sizing: Default::default(),
+            block_checksum: self.metadata.block_checksum,
+        };
+        let blocks = Self::prepare_blocks(&data, DataType::Binary, &encoding)?;
+        let (blocks, size) = self.write_blocks(&blocks)?;
+        let id = self.metadata.next_dictionary_id;
+        self.metadata.next_dictionary_id += 1;
+        let created = self.now();
+        self.metadata.dictionaries.insert(name.to_string(), StoredDictionary { id, blocks, size, created });
+        self.metadata.modified = created;
+        self.update_metadata()?;
+        self.dictionaries.data.insert(id, Arc::new(data));
+        Ok(id)
+    }
+
+    /// Compresses values stored under `prefix` from now on with dictionary
+    /// `id`, replacing any dictionary already set for the prefix. Keys
+    /// under several such prefixes use the longest. Values already stored
+    /// keep their compression.
+    pub fn store_with_dictionary(&mut self, prefix: &str, id: u32) -> Result<()> {
+        if self.dictionary_name(id).is_none() {
+            return Err(UsfError::UnknownDictionary(id));
+        }
+        self.metadata.dictionary_prefixes.insert(prefix.to_string(), id);
+        self.metadata.modified = self.now();
+        self.update_metadata()?;
+        self.dictionaries.prefixes = self.metadata.dictionary_prefixes.clone();
+        Ok(())
+    }
+
+    /// Stops compressing new values under `prefix` with a dictionary.
+    pub fn clear_dictionary(&mut self, prefix: &str) -> Result<()> {
+        if self.metadata.dictionary_prefixes.remove(prefix).is_some() {
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+            self.dictionaries.prefixes = self.metadata.dictionary_prefixes.clone();
+        }
+        Ok(())
+    }
+
+    /// Stored dictionaries, ordered by id.
+    pub fn dictionaries(&self) -> Vec<DictionaryInfo> {
+        let mut dictionaries: Vec<_> = self.metadata.dictionaries.iter()
+            .map(|(name, stored)| DictionaryInfo {
+                id: stored.id,
+                name: name.clone(),
+                size: stored.size,
+                created: stored.created,
+                prefixes: self.metadata.dictionary_prefixes.iter()
+                    .filter(|(_, id)| **id == stored.id)
+                    .map(|(prefix, _)| prefix.clone())
+                    .collect(),
+            })
+            .collect();
+        dictionaries.sort_by_key(|info| info.id);
+        dictionaries
+    }
+
+    pub fn dictionary_id(&self, name: &str) -> Option<u32> {
+        self.metadata.dictionaries.get(name).map(|stored| stored.id)
+    }
+
+    /// Blocks compressed with dictionary `id`, counting those of trashed
+    /// values and interrupted ingests. Reads every block header.
+    pub fn dictionary_references(&self, id: u32) -> Result<u64> {
+        let mut seen = HashSet::new();
+        let mut references = 0;
+        let chains = self.metadata.index.values()
+            .map(|entry| entry.blocks.as_slice())
+            .chain(self.pinned_chains().map(|(_, locations)| locations));
+        for location in chains.flatten() {
+            if seen.insert(location.offset)
+                && self.read_header(location)?.compression_method == CompressionMethod::ZstdDictionary(id)
+            {
+                references += 1;
+            }
+        }
+        Ok(references)
+    }
+
+    /// Removes dictionary `id` from the archive. Refused while a prefix is
+    /// set to use it or any block still needs it to be read.
+    pub fn delete_dictionary(&mut self, id: u32) -> Result<()> {
+        let name = self.dictionary_name(id).ok_or(UsfError::UnknownDictionary(id))?.to_string();
+        let prefixes = self.metadata.dictionary_prefixes.values().filter(|prefix_id| **prefix_id == id).count();
+        let blocks = self.dictionary_references(id)?;
+        if prefixes > 0 || blocks > 0 {
+            return Err(UsfError::DictionaryInUse { id, prefixes, blocks });
+        }
+
+        if let Some(stored) = self.metadata.dictionaries.remove(&name) {
+            self.record_freed(&stored.blocks);
+        }
+        self.metadata.modified = self.now();
+        self.update_metadata()?;
+        self.dictionaries.data.remove(&id);
+        Ok(())
+    }
+
+    // Reads every stored dictionary into memory
+    pub(crate) fn load_dictionaries(&self) -> Result<Dictionaries> {
+        let mut data = BTreeMap::new();
+        for stored in self.metadata.dictionaries.values() {
+            let mut bytes = Vec::with_capacity(stored.size as usize);
+            for location in &stored.blocks {
+                let block = self.read_block(location)?;
+                bytes.extend(self.unpack_block(location, block)?);
+            }
+            data.insert(stored.id, Arc::new(bytes));
+        }
+        Ok(Dictionaries { data, prefixes: self.metadata.dictionary_prefixes.clone() })
+    }
+
+    fn dictionary_name(&self, id: u32) -> Option<&str> {
+        self.metadata.dictionaries.iter()
+            .find(|(_, stored)| stored.id == id)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn log_line(i: usize) -> String {
+        format!(
+            r#"{{"level":"info","service":"checkout","request_id":"req-{:06}","latency_ms":{},"path":"/api/v1/orders/{}","status":200}}"#,
+            i * 7919 % 1_000_000, i % 250, i % 97,
+        )
+    }
+
+    #[test]
+    fn test_dictionary_lifecycle() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("dictionaries.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let samples: Vec<String> = (0..500).map(log_line).collect();
+        let id = storage.create_dictionary("logs", &samples, 4096)?;
+        assert!(matches!(storage.create_dictionary("logs", &samples, 4096), Err(UsfError::DictionaryExists(_))));
+        assert!(matches!(storage.store_with_dictionary("logs/", id + 1), Err(UsfError::UnknownDictionary(_))));
+
+        storage.store_with_dictionary("logs/", id)?;
+        let line = log_line(1234);
+        storage.store("logs/1234", line.as_bytes(), DataType::Json)?;
+        storage.store("other", line.as_bytes(), DataType::Json)?;
+        assert_eq!(storage.entry_info("logs/1234")?.compression_method(), Some(CompressionMethod::ZstdDictionary(id)));
+        // Too short to compress without a dictionary
+        assert_eq!(storage.entry_info("other")?.compression_method(), Some(CompressionMethod::None));
+        assert_eq!(storage.dictionary_references(id)?, 1);
+
+        let mut reopened = UniversalStorage::open(&path)?;
+        assert_eq!(reopened.retrieve("logs/1234")?, line.as_bytes());
+        let listed = reopened.dictionaries();
+        assert_eq!((listed[0].id, listed[0].name.as_str()), (id, "logs"));
+        assert_eq!(listed[0].prefixes, ["logs/"]);
+
+        // Refused while a prefix uses it, then while a value does
+        assert!(matches!(storage.delete_dictionary(id), Err(UsfError::DictionaryInUse { prefixes: 1, blocks: 1, .. })));
+        storage.clear_dictionary("logs/")?;
+        assert!(matches!(storage.delete_dictionary(id), Err(UsfError::DictionaryInUse { prefixes: 0, blocks: 1, .. })));
+        storage.compact()?;
+        assert_eq!(storage.retrieve("logs/1234")?, line.as_bytes());
+
+        storage.delete("logs/1234")?;
+        storage.delete_dictionary(id)?;
+        assert!(storage.dictionaries().is_empty());
+        assert_eq!(storage.dictionary_id("logs"), None);
+        assert_ne!(storage.create_dictionary("logs", &samples, 4096)?, id);
+
+        Ok(())
+    }
+}
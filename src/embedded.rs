@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+use crate::dictionary::Dictionaries;
+use crate::format::{self, CompressionMethod, BLOCK_HEADER_PREFIX_SIZE};
+use crate::{clamp, textdelta, Block, BlockLocation, DataType, IndexEntry, MetaData, Result, UniversalStorage, UsfError};
+
+/// A read-only archive held in memory for the life of the program, such
+/// as one compiled in with `include_bytes!`:
+///
+/// ```ignore
+/// static ASSETS: &[u8] = include_bytes!("../assets.usf");
+/// let assets = usf::EmbeddedArchive::new(ASSETS)?;
+/// let shader = assets.get("shaders/main.wgsl")?;
+/// ```
+///
+/// Only the index is decoded up front. Values held in one uncompressed
+/// block are returned as slices of the archive bytes without copying;
+/// everything else is decompressed into a new buffer.
+pub struct EmbeddedArchive {
+    bytes: &'static [u8],
+    metadata: MetaData,
+}
+
+impl EmbeddedArchive {
+    pub fn new(bytes: &'static [u8]) -> Result<Self> {
+        let superblock = format::read_superblock(Cursor::new(bytes))?;
+        let start = superblock.metadata_offset() as usize;
+        let metadata_bytes = bytes.get(start..start + superblock.metadata_size as usize)
+            .ok_or_else(|| UsfError::Corruption("archive is shorter than its metadata".to_string()))?;
+        Ok(Self { bytes, metadata: bincode::deserialize(metadata_bytes)? })
+    }
+
+    /// Stored keys in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.metadata.index.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.metadata.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metadata.index.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entry(key).is_ok()
+    }
+
+    pub fn data_type_of(&self, key: &str) -> Result<DataType> {
+        Ok(self.entry(key)?.data_type.clone())
+    }
+
+    /// The value stored under `key`. Every block is checksummed before use.
+    /// Values needing a registered transform cannot be read, since an
+    /// embedded archive has none.
+    pub fn get(&self, key: &str) -> Result<Cow<'static, [u8]>> {
+        let entry = self.entry(key)?;
+        let ranges = entry.block_ranges();
+
+        if let (None, [(location, range)]) = (entry.text_deltas, ranges.as_slice()) {
+            let (header, data) = self.block(location)?;
+            if header.compression_method == CompressionMethod::None && header.transforms.is_empty() {
+                return Ok(Cow::Borrowed(&data[clamp(range, data.len())]));
+            }
+        }
+
+        let mut value = Vec::with_capacity(entry.size as usize);
+        for (location, range) in &ranges {
+            let (header, data) = self.block(location)?;
+            if let Some(name) = header.transforms.first() {
+                return Err(UsfError::UnknownTransform(name.clone()));
+            }
+            let original_checksum = header.original_checksum;
+            let data = UniversalStorage::decompress_block(Block { header, data: data.to_vec() }, &Dictionaries::default(), None)?;
+            UniversalStorage::check_original(location, original_checksum, &data)?;
+            value.extend_from_slice(&data[clamp(range, data.len())]);
+        }
+        if entry.text_deltas.is_some() {
+            value = textdelta::replay(&value)?;
+        }
+        Ok(Cow::Owned(value))
+    }
+
+    fn entry(&self, key: &str) -> Result<&IndexEntry> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.metadata.index.get(&key).ok_or(UsfError::KeyNotFound(key))
+    }
+
+    // A block's header and verified stored data, borrowed from the archive
+    fn block(&self, location: &BlockLocation) -> Result<(format::BlockHeader, &'static [u8])> {
+        let bytes = self.bytes;
+        let out_of_bounds = || UsfError::Corruption(format!("block at offset {} lies outside the archive", location.offset));
+
+        let header_bytes = bytes.get(location.offset as usize..).ok_or_else(out_of_bounds)?;
+        let (header_size, header) = format::read_block_header(header_bytes, location.offset)?;
+        if header_size != location.header_size || header.compressed_size != location.data_size {
+            return Err(UsfError::Corruption(format!("block header mismatch at offset {}", location.offset)));
+        }
+
+        let start = (location.offset + BLOCK_HEADER_PREFIX_SIZE + header_size as u64) as usize;
+        let data = bytes.get(start..start + location.data_size as usize).ok_or_else(out_of_bounds)?;
+        if !header.checksum_matches(data) {
+            return Err(UsfError::Corruption(format!("checksum mismatch in block at offset {}", location.offset)));
+        }
+        Ok((header, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_embedded_archive_borrows_uncompressed_values() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("assets.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("icon.bin", &[1, 2, 3, 4], DataType::Binary)?;
+        storage.store("level.json", "[0,0,0,0]".repeat(500).as_bytes(), DataType::Json)?;
+        storage.add_solid_prefix("packed/")?;
+        storage.store_many(&[("packed/a", b"first"), ("packed/b", b"second")], DataType::Text)?;
+        drop(storage);
+
+        let bytes: &'static [u8] = Box::leak(fs::read(&path)?.into_boxed_slice());
+        let archive = EmbeddedArchive::new(bytes)?;
+        assert_eq!(archive.len(), 4);
+        assert!(archive.contains_key("icon.bin") && !archive.contains_key("missing"));
+        assert_eq!(archive.data_type_of("level.json")?, DataType::Json);
+
+        let within = |value: &[u8]| bytes.as_ptr_range().contains(&value.as_ptr());
+        let icon = archive.get("icon.bin")?;
+        assert!(matches!(icon, Cow::Borrowed(_)) && within(&icon));
+        assert_eq!(&*icon, &[1, 2, 3, 4]);
+
+        let level = archive.get("level.json")?;
+        assert!(matches!(level, Cow::Owned(_)));
+        assert_eq!(&*level, "[0,0,0,0]".repeat(500).as_bytes());
+
+        assert_eq!(&*archive.get("packed/b")?, b"second");
+        assert!(matches!(archive.get("missing"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::io;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UsfError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(String),
+
+    #[error("Key not found: {0}")]
+    KeyNotFound(String),
+
+    #[error("Key already exists: {0}")]
+    KeyExists(String),
+
+    #[error("Invalid key {key:?}: {reason}")]
+    InvalidKey { key: String, reason: String },
+
+    #[error("Data corruption detected: {0}")]
+    Corruption(String),
+
+    #[error("Block header at offset {offset} fails its checksum")]
+    HeaderChecksumMismatch { offset: u64 },
+
+    #[error("Key of {length} bytes is above the limit of {limit}")]
+    KeyTooLong { length: usize, limit: usize },
+
+    #[error("Value for {key:?} is {size} bytes, above the limit of {limit}")]
+    ValueTooLarge { key: String, size: u64, limit: u64 },
+
+    #[error("{what} of {size} exceeds the limit of {limit}")]
+    LimitExceeded { what: &'static str, size: u64, limit: u64 },
+
+    #[error("Block was written with transform {0:?}, which is not registered")]
+    UnknownTransform(String),
+
+    #[error("Block was compressed with dictionary {0}, which is not in the archive")]
+    UnknownDictionary(u32),
+
+    #[error("Dictionary {0:?} already exists")]
+    DictionaryExists(String),
+
+    #[error("Dictionary {id} is used by {prefixes} prefix(es) and {blocks} block(s)")]
+    DictionaryInUse { id: u32, prefixes: usize, blocks: u64 },
+
+    #[error("Background writer has shut down")]
+    WriterClosed,
+
+    #[error("{0} snapshot(s) still frozen")]
+    Frozen(usize),
+
+    #[error("Archive was opened read-only")]
+    ReadOnly,
+
+    #[error("Archive is locked by another handle")]
+    Locked,
+
+    #[error("Metadata region full: {size} bytes needed, {capacity} available")]
+    MetadataOverflow { size: u64, capacity: u64 },
+
+    #[error("Format version {0} is not supported; this build reads versions 1 to {max}", max = crate::format::VERSION)]
+    UnsupportedVersion(u8),
+}
+
+impl From<bincode::Error> for UsfError {
+    fn from(e: bincode::Error) -> Self {
+        UsfError::Serialization(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for UsfError {
+    fn from(e: serde_json::Error) -> Self {
+        UsfError::Serialization(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for UsfError {
+    fn from(e: rusqlite::Error) -> Self {
+        UsfError::Io(io::Error::other(e))
+    }
+}
+
+impl From<UsfError> for io::Error {
+    fn from(e: UsfError) -> Self {
+        match e {
+            UsfError::Io(e) => e,
+            UsfError::KeyNotFound(_) => io::Error::new(io::ErrorKind::NotFound, e),
+            UsfError::InvalidKey { .. } | UsfError::KeyTooLong { .. } => io::Error::new(io::ErrorKind::InvalidInput, e),
+            UsfError::Corruption(_) | UsfError::HeaderChecksumMismatch { .. } => io::Error::new(io::ErrorKind::InvalidData, e),
+            UsfError::UnsupportedVersion(_) => io::Error::new(io::ErrorKind::Unsupported, e),
+            UsfError::ReadOnly => io::Error::new(io::ErrorKind::PermissionDenied, e),
+            UsfError::Locked => io::Error::new(io::ErrorKind::WouldBlock, e),
+            _ => io::Error::other(e),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, UsfError>;
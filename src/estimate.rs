@@ -0,0 +1,66 @@
+use crate::{CompressionPolicy, DataType, UniversalStorage, MIN_COMPRESS_SIZE};
+
+// Bytes sampled from each of up to SAMPLE_COUNT evenly spaced positions
+const SAMPLE_SIZE: usize = 16 * 1024;
+const SAMPLE_COUNT: usize = 8;
+// Rough on-disk cost of a block's length prefix and header
+const BLOCK_OVERHEAD: u64 = 128;
+
+impl UniversalStorage {
+    /// Cheaply estimates how many bytes storing `data` under `key` would
+    /// add to the file, by compressing a few samples with fast zstd. Stores
+    /// compress harder than the estimate, so it errs on the high side.
+    pub fn estimate_compressed_size(&self, key: &str, data: &[u8], data_type: &DataType) -> u64 {
+        let blocks = data.len().div_ceil(self.block_size_for(data.len() as u64)).max(1) as u64;
+        let overhead = blocks * BLOCK_OVERHEAD;
+
+        let compressible = data.len() >= MIN_COMPRESS_SIZE
+            && self.encoding_for(key, data_type).compression != CompressionPolicy::None;
+        if !compressible {
+            return data.len() as u64 + overhead;
+        }
+
+        let stride = (data.len() / SAMPLE_COUNT).max(SAMPLE_SIZE);
+        let (mut sampled, mut compressed) = (0usize, 0usize);
+        for start in (0..data.len()).step_by(stride).take(SAMPLE_COUNT) {
+            let sample = &data[start..(start + SAMPLE_SIZE).min(data.len())];
+            sampled += sample.len();
+            compressed += zstd::bulk::compress(sample, 1).map_or(sample.len(), |c| c.len().min(sample.len()));
+        }
+
+        let ratio = compressed as f64 / sampled as f64;
+        (data.len() as f64 * ratio).ceil() as u64 + overhead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_estimate_brackets_actual_size() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("estimate.usf"))?;
+        let text = "the quick brown fox jumps over the lazy dog\n".repeat(2000);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..40_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+
+        let text_estimate = storage.estimate_compressed_size("doc", text.as_bytes(), &DataType::Text);
+        let noise_estimate = storage.estimate_compressed_size("noise", &noise, &DataType::Binary);
+        assert!(text_estimate < text.len() as u64 / 10);
+        assert!(noise_estimate >= noise.len() as u64 * 9 / 10);
+
+        storage.store("doc", text.as_bytes(), DataType::Text)?;
+        let actual = storage.stat()?.live_bytes;
+        assert!(actual <= text_estimate);
+
+        Ok(())
+    }
+}
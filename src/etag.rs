@@ -0,0 +1,89 @@
+use std::io::Read;
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+use crate::stream::ValueReader;
+use crate::{DataType, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// A strong HTTP entity tag for the value under `key`, quoted. It is
+    /// derived from the checksums recorded in the value's block headers,
+    /// so no data is read or decompressed, and it changes whenever the
+    /// stored bytes do. With a reference resolver set, a
+    /// [`DataType::Reference`] is resolved and tagged by the content hash
+    /// of its target, which is what it retrieves as.
+    pub fn etag(&mut self, key: &str) -> Result<String> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+
+        if entry.data_type == DataType::Reference && self.resolver.is_some() {
+            let mut payload = Vec::new();
+            ValueReader::new(self, &entry).read_to_end(&mut payload)?;
+            let target = self.resolve_reference(payload)?;
+            return Ok(format!("\"{:032x}\"", xxh3_128(&target)));
+        }
+
+        let mut hasher = Xxh3::new();
+        for location in &entry.blocks {
+            hasher.update(&self.read_header(location)?.checksum.to_le_bytes());
+        }
+        // Values in a solid group share blocks but not their slice of them
+        hasher.update(&entry.solid_offset.unwrap_or(0).to_le_bytes());
+        hasher.update(&entry.size.to_le_bytes());
+        Ok(format!("\"{:016x}\"", hasher.digest()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_etag_tracks_content() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("etag.usf"))?;
+        storage.store("a", b"same", DataType::Text)?;
+        storage.store("b", b"same", DataType::Text)?;
+        let etag = storage.etag("a")?;
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag, storage.etag("b")?);
+
+        storage.store("a", b"changed", DataType::Text)?;
+        assert_ne!(storage.etag("a")?, etag);
+        assert!(matches!(storage.etag("missing"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+    #[test]
+    fn test_references_stream_and_tag_their_target() -> io::Result<()> {
+        let dir = tempdir()?;
+        let shard_path = dir.path().join("shard.usf");
+        let mut shard = UniversalStorage::create(&shard_path)?;
+        shard.store("images/0001", b"pixels", DataType::Binary)?;
+        drop(shard);
+
+        let mut manifest = UniversalStorage::create(dir.path().join("manifest.usf"))?;
+        let reference = crate::Reference::new(shard_path.to_string_lossy(), "images/0001");
+        manifest.store_reference("train/0001", &reference)?;
+        manifest.store("plain", b"plain", DataType::Text)?;
+        manifest.set_reference_resolver(Some(std::sync::Arc::new(crate::ArchiveResolver)));
+
+        let mut reader = manifest.retrieve_many_stream(&["train/0001", "plain"])?;
+        assert_eq!(reader.spans()[0].size, 6);
+        assert_eq!(reader.spans()[1].start, 6);
+        let mut streamed = Vec::new();
+        reader.read_to_end(&mut streamed)?;
+        assert_eq!(streamed, b"pixelsplain");
+        drop(reader);
+
+        // The tag follows the target, not the unchanged reference record
+        let etag = manifest.etag("train/0001")?;
+        crate::UsfOptions::new().write(true).open(&shard_path)?.store("images/0001", b"repainted", DataType::Binary)?;
+        assert_ne!(manifest.etag("train/0001")?, etag);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use crate::UniversalStorage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    /// Older than a rule's `max_age`
+    MaxAge,
+    /// Pushed out by a rule's `keep_last`
+    KeepLast,
+}
+
+/// A key removed by a retention rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiryEvent {
+    pub key: String,
+    pub reason: ExpiryReason,
+    pub stored_at: DateTime<Utc>,
+}
+
+/// Notified whenever retention rules remove a key, whether through
+/// [`UniversalStorage::apply_retention`] or compaction.
+pub trait ExpiryListener: Send + Sync {
+    fn on_expired(&self, event: &ExpiryEvent);
+}
+
+/// Forwards events to a channel, for consumers that poll.
+impl ExpiryListener for Sender<ExpiryEvent> {
+    fn on_expired(&self, event: &ExpiryEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+impl UniversalStorage {
+    pub fn set_expiry_listener(&mut self, listener: Option<Arc<dyn ExpiryListener>>) {
+        self.expiry = listener;
+    }
+
+    pub(crate) fn notify_expired(&self, event: &ExpiryEvent) {
+        if let Some(listener) = &self.expiry {
+            listener.on_expired(event);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{Read, Write};
+use tar::{Archive, Builder, EntryType, Header};
+use crate::stream::ValueReader;
+use crate::{DataType, ProgressPhase, Result, UniversalStorage};
+
+// PAX records carrying what a plain tar header cannot
+const PAX_KEY: &str = "USF.key";
+const PAX_DATA_TYPE: &str = "USF.data_type";
+const EXPORT_COMPRESSION_LEVEL: i32 = 3;
+
+impl UniversalStorage {
+    /// Streams every entry as a tar.zst archive, in key order. Values are
+    /// decompressed block by block, so memory use stays bounded.
+    pub fn export_stream<W: Write>(&mut self, writer: W) -> Result<()> {
+        let encoder = zstd::Encoder::new(writer, EXPORT_COMPRESSION_LEVEL)?;
+        let mut builder = Builder::new(encoder);
+
+        let mut entries: Vec<_> = self.metadata.index.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let total: u64 = entries.iter().map(|(_, entry)| entry.size).sum();
+        let mut exported = 0;
+
+        for (key, entry) in entries {
+            let data_type = entry.data_type.to_string();
+            builder.append_pax_extensions([
+                (PAX_KEY, key.as_bytes()),
+                (PAX_DATA_TYPE, data_type.as_bytes()),
+            ])?;
+
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(entry.size);
+            header.set_mode(0o644);
+            header.set_mtime(entry.stored_at.timestamp().max(0) as u64);
+
+            let reader = ValueReader::new(self, &entry);
+            builder.append_data(&mut header, tar_path(&key), reader)?;
+            exported += entry.size;
+            self.report_progress(exported, total, ProgressPhase::Export);
+        }
+
+        builder.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    /// Stores every regular file of a tar.zst stream, restoring keys and
+    /// data types recorded by [`UniversalStorage::export_stream`]. Files
+    /// without a recorded type are classified by the archive's type rules,
+    /// falling back to `Binary`. Returns
+    /// the number of entries imported.
+    pub fn import_stream<R: Read>(&mut self, reader: R) -> Result<usize> {
+        let decoder = zstd::Decoder::new(reader)?;
+        let mut archive = Archive::new(decoder);
+        let mut imported = 0;
+        let mut bytes_imported = 0;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+
+            let mut key = None;
+            let mut data_type = None;
+            if let Some(extensions) = entry.pax_extensions()? {
+                for extension in extensions {
+                    let extension = extension?;
+                    match (extension.key(), extension.value()) {
+                        (Ok(PAX_KEY), Ok(value)) => key = Some(value.to_string()),
+                        (Ok(PAX_DATA_TYPE), Ok(value)) => {
+                            data_type = value.parse().ok();
+                        },
+                        _ => {},
+                    }
+                }
+            }
+            let key = match key {
+                Some(key) => key,
+                None => entry.path()?.to_string_lossy().into_owned(),
+            };
+
+            let key = self.metadata.key_policy.canonicalize(&key)?;
+            let data_type = data_type
+                .or_else(|| self.classify(&key))
+                .unwrap_or(DataType::Binary);
+            self.store_from(&key, &mut entry, data_type)?;
+            imported += 1;
+            bytes_imported += entry.size();
+            self.report_progress(bytes_imported, 0, ProgressPhase::Import);
+        }
+
+        Ok(imported)
+    }
+}
+
+// Tar paths may not be absolute or climb with `..`; the exact key travels
+// in a PAX record, so the path only needs to be safe for standard tools.
+fn tar_path(key: &str) -> String {
+    let path = key.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .map(|part| if part == ".." { "_" } else { part })
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.is_empty() { "_".to_string() } else { path }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BLOCK_SIZE;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_import_round_trip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut source = UniversalStorage::create(dir.path().join("source.usf"))?;
+
+        let large: Vec<u8> = (0..BLOCK_SIZE * 2 + 17).map(|i| (i % 251) as u8).collect();
+        source.store("data/large.bin", &large, DataType::Binary)?;
+        source.store("/etc/../weird key", b"{\"a\": 1}", DataType::Json)?;
+        source.store("empty", b"", DataType::Text)?;
+
+        let mut stream = Vec::new();
+        source.export_stream(&mut stream)?;
+
+        let mut target = UniversalStorage::create(dir.path().join("target.usf"))?;
+        assert_eq!(target.import_stream(stream.as_slice())?, 3);
+        assert_eq!(target.retrieve("data/large.bin")?, large);
+        assert_eq!(target.retrieve("/etc/../weird key")?, b"{\"a\": 1}");
+        assert_eq!(target.retrieve("empty")?, b"");
+        assert_eq!(target.stat()?.by_data_type[&DataType::Json].entries, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use crate::classify::glob_matches;
+use crate::stream::ValueReader;
+use crate::{Result, UniversalStorage, UsfError};
+
+/// What [`UniversalStorage::export_keys`] does when the target file exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Fail the export
+    #[default]
+    Error,
+    Skip,
+    Overwrite,
+    /// Write `name (1).ext`, `name (2).ext`, ... instead
+    Rename,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub collisions: CollisionPolicy,
+    /// Removed from the front of each key to form its relative path
+    pub strip_prefix: String,
+    /// Apply the `mtime` and `mode` attributes recorded by
+    /// [`UniversalStorage::import_dir`]
+    pub restore_attributes: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportKeysReport {
+    pub written: Vec<PathBuf>,
+    /// Keys that would not map to a path inside the destination
+    pub unsafe_keys: Vec<String>,
+    /// Keys skipped under [`CollisionPolicy::Skip`]
+    pub existing: Vec<String>,
+}
+
+impl UniversalStorage {
+    /// Writes every key matching the glob `selector` to a file under
+    /// `dest`, at the path its `/`-separated segments name. Keys that
+    /// would escape `dest` (`..`, absolute or drive paths, empty segments,
+    /// or a path through a symlink) are skipped and reported.
+    pub fn export_keys<P: AsRef<Path>>(&mut self, selector: &str, dest: P, options: &ExportOptions) -> Result<ExportKeysReport> {
+        let dest = dest.as_ref();
+        fs::create_dir_all(dest)?;
+        let mut report = ExportKeysReport::default();
+
+        let selected: Vec<String> = self.metadata.index.keys()
+            .filter(|key| glob_matches(selector, key))
+            .cloned()
+            .collect();
+        for key in selected {
+            let relative = key.strip_prefix(options.strip_prefix.as_str()).unwrap_or(&key);
+            let Some(path) = safe_path(dest, relative)? else {
+                report.unsafe_keys.push(key);
+                continue;
+            };
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let Some((path, file)) = create_target(path, &key, options.collisions)? else {
+                report.existing.push(key);
+                continue;
+            };
+            let entry = self.metadata.index[&key].clone();
+            let mut writer = BufWriter::new(file);
+            io::copy(&mut ValueReader::new(self, &entry), &mut writer)?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+
+            if options.restore_attributes {
+                restore_attributes(&file, &entry.attributes)?;
+            }
+            report.written.push(path);
+        }
+        Ok(report)
+    }
+}
+
+// Joins a key's segments onto `dest`, or None if the result could land
+// outside it
+fn safe_path(dest: &Path, relative: &str) -> Result<Option<PathBuf>> {
+    let mut path = dest.to_path_buf();
+    for segment in relative.split('/') {
+        let unsafe_segment = matches!(segment, "" | "." | "..")
+            || segment.contains(['\\', ':', '\0']);
+        if unsafe_segment {
+            return Ok(None);
+        }
+        path.push(segment);
+        // An existing symlink along the way could point anywhere
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(path))
+}
+
+// Opens the file to write per the collision policy; None means skip
+fn create_target(path: PathBuf, key: &str, policy: CollisionPolicy) -> Result<Option<(PathBuf, File)>> {
+    let create_new = |path: &Path| OpenOptions::new().write(true).create_new(true).open(path);
+    match create_new(&path) {
+        Ok(file) => return Ok(Some((path, file))),
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e.into()),
+        Err(_) => {},
+    }
+
+    match policy {
+        CollisionPolicy::Error => Err(UsfError::KeyExists(key.to_string())),
+        CollisionPolicy::Skip => Ok(None),
+        CollisionPolicy::Overwrite => {
+            let file = File::create(&path)?;
+            Ok(Some((path, file)))
+        },
+        CollisionPolicy::Rename => {
+            let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+            for n in 1.. {
+                let candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+                match create_new(&candidate) {
+                    Ok(file) => return Ok(Some((candidate, file))),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            unreachable!("ran out of candidate names")
+        },
+    }
+}
+
+fn restore_attributes(file: &File, attributes: &std::collections::BTreeMap<String, String>) -> Result<()> {
+    if let Some(mtime) = attributes.get("mtime").and_then(|m| DateTime::parse_from_rfc3339(m).ok()) {
+        file.set_modified(mtime.with_timezone(&Utc).into())?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = attributes.get("mode").and_then(|m| u32::from_str_radix(m, 8).ok()) {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_keys_is_confined_to_dest() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("export.usf"))?;
+        storage.store("site/index.html", b"<html>", DataType::Text)?;
+        storage.store("site/css/main.css", b"body {}", DataType::Text)?;
+        storage.store("site/../../escape", b"nope", DataType::Text)?;
+        storage.store("other/file", b"not selected", DataType::Text)?;
+        storage.set_attribute("site/index.html", "mtime", "2001-02-03T04:05:06+00:00")?;
+
+        let dest = dir.path().join("out");
+        let options = ExportOptions {
+            strip_prefix: "site/".to_string(),
+            restore_attributes: true,
+            ..ExportOptions::default()
+        };
+        let report = storage.export_keys("site/**", &dest, &options)?;
+        assert_eq!(report.written, [dest.join("css/main.css"), dest.join("index.html")]);
+        assert_eq!(report.unsafe_keys, ["site/../../escape"]);
+        assert_eq!(fs::read(dest.join("css/main.css"))?, b"body {}");
+        let mtime: DateTime<Utc> = fs::metadata(dest.join("index.html"))?.modified()?.into();
+        assert_eq!(mtime.to_rfc3339(), "2001-02-03T04:05:06+00:00");
+
+        // A second export collides with the first
+        assert!(matches!(storage.export_keys("site/**", &dest, &options), Err(UsfError::KeyExists(_))));
+        let rename = ExportOptions { collisions: CollisionPolicy::Rename, ..options.clone() };
+        let report = storage.export_keys("index.html", &dest, &rename)?;
+        assert_eq!(report.written, [dest.join("index (1).html")]);
+        let skip = ExportOptions { collisions: CollisionPolicy::Skip, ..options };
+        assert_eq!(storage.export_keys("site/**", &dest, &skip)?.existing.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Failure injection for testing recovery paths, behind the
+//! `fault-injection` feature.
+//!
+//! Queued faults fire once, the next time the archive performs the
+//! matching operation, and surface as ordinary I/O errors after leaving
+//! the file as a real crash or failing disk would.
+
+use std::io;
+use crate::UniversalStorage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The next block write persists only its first `written` bytes
+    ShortWrite { written: usize },
+    /// The next fsync fails
+    SyncFailure,
+    /// The next metadata commit persists only its first `written` bytes
+    TornMetadata { written: usize },
+}
+
+impl UniversalStorage {
+    /// Queues `fault` to fire on the next matching operation.
+    pub fn inject_fault(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    /// Faults queued but not yet fired.
+    pub fn pending_faults(&self) -> &[Fault] {
+        &self.faults
+    }
+
+    pub(crate) fn take_fault(&mut self, matches: impl Fn(&Fault) -> bool) -> Option<Fault> {
+        let index = self.faults.iter().position(matches)?;
+        Some(self.faults.remove(index))
+    }
+}
+
+pub(crate) fn injected(what: &str) -> io::Error {
+    io::Error::other(format!("injected {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfError};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_injected_faults() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("faults.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("a", b"committed", DataType::Text)?;
+
+        // A short block write fails the store and leaves the index alone
+        storage.inject_fault(Fault::ShortWrite { written: 10 });
+        assert!(storage.store("b", b"lost", DataType::Text).is_err());
+        assert!(storage.pending_faults().is_empty());
+        drop(storage);
+        let mut reopened = UniversalStorage::open_verified(&path)?;
+        assert_eq!(reopened.retrieve("a")?, b"committed");
+        assert!(matches!(reopened.retrieve("b"), Err(UsfError::KeyNotFound(_))));
+        drop(reopened);
+
+        // A torn metadata commit leaves the previous one in force
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("a", b"committed", DataType::Text)?;
+        storage.inject_fault(Fault::TornMetadata { written: 40 });
+        assert!(storage.store("b", b"torn", DataType::Text).is_err());
+        drop(storage);
+        let mut storage = UniversalStorage::open_verified(&path)?;
+        assert_eq!(storage.retrieve("a")?, b"committed");
+        assert!(!storage.contains_key("b") && storage.trailing_data()?.is_some());
+
+        let writer = UniversalStorage::create(&path)?.into_background_writer();
+        writer.store("a", b"value", DataType::Text)?.wait()?;
+        let mut storage = writer.finish()?;
+        storage.inject_fault(Fault::SyncFailure);
+        let writer = storage.into_background_writer();
+        assert!(writer.flush()?.wait().is_err());
+        writer.flush()?.wait()?;
+
+        Ok(())
+    }
+}
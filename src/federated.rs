@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use crate::{DataType, Result, UniversalStorage, UsfError};
+
+/// Which archive of a [`FederatedStorage`] supplies a key present in
+/// several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precedence {
+    /// Archives later in the list override earlier ones, as when layering
+    /// corrections over a base dataset
+    #[default]
+    LastWins,
+    FirstWins,
+}
+
+/// A read-only union of several archives, without merging them on disk.
+/// Each key is read from the archive with the highest precedence that
+/// holds it. Keys are canonicalized by each archive's own key policy.
+pub struct FederatedStorage {
+    // Highest precedence first
+    layers: Vec<UniversalStorage>,
+}
+
+impl FederatedStorage {
+    /// Opens `paths` as layers, later archives overriding earlier ones.
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        Self::open_with_precedence(paths, Precedence::default())
+    }
+
+    pub fn open_with_precedence<P: AsRef<Path>>(paths: &[P], precedence: Precedence) -> Result<Self> {
+        let mut layers = paths.iter()
+            .map(UniversalStorage::open)
+            .collect::<Result<Vec<_>>>()?;
+        if precedence == Precedence::LastWins {
+            layers.reverse();
+        }
+        Ok(Self { layers })
+    }
+
+    /// Every key in any layer, in sorted order and listed once.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter()
+            .flat_map(UniversalStorage::keys)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.iter().all(|layer| layer.metadata.index.is_empty())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.layer_of(key).is_some()
+    }
+
+    /// Path of the archive that supplies `key`.
+    pub fn source_of(&self, key: &str) -> Option<&Path> {
+        self.layer_of(key).map(|index| self.layers[index].path.as_path())
+    }
+
+    pub fn data_type_of(&self, key: &str) -> Result<DataType> {
+        let index = self.layer_of(key).ok_or_else(|| UsfError::KeyNotFound(key.to_string()))?;
+        self.layers[index].data_type_of(key)
+    }
+
+    pub fn retrieve(&mut self, key: &str) -> Result<Vec<u8>> {
+        let index = self.layer_of(key).ok_or_else(|| UsfError::KeyNotFound(key.to_string()))?;
+        self.layers[index].retrieve(key)
+    }
+
+    /// See [`UniversalStorage::retrieve_range`].
+    pub fn retrieve_range(&mut self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let index = self.layer_of(key).ok_or_else(|| UsfError::KeyNotFound(key.to_string()))?;
+        self.layers[index].retrieve_range(key, offset, len)
+    }
+
+    // Position of the highest-precedence layer holding `key`
+    fn layer_of(&self, key: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.contains_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_federated_layers_override_by_precedence() -> io::Result<()> {
+        let dir = tempdir()?;
+        let (base, overlay) = (dir.path().join("base.usf"), dir.path().join("overlay.usf"));
+        let mut storage = UniversalStorage::create(&base)?;
+        storage.store("a", b"base a", DataType::Text)?;
+        storage.store("b", b"base b", DataType::Text)?;
+        let mut storage = UniversalStorage::create(&overlay)?;
+        storage.store("b", b"corrected b", DataType::Json)?;
+        storage.store("c", b"overlay c", DataType::Text)?;
+        drop(storage);
+
+        let mut federated = FederatedStorage::open(&[&base, &overlay])?;
+        assert_eq!(federated.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(federated.len(), 3);
+        assert_eq!(federated.retrieve("a")?, b"base a");
+        assert_eq!(federated.retrieve("b")?, b"corrected b");
+        assert_eq!(federated.data_type_of("b")?, DataType::Json);
+        assert_eq!(federated.source_of("b"), Some(overlay.as_path()));
+        assert_eq!(federated.retrieve_range("b", 10, 1)?, b"b");
+        assert!(matches!(federated.retrieve("d"), Err(UsfError::KeyNotFound(_))));
+
+        let mut federated = FederatedStorage::open_with_precedence(&[&base, &overlay], Precedence::FirstWins)?;
+        assert_eq!(federated.retrieve("b")?, b"base b");
+        assert_eq!(federated.source_of("c"), Some(overlay.as_path()));
+
+        Ok(())
+    }
+}
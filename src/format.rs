@@ -0,0 +1,798 @@
+//! On-disk layout of a `.usf` archive.
+//!
+//! ```text
+//! offset 0        magic "USF1"
+//! offset 4        format version (u8)
+//! offset 5        commit pointer: offset of the current commit (u64 LE),
+//!                 metadata length (u64 LE) and the xxh3-64 of those
+//!                 16 bytes (u64 LE)
+//! DATA_OFFSET     blocks and commits, appended back to back
+//! ```
+//!
+//! Every metadata change appends a commit at the end of the file: the
+//! metadata length (u64 LE), the bincode metadata, starting with the
+//! bincode ArchiveInfo, the xxh3-64 of the metadata bytes (u64 LE), then a
+//! trailer of the commit's offset (u64 LE) and [`TRAILER_MAGIC_BYTES`].
+//! Once the commit is written the pointer is rewritten to it, so a commit
+//! cut short leaves the previous one in force, and no commit is ever
+//! overwritten: each earlier one stays intact as a shadow copy. A torn
+//! pointer is recovered from the trailer when the file still ends in one,
+//! and otherwise, as when the pointer names a commit past the end of the
+//! file, from the latest intact commit in it. A named commit that fails
+//! its checksum, as when the pointer reached the disk before the commit
+//! did, is passed over for the latest intact commit before it (see
+//! [`find_commit_before`]); the metadata's generation counter says how far
+//! back that was. Superseded commits are dead space until the next
+//! compaction.
+//!
+//! Each block is a `u32` LE header length, a [`BlockHeader`] and
+//! `compressed_size` bytes of data whose checksum, by the algorithm the
+//! header names, must equal `checksum`.
+//! Block headers have a fixed layout, independent of any serialization
+//! library:
+//!
+//! ```text
+//! offset 0        magic "USFB"
+//! offset 4        data type tag (u8): 0 Text, 1 Binary, 2 Image, 3 Json,
+//!                 4 Structured, 5 Custom, 6 Reference
+//! offset 5        custom type id (u16), 0 unless the tag is Custom
+//! offset 7        compression tag (u8): 0 None, 1 Zstd, 2 DeltaEncoding,
+//!                 3 ZstdDictionary
+//! offset 8        dictionary id (u32), 0 unless the tag is ZstdDictionary
+//! offset 12       original size (u64)
+//! offset 20       compressed size (u64)
+//! offset 28       checksum (u64), the first 8 bytes of a BLAKE3 digest
+//! offset 36       write time: seconds since the Unix epoch (i64), then
+//!                 nanoseconds (u32)
+//! offset 48       transform count (u16), then each transform name as a
+//!                 u16 length and UTF-8 bytes
+//! then            flags (u8): bit 0 set if the xxh3-64 of the
+//!                 uncompressed data (u64) that follows is set, bit 1 set
+//!                 if the header ends in a checksum
+//! then            checksum algorithm (u8): 0 xxh3-64, 1 CRC-32C, 2 BLAKE3,
+//!                 followed for BLAKE3 by the full 32-byte digest
+//! last 4 bytes    CRC-32C (u32) of the header length prefix and every
+//!                 header byte before it
+//! ```
+//!
+//! Fields may be added before the header checksum in later versions, so
+//! readers skip whatever follows the fields they know, up to the checksum
+//! or the end of the header. A header that fails its checksum is reported
+//! as [`UsfError::HeaderChecksumMismatch`] rather than decoded into wrong
+//! sizes; headers written before the checksum was added, which end with
+//! the original checksum, are not checked. Whether a header is checked
+//! follows from its length as well as its flags, so no flipped flag bit
+//! can turn the check off.
+//! Headers without a checksum algorithm are xxh3-64.
+//!
+//! Version 2 archives have the same layout but bincode block headers, which
+//! never begin with the block header magic, carry no checksum and are
+//! always xxh3-64. They are still read and written as version 2, and
+//! compaction or [`crate::UniversalStorage::migrate`] rewrites their
+//! headers.
+//!
+//! Version 1 archives keep a single commit, without the trailer, in a
+//! fixed region of [`METADATA_CAPACITY`] bytes at offset 5 that is
+//! rewritten in place, with no earlier copy to fall back to unless the
+//! write-ahead journal is enabled. Their blocks start at
+//! [`V1_DATA_OFFSET`] and their block headers are bincode. They are still
+//! read and written as version 1; compaction or migration rewrites them as
+//! the current version. Any other version fails to open with
+//! [`UsfError::UnsupportedVersion`].
+//!
+//! A tiered archive (see [`crate::UniversalStorage::compact_tiered`])
+//! keeps some blocks in a separate cold file: [`COLD_MAGIC_BYTES`], the
+//! u64 LE tier id recorded in the metadata, then blocks in the same
+//! encoding. Block offsets from [`COLD_TIER_BASE`] up address that file,
+//! at `offset - COLD_TIER_BASE`.
+//!
+//! An index sidecar (see [`crate::UniversalStorage::set_index_sidecar`])
+//! holds [`SIDECAR_MAGIC_BYTES`], the format version, then a copy of the
+//! last commit: length, metadata and checksum.
+//!
+//! A write-ahead journal (see
+//! [`crate::UniversalStorage::set_write_ahead_log`]) holds
+//! [`JOURNAL_MAGIC_BYTES`], then each pending write as its file offset
+//! (u64 LE), length (u64 LE) and bytes, then the xxh3-64 of everything
+//! before it (u64 LE). An empty journal has nothing pending.
+//!
+//! Every integer is little-endian. The metadata is still bincode, in its
+//! default fixed-width encoding, so the helpers here are enough to walk an
+//! archive without going through [`crate::UniversalStorage`].
+
+use std::io::{Read, Seek, SeekFrom};
+use xxhash_rust::xxh3::xxh3_64;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::{ArchiveInfo, BlockChecksum, DataType, Result, UsfError};
+
+pub const MAGIC_BYTES: &[u8; 4] = b"USF1";
+pub const VERSION: u8 = 3;
+/// The version whose metadata is rewritten in place
+pub const VERSION_1: u8 = 1;
+/// The last version with bincode block headers
+pub const VERSION_2: u8 = 2;
+/// Default amount of uncompressed data held by a single block; larger
+/// values may use larger blocks (see
+/// [`crate::UniversalStorage::set_block_size_bounds`])
+pub const BLOCK_SIZE: usize = 1024 * 64;
+/// Largest amount of uncompressed data held by a single block
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024 * 8;
+/// Blocks smaller than this are stored uncompressed
+pub const MIN_COMPRESS_SIZE: usize = 1024;
+/// Where the commit pointer, or a version 1 metadata region, starts
+pub const METADATA_OFFSET: u64 = 5;
+pub const COMMIT_POINTER_SIZE: u64 = 24;
+pub const DATA_OFFSET: u64 = METADATA_OFFSET + COMMIT_POINTER_SIZE;
+/// Bytes reserved for the metadata length and the metadata itself in a
+/// version 1 archive
+pub const METADATA_CAPACITY: u64 = 1024 * 1024;
+pub const V1_DATA_OFFSET: u64 = METADATA_OFFSET + METADATA_CAPACITY;
+/// Ends the trailer after every commit
+pub const TRAILER_MAGIC_BYTES: &[u8; 4] = b"USFE";
+/// Commit offset and magic
+pub const TRAILER_SIZE: u64 = 12;
+/// Size of the length prefix in front of every block header
+pub const BLOCK_HEADER_PREFIX_SIZE: u64 = 4;
+pub const BLOCK_HEADER_MAGIC: &[u8; 4] = b"USFB";
+/// Bytes of a block header up to and including the transform count
+pub const BLOCK_HEADER_FIXED_SIZE: usize = 50;
+/// Bytes after the transforms: the flags, original checksum, checksum
+/// algorithm and header checksum, not counting a BLAKE3 digest
+pub const BLOCK_HEADER_TAIL_SIZE: usize = 14;
+// Flag bits after the transforms
+const ORIGINAL_CHECKSUM_FLAG: u8 = 1;
+const HEADER_CHECKSUM_FLAG: u8 = 2;
+pub const COLD_MAGIC_BYTES: &[u8; 4] = b"USFC";
+/// Where blocks start in a cold tier file, after the magic and tier id
+pub const COLD_DATA_OFFSET: u64 = 12;
+/// Block offsets at or above this address the cold tier file
+pub const COLD_TIER_BASE: u64 = 1 << 62;
+pub const SIDECAR_MAGIC_BYTES: &[u8; 4] = b"USFI";
+pub const JOURNAL_MAGIC_BYTES: &[u8; 4] = b"USFW";
+
+/// The fixed fields at the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub version: u8,
+    /// Where the current commit starts, at its length field
+    pub commit_offset: u64,
+    /// Length of the bincode metadata following the length field
+    pub metadata_size: u64,
+}
+
+impl Superblock {
+    /// Where the bincode metadata starts.
+    pub fn metadata_offset(&self) -> u64 {
+        self.commit_offset + 8
+    }
+
+    /// Bytes of the commit: length, metadata, checksum and, from version 2
+    /// on, the trailer.
+    pub fn commit_size(&self) -> u64 {
+        let trailer = if self.version == VERSION_1 { 0 } else { TRAILER_SIZE };
+        self.metadata_size.saturating_add(16 + trailer)
+    }
+}
+
+/// Where blocks start in an archive of `version`.
+pub fn data_offset(version: u8) -> u64 {
+    match version {
+        VERSION_1 => V1_DATA_OFFSET,
+        _ => DATA_OFFSET,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    None,
+    Zstd,
+    DeltaEncoding,
+    /// Zstd with the archive's dictionary of this id
+    ZstdDictionary(u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub data_type: DataType,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub compression_method: CompressionMethod,
+    /// Checksum of the stored (compressed) data, by `checksum_algorithm`
+    pub checksum: u64,
+    pub timestamp: DateTime<Utc>,
+    /// Names of the transforms applied before compression, in order
+    pub transforms: Vec<String>,
+    /// xxh3-64 of the uncompressed data, after any transforms, checked
+    /// once the block is decompressed. Transforms may be lossy, so their
+    /// reversal is not covered. Unset in bincode headers and in blocks
+    /// whose stored form does not decompress back to that data, such as
+    /// transcoded images.
+    #[serde(skip)]
+    pub original_checksum: Option<u64>,
+    /// Xxh3 in bincode headers and those written before the field was
+    /// added
+    #[serde(skip)]
+    pub checksum_algorithm: BlockChecksum,
+    /// The full digest of the stored data, for algorithms wider than
+    /// `checksum`
+    #[serde(skip)]
+    pub digest: Option<[u8; 32]>,
+}
+
+impl BlockHeader {
+    /// Bytes the block occupies on disk, given its encoded header length.
+    pub fn disk_size(&self, header_size: u32) -> u64 {
+        BLOCK_HEADER_PREFIX_SIZE + header_size as u64 + self.compressed_size
+    }
+
+    /// Whether `data` is the stored data this header describes, by its
+    /// checksum algorithm and, when there is one, the full digest.
+    pub fn checksum_matches(&self, data: &[u8]) -> bool {
+        self.checksum_algorithm.compute(data) == (self.checksum, self.digest)
+    }
+
+    /// Encodes the header for an archive of `version`: bincode up to
+    /// [`VERSION_2`], the fixed layout from then on.
+    pub fn encode(&self, version: u8) -> Result<Vec<u8>> {
+        if version <= VERSION_2 {
+            return Ok(bincode::serialize(self)?);
+        }
+
+        let (data_type, custom_id) = match self.data_type {
+            DataType::Text => (0u8, 0u16),
+            DataType::Binary => (1, 0),
+            DataType::Image => (2, 0),
+            DataType::Json => (3, 0),
+            DataType::Structured => (4, 0),
+            DataType::Custom(id) => (5, id),
+            DataType::Reference => (6, 0),
+        };
+        let (compression, dictionary_id) = match self.compression_method {
+            CompressionMethod::None => (0u8, 0u32),
+            CompressionMethod::Zstd => (1, 0),
+            CompressionMethod::DeltaEncoding => (2, 0),
+            CompressionMethod::ZstdDictionary(id) => (3, id),
+        };
+        let transform_count = u16::try_from(self.transforms.len())
+            .map_err(|_| UsfError::Serialization("too many transforms for a block header".to_string()))?;
+
+        let transform_size = self.transforms.iter().map(|t| 2 + t.len()).sum::<usize>();
+        let digest_size = self.digest.map_or(0, |digest| digest.len());
+        let mut bytes = Vec::with_capacity(BLOCK_HEADER_FIXED_SIZE + transform_size + BLOCK_HEADER_TAIL_SIZE + digest_size);
+        bytes.extend_from_slice(BLOCK_HEADER_MAGIC);
+        bytes.push(data_type);
+        bytes.extend_from_slice(&custom_id.to_le_bytes());
+        bytes.push(compression);
+        bytes.extend_from_slice(&dictionary_id.to_le_bytes());
+        bytes.extend_from_slice(&self.original_size.to_le_bytes());
+        bytes.extend_from_slice(&self.compressed_size.to_le_bytes());
+        bytes.extend_from_slice(&self.checksum.to_le_bytes());
+        bytes.extend_from_slice(&self.timestamp.timestamp().to_le_bytes());
+        bytes.extend_from_slice(&self.timestamp.timestamp_subsec_nanos().to_le_bytes());
+        bytes.extend_from_slice(&transform_count.to_le_bytes());
+        for name in &self.transforms {
+            let len = u16::try_from(name.len())
+                .map_err(|_| UsfError::Serialization(format!("transform name {:?} is too long for a block header", name)))?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        let flags = match self.original_checksum {
+            Some(_) => ORIGINAL_CHECKSUM_FLAG | HEADER_CHECKSUM_FLAG,
+            None => HEADER_CHECKSUM_FLAG,
+        };
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.original_checksum.unwrap_or(0).to_le_bytes());
+        match (self.checksum_algorithm, self.digest) {
+            (BlockChecksum::Xxh3, None) => bytes.push(0),
+            (BlockChecksum::Crc32c, None) => bytes.push(1),
+            (BlockChecksum::Blake3, Some(digest)) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&digest);
+            },
+            (algorithm, _) => return Err(UsfError::Serialization(format!("block header digest does not match its {:?} checksum", algorithm))),
+        }
+        bytes.extend_from_slice(&header_checksum(&bytes, bytes.len() + 4).to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Decodes a header of any version, telling the fixed layout from
+    /// bincode by its magic. `offset` is where the block starts, for
+    /// errors.
+    pub fn decode(bytes: &[u8], offset: u64) -> Result<Self> {
+        let Some(mut fields) = bytes.strip_prefix(BLOCK_HEADER_MAGIC.as_slice()) else {
+            return Ok(bincode::deserialize(bytes)?);
+        };
+
+        let data_type = match (take_u8(&mut fields)?, take_u16(&mut fields)?) {
+            (0, _) => DataType::Text,
+            (1, _) => DataType::Binary,
+            (2, _) => DataType::Image,
+            (3, _) => DataType::Json,
+            (4, _) => DataType::Structured,
+            (5, id) => DataType::Custom(id),
+            (6, _) => DataType::Reference,
+            (tag, _) => return Err(UsfError::Corruption(format!("unknown data type tag {} in block header", tag))),
+        };
+        let compression_method = match (take_u8(&mut fields)?, take_u32(&mut fields)?) {
+            (0, _) => CompressionMethod::None,
+            (1, _) => CompressionMethod::Zstd,
+            (2, _) => CompressionMethod::DeltaEncoding,
+            (3, id) => CompressionMethod::ZstdDictionary(id),
+            (tag, _) => return Err(UsfError::Corruption(format!("unknown compression tag {} in block header", tag))),
+        };
+        let original_size = take_u64(&mut fields)?;
+        let compressed_size = take_u64(&mut fields)?;
+        let checksum = take_u64(&mut fields)?;
+        let seconds = take_u64(&mut fields)? as i64;
+        let nanos = take_u32(&mut fields)?;
+        let timestamp = DateTime::from_timestamp(seconds, nanos)
+            .ok_or_else(|| UsfError::Corruption("block header time is out of range".to_string()))?;
+
+        let transform_count = take_u16(&mut fields)?;
+        let mut transforms = Vec::with_capacity(transform_count as usize);
+        for _ in 0..transform_count {
+            let len = take_u16(&mut fields)? as usize;
+            let name = take(&mut fields, len)?;
+            let name = String::from_utf8(name.to_vec())
+                .map_err(|_| UsfError::Corruption("transform name in block header is not UTF-8".to_string()))?;
+            transforms.push(name);
+        }
+        // Headers written before the fields were added end here
+        let (flags, original_checksum) = match fields.len() >= 9 {
+            true => (take_u8(&mut fields)?, take_u64(&mut fields)?),
+            false => (0, 0),
+        };
+        // Nothing followed the original checksum before the header checksum
+        // was added, so a longer header is checked whatever its flags say
+        if flags & HEADER_CHECKSUM_FLAG != 0 || !fields.is_empty() {
+            let (covered, stored) = bytes.split_at(bytes.len().saturating_sub(4));
+            if fields.len() < 4 || header_checksum(covered, bytes.len()).to_le_bytes() != stored {
+                return Err(UsfError::HeaderChecksumMismatch { offset });
+            }
+            fields = &fields[..fields.len() - 4];
+        }
+        let original_checksum = (flags & ORIGINAL_CHECKSUM_FLAG != 0).then_some(original_checksum);
+        let (checksum_algorithm, digest) = match fields.is_empty() {
+            true => (BlockChecksum::Xxh3, None),
+            false => match take_u8(&mut fields)? {
+                0 => (BlockChecksum::Xxh3, None),
+                1 => (BlockChecksum::Crc32c, None),
+                2 => (BlockChecksum::Blake3, Some(take(&mut fields, 32)?.try_into().expect("32 bytes"))),
+                tag => return Err(UsfError::Corruption(format!("unknown checksum algorithm tag {} in block header", tag))),
+            },
+        };
+
+        Ok(Self {
+            data_type,
+            original_size,
+            compressed_size,
+            compression_method,
+            checksum,
+            timestamp,
+            transforms,
+            original_checksum,
+            checksum_algorithm,
+            digest,
+        })
+    }
+}
+
+// CRC-32C of a header's length prefix, for a header of `header_size`
+// bytes, and `covered`, its bytes before the checksum
+fn header_checksum(covered: &[u8], header_size: usize) -> u32 {
+    let prefix = crc32c::crc32c(&(header_size as u32).to_le_bytes());
+    crc32c::crc32c_append(prefix, covered)
+}
+
+// Splits `len` bytes off the front of a block header
+fn take<'a>(fields: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if fields.len() < len {
+        return Err(UsfError::Corruption("block header is truncated".to_string()));
+    }
+    let (taken, rest) = fields.split_at(len);
+    *fields = rest;
+    Ok(taken)
+}
+
+fn take_u8(fields: &mut &[u8]) -> Result<u8> {
+    Ok(take(fields, 1)?[0])
+}
+
+fn take_u16(fields: &mut &[u8]) -> Result<u16> {
+    Ok(u16::from_le_bytes(take(fields, 2)?.try_into().expect("2 bytes")))
+}
+
+fn take_u32(fields: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(fields, 4)?.try_into().expect("4 bytes")))
+}
+
+fn take_u64(fields: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(take(fields, 8)?.try_into().expect("8 bytes")))
+}
+
+/// Reads and validates the superblock from the start of an archive,
+/// falling back to the trailer at the end of the file if the commit
+/// pointer is torn.
+pub fn read_superblock<R: Read + Seek>(mut reader: R) -> Result<Superblock> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC_BYTES {
+        return Err(UsfError::Corruption("invalid magic bytes".to_string()));
+    }
+
+    let mut version = [0u8];
+    reader.read_exact(&mut version)?;
+    let version = version[0];
+    if version == VERSION_1 {
+        let metadata_size = read_u64(&mut reader)?;
+        if metadata_size > METADATA_CAPACITY - 16 {
+            return Err(UsfError::Corruption(format!("metadata size {} exceeds the metadata region", metadata_size)));
+        }
+        return Ok(Superblock { version, commit_offset: METADATA_OFFSET, metadata_size });
+    }
+    if version != VERSION && version != VERSION_2 {
+        return Err(UsfError::UnsupportedVersion(version));
+    }
+
+    let mut pointer = [0u8; COMMIT_POINTER_SIZE as usize];
+    reader.read_exact(&mut pointer)?;
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let field = |i: usize| u64::from_le_bytes(pointer[i * 8..i * 8 + 8].try_into().expect("8 bytes"));
+    let named = match xxh3_64(&pointer[..16]) == field(2) {
+        true => Some(Superblock { version, commit_offset: field(0), metadata_size: field(1) }),
+        false => read_trailer(&mut reader, version, file_size)?,
+    };
+    let within_file = |superblock: &Superblock| {
+        let end = superblock.commit_offset.checked_add(superblock.commit_size());
+        superblock.commit_offset >= DATA_OFFSET && end.is_some_and(|end| end <= file_size)
+    };
+    if let Some(superblock) = named.filter(within_file) {
+        return Ok(superblock);
+    }
+
+    // The pointer reached the disk without the commit it names, or was torn
+    // with no trailer to follow: the latest intact commit stands in
+    find_commit_before(&mut reader, version, file_size)?
+        .ok_or_else(|| UsfError::Corruption("commit pointer is damaged and the file holds no intact commit".to_string()))
+}
+
+// Locates the commit from the trailer the file ends with, if it does
+fn read_trailer<R: Read + Seek>(reader: &mut R, version: u8, file_size: u64) -> Result<Option<Superblock>> {
+    if file_size < DATA_OFFSET + TRAILER_SIZE {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(file_size - TRAILER_SIZE))?;
+    let commit_offset = read_u64(&mut *reader)?;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != TRAILER_MAGIC_BYTES || commit_offset >= file_size {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(commit_offset))?;
+    Ok(Some(Superblock { version, commit_offset, metadata_size: read_u64(reader)? }))
+}
+
+/// Reads the metadata of the commit `superblock` names, failing with
+/// [`UsfError::Corruption`] unless it matches the commit's checksum.
+pub fn read_metadata<R: Read + Seek>(mut reader: R, superblock: &Superblock) -> Result<Vec<u8>> {
+    let mut metadata = vec![0u8; superblock.metadata_size as usize];
+    reader.seek(SeekFrom::Start(superblock.metadata_offset()))?;
+    reader.read_exact(&mut metadata)?;
+    if xxh3_64(&metadata) != read_u64(reader)? {
+        return Err(UsfError::Corruption(format!("metadata commit at offset {} fails its checksum", superblock.commit_offset)));
+    }
+    Ok(metadata)
+}
+
+/// The latest intact commit ending at or before `before`, found by
+/// scanning back for a trailer that points at a commit ending there whose
+/// checksum matches. Commits are appended in order, so this is the one
+/// committed just before any commit starting at `before`.
+pub fn find_commit_before<R: Read + Seek>(mut reader: R, version: u8, before: u64) -> Result<Option<Superblock>> {
+    const WINDOW: u64 = 64 * 1024;
+    let overlap = TRAILER_MAGIC_BYTES.len() as u64 - 1;
+    let mut end = before;
+    while end >= DATA_OFFSET + 16 + TRAILER_SIZE {
+        let start = end.saturating_sub(WINDOW).max(DATA_OFFSET);
+        let mut window = vec![0u8; (end - start) as usize];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut window)?;
+
+        for i in (0..window.len().saturating_sub(overlap as usize)).rev() {
+            if !window[i..].starts_with(TRAILER_MAGIC_BYTES) {
+                continue;
+            }
+            let trailer_end = start + i as u64 + TRAILER_MAGIC_BYTES.len() as u64;
+            if let Some(superblock) = commit_ending_at(&mut reader, version, trailer_end)? {
+                return Ok(Some(superblock));
+            }
+        }
+        if start == DATA_OFFSET {
+            break;
+        }
+        end = start + overlap;
+    }
+    Ok(None)
+}
+
+// The intact commit whose trailer ends at `end`, if there is one
+fn commit_ending_at<R: Read + Seek>(reader: &mut R, version: u8, end: u64) -> Result<Option<Superblock>> {
+    if end < DATA_OFFSET + 16 + TRAILER_SIZE {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(end - TRAILER_SIZE))?;
+    let commit_offset = read_u64(&mut *reader)?;
+    if commit_offset < DATA_OFFSET || commit_offset > end - 16 - TRAILER_SIZE {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(commit_offset))?;
+    let superblock = Superblock { version, commit_offset, metadata_size: read_u64(&mut *reader)? };
+    if commit_offset.checked_add(superblock.commit_size()) != Some(end) {
+        return Ok(None);
+    }
+    match read_metadata(reader, &superblock) {
+        Ok(_) => Ok(Some(superblock)),
+        Err(UsfError::Corruption(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_u64<R: Read>(mut reader: R) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads the superblock and the [`ArchiveInfo`] that opens the metadata,
+/// without decoding the rest of it.
+pub fn read_archive_info<R: Read + Seek>(mut reader: R) -> Result<ArchiveInfo> {
+    let superblock = read_superblock(&mut reader)?;
+    reader.seek(SeekFrom::Start(superblock.metadata_offset()))?;
+    Ok(bincode::deserialize_from(reader.take(superblock.metadata_size))?)
+}
+
+// The commit pointer naming a commit at `commit_offset`
+pub(crate) fn commit_pointer(commit_offset: u64, metadata_size: u64) -> Vec<u8> {
+    let mut pointer = Vec::with_capacity(COMMIT_POINTER_SIZE as usize);
+    pointer.extend_from_slice(&commit_offset.to_le_bytes());
+    pointer.extend_from_slice(&metadata_size.to_le_bytes());
+    pointer.extend_from_slice(&xxh3_64(&pointer).to_le_bytes());
+    pointer
+}
+
+// `commit` followed by its trailer, ready to append at `commit_offset`
+pub(crate) fn with_trailer(commit: &[u8], commit_offset: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(commit.len() + TRAILER_SIZE as usize);
+    bytes.extend_from_slice(commit);
+    bytes.extend_from_slice(&commit_offset.to_le_bytes());
+    bytes.extend_from_slice(TRAILER_MAGIC_BYTES);
+    bytes
+}
+
+/// Reads the length prefix and header of the block at `offset`, leaving
+/// `reader` positioned at the block data. Returns the encoded header
+/// length alongside the header.
+pub fn read_block_header<R: Read>(mut reader: R, offset: u64) -> Result<(u32, BlockHeader)> {
+    let mut size_bytes = [0u8; 4];
+    reader.read_exact(&mut size_bytes)?;
+    let header_size = u32::from_le_bytes(size_bytes);
+    if header_size == 0 {
+        return Err(UsfError::Corruption("empty block header".to_string()));
+    }
+
+    let mut header_bytes = vec![0u8; header_size as usize];
+    reader.read_exact(&mut header_bytes)?;
+    Ok((header_size, BlockHeader::decode(&header_bytes, offset)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MetaData, UniversalStorage, UsfOptions};
+    use std::fs::File;
+    use std::io::{self, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_walk_archive_with_format_helpers() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("format.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("a", b"hello", DataType::Text)?;
+        storage.store("b", &[7u8; 2000], DataType::Binary)?;
+        drop(storage);
+
+        let mut file = File::open(&path)?;
+        let superblock = read_superblock(&mut file)?;
+        assert_eq!(superblock.version, VERSION);
+        assert_eq!(read_archive_info(&mut file)?, ArchiveInfo::default());
+
+        // The commit pointer leads to the metadata and the trailer back to
+        // the commit
+        let file_size = file.metadata()?.len();
+        assert_eq!(superblock.commit_offset + superblock.commit_size(), file_size);
+        file.seek(SeekFrom::Start(superblock.metadata_offset()))?;
+        let mut metadata_bytes = vec![0u8; superblock.metadata_size as usize];
+        file.read_exact(&mut metadata_bytes)?;
+        let metadata: MetaData = bincode::deserialize(&metadata_bytes).map_err(io::Error::other)?;
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        file.seek(SeekFrom::End(-(TRAILER_SIZE as i64)))?;
+        file.read_exact(&mut trailer)?;
+        assert_eq!(trailer[..8], superblock.commit_offset.to_le_bytes());
+        assert_eq!(&trailer[8..], TRAILER_MAGIC_BYTES);
+
+        let mut headers = Vec::new();
+        for entry in metadata.index.values() {
+            file.seek(SeekFrom::Start(entry.blocks[0].offset))?;
+            let (_, header) = read_block_header(&mut file, entry.blocks[0].offset)?;
+            let mut data = vec![0u8; header.compressed_size as usize];
+            file.read_exact(&mut data)?;
+            assert_eq!(xxh3_64(&data), header.checksum);
+            headers.push(header);
+        }
+
+        assert_eq!(headers[0].compression_method, CompressionMethod::None);
+        assert_eq!(headers[1].compression_method, CompressionMethod::Zstd);
+        assert_eq!(headers[1].original_size, 2000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_1_archives_still_open() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("v1.usf");
+        let metadata = bincode::serialize(&MetaData::new(Default::default(), chrono::Utc::now())).map_err(io::Error::other)?;
+        let mut file = File::create(&path)?;
+        file.write_all(MAGIC_BYTES)?;
+        file.write_all(&[VERSION_1])?;
+        file.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        file.write_all(&metadata)?;
+        file.write_all(&xxh3_64(&metadata).to_le_bytes())?;
+        file.set_len(V1_DATA_OFFSET)?;
+        drop(file);
+
+        // Commits to a version 1 archive stay in its metadata region
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        storage.store("old", b"from version 1", DataType::Text)?;
+        storage.verify_metadata()?;
+        assert_eq!(read_superblock(File::open(&path)?)?.version, VERSION_1);
+        assert_eq!(storage.metadata.index["old"].blocks[0].offset, V1_DATA_OFFSET);
+        let header_magic = |storage: &UniversalStorage| -> io::Result<[u8; 4]> {
+            let mut magic = [0u8; 4];
+            storage.read_at(&mut magic, storage.metadata.index["old"].blocks[0].offset + BLOCK_HEADER_PREFIX_SIZE)?;
+            Ok(magic)
+        };
+        assert_ne!(&header_magic(&storage)?, BLOCK_HEADER_MAGIC);
+        let large = ArchiveInfo { description: Some("x".repeat(METADATA_CAPACITY as usize)), ..ArchiveInfo::default() };
+        assert!(matches!(storage.set_archive_info(large.clone()), Err(UsfError::MetadataOverflow { .. })));
+        // The refused change is dropped, so the handle keeps committing
+        assert_eq!(storage.archive_info(), &ArchiveInfo::default());
+        storage.store("after", b"after the overflow", DataType::Text)?;
+
+        // Compaction upgrades it, lifting the metadata size limit and
+        // rewriting block headers in the fixed layout
+        storage.compact()?;
+        assert_eq!(&header_magic(&storage)?, BLOCK_HEADER_MAGIC);
+        storage.set_archive_info(large.clone())?;
+        drop(storage);
+        assert_eq!(read_superblock(File::open(&path)?)?.version, VERSION);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.retrieve("old")?, b"from version 1");
+        assert_eq!(storage.retrieve("after")?, b"after the overflow");
+        assert_eq!(storage.archive_info(), &large);
+        assert!(storage.trailing_data()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unusable_pointer_falls_back_to_latest_commit() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("pointer.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("a", b"first", DataType::Text)?;
+        storage.store("b", b"second", DataType::Text)?;
+        let (latest, _) = storage.commit.expect("version 3 commit");
+        drop(storage);
+
+        // A torn pointer, with uncommitted bytes after the last trailer
+        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        crate::platform::write_all_at(&file, b"uncommitted block", file.metadata()?.len())?;
+        crate::platform::write_all_at(&file, &[0xff; COMMIT_POINTER_SIZE as usize], METADATA_OFFSET)?;
+        assert_eq!(read_superblock(File::open(&path)?)?.commit_offset, latest);
+
+        // A pointer that reached the disk ahead of its commit
+        let file_size = file.metadata()?.len();
+        crate::platform::write_all_at(&file, &commit_pointer(file_size + 64, 100), METADATA_OFFSET)?;
+        let storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.keys().collect::<Vec<_>>(), ["a", "b"]);
+        drop(storage);
+
+        // With no intact commit left, there is nothing to fall back to
+        crate::platform::write_all_at(&file, b"torn", latest + 12)?;
+        let mut before = latest;
+        while let Some(commit) = find_commit_before(File::open(&path)?, VERSION, before)? {
+            crate::platform::write_all_at(&file, b"torn", commit.commit_offset + 12)?;
+            before = commit.commit_offset;
+        }
+        assert!(matches!(read_superblock(File::open(&path)?), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_header_wire_layout() -> Result<()> {
+        let header = BlockHeader {
+            data_type: DataType::Custom(7),
+            original_size: 70_000,
+            compressed_size: 1234,
+            compression_method: CompressionMethod::ZstdDictionary(3),
+            checksum: 0x0102_0304_0506_0708,
+            timestamp: DateTime::from_timestamp(1_700_000_000, 42).expect("valid time"),
+            transforms: vec!["xor".to_string(), "rot".to_string()],
+            original_checksum: Some(0x1112_1314_1516_1718),
+            checksum_algorithm: BlockChecksum::Crc32c,
+            digest: None,
+        };
+        let bytes = header.encode(VERSION)?;
+        assert_eq!(bytes.len(), BLOCK_HEADER_FIXED_SIZE + 2 * (2 + 3) + BLOCK_HEADER_TAIL_SIZE);
+        assert_eq!(&bytes[..4], BLOCK_HEADER_MAGIC);
+        assert_eq!((bytes[4], &bytes[5..7]), (5, 7u16.to_le_bytes().as_slice()));
+        assert_eq!((bytes[7], &bytes[8..12]), (3, 3u32.to_le_bytes().as_slice()));
+        assert_eq!(bytes[28..36], 0x0102_0304_0506_0708u64.to_le_bytes());
+        assert_eq!(bytes[36..44], 1_700_000_000i64.to_le_bytes());
+        assert_eq!(&bytes[48..53], b"\x02\x00\x03\x00x");
+        assert_eq!((bytes[60], &bytes[61..69]), (3, 0x1112_1314_1516_1718u64.to_le_bytes().as_slice()));
+        assert_eq!(bytes[69], 1);
+        let prefixed = [(bytes.len() as u32).to_le_bytes().as_slice(), &bytes[..70]].concat();
+        assert_eq!(bytes[70..], crc32c::crc32c(&prefixed).to_le_bytes());
+        assert_eq!(BlockHeader::decode(&bytes, 0)?, header);
+        // As are headers from before the original and header checksums and
+        // the checksum algorithm, which are xxh3
+        let unchecked = BlockHeader { original_checksum: None, checksum_algorithm: BlockChecksum::Xxh3, ..header.clone() };
+        assert_eq!(BlockHeader::decode(&bytes[..60], 0)?, unchecked);
+        let mut early = bytes[..69].to_vec();
+        early[60] = 1;
+        assert_eq!(BlockHeader::decode(&early, 0)?, BlockHeader { checksum_algorithm: BlockChecksum::Xxh3, ..header.clone() });
+
+        // A BLAKE3 header carries the full digest
+        let blake3 = BlockHeader { checksum_algorithm: BlockChecksum::Blake3, digest: Some([9; 32]), ..header.clone() };
+        let encoded = blake3.encode(VERSION)?;
+        assert_eq!((encoded.len(), encoded[69], &encoded[70..102]), (bytes.len() + 32, 2, [9; 32].as_slice()));
+        assert_eq!(BlockHeader::decode(&encoded, 0)?, blake3);
+        assert!(BlockHeader { digest: None, ..blake3 }.encode(VERSION).is_err());
+
+        // A flipped bit anywhere in the header, or a header cut short or
+        // padded, fails its checksum
+        let mut flipped = bytes.clone();
+        flipped[25] ^= 0x10;
+        assert!(matches!(BlockHeader::decode(&flipped, 77), Err(UsfError::HeaderChecksumMismatch { offset: 77 })));
+        for flag in [ORIGINAL_CHECKSUM_FLAG, HEADER_CHECKSUM_FLAG, ORIGINAL_CHECKSUM_FLAG | HEADER_CHECKSUM_FLAG] {
+            let mut flipped = bytes.clone();
+            flipped[60] ^= flag;
+            assert!(matches!(BlockHeader::decode(&flipped, 0), Err(UsfError::HeaderChecksumMismatch { .. })), "{}", flag);
+        }
+        assert!(matches!(BlockHeader::decode(&bytes[..bytes.len() - 1], 0), Err(UsfError::HeaderChecksumMismatch { .. })));
+        assert!(matches!(BlockHeader::decode(&[bytes.as_slice(), b"future"].concat(), 0), Err(UsfError::HeaderChecksumMismatch { .. })));
+        assert!(matches!(BlockHeader::decode(&bytes[..40], 0), Err(UsfError::Corruption(_))));
+        let mut unknown = bytes.clone();
+        unknown[4] = 99;
+        assert!(matches!(BlockHeader::decode(&unknown, 0), Err(UsfError::Corruption(_))));
+
+        // Fields a later version adds before the header checksum are skipped
+        let mut future = [&bytes[..70], b"future"].concat();
+        let checksum = header_checksum(&future, future.len() + 4);
+        future.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(BlockHeader::decode(&future, 0)?, header);
+
+        // Earlier versions keep their bincode headers, without it
+        let legacy = header.encode(VERSION_2)?;
+        assert_eq!(legacy, bincode::serialize(&header)?);
+        assert_eq!(BlockHeader::decode(&legacy, 0)?, unchecked);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use crate::format::COLD_DATA_OFFSET;
+use crate::{BlockLocation, Result, UniversalStorage};
+
+impl UniversalStorage {
+    /// Byte ranges `(offset, len)` of blocks no longer referenced by any
+    /// entry, in file order with adjacent ranges merged. Blocks are never
+    /// overwritten in place, since snapshots and parity may still read
+    /// them, so compaction is what reclaims the space.
+    pub fn freed_extents(&self) -> Vec<(u64, u64)> {
+        self.metadata.freed.iter().map(|(offset, len)| (*offset, *len)).collect()
+    }
+
+    pub fn freed_bytes(&self) -> u64 {
+        self.metadata.freed.values().sum()
+    }
+
+    /// Bytes of the data region no live block uses: freed extents plus
+    /// anything else unreferenced, such as the tail of an interrupted
+    /// write. Worked out from the index and file sizes alone, so it is
+    /// cheap enough to decide when to [`UniversalStorage::compact`].
+    pub fn wasted_bytes(&self) -> Result<u64> {
+        let mut data_region = self.file.metadata()?.len().saturating_sub(self.data_offset() + self.commit_size());
+        if self.cold.is_some() {
+            data_region += self.cold_tier_len()?.saturating_sub(COLD_DATA_OFFSET);
+        }
+        let parity_bytes: u64 = self.metadata.parity.iter()
+            .flat_map(|group| group.parity_blocks())
+            .map(|loc| loc.disk_size())
+            .sum();
+        Ok(data_region.saturating_sub(self.live_block_bytes() + parity_bytes))
+    }
+
+    // Records the blocks of a released chain as free space
+    pub(crate) fn record_freed(&mut self, locations: &[BlockLocation]) {
+        for location in locations {
+            let (mut offset, mut len) = (location.offset, location.disk_size());
+            if let Some((&before, &before_len)) = self.metadata.freed.range(..offset).next_back() {
+                if before + before_len == offset {
+                    self.metadata.freed.remove(&before);
+                    offset = before;
+                    len += before_len;
+                }
+            }
+            if let Some(after_len) = self.metadata.freed.remove(&(offset + len)) {
+                len += after_len;
+            }
+            self.metadata.freed.insert(offset, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_delete_records_freed_extents() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("freed.usf"))?;
+        let large: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        storage.store("large", &large, DataType::Binary)?;
+        storage.store("small", b"small", DataType::Text)?;
+        let large_offset = storage.metadata.index["large"].blocks[0].offset;
+        let large_bytes: u64 = storage.metadata.index["large"].blocks.iter().map(|loc| loc.disk_size()).sum();
+
+        storage.delete("large")?;
+        assert_eq!(storage.freed_extents(), [(large_offset, large_bytes)]);
+
+        // Linked chains are only freed with their last reference
+        storage.link("small", "alias")?;
+        storage.delete("small")?;
+        assert_eq!(storage.freed_bytes(), large_bytes);
+        storage.store("alias", b"replaced", DataType::Text)?;
+        // The commit written after "large" keeps the two extents apart
+        assert_eq!(storage.freed_extents().len(), 2);
+        assert!(storage.freed_bytes() > large_bytes);
+
+        storage.compact()?;
+        assert!(storage.freed_extents().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overwrite_counts_superseded_blocks_as_wasted() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("wasted.usf"))?;
+        storage.store("report", &[1u8; 5000], DataType::Binary)?;
+        storage.add_parity(4, 1)?;
+
+        // Superseded commits count as wasted alongside freed blocks
+        let wasted = storage.wasted_bytes()?;
+        let old_bytes: u64 = storage.metadata.index["report"].blocks.iter().map(|loc| loc.disk_size()).sum();
+        let old_commit = storage.commit_size();
+        storage.store("report", &[2u8; 5000], DataType::Binary)?;
+        assert_eq!(storage.retrieve("report")?, [2u8; 5000]);
+        assert_eq!(storage.freed_bytes(), old_bytes);
+        assert_eq!(storage.wasted_bytes()?, wasted + old_bytes + old_commit);
+
+        storage.compact()?;
+        assert_eq!(storage.wasted_bytes()?, 0);
+
+        Ok(())
+    }
+}
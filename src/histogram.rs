@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use crate::{Result, UniversalStorage};
+
+// Edges of the compression ratio buckets; the last is open-ended
+const RATIO_BOUNDS: [f64; 8] = [0.0, 1.0, 1.5, 2.0, 3.0, 5.0, 10.0, f64::INFINITY];
+
+/// Values counted between `low` (inclusive) and `high` (exclusive).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket<T> {
+    pub low: T,
+    pub high: T,
+    pub count: u64,
+}
+
+/// A distribution of values over ascending, adjacent buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram<T> {
+    pub buckets: Vec<Bucket<T>>,
+}
+
+impl<T> Histogram<T> {
+    /// Number of values counted.
+    pub fn total(&self) -> u64 {
+        self.buckets.iter().map(|bucket| bucket.count).sum()
+    }
+}
+
+impl Histogram<u64> {
+    // Buckets of [0, 1), [1, 2), [2, 4), [4, 8), ... up to the largest value
+    fn powers_of_two(values: impl IntoIterator<Item = u64>) -> Self {
+        let mut counts: Vec<u64> = Vec::new();
+        for value in values {
+            let index = (u64::BITS - value.leading_zeros()) as usize;
+            if counts.len() <= index {
+                counts.resize(index + 1, 0);
+            }
+            counts[index] += 1;
+        }
+
+        let edge = |index: usize| match index {
+            0 => 0,
+            65.. => u64::MAX,
+            _ => 1u64 << (index - 1),
+        };
+        let buckets = counts.into_iter().enumerate()
+            .map(|(index, count)| Bucket { low: edge(index), high: edge(index + 1), count })
+            .collect();
+        Self { buckets }
+    }
+}
+
+impl Histogram<f64> {
+    fn with_bounds(bounds: &[f64], values: impl IntoIterator<Item = f64>) -> Self {
+        let mut buckets: Vec<Bucket<f64>> = bounds.windows(2)
+            .map(|edges| Bucket { low: edges[0], high: edges[1], count: 0 })
+            .collect();
+        for value in values {
+            if let Some(bucket) = buckets.iter_mut().find(|bucket| value < bucket.high) {
+                bucket.count += 1;
+            }
+        }
+        Self { buckets }
+    }
+}
+
+/// The shape of an archive, from [`UniversalStorage::histograms`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveHistograms {
+    /// Value lengths in bytes, in power-of-two buckets
+    pub value_sizes: Histogram<u64>,
+    /// Original over stored size of each block, counting blocks shared
+    /// by several keys once
+    pub compression_ratios: Histogram<f64>,
+    /// Key lengths in bytes, in power-of-two buckets
+    pub key_lengths: Histogram<u64>,
+}
+
+impl UniversalStorage {
+    /// Distributions of value sizes, block compression ratios and key
+    /// lengths across the index, for sizing archives and tuning block
+    /// size. Block headers are read for the ratios; values are not.
+    pub fn histograms(&self) -> Result<ArchiveHistograms> {
+        let mut seen_blocks = HashSet::new();
+        let mut ratios = Vec::new();
+        for entry in self.metadata.index.values() {
+            for loc in entry.blocks.iter().filter(|loc| seen_blocks.insert(loc.offset)) {
+                let header = self.read_header(loc)?;
+                ratios.push(match header.compressed_size {
+                    0 => 1.0,
+                    stored => header.original_size as f64 / stored as f64,
+                });
+            }
+        }
+
+        Ok(ArchiveHistograms {
+            value_sizes: Histogram::powers_of_two(self.metadata.index.values().map(|entry| entry.size)),
+            compression_ratios: Histogram::with_bounds(&RATIO_BOUNDS, ratios),
+            key_lengths: Histogram::powers_of_two(self.metadata.index.keys().map(|key| key.len() as u64)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_histograms_bucket_sizes_ratios_and_keys() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("histograms.usf"))?;
+        storage.store("a", b"", DataType::Binary)?;
+        storage.store("bb", b"x", DataType::Binary)?;
+        storage.store("ccc", b"xyz", DataType::Binary)?;
+        storage.store("dddd", "compress me ".repeat(1000).as_bytes(), DataType::Text)?;
+        storage.link("dddd", "eeeee-alias")?;
+
+        let histograms = storage.histograms()?;
+        let sizes: Vec<(u64, u64, u64)> = histograms.value_sizes.buckets.iter()
+            .map(|bucket| (bucket.low, bucket.high, bucket.count))
+            .filter(|bucket| bucket.2 > 0)
+            .collect();
+        assert_eq!(sizes, [(0, 1, 1), (1, 2, 1), (2, 4, 1), (8192, 16384, 2)]);
+        assert_eq!(histograms.value_sizes.total(), 5);
+
+        // Two uncompressed blocks at 1.0, one compressed well; the link's
+        // shared block is counted once
+        let ratios = &histograms.compression_ratios;
+        assert_eq!(ratios.total(), 3);
+        assert_eq!((ratios.buckets[1].low, ratios.buckets[1].count), (1.0, 2));
+        assert_eq!(ratios.buckets.last().map(|bucket| bucket.count), Some(1));
+
+        let keys: Vec<u64> = histograms.key_lengths.buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(keys, [0, 1, 2, 1, 1]);
+
+        Ok(())
+    }
+}
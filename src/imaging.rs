@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use xxhash_rust::xxh3::xxh3_128;
+use crate::limits::check_value_size;
+use crate::{DataType, EntryOptions, Result, UniversalStorage, UsfError};
+
+// Attribute on a cached conversion naming the source version it came from
+const SOURCE_ETAG: &str = "source-etag";
+
+/// What [`UniversalStorage::retrieve_image`] converts a stored image to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRequest {
+    pub format: ImageFormat,
+    /// Scales the image down, keeping its aspect ratio, until it fits
+    /// within this width and height. Smaller images are left as they are.
+    pub fit_within: Option<(u32, u32)>,
+    /// Keeps the result under a derived key (see
+    /// [`ImageRequest::derived_key`]) and serves it from there until the
+    /// source is stored again. Needs a writable handle.
+    pub cache: bool,
+}
+
+impl ImageRequest {
+    pub fn new(format: ImageFormat) -> Self {
+        Self { format, fit_within: None, cache: false }
+    }
+
+    /// The key a cached conversion of `key` is kept under, such as
+    /// `photo.jpg.as-64x64.png`.
+    pub fn derived_key(&self, key: &str) -> String {
+        let extension = self.format.extensions_str().first().copied().unwrap_or("bin");
+        match self.fit_within {
+            Some((width, height)) => format!("{}.as-{}x{}.{}", key, width, height, extension),
+            None => format!("{}.as.{}", key, extension),
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Returns the image stored under `key` encoded as `format`, whatever
+    /// format it was stored in.
+    pub fn retrieve_image_as(&mut self, key: &str, format: ImageFormat) -> Result<Vec<u8>> {
+        self.retrieve_image(key, &ImageRequest::new(format))
+    }
+
+    /// Decodes the image stored under `key` and re-encodes it as
+    /// `request` asks. Conversions are cached as [`DataType::Binary`], so
+    /// they are kept byte for byte rather than re-encoded like images.
+    pub fn retrieve_image(&mut self, key: &str, request: &ImageRequest) -> Result<Vec<u8>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        if !request.cache {
+            return self.convert_image(&key, request);
+        }
+
+        let derived = self.metadata.key_policy.canonicalize(&request.derived_key(&key))?;
+        let source_etag = self.etag(&key)?;
+        let cached = self.metadata.index.get(&derived)
+            .is_some_and(|entry| entry.attributes.get(SOURCE_ETAG) == Some(&source_etag));
+        if cached {
+            return self.retrieve(&derived);
+        }
+
+        let converted = self.convert_image(&key, request)?;
+        check_value_size(&derived, converted.len() as u64, self.metadata.max_value_size)?;
+        let encoding = self.encoding_for(&derived, &DataType::Binary);
+        let blocks = Self::prepare_blocks(&converted, DataType::Binary, &encoding)?;
+        let options = EntryOptions {
+            hint: None,
+            attributes: BTreeMap::from([(SOURCE_ETAG.to_string(), source_etag)]),
+            content_hash: Some(xxh3_128(&converted)),
+            block_size: None,
+            text_deltas: None,
+        };
+        self.write_entry(derived, blocks, DataType::Binary, options)?;
+        Ok(converted)
+    }
+
+    fn convert_image(&mut self, key: &str, request: &ImageRequest) -> Result<Vec<u8>> {
+        let stored = self.retrieve(key)?;
+        let mut image = image::load_from_memory(&stored)
+            .map_err(|e| UsfError::Serialization(format!("{:?} is not a decodable image: {}", key, e)))?;
+        if let Some((width, height)) = request.fit_within {
+            if image.width() > width || image.height() > height {
+                image = image.resize(width, height, FilterType::Lanczos3);
+            }
+        }
+        // JPEG has no alpha channel
+        if request.format == ImageFormat::Jpeg {
+            image = DynamicImage::ImageRgb8(image.to_rgb8());
+        }
+
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, request.format).map_err(io::Error::other)?;
+        Ok(output.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgb, RgbImage};
+    use tempfile::tempdir;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| Rgb([(x * 30) as u8, (y * 60) as u8, 128]));
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, ImageFormat::Png).expect("encode");
+        output.into_inner()
+    }
+
+    #[test]
+    fn test_retrieve_image_transcodes_and_caches() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("images.usf"))?;
+        storage.store("photo", &png(8, 4), DataType::Image)?;
+
+        let converted = storage.retrieve_image_as("photo", ImageFormat::Png)?;
+        assert_eq!(image::guess_format(&converted).ok(), Some(ImageFormat::Png));
+        let jpeg = storage.retrieve_image_as("photo", ImageFormat::Jpeg)?;
+        assert_eq!(image::guess_format(&jpeg).ok(), Some(ImageFormat::Jpeg));
+
+        let request = ImageRequest { fit_within: Some((2, 2)), cache: true, ..ImageRequest::new(ImageFormat::Png) };
+        let thumbnail = storage.retrieve_image("photo", &request)?;
+        assert_eq!(image::load_from_memory(&thumbnail).expect("decode").dimensions(), (2, 1));
+        assert_eq!(storage.retrieve("photo.as-2x2.png")?, thumbnail);
+        assert_eq!(storage.retrieve_image("photo", &request)?, thumbnail);
+
+        // Storing the source again invalidates the cached conversion
+        storage.store("photo", &png(4, 8), DataType::Image)?;
+        let thumbnail = storage.retrieve_image("photo", &request)?;
+        assert_eq!(image::load_from_memory(&thumbnail).expect("decode").dimensions(), (1, 2));
+
+        storage.store("notes", b"not an image", DataType::Text)?;
+        assert!(matches!(storage.retrieve_image_as("notes", ImageFormat::Png), Err(UsfError::Serialization(_))));
+
+        Ok(())
+    }
+}
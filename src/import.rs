@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use chrono::{DateTime, Utc};
+use crate::classify::glob_matches;
+use crate::limits::check_value_size;
+use crate::transform::Encoding;
+use crate::{chunk_size, Block, DataType, EntryOptions, Result, UniversalStorage, UsfError};
+
+// Files indexed between commits; each commit rewrites the whole index, so
+// committing per file grows the archive quadratically
+const COMMIT_INTERVAL_FILES: usize = 1024;
+
+/// What [`UniversalStorage::import_dir`] does with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    #[default]
+    Skip,
+    /// Import the link target; directory cycles are visited once
+    Follow,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Globs a file's relative path must match one of; empty imports all
+    pub include: Vec<String>,
+    /// Globs excluding files, and whole directories when a directory's
+    /// path matches
+    pub exclude: Vec<String>,
+    pub symlinks: SymlinkPolicy,
+    /// Prepended to each relative path to form the key
+    pub key_prefix: String,
+    /// Threads reading and compressing files
+    pub threads: usize,
+    /// Record `mtime` and `mode` attributes on each entry
+    pub preserve_metadata: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            key_prefix: String::new(),
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            preserve_metadata: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub files: usize,
+    pub bytes: u64,
+    /// Symlinks left out under [`SymlinkPolicy::Skip`]
+    pub skipped_symlinks: usize,
+}
+
+// A file found by the walk, with its key relative to the root
+struct Candidate {
+    path: PathBuf,
+    key: String,
+    metadata: Metadata,
+}
+
+impl UniversalStorage {
+    /// Stores every file under `root` that passes the filters, keyed by its
+    /// relative path with `/` separators. Data types come from the
+    /// archive's type rules, defaulting to `Binary`. Files are read and
+    /// compressed in parallel and written as they finish, so the order of
+    /// blocks in the file is unspecified. The index is committed once per
+    /// 1024 files and at the end; on error, files since the last commit
+    /// are dropped.
+    pub fn import_dir<P: AsRef<Path>>(&mut self, root: P, options: &ImportOptions) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut candidates = Vec::new();
+        let mut visited = HashSet::new();
+        walk(root.as_ref(), "", options, &mut visited, &mut candidates, &mut report)?;
+
+        let key_policy = self.metadata.key_policy.clone();
+        let max_value_size = self.metadata.max_value_size;
+        let block_sizing = self.metadata.block_sizing;
+        let block_checksum = self.metadata.block_checksum;
+        let custom_types = self.metadata.custom_types.clone();
+        let transforms = self.transforms.clone();
+        let dictionaries = self.dictionaries.clone();
+        let keys = candidates.iter()
+            .map(|candidate| key_policy.canonicalize(&format!("{}{}", options.key_prefix, candidate.key)))
+            .collect::<Result<Vec<_>>>()?;
+        let data_types: Vec<DataType> = keys.iter()
+            .map(|key| self.classify(key).unwrap_or(DataType::Binary))
+            .collect();
+
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let threads = options.threads.clamp(1, candidates.len().max(1));
+        let (results, prepared) = mpsc::sync_channel::<Result<(usize, Vec<Block>)>>(threads * 2);
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let results = results.clone();
+                let (next, failed, candidates, keys, data_types) = (&next, &failed, &candidates, &keys, &data_types);
+                let (custom_types, transforms, dictionaries) = (&custom_types, &transforms, &dictionaries);
+                scope.spawn(move || {
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(candidate) = candidates.get(i) else { break };
+                        let blocks = fs::read(&candidate.path).map_err(UsfError::from).and_then(|data| {
+                            check_value_size(&keys[i], data.len() as u64, max_value_size)?;
+                            let encoding = Encoding::resolve(custom_types, transforms, dictionaries, block_sizing, block_checksum, &keys[i], &data_types[i]);
+                            UniversalStorage::prepare_blocks(&data, data_types[i].clone(), &encoding)
+                        });
+                        if results.send(blocks.map(|blocks| (i, blocks))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(results);
+
+            let mut pending = 0;
+            for result in prepared {
+                let indexed = result.and_then(|(i, blocks)| {
+                    let attributes = match options.preserve_metadata {
+                        true => file_attributes(&candidates[i].metadata),
+                        false => BTreeMap::new(),
+                    };
+                    let entry = EntryOptions { attributes, block_size: Some(chunk_size(&blocks)), ..EntryOptions::default() };
+                    let (locations, size) = self.write_blocks(&blocks)?;
+                    self.index_entry(keys[i].clone(), locations, size, data_types[i].clone(), entry);
+                    report.files += 1;
+                    report.bytes += size;
+                    pending += 1;
+                    if pending == COMMIT_INTERVAL_FILES {
+                        pending = 0;
+                        self.metadata.modified = self.now();
+                        self.update_metadata()?;
+                    }
+                    Ok(())
+                });
+                if let Err(e) = indexed {
+                    // Stop the workers and let them drain before returning
+                    failed.store(true, Ordering::Relaxed);
+                    self.discard_uncommitted()?;
+                    return Err(e);
+                }
+            }
+            if pending > 0 {
+                self.metadata.modified = self.now();
+                self.update_metadata()?;
+            }
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+}
+
+fn walk(
+    dir: &Path,
+    relative: &str,
+    options: &ImportOptions,
+    visited: &mut HashSet<PathBuf>,
+    candidates: &mut Vec<Candidate>,
+    report: &mut ImportReport,
+) -> Result<()> {
+    // Guards against symlink cycles when following links
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().into_string().map_err(|name| UsfError::InvalidKey {
+            key: name.to_string_lossy().into_owned(),
+            reason: "file name is not valid UTF-8".to_string(),
+        })?;
+        let key = format!("{}{}", relative, name);
+        let path = entry.path();
+
+        let mut metadata = entry.metadata()?;
+        if metadata.file_type().is_symlink() {
+            match options.symlinks {
+                SymlinkPolicy::Skip => {
+                    report.skipped_symlinks += 1;
+                    continue;
+                },
+                SymlinkPolicy::Follow => metadata = fs::metadata(&path)?,
+            }
+        }
+
+        if options.exclude.iter().any(|pattern| glob_matches(pattern, &key)) {
+            continue;
+        }
+        if metadata.is_dir() {
+            walk(&path, &format!("{}/", key), options, visited, candidates, report)?;
+        } else if metadata.is_file()
+            && (options.include.is_empty() || options.include.iter().any(|pattern| glob_matches(pattern, &key)))
+        {
+            candidates.push(Candidate { path, key, metadata });
+        }
+    }
+    Ok(())
+}
+
+fn file_attributes(metadata: &Metadata) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    if let Ok(modified) = metadata.modified() {
+        attributes.insert("mtime".to_string(), DateTime::<Utc>::from(modified).to_rfc3339());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        attributes.insert("mode".to_string(), format!("{:o}", metadata.permissions().mode() & 0o7777));
+    }
+    #[cfg(not(unix))]
+    attributes.insert("readonly".to_string(), metadata.permissions().readonly().to_string());
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_import_dir_with_filters() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("project");
+        fs::create_dir_all(root.join("src/nested"))?;
+        fs::create_dir_all(root.join("target/debug"))?;
+        fs::write(root.join("README.md"), "readme")?;
+        fs::write(root.join("config.json"), "{}")?;
+        fs::write(root.join("src/main.rs"), "fn main() {}")?;
+        fs::write(root.join("src/nested/lib.rs"), "pub fn f() {}".repeat(200))?;
+        fs::write(root.join("src/notes.tmp"), "scratch")?;
+        fs::write(root.join("target/debug/app"), "binary")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("README.md"), root.join("link.md"))?;
+
+        let mut storage = UniversalStorage::create(dir.path().join("import.usf"))?;
+        storage.add_type_rule("*.json", DataType::Json)?;
+        let options = ImportOptions {
+            exclude: vec!["target".to_string(), "*.tmp".to_string()],
+            key_prefix: "project/".to_string(),
+            threads: 3,
+            ..ImportOptions::default()
+        };
+        let report = storage.import_dir(&root, &options)?;
+
+        assert_eq!(report.files, 4);
+        assert_eq!(storage.keys().collect::<Vec<_>>(), [
+            "project/README.md",
+            "project/config.json",
+            "project/src/main.rs",
+            "project/src/nested/lib.rs",
+        ]);
+        assert_eq!(storage.retrieve("project/src/nested/lib.rs")?, "pub fn f() {}".repeat(200).as_bytes());
+        assert_eq!(storage.data_type_of("project/config.json")?, DataType::Json);
+        assert!(storage.attributes("project/README.md")?.contains_key("mtime"));
+        #[cfg(unix)]
+        assert_eq!(report.skipped_symlinks, 1);
+
+        storage.set_attribute("project/README.md", "owner", "docs")?;
+        assert_eq!(storage.remove_attribute("project/README.md", "owner")?, Some("docs".to_string()));
+
+        let rust_only = ImportOptions { include: vec!["*.rs".to_string()], ..ImportOptions::default() };
+        assert_eq!(storage.import_dir(&root, &rust_only)?.files, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_dir_commits_in_chunks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("many");
+        fs::create_dir_all(&root)?;
+        let files = COMMIT_INTERVAL_FILES + 100;
+        for i in 0..files {
+            fs::write(root.join(format!("{:05}", i)), [i as u8])?;
+        }
+
+        let path = dir.path().join("many.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let generation = storage.metadata.generation;
+        let report = storage.import_dir(&root, &ImportOptions::default())?;
+        assert_eq!(report.files, files);
+        // One commit per full chunk and one for the rest, not one per file
+        assert_eq!(storage.metadata.generation, generation + 2);
+
+        let mut reopened = UniversalStorage::open(&path)?;
+        assert_eq!(reopened.len(), files);
+        assert_eq!(reopened.retrieve(&format!("{:05}", files - 1))?, [(files - 1) as u8]);
+
+        Ok(())
+    }
+}
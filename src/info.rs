@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::{format, platform, Result, UniversalStorage};
+
+/// Descriptive fields identifying an archive. Stored at the start of the
+/// metadata, so catalogs can read it without loading the key index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub creator: Option<String>,
+    /// Where the archive's contents came from
+    pub source_uri: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl UniversalStorage {
+    pub fn archive_info(&self) -> &ArchiveInfo {
+        &self.metadata.info
+    }
+
+    pub fn set_archive_info(&mut self, info: ArchiveInfo) -> Result<()> {
+        self.metadata.info = info;
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Reads only the archive info of the file at `path`.
+    pub fn read_archive_info<P: AsRef<Path>>(path: P) -> Result<ArchiveInfo> {
+        format::read_archive_info(BufReader::new(File::open(platform::native_path(path.as_ref()))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_archive_info_round_trip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("info.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let info = ArchiveInfo {
+            name: Some("nightly-build".to_string()),
+            creator: Some("ci".to_string()),
+            labels: BTreeMap::from([("branch".to_string(), "main".to_string())]),
+            ..ArchiveInfo::default()
+        };
+        storage.set_archive_info(info.clone())?;
+
+        assert_eq!(UniversalStorage::read_archive_info(&path)?, info);
+        assert_eq!(UniversalStorage::open(&path)?.archive_info(), &info);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::Read;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::limits::check_value_size;
+use crate::stream::read_full;
+use crate::{BlockLocation, DataType, EntryOptions, ProgressPhase, Result, UniversalStorage, UsfError, BLOCK_SIZE};
+
+// Blocks written between persisted checkpoints (4MB at 64KB blocks)
+const CHECKPOINT_INTERVAL_BLOCKS: usize = 64;
+
+/// Progress of a streaming store, persisted periodically so an interrupted
+/// ingest can resume instead of starting over.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IngestCheckpoint {
+    pub data_type: DataType,
+    /// Bytes of input already stored; resume by skipping this many bytes
+    pub bytes_consumed: u64,
+    pub updated_at: DateTime<Utc>,
+    pub(crate) blocks: Vec<BlockLocation>,
+}
+
+impl IngestCheckpoint {
+    pub(crate) fn new(data_type: DataType) -> Self {
+        Self { data_type, bytes_consumed: 0, updated_at: Utc::now(), blocks: Vec::new() }
+    }
+
+    pub fn blocks_written(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+impl UniversalStorage {
+    /// Stores everything `reader` yields under `key`, persisting a
+    /// checkpoint every few megabytes. If the process dies mid-ingest,
+    /// [`UniversalStorage::ingest_checkpoint`] reports how far it got.
+    pub fn store_from_reader<R: Read>(&mut self, key: &str, reader: &mut R, data_type: DataType) -> Result<()> {
+        self.start_ingest(key, reader, data_type, Some(CHECKPOINT_INTERVAL_BLOCKS))
+    }
+
+    /// Stores everything `reader` yields under `key`, reading, compressing
+    /// and writing one block at a time, so the value never has to fit in
+    /// memory. No checkpoints are kept: an interrupted store leaves the key
+    /// as it was and must start over.
+    pub fn store_from<R: Read>(&mut self, key: &str, reader: &mut R, data_type: DataType) -> Result<()> {
+        self.start_ingest(key, reader, data_type, None)
+    }
+
+    /// Continues an interrupted [`UniversalStorage::store_from_reader`].
+    /// `reader` must yield the input starting at the checkpoint's
+    /// `bytes_consumed`.
+    pub fn resume_store_from_reader<R: Read>(&mut self, key: &str, reader: &mut R) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let checkpoint = self.metadata.ingests.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+        self.ingest(key, reader, checkpoint, Some(CHECKPOINT_INTERVAL_BLOCKS))
+    }
+
+    pub fn ingest_checkpoint(&self, key: &str) -> Option<IngestCheckpoint> {
+        let key = self.metadata.key_policy.canonicalize(key).ok()?;
+        self.metadata.ingests.get(&key).cloned()
+    }
+
+    /// Discards an interrupted ingest; its blocks become dead space.
+    pub fn abort_ingest(&mut self, key: &str) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        if self.metadata.ingests.remove(&key).is_some() {
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+        }
+        Ok(())
+    }
+
+    // Starts a new ingest of `key`, discarding any interrupted one
+    fn start_ingest<R: Read>(
+        &mut self,
+        key: &str,
+        reader: &mut R,
+        data_type: DataType,
+        checkpoint_every: Option<usize>,
+    ) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        if let Some(stale) = self.metadata.ingests.remove(&key) {
+            self.release_chain(&stale.blocks);
+        }
+        self.ingest(key, reader, IngestCheckpoint::new(data_type), checkpoint_every)
+    }
+
+    fn ingest<R: Read>(
+        &mut self,
+        key: String,
+        reader: &mut R,
+        mut state: IngestCheckpoint,
+        checkpoint_every: Option<usize>,
+    ) -> Result<()> {
+        let mut chunk = vec![0u8; BLOCK_SIZE];
+        let encoding = self.encoding_for(&key, &state.data_type);
+
+        loop {
+            let filled = read_full(reader, &mut chunk)?;
+            if filled == 0 {
+                break;
+            }
+            check_value_size(&key, state.bytes_consumed + filled as u64, self.metadata.max_value_size)?;
+            let block = Self::prepare_block(&chunk[..filled], &state.data_type, &encoding)?;
+            state.blocks.push(self.write_block(&block)?);
+            state.bytes_consumed += filled as u64;
+            self.report_progress(state.bytes_consumed, 0, ProgressPhase::Store);
+
+            if checkpoint_every.is_some_and(|n| state.blocks.len().is_multiple_of(n)) {
+                state.updated_at = self.now();
+                self.metadata.ingests.insert(key.clone(), state.clone());
+                self.update_metadata()?;
+            }
+            if filled < chunk.len() {
+                break;
+            }
+        }
+
+        self.metadata.ingests.remove(&key);
+        self.commit_entry(key, state.blocks, state.bytes_consumed, state.data_type, EntryOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    // Yields `limit` bytes of `data` and then fails, like a dropped connection
+    struct FailingReader<'a> {
+        data: &'a [u8],
+        limit: usize,
+    }
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.limit == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "source went away"));
+            }
+            let n = buf.len().min(self.limit).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.limit -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_resume_interrupted_ingest() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("ingest.usf");
+        let data: Vec<u8> = (0..BLOCK_SIZE * 7).map(|i| (i % 253) as u8).collect();
+
+        // Checkpoint every 2 blocks and fail after 5
+        let mut storage = UniversalStorage::create(&file_path)?;
+        let mut failing = FailingReader { data: &data, limit: BLOCK_SIZE * 5 };
+        let state = IngestCheckpoint::new(DataType::Binary);
+        assert!(storage.ingest("dataset".to_string(), &mut failing, state, Some(2)).is_err());
+
+        // The checkpoint is on disk, not just in memory
+        let reopened = UniversalStorage::open(&file_path)?;
+        let checkpoint = reopened.ingest_checkpoint("dataset").expect("checkpoint persisted");
+        assert_eq!(checkpoint.blocks_written(), 4);
+        assert_eq!(checkpoint.bytes_consumed, BLOCK_SIZE as u64 * 4);
+        assert!(storage.retrieve("dataset").is_err());
+
+        let mut rest = &data[checkpoint.bytes_consumed as usize..];
+        storage.resume_store_from_reader("dataset", &mut rest)?;
+        assert!(storage.ingest_checkpoint("dataset").is_none());
+        assert_eq!(storage.retrieve("dataset")?, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_from_writes_block_by_block() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("store_from.usf"))?;
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 17).map(|i| (i % 251) as u8).collect();
+
+        // Short reads are gathered into full blocks
+        let mut reader = io::Read::chain(&data[..1000], &data[1000..]);
+        storage.store_from("file.bin", &mut reader, DataType::Binary)?;
+        assert_eq!(storage.metadata.index["file.bin"].blocks.len(), 3);
+        assert_eq!(storage.retrieve("file.bin")?, data);
+        assert!(storage.ingest_checkpoint("file.bin").is_none());
+
+        // A failed read leaves the previous value in place
+        let mut failing = FailingReader { data: &data, limit: BLOCK_SIZE + 1 };
+        assert!(storage.store_from("file.bin", &mut failing, DataType::Binary).is_err());
+        assert_eq!(storage.retrieve("file.bin")?, data);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{Read, Seek, SeekFrom};
+use crate::format::{BlockHeader, BLOCK_HEADER_PREFIX_SIZE, COLD_TIER_BASE, TRAILER_MAGIC_BYTES, TRAILER_SIZE, VERSION_1};
+use crate::{Result, UniversalStorage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtentKind {
+    /// Magic bytes, version and commit pointer, or metadata region in a
+    /// version 1 archive, and the current metadata commit
+    Metadata,
+    /// A block referenced by the index
+    LiveBlock,
+    /// A well-formed block no longer referenced by the index
+    DeadBlock,
+    /// A metadata commit superseded by a later one
+    DeadCommit,
+    /// Repair data written by [`UniversalStorage::add_parity`]
+    Parity,
+    /// Bytes that do not parse as a block
+    FreeHole,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub kind: ExtentKind,
+    pub offset: u64,
+    pub size: u64,
+    /// Owning key, for live blocks
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LayoutReport {
+    pub file_size: u64,
+    /// Extents in file order, covering the whole file without overlap
+    pub extents: Vec<Extent>,
+}
+
+impl LayoutReport {
+    pub fn bytes_of(&self, kind: ExtentKind) -> u64 {
+        self.extents.iter().filter(|e| e.kind == kind).map(|e| e.size).sum()
+    }
+}
+
+impl UniversalStorage {
+    /// Maps every byte of the file to an extent, so tooling can visualize
+    /// where live data, dead blocks and unusable gaps sit. Only the main
+    /// file is mapped; blocks in a cold tier are left out.
+    pub fn layout_report(&mut self) -> Result<LayoutReport> {
+        let file_size = self.file.metadata()?.len();
+
+        let mut live: Vec<Extent> = self.metadata.index.iter()
+            .map(|(key, entry)| (key, entry.blocks.as_slice()))
+            .chain(self.pinned_chains())
+            .flat_map(|(key, locations)| locations.iter().map(move |loc| Extent {
+                kind: ExtentKind::LiveBlock,
+                offset: loc.offset,
+                size: loc.disk_size(),
+                key: Some(key.clone()),
+            }))
+            .collect();
+        live.extend(self.metadata.parity.iter().flat_map(|group| group.parity_blocks()).map(|loc| Extent {
+            kind: ExtentKind::Parity,
+            offset: loc.offset,
+            size: loc.disk_size(),
+            key: None,
+        }));
+        live.extend(self.commit.map(|(offset, size)| Extent { kind: ExtentKind::Metadata, offset, size, key: None }));
+        live.retain(|e| e.offset < COLD_TIER_BASE);
+        live.sort_by_key(|e| e.offset);
+        // Blocks shared between linked keys are reported once
+        live.dedup_by_key(|e| e.offset);
+
+        let mut extents = vec![Extent {
+            kind: ExtentKind::Metadata,
+            offset: 0,
+            size: self.data_offset().min(file_size),
+            key: None,
+        }];
+
+        let mut cursor = self.data_offset();
+        for extent in live {
+            if extent.offset > cursor {
+                self.classify_gap(cursor, extent.offset, &mut extents)?;
+            }
+            cursor = cursor.max(extent.offset + extent.size);
+            extents.push(extent);
+        }
+        if file_size > cursor {
+            self.classify_gap(cursor, file_size, &mut extents)?;
+        }
+
+        Ok(LayoutReport { file_size, extents })
+    }
+
+    // Splits an unreferenced range into dead blocks, dead commits and free
+    // holes
+    fn classify_gap(&mut self, start: u64, end: u64, extents: &mut Vec<Extent>) -> Result<()> {
+        let mut offset = start;
+        while offset < end {
+            let probed = match self.probe_block(offset, end - offset)? {
+                Some(size) => Some((ExtentKind::DeadBlock, size)),
+                None => self.probe_commit(offset, end - offset)?.map(|size| (ExtentKind::DeadCommit, size)),
+            };
+            match probed {
+                Some((kind, size)) => {
+                    extents.push(Extent { kind, offset, size, key: None });
+                    offset += size;
+                },
+                None => {
+                    extents.push(Extent { kind: ExtentKind::FreeHole, offset, size: end - offset, key: None });
+                    offset = end;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the on-disk size of the block at `offset` if one fits in `limit` bytes
+    fn probe_block(&mut self, offset: u64, limit: u64) -> Result<Option<u64>> {
+        if limit < BLOCK_HEADER_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut header_size_bytes = [0u8; 4];
+        self.file.read_exact(&mut header_size_bytes)?;
+        let header_size = u32::from_le_bytes(header_size_bytes) as u64;
+        if header_size == 0 || BLOCK_HEADER_PREFIX_SIZE + header_size > limit {
+            return Ok(None);
+        }
+
+        let mut header_bytes = vec![0u8; header_size as usize];
+        self.file.read_exact(&mut header_bytes)?;
+        let header = match BlockHeader::decode(&header_bytes, offset) {
+            Ok(header) => header,
+            Err(_) => return Ok(None),
+        };
+
+        let size = header.disk_size(header_size as u32);
+        Ok((size <= limit).then_some(size))
+    }
+
+    // Returns the size of the commit at `offset` if one whose trailer
+    // points back at it fits in `limit` bytes
+    fn probe_commit(&mut self, offset: u64, limit: u64) -> Result<Option<u64>> {
+        if self.version == VERSION_1 || limit < 16 + TRAILER_SIZE {
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut metadata_size = [0u8; 8];
+        self.file.read_exact(&mut metadata_size)?;
+        let size = match u64::from_le_bytes(metadata_size).checked_add(16 + TRAILER_SIZE) {
+            Some(size) if size <= limit => size,
+            _ => return Ok(None),
+        };
+
+        self.file.seek(SeekFrom::Start(offset + size - TRAILER_SIZE))?;
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        self.file.read_exact(&mut trailer)?;
+        Ok((trailer[..8] == offset.to_le_bytes() && &trailer[8..] == TRAILER_MAGIC_BYTES).then_some(size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_layout_covers_file() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("layout.usf"))?;
+
+        storage.store("a", b"first version", DataType::Text)?;
+        storage.store("b", b"other", DataType::Text)?;
+        storage.store("a", b"second version", DataType::Text)?;
+
+        let report = storage.layout_report()?;
+        assert_eq!(report.extents[0].kind, ExtentKind::Metadata);
+        assert_eq!(report.extents.iter().map(|e| e.size).sum::<u64>(), report.file_size);
+
+        // Every store appends a commit, superseding the one before it
+        let kinds: Vec<_> = report.extents.iter().skip(1).map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![
+            ExtentKind::DeadCommit,
+            ExtentKind::DeadBlock,
+            ExtentKind::DeadCommit,
+            ExtentKind::LiveBlock,
+            ExtentKind::DeadCommit,
+            ExtentKind::LiveBlock,
+            ExtentKind::Metadata,
+        ]);
+        assert_eq!(report.extents[6].key.as_deref(), Some("a"));
+
+        Ok(())
+    }
+}
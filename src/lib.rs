@@ -1,52 +1,347 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::ops::{Bound, Range};
 use std::io::{self, Read, Write, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::str::FromStr;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
-use image::{ImageFormat};
-use zstd;
-use bincode;
-use xxhash_rust::xxh3::xxh3_64;
-use std::io::Cursor;
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+use blocksize::BlockSizing;
+use dictionary::{Dictionaries, StoredDictionary};
+use format::{BlockHeader, CompressionMethod, BLOCK_HEADER_PREFIX_SIZE, BLOCK_SIZE, DATA_OFFSET, MAGIC_BYTES, METADATA_CAPACITY, METADATA_OFFSET, MIN_COMPRESS_SIZE, VERSION, VERSION_1};
+use limits::{check_limit, check_value_size, read_capped};
+use parity::ParityGroup;
+use sampling::VerificationCounters;
+use slowlog::distinct_codecs;
+use tier::ColdTier;
+use transform::{Encoding, Transforms};
 
-const MAGIC_BYTES: &[u8; 4] = b"USF1";
-const VERSION: u8 = 1;
-const BLOCK_SIZE: usize = 1024 * 64; // 64KB blocks
-const MIN_COMPRESS_SIZE: usize = 1024; // Minimum size to attempt compression
+mod access;
+mod append;
+mod attributes;
+mod batch;
+mod blockchecksum;
+mod blocksize;
+mod cache;
+mod catalog;
+mod checksums;
+mod classify;
+mod collection;
+mod compact;
+mod dictionary;
+mod embedded;
+mod error;
+mod estimate;
+mod etag;
+mod expiry;
+mod export;
+mod extract;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod federated;
+pub mod format;
+mod freelist;
+mod histogram;
+mod imaging;
+mod import;
+mod info;
+mod ingest;
+mod layout;
+mod limits;
+mod links;
+mod listing;
+mod merkle;
+mod metrics;
+mod migrate;
+mod options;
+mod parity;
+mod placement;
+mod platform;
+mod policy;
+mod progress;
+mod range;
+mod readonly;
+mod recovery;
+mod reference;
+mod relocate;
+mod reproducible;
+mod retention;
+mod retrieval;
+mod salvage;
+mod sampling;
+mod scope;
+mod sidecar;
+mod sink;
+mod slowlog;
+mod snapshot;
+mod solid;
+mod split;
+mod stats;
+mod stream;
+mod textdelta;
+mod throttle;
+mod tier;
+mod trash;
+mod transform;
+mod typed;
+mod types;
+mod verify;
+mod wal;
+mod writer;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+pub use access::AccessStats;
+pub use blockchecksum::BlockChecksum;
+pub use bytes::Bytes;
+pub use cache::SharedStorage;
+pub use checksums::ChecksumAlgorithm;
+pub use classify::TypeRule;
+pub use collection::{Collection, SCHEMA_VERSION_ATTRIBUTE};
+pub use compact::CompactionReport;
+pub use dictionary::DictionaryInfo;
+pub use embedded::EmbeddedArchive;
+pub use error::{Result, UsfError};
+pub use expiry::{ExpiryEvent, ExpiryListener, ExpiryReason};
+pub use extract::{CollisionPolicy, ExportKeysReport, ExportOptions};
+#[cfg(feature = "fault-injection")]
+pub use fault::Fault;
+pub use federated::{FederatedStorage, Precedence};
+pub use histogram::{ArchiveHistograms, Bucket, Histogram};
+pub use image::ImageFormat;
+pub use imaging::ImageRequest;
+pub use import::{ImportOptions, ImportReport, SymlinkPolicy};
+pub use info::ArchiveInfo;
+pub use ingest::IngestCheckpoint;
+pub use layout::{Extent, ExtentKind, LayoutReport};
+pub use limits::ParseLimits;
+pub use listing::{BlockInfo, EntryInfo, EntrySummary, KeyPattern};
+pub use merkle::MerkleProof;
+pub use metrics::OperationMetrics;
+pub use migrate::MigrationReport;
+pub use options::UsfOptions;
+pub use parity::RepairReport;
+pub use placement::Hint;
+pub use policy::{KeyCharset, KeyPolicy, MAX_KEY_LENGTH};
+pub use progress::{ProgressPhase, ProgressSink};
+pub use recovery::{OpenWarning, RecoveryReport, RolledBackCommit, TrailingData, TrailingDataAction, RECOVERED_PREFIX};
+pub use reference::{ArchiveResolver, Reference, ReferenceResolver};
+pub use retention::RetentionRule;
+pub use retrieval::{RetrieveMode, RetrieveOptions, Retrieved};
+pub use salvage::{DroppedEntry, SalvageReport};
+pub use sampling::{ChecksumPolicy, VerificationStats};
+pub use scope::ScopedStorage;
+pub use sink::IngestSink;
+pub use slowlog::{SlowOpThresholds, SlowOperation};
+pub use snapshot::Snapshot;
+pub use split::{SplitManifest, SplitPart};
+pub use stats::{CompressionStats, StorageStats};
+pub use stream::{KeySpan, MultiValueReader, ValueReader};
+pub use textdelta::TextDeltaRule;
+pub use throttle::{TenantLimits, Throttle};
+pub use tier::TierPolicy;
+pub use trash::TrashEntry;
+pub use transform::Transform;
+pub use typed::ValueFormat;
+pub use types::{CompressionPolicy, CustomType};
+pub use wal::ReplayedJournal;
+pub use verify::{CheckLevel, VerifyIssue, VerifyReport};
+pub use writer::{BackgroundWriter, BarrierToken, Priority, StoreOptions, WriteHandle};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DataType {
     Text,
     Binary,
     Image,
     Json,
     Structured,
+    /// A user-defined type registered with
+    /// [`UniversalStorage::register_data_type`]
+    Custom(u16),
+    /// A [`Reference`] to data held elsewhere
+    Reference,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct BlockHeader {
-    data_type: DataType,
-    original_size: u64,
-    compressed_size: u64,
-    compression_method: CompressionMethod,
-    checksum: u64,
-    timestamp: DateTime<Utc>,
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-enum CompressionMethod {
-    None,
-    Zstd,
-    DeltaEncoding,
+impl FromStr for DataType {
+    type Err = UsfError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Text" => Ok(DataType::Text),
+            "Binary" => Ok(DataType::Binary),
+            "Image" => Ok(DataType::Image),
+            "Json" => Ok(DataType::Json),
+            "Structured" => Ok(DataType::Structured),
+            "Reference" => Ok(DataType::Reference),
+            _ => s.strip_prefix("Custom(")
+                .and_then(|rest| rest.strip_suffix(')'))
+                .and_then(|id| id.parse().ok())
+                .map(DataType::Custom)
+                .ok_or_else(|| UsfError::Serialization(format!("unknown data type: {}", s))),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct MetaData {
+    // Kept first so it can be read without decoding the index
+    info: ArchiveInfo,
     created: DateTime<Utc>,
     modified: DateTime<Utc>,
+    // Bumped on every metadata write
+    generation: u64,
     total_blocks: u64,
-    index: HashMap<String, BlockLocation>,
+    key_policy: KeyPolicy,
+    index: BTreeMap<String, IndexEntry>,
+    access: BTreeMap<String, AccessStats>,
+    // Index entries sharing a block chain, keyed by first block offset.
+    // Chains referenced by a single key are not listed.
+    chain_refs: BTreeMap<u64, u32>,
+    // Unreferenced block extents, offset to length, adjacent ones merged
+    freed: BTreeMap<u64, u64>,
+    trash: BTreeMap<String, TrashEntry>,
+    trash_retention_secs: Option<u64>,
+    retention_rules: Vec<RetentionRule>,
+    ingests: BTreeMap<String, IngestCheckpoint>,
+    max_value_size: Option<u64>,
+    block_sizing: BlockSizing,
+    block_checksum: BlockChecksum,
+    custom_types: BTreeMap<u16, CustomType>,
+    solid_prefixes: Vec<String>,
+    text_delta_rules: Vec<TextDeltaRule>,
+    // Trained compression dictionaries by name, and the key prefixes
+    // compressed with them
+    dictionaries: BTreeMap<String, StoredDictionary>,
+    dictionary_prefixes: BTreeMap<String, u32>,
+    next_dictionary_id: u32,
+    type_rules: Vec<TypeRule>,
+    parity: Vec<ParityGroup>,
+    cold_tier: Option<ColdTier>,
+    // Mirror every commit to `<path>.idx`
+    index_sidecar: bool,
+    // Journal every commit to `<path>.wal` before applying it
+    write_ahead_log: bool,
+    // Timestamp recorded in place of the clock in reproducible archives
+    fixed_time: Option<DateTime<Utc>>,
+    // BLAKE3 of values by content hash, for the Merkle tree
+    merkle_digests: BTreeMap<u128, [u8; 32]>,
+}
+
+impl MetaData {
+    fn new(key_policy: KeyPolicy, now: DateTime<Utc>) -> Self {
+        Self {
+            info: ArchiveInfo::default(),
+            created: now,
+            modified: now,
+            generation: 0,
+            total_blocks: 0,
+            key_policy,
+            index: BTreeMap::new(),
+            access: BTreeMap::new(),
+            chain_refs: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            trash: BTreeMap::new(),
+            trash_retention_secs: None,
+            retention_rules: Vec::new(),
+            ingests: BTreeMap::new(),
+            max_value_size: None,
+            block_sizing: BlockSizing::default(),
+            block_checksum: BlockChecksum::default(),
+            custom_types: BTreeMap::new(),
+            solid_prefixes: Vec::new(),
+            text_delta_rules: Vec::new(),
+            dictionaries: BTreeMap::new(),
+            dictionary_prefixes: BTreeMap::new(),
+            next_dictionary_id: 0,
+            type_rules: Vec::new(),
+            parity: Vec::new(),
+            cold_tier: None,
+            index_sidecar: false,
+            write_ahead_log: false,
+            fixed_time: None,
+            merkle_digests: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexEntry {
+    blocks: Vec<BlockLocation>,
+    data_type: DataType,
+    size: u64,
+    stored_at: DateTime<Utc>,
+    // Start of the value within the uncompressed chain, for values packed
+    // into a solid group. `blocks` is then the whole group.
+    solid_offset: Option<u64>,
+    hint: Option<Hint>,
+    attributes: BTreeMap<String, String>,
+    // xxh3-128 of the value, for values stored from a single buffer
+    content_hash: Option<u128>,
+    // Set once an append leaves a block before the last holding less than
+    // block_size, so value positions must be found from block headers
+    ragged: bool,
+    // Uncompressed bytes in every block of the chain but the last
+    block_size: u64,
+    // For a value stored as a text delta log, the deltas after its
+    // snapshot. The blocks then hold the log, and `size` is the value's.
+    text_deltas: Option<u32>,
+}
+
+impl IndexEntry {
+    // Blocks holding the value, each with the range of its decompressed
+    // bytes that belongs to the value
+    fn block_ranges(&self) -> Vec<(BlockLocation, Range<usize>)> {
+        let Some(offset) = self.solid_offset else {
+            return self.blocks.iter().map(|loc| (loc.clone(), 0..usize::MAX)).collect();
+        };
+        if self.size == 0 {
+            return Vec::new();
+        }
+
+        let block_size = self.block_size;
+        let last_byte = offset + self.size - 1;
+        let (first, last) = ((offset / block_size) as usize, (last_byte / block_size) as usize);
+        self.blocks[first..=last].iter().enumerate().map(|(i, loc)| {
+            let start = if i == 0 { (offset % block_size) as usize } else { 0 };
+            let end = if first + i == last { (last_byte % block_size) as usize + 1 } else { block_size as usize };
+            (loc.clone(), start..end)
+        }).collect()
+    }
+}
+
+// Per-entry settings recorded when an entry is committed
+#[derive(Debug, Clone, Default)]
+struct EntryOptions {
+    hint: Option<Hint>,
+    attributes: BTreeMap<String, String>,
+    content_hash: Option<u128>,
+    // Chunk size the blocks were prepared with, BLOCK_SIZE if unset
+    block_size: Option<u64>,
+    text_deltas: Option<u32>,
+}
+
+// Clamps a block range to the data actually decoded
+fn clamp(range: &Range<usize>, len: usize) -> Range<usize> {
+    range.start.min(len)..range.end.min(len)
+}
+
+// The chunk size prepared blocks were cut at. A lone block may have been
+// cut at anything from its own size up, so the larger of that and
+// BLOCK_SIZE is recorded, which later appends then continue with.
+fn chunk_size(blocks: &[Block]) -> u64 {
+    match blocks {
+        [first, _, ..] => first.header.original_size,
+        [only] => only.header.original_size.max(BLOCK_SIZE as u64),
+        [] => BLOCK_SIZE as u64,
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -56,199 +351,502 @@ struct BlockLocation {
     data_size: u64,
 }
 
+impl BlockLocation {
+    // Bytes occupied on disk: header length prefix, header and data
+    fn disk_size(&self) -> u64 {
+        4 + self.header_size as u64 + self.data_size
+    }
+}
+
+// How `open_inner` opens an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    // From `open`: not for writing, though the journal may be replayed
+    Read,
+    // From `open_read_only`: under a shared lock, never writing
+    ReadOnly,
+    // Under an exclusive lock
+    Write,
+}
+
 pub struct UniversalStorage {
     file: File,
+    // Cold tier file, for archives compacted with `compact_tiered`
+    cold: Option<File>,
+    path: PathBuf,
+    // Format version of the file, which commits keep to
+    version: u8,
+    // Offset and size of the current commit, from version 2 on
+    commit: Option<(u64, u64)>,
     metadata: MetaData,
+    access_tracking: bool,
+    pending_access: HashMap<String, AccessStats>,
+    progress: Option<Arc<dyn ProgressSink>>,
+    expiry: Option<Arc<dyn ExpiryListener>>,
+    resolver: Option<Arc<dyn ReferenceResolver>>,
+    transforms: Transforms,
+    dictionaries: Dictionaries,
+    metrics: OperationMetrics,
+    limits: Option<ParseLimits>,
+    throttle: Option<Throttle>,
+    slow_ops: SlowOpThresholds,
+    checksum_policy: ChecksumPolicy,
+    verification: VerificationCounters,
+    open_warnings: Vec<OpenWarning>,
+    // From `open_read_only`: every change is refused
+    read_only: bool,
+    // The file is open for writing, under the exclusive lock where one is
+    // taken: created, recovered or opened through `UsfOptions::write`
+    writable: bool,
+    // Shared with live snapshots, which block compaction while held
+    fence: Arc<()>,
+    #[cfg(feature = "fault-injection")]
+    faults: Vec<Fault>,
 }
 
 impl UniversalStorage {
-    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let mut file = File::create(path)?;
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::create_with_key_policy(path, KeyPolicy::default())
+    }
+
+    pub fn create_with_key_policy<P: AsRef<Path>>(path: P, key_policy: KeyPolicy) -> Result<Self> {
+        Self::initialize(path.as_ref(), MetaData::new(key_policy, Utc::now()))
+    }
+
+    // Writes a fresh archive containing `metadata` and no blocks
+    fn initialize(path: &Path, metadata: MetaData) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(platform::native_path(path))?;
         file.write_all(MAGIC_BYTES)?;
         file.write_all(&[VERSION])?;
 
-        let metadata = MetaData {
-            created: Utc::now(),
-            modified: Utc::now(),
-            total_blocks: 0,
-            index: HashMap::new(),
-        };
+        file.write_all(&[0u8; (DATA_OFFSET - METADATA_OFFSET) as usize])?;
 
-        let metadata_bytes = bincode::serialize(&metadata)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        
-        let metadata_size = metadata_bytes.len() as u64;
-        file.write_all(&metadata_size.to_le_bytes())?;
-        file.write_all(&metadata_bytes)?;
+        let mut storage = Self::from_parts(file, path, metadata);
+        storage.writable = true;
+        storage.update_metadata()?;
+        Ok(storage)
+    }
 
-        Ok(Self { file, metadata })
+    /// Opens an existing archive read-only. Use [`UsfOptions`] to open
+    /// one for writing, or [`UniversalStorage::open_read_only`] to also
+    /// rule out the writes this may still make, such as replaying the
+    /// write-ahead journal.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_inner(path.as_ref(), None, Access::Read)
     }
 
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let mut file = File::open(path)?;
-        let mut magic = [0u8; 4];
-        file.read_exact(&mut magic)?;
-        
-        if &magic != MAGIC_BYTES {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid file format"));
+    fn open_inner(path: &Path, limits: Option<ParseLimits>, access: Access) -> Result<Self> {
+        let write = access == Access::Write;
+        let mut file = OpenOptions::new().read(true).write(write).open(platform::native_path(path))?;
+        readonly::lock(&file, access)?;
+        let replayed = match access {
+            Access::ReadOnly => None,
+            Access::Read => Self::replay_journal(path, None)?,
+            Access::Write => Self::replay_journal(path, Some(&file))?,
+        };
+        let superblock = format::read_superblock(&mut file)?;
+        let (superblock, metadata, rolled_back) = recovery::read_commit(&mut file, superblock, limits.as_ref())?;
+        check_limit(limits.as_ref(), "key count", metadata.index.len() as u64, |l| l.max_keys)?;
+
+        let mut storage = Self::from_parts(file, path, metadata);
+        storage.read_only = access == Access::ReadOnly;
+        storage.writable = write;
+        storage.open_warnings.extend(replayed.map(OpenWarning::JournalReplayed));
+        storage.open_warnings.extend(rolled_back.map(OpenWarning::CommitRolledBack));
+        storage.version = superblock.version;
+        if superblock.version != VERSION_1 {
+            storage.commit = Some((superblock.commit_offset, superblock.commit_size()));
+        }
+        storage.limits = limits;
+        storage.cold = storage.open_cold_tier(write)?;
+        storage.dictionaries = storage.load_dictionaries()?;
+        storage.check_on_open()?;
+        Ok(storage)
+    }
+
+    fn from_parts(file: File, path: &Path, metadata: MetaData) -> Self {
+        Self {
+            file,
+            cold: None,
+            path: path.to_path_buf(),
+            version: VERSION,
+            commit: None,
+            metadata,
+            access_tracking: false,
+            pending_access: HashMap::new(),
+            progress: None,
+            expiry: None,
+            resolver: None,
+            transforms: Transforms::default(),
+            dictionaries: Dictionaries::default(),
+            metrics: OperationMetrics::default(),
+            limits: None,
+            throttle: None,
+            slow_ops: SlowOpThresholds::default(),
+            checksum_policy: ChecksumPolicy::default(),
+            verification: VerificationCounters::default(),
+            open_warnings: Vec::new(),
+            read_only: false,
+            writable: false,
+            fence: Arc::new(()),
+            #[cfg(feature = "fault-injection")]
+            faults: Vec::new(),
         }
+    }
+
+    pub fn key_policy(&self) -> &KeyPolicy {
+        &self.metadata.key_policy
+    }
+
+    /// Stored keys in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.metadata.index.keys().map(String::as_str)
+    }
+
+    /// Stored keys starting with `prefix`, in sorted order. Only the
+    /// matching part of the index is visited. The prefix is compared
+    /// against canonical keys as is, without canonicalizing it.
+    pub fn keys_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries_with_prefix(prefix).map(|(key, _)| key.as_str())
+    }
 
-        let mut version = [0u8];
-        file.read_exact(&mut version)?;
-        if version[0] != VERSION {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported version"));
+    // Index entries whose keys start with `prefix`, in key order
+    fn entries_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a String, &'a IndexEntry)> {
+        self.metadata.index.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(prefix))
+    }
+
+    pub fn data_type_of(&self, key: &str) -> Result<DataType> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.metadata.index.get(&key)
+            .map(|entry| entry.data_type.clone())
+            .ok_or(UsfError::KeyNotFound(key))
+    }
+
+    /// Stores `data` under `key`, replacing any existing value. Blocks of a
+    /// replaced value are recorded as freed unless another key links them;
+    /// see [`UniversalStorage::wasted_bytes`].
+    pub fn store(&mut self, key: &str, data: &[u8], data_type: DataType) -> Result<()> {
+        let started = Instant::now();
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        check_value_size(&key, data.len() as u64, self.metadata.max_value_size)?;
+        if let Some(snapshot_every) = self.text_delta_rule_for(&key, &data_type) {
+            return self.store_text_delta(key, data, data_type, snapshot_every);
         }
+        let encoding = self.encoding_for(&key, &data_type);
+        let blocks = Self::prepare_blocks(data, data_type.clone(), &encoding)?;
+        let options = EntryOptions { content_hash: Some(xxh3_128(data)), block_size: Some(chunk_size(&blocks)), ..EntryOptions::default() };
+        let compressed = started.elapsed();
+        let (locations, size) = self.write_blocks(&blocks)?;
+        let written = started.elapsed();
+        self.commit_entry(key.clone(), locations, size, data_type, options)?;
 
-        let mut size_bytes = [0u8; 8];
-        file.read_exact(&mut size_bytes)?;
-        let metadata_size = u64::from_le_bytes(size_bytes);
+        let total = started.elapsed();
+        self.report_if_slow(self.slow_ops.store, total, || SlowOperation {
+            operation: "store",
+            key,
+            size,
+            stored_size: blocks.iter().map(|block| block.header.compressed_size).sum(),
+            codecs: distinct_codecs(blocks.iter().map(|block| block.header.compression_method)),
+            codec_time: compressed,
+            io_time: written - compressed,
+            metadata_time: total - written,
+            total,
+        });
+        Ok(())
+    }
 
-        let mut metadata_bytes = vec![0u8; metadata_size as usize];
-        file.read_exact(&mut metadata_bytes)?;
+    /// Stores `data` unless `key` already holds the same bytes with the
+    /// same data type, in which case nothing is written and the metadata
+    /// is not committed. Returns whether a write happened. Values stored
+    /// through [`UniversalStorage::store`] are compared by content hash;
+    /// others are read back and compared.
+    pub fn store_if_changed(&mut self, key: &str, data: &[u8], data_type: DataType) -> Result<bool> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        if self.holds_value(&key, data, &data_type)? {
+            return Ok(false);
+        }
+        self.store(&key, data, data_type)?;
+        Ok(true)
+    }
 
-        let metadata: MetaData = bincode::deserialize(&metadata_bytes)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    /// Whether `key` holds exactly `data` with `data_type`, compared as
+    /// [`UniversalStorage::store_if_changed`] does. A missing key holds
+    /// nothing.
+    pub fn holds_value(&mut self, key: &str, data: &[u8], data_type: &DataType) -> Result<bool> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let Some(entry) = self.metadata.index.get(&key).cloned() else {
+            return Ok(false);
+        };
+        if entry.data_type != *data_type || entry.size != data.len() as u64 {
+            return Ok(false);
+        }
+        match entry.content_hash {
+            Some(hash) => Ok(hash == xxh3_128(data)),
+            None => {
+                let mut stored = Vec::with_capacity(data.len());
+                ValueReader::new(self, &entry).read_to_end(&mut stored)?;
+                Ok(stored == data)
+            },
+        }
+    }
 
-        Ok(Self { file, metadata })
+    // Appends prepared blocks and commits the index entry for `key`
+    fn write_entry(&mut self, key: String, blocks: Vec<Block>, data_type: DataType, mut options: EntryOptions) -> Result<()> {
+        options.block_size.get_or_insert(chunk_size(&blocks));
+        let (locations, size) = self.write_blocks(&blocks)?;
+        self.commit_entry(key, locations, size, data_type, options)
     }
 
-    pub fn store(&mut self, key: &str, data: &[u8], data_type: DataType) -> io::Result<()> {
-        let blocks = self.prepare_blocks(data, data_type)?;
-        let mut locations = Vec::new();
+    // Appends prepared blocks, returning their locations and the total
+    // uncompressed size
+    fn write_blocks(&mut self, blocks: &[Block]) -> Result<(Vec<BlockLocation>, u64)> {
+        let mut locations = Vec::with_capacity(blocks.len());
+        let size = blocks.iter().map(|b| b.header.original_size).sum();
+        let mut written = 0;
 
         for block in blocks {
-            let location = self.write_block(&block)?;
-            locations.push(location);
+            locations.push(self.write_block(block)?);
+            written += block.header.original_size;
+            self.report_progress(written, size, ProgressPhase::Store);
         }
+        Ok((locations, size))
+    }
 
-        // Update index with first block location
-        if let Some(first_location) = locations.first() {
-            self.metadata.index.insert(key.to_string(), first_location.clone());
-            self.metadata.total_blocks += locations.len() as u64;
-            self.metadata.modified = Utc::now();
-            self.update_metadata()?;
-        }
+    // Points `key` at already written blocks and persists the index
+    fn commit_entry(&mut self, key: String, locations: Vec<BlockLocation>, size: u64, data_type: DataType, options: EntryOptions) -> Result<()> {
+        self.index_entry(key, locations, size, data_type, options);
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
 
-        Ok(())
+    // Points `key` at already written blocks in memory only
+    fn index_entry(&mut self, key: String, locations: Vec<BlockLocation>, size: u64, data_type: DataType, options: EntryOptions) {
+        self.metadata.total_blocks += locations.len() as u64;
+        let entry = IndexEntry {
+            blocks: locations,
+            data_type,
+            size,
+            stored_at: self.now(),
+            solid_offset: None,
+            hint: options.hint,
+            attributes: options.attributes,
+            content_hash: options.content_hash,
+            ragged: false,
+            block_size: options.block_size.unwrap_or(BLOCK_SIZE as u64),
+            text_deltas: options.text_deltas,
+        };
+        if let Some(previous) = self.metadata.index.insert(key, entry) {
+            self.release_chain(&previous.blocks);
+        }
+        self.record_store(size);
     }
 
-    pub fn retrieve(&mut self, key: &str) -> io::Result<Vec<u8>> {
-        let location = self.metadata.index.get(key)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Key not found"))?;
+    pub fn retrieve(&mut self, key: &str) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+        check_limit(self.limits.as_ref(), "entry size", entry.size, |l| l.max_entry_size)?;
     
-        let mut result = Vec::new();
-        let mut current_location = Some(location.clone());
+        let permits = self.admit([key.as_str()]);
+        let mut result = Vec::with_capacity(entry.size as usize);
+        let (mut io_time, mut codec_time, mut codecs) = (Duration::ZERO, Duration::ZERO, Vec::new());
     
-        while let Some(loc) = current_location {
+        for (loc, range) in entry.block_ranges() {
+            let read_started = Instant::now();
             let block = self.read_block(&loc)?;
-            
-            // Verify checksum
-            let checksum = xxh3_64(&block.data);
-            if checksum != block.header.checksum {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Data corruption detected"));
-            }
-    
-            result.extend_from_slice(&block.data);
-            // current_location = block.next_location;  // This should work now that BlockLocation implements Clone
-            current_location = block.next_location;
+            let unpack_started = Instant::now();
+            codecs.push(block.header.compression_method);
+            let data = self.unpack_block(&loc, block)?;
+            io_time += unpack_started - read_started;
+            codec_time += unpack_started.elapsed();
+            let piece = &data[clamp(&range, data.len())];
+            permits.charge(&key, piece.len() as u64);
+            result.extend_from_slice(piece);
+            self.report_progress(result.len() as u64, entry.size, ProgressPhase::Retrieve);
+        }
+
+        if self.access_tracking {
+            self.record_access(&key);
+        }
+        let total = started.elapsed();
+        self.record_retrieval(&key, result.len() as u64, total);
+        self.report_if_slow(self.slow_ops.retrieve, total, || SlowOperation {
+            operation: "retrieve",
+            size: result.len() as u64,
+            stored_size: entry.blocks.iter().map(|loc| loc.data_size).sum(),
+            codecs: distinct_codecs(codecs),
+            codec_time,
+            io_time,
+            metadata_time: Duration::ZERO,
+            total,
+            key,
+        });
+
+        if entry.text_deltas.is_some() {
+            return textdelta::replay(&result);
+        }
+        if entry.data_type == DataType::Reference {
+            return self.resolve_reference(result);
         }
-    
         Ok(result)
     }
 
-    fn prepare_blocks(&self, data: &[u8], data_type: DataType) -> io::Result<Vec<Block>> {
-        let mut blocks = Vec::new();
-        let mut offset = 0;
+    /// Retrieves a value stored as a bincode `Vec<i64>`, such as
+    /// `Structured` data, decoded into its numbers.
+    pub fn retrieve_i64s(&mut self, key: &str) -> Result<Vec<i64>> {
+        Ok(bincode::deserialize(&self.retrieve(key)?)?)
+    }
 
-        while offset < data.len() {
-            let chunk_size = std::cmp::min(BLOCK_SIZE, data.len() - offset);
-            let chunk = &data[offset..offset + chunk_size];
+    // Reads a block, checks it as the checksum policy says and returns the
+    // decompressed data
+    fn load_block(&self, location: &BlockLocation) -> Result<Vec<u8>> {
+        let block = self.read_block(location)?;
+        self.unpack_block(location, block)
+    }
 
-            let (compressed_data, method) = if chunk.len() >= MIN_COMPRESS_SIZE {
-                match self.compress_data(chunk, &data_type) {
-                    Ok((compressed, method)) => (compressed, method),
-                    Err(_) => (chunk.to_vec(), CompressionMethod::None),
-                }
-            } else {
-                (chunk.to_vec(), CompressionMethod::None)
-            };
+    // Checks a block read from `location` as the checksum policy says and
+    // returns its decompressed data
+    fn unpack_block(&self, location: &BlockLocation, mut block: Block) -> Result<Vec<u8>> {
+        let verified = self.check_block(location, &block)?;
+        check_limit(self.limits.as_ref(), "decompressed block size", block.header.original_size, |l| l.max_decompressed_block_size)?;
 
-            let checksum = xxh3_64(&compressed_data);
+        let transforms = std::mem::take(&mut block.header.transforms);
+        let original_checksum = block.header.original_checksum;
+        let data = Self::decompress_block(block, &self.dictionaries, self.limits.as_ref())?;
+        if verified {
+            Self::check_original(location, original_checksum, &data)?;
+        }
+        self.reverse_transforms(&transforms, data)
+    }
 
-            let header = BlockHeader {
-                data_type: data_type.clone(),
-                original_size: chunk.len() as u64,
-                compressed_size: compressed_data.len() as u64,
-                compression_method: method,
-                checksum,
-                timestamp: Utc::now(),
-            };
+    // Checks decompressed block data against the checksum taken before
+    // compression, so a fault in a codec or in delta decoding is caught
+    fn check_original(location: &BlockLocation, original_checksum: Option<u64>, data: &[u8]) -> Result<()> {
+        match original_checksum {
+            Some(checksum) if xxh3_64(data) != checksum => Err(UsfError::Corruption(format!(
+                "decompressed data of block at offset {} does not match its checksum", location.offset,
+            ))),
+            _ => Ok(()),
+        }
+    }
 
-            blocks.push(Block {
-                header,
-                data: compressed_data,
-                next_location: None,
-            });
+    fn prepare_blocks(data: &[u8], data_type: DataType, encoding: &Encoding) -> Result<Vec<Block>> {
+        let block_size = encoding.block_sizing.block_size_for(data.len() as u64);
+        Self::prepare_chunks(data, block_size, data_type, encoding)
+    }
 
-            offset += chunk_size;
-        }
+    fn prepare_chunks(data: &[u8], block_size: usize, data_type: DataType, encoding: &Encoding) -> Result<Vec<Block>> {
+        data.chunks(block_size)
+            .map(|chunk| Self::prepare_block(chunk, &data_type, encoding))
+            .collect()
+    }
 
-        // Link blocks together
-        for i in 0..blocks.len() - 1 {
-            blocks[i].next_location = Some(BlockLocation {
-                offset: 0, // Will be set during writing
-                header_size: 0,
-                data_size: blocks[i + 1].data.len() as u64,
-            });
-        }
+    fn prepare_block(chunk: &[u8], data_type: &DataType, encoding: &Encoding) -> Result<Block> {
+        let transformed = encoding.apply(chunk)?;
+        // Dictionaries pay off on values too small to compress alone
+        let with_dictionary = encoding.compress_with_dictionary(&transformed)
+            .and_then(io::Result::ok)
+            .filter(|(compressed, _)| compressed.len() < transformed.len());
+        let (compressed_data, method) = if let Some(compressed) = with_dictionary {
+            compressed
+        } else if transformed.len() >= MIN_COMPRESS_SIZE {
+            match Self::compress_data(&transformed, data_type, encoding.compression) {
+                Ok((compressed, method)) => (compressed, method),
+                Err(_) => (transformed.to_vec(), CompressionMethod::None),
+            }
+        } else {
+            (transformed.to_vec(), CompressionMethod::None)
+        };
+
+        let (checksum, digest) = encoding.block_checksum.compute(&compressed_data);
+        // Stored as is yet changed, the block was transcoded and does not
+        // decode back to the original
+        let lossless = method != CompressionMethod::None || compressed_data[..] == transformed[..];
+
+        let header = BlockHeader {
+            data_type: data_type.clone(),
+            original_size: chunk.len() as u64,
+            compressed_size: compressed_data.len() as u64,
+            compression_method: method,
+            checksum,
+            timestamp: Utc::now(),
+            transforms: encoding.transform_names(),
+            original_checksum: lossless.then(|| xxh3_64(&transformed)),
+            checksum_algorithm: encoding.block_checksum,
+            digest,
+        };
 
-        Ok(blocks)
+        Ok(Block {
+            header,
+            data: compressed_data,
+        })
     }
 
-    fn compress_data(&self, data: &[u8], data_type: &DataType) -> io::Result<(Vec<u8>, CompressionMethod)> {
+    fn compress_data(data: &[u8], data_type: &DataType, compression: CompressionPolicy) -> io::Result<(Vec<u8>, CompressionMethod)> {
+        match compression {
+            CompressionPolicy::None => return Ok((data.to_vec(), CompressionMethod::None)),
+            CompressionPolicy::Zstd { level } => return Ok((zstd::encode_all(data, level)?, CompressionMethod::Zstd)),
+            CompressionPolicy::Auto => {},
+        }
+
         match data_type {
             DataType::Text | DataType::Json => {
                 // Use Zstd for text-based data
-                let compressed = zstd::encode_all(data, 21)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                let compressed = zstd::encode_all(data, 21)?;
                 Ok((compressed, CompressionMethod::Zstd))
             },
             DataType::Image => {
                 // For images, attempt to optimize using image crate
                 if let Ok(img) = image::load_from_memory(data) {
-                    let mut output: Vec<u8> = Vec::new();
                     let mut output = std::io::Cursor::new(Vec::new());
-                    img.write_to(&mut output, ImageFormat::WebP).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    img.write_to(&mut output, ImageFormat::WebP).map_err(io::Error::other)?;
                     Ok((output.into_inner(), CompressionMethod::None))
                 } else {
                     // Fallback to regular compression
-                    let compressed = zstd::encode_all(data, 21)
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    let compressed = zstd::encode_all(data, 21)?;
                     Ok((compressed, CompressionMethod::Zstd))
                 }
             },
             DataType::Structured => {
-                // Use delta encoding for structured data if possible
-                if let Ok(numbers) = bincode::deserialize::<Vec<i64>>(data) {
-                    let encoded = self.delta_encode(&numbers);
+                // Use delta encoding for structured data if the block is
+                // exactly one encoded Vec<i64>, so decoding restores it
+                let numbers = bincode::deserialize::<Vec<i64>>(data).ok()
+                    .filter(|numbers| 8 + numbers.len() * 8 == data.len());
+                if let Some(numbers) = numbers {
+                    let encoded = Self::delta_encode(&numbers);
                     Ok((encoded, CompressionMethod::DeltaEncoding))
                 } else {
                     // Fallback to regular compression
-                    let compressed = zstd::encode_all(data, 21)
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    let compressed = zstd::encode_all(data, 21)?;
                     Ok((compressed, CompressionMethod::Zstd))
                 }
             },
             _ => {
                 // Default to Zstd compression
-                let compressed = zstd::encode_all(data, 21)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                let compressed = zstd::encode_all(data, 21)?;
                 Ok((compressed, CompressionMethod::Zstd))
             }
         }
     }
 
-    fn delta_encode(&self, numbers: &[i64]) -> Vec<u8> {
+    fn decompress_block(block: Block, dictionaries: &Dictionaries, limits: Option<&ParseLimits>) -> Result<Vec<u8>> {
+        match block.header.compression_method {
+            CompressionMethod::Zstd => read_capped(zstd::stream::Decoder::new(block.data.as_slice())?, limits),
+            CompressionMethod::ZstdDictionary(id) => dictionaries.decompress(id, &block.data, limits),
+            CompressionMethod::DeltaEncoding => Ok(bincode::serialize(&Self::delta_decode(&block.data)?)?),
+            CompressionMethod::None => Ok(block.data),
+        }
+    }
+
+    fn delta_encode(numbers: &[i64]) -> Vec<u8> {
         let mut encoded = Vec::with_capacity(numbers.len() * 8);
         if numbers.is_empty() {
             return encoded;
@@ -259,28 +857,48 @@ impl UniversalStorage {
 
         // Store differences
         for window in numbers.windows(2) {
-            let diff = window[1] - window[0];
+            let diff = window[1].wrapping_sub(window[0]);
             encoded.extend_from_slice(&diff.to_le_bytes());
         }
 
         encoded
     }
 
-    fn write_block(&mut self, block: &Block) -> io::Result<BlockLocation> {
+    fn delta_decode(encoded: &[u8]) -> Result<Vec<i64>> {
+        if !encoded.len().is_multiple_of(8) {
+            return Err(UsfError::Corruption("delta encoded block is not a whole number of values".to_string()));
+        }
+
+        let mut previous = 0i64;
+        Ok(encoded.chunks_exact(8).enumerate().map(|(i, bytes)| {
+            let value = i64::from_le_bytes(bytes.try_into().expect("chunks of 8 bytes"));
+            previous = if i == 0 { value } else { previous.wrapping_add(value) };
+            previous
+        }).collect())
+    }
+
+    fn write_block(&mut self, block: &Block) -> Result<BlockLocation> {
+        self.check_writable()?;
         // Seek to end of file
         self.file.seek(SeekFrom::End(0))?;
         let offset = self.file.stream_position()?;
 
-        // Serialize and write header
-        let header_bytes = bincode::serialize(&block.header)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        
+        // Serialize and write header, stamped with the write time
+        let header = BlockHeader { timestamp: self.now(), ..block.header.clone() };
+        let header_bytes = header.encode(self.version)?;
+
         let header_size = header_bytes.len() as u32;
-        self.file.write_all(&header_size.to_le_bytes())?;
-        self.file.write_all(&header_bytes)?;
+        let mut bytes = Vec::with_capacity(4 + header_bytes.len() + block.data.len());
+        bytes.extend_from_slice(&header_size.to_le_bytes());
+        bytes.extend_from_slice(&header_bytes);
+        bytes.extend_from_slice(&block.data);
 
-        // Write data
-        self.file.write_all(&block.data)?;
+        #[cfg(feature = "fault-injection")]
+        if let Some(Fault::ShortWrite { written }) = self.take_fault(|f| matches!(f, Fault::ShortWrite { .. })) {
+            self.file.write_all(&bytes[..written.min(bytes.len())])?;
+            return Err(fault::injected("short write").into());
+        }
+        self.file.write_all(&bytes)?;
 
         Ok(BlockLocation {
             offset,
@@ -289,48 +907,131 @@ impl UniversalStorage {
         })
     }
 
-    fn read_block(&mut self, location: &BlockLocation) -> io::Result<Block> {
-        self.file.seek(SeekFrom::Start(location.offset))?;
+    // Block chains referenced outside the index: trashed entries and
+    // interrupted ingests. Their blocks are live until released.
+    fn pinned_chains(&self) -> impl Iterator<Item = (&String, &[BlockLocation])> {
+        let trashed = self.metadata.trash.iter()
+            .map(|(key, trashed)| (key, trashed.entry.blocks.as_slice()));
+        let ingests = self.metadata.ingests.iter()
+            .map(|(key, checkpoint)| (key, checkpoint.blocks.as_slice()));
+        let dictionaries = self.metadata.dictionaries.iter()
+            .map(|(name, stored)| (name, stored.blocks.as_slice()));
+        trashed.chain(ingests).chain(dictionaries)
+    }
 
-        // Read header
+    fn read_header(&self, location: &BlockLocation) -> Result<BlockHeader> {
         let mut header_size_bytes = [0u8; 4];
-        self.file.read_exact(&mut header_size_bytes)?;
+        self.read_at(&mut header_size_bytes, location.offset)?;
         let header_size = u32::from_le_bytes(header_size_bytes);
+        if header_size != location.header_size {
+            return Err(UsfError::Corruption(format!("block header size mismatch at offset {}", location.offset)));
+        }
+        check_limit(self.limits.as_ref(), "block header size", header_size as u64, |l| l.max_header_size)?;
 
         let mut header_bytes = vec![0u8; header_size as usize];
-        self.file.read_exact(&mut header_bytes)?;
+        self.read_at(&mut header_bytes, location.offset + BLOCK_HEADER_PREFIX_SIZE)?;
+
+        BlockHeader::decode(&header_bytes, location.offset)
+    }
 
-        let header: BlockHeader = bincode::deserialize(&header_bytes)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fn read_block(&self, location: &BlockLocation) -> Result<Block> {
+        let header = self.read_header(location)?;
+        if header.compressed_size != location.data_size {
+            return Err(UsfError::Corruption(format!("block size mismatch at offset {}", location.offset)));
+        }
+        check_limit(self.limits.as_ref(), "block size", header.compressed_size, |l| l.max_block_size)?;
 
         // Read data
         let mut data = vec![0u8; header.compressed_size as usize];
-        self.file.read_exact(&mut data)?;
+        let data_offset = location.offset + BLOCK_HEADER_PREFIX_SIZE + location.header_size as u64;
+        self.read_at(&mut data, data_offset)?;
 
         Ok(Block {
             header,
             data,
-            next_location: None, // Will be set if needed
         })
     }
 
-    fn update_metadata(&mut self) -> io::Result<()> {
-        let metadata_bytes = bincode::serialize(&self.metadata)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    // Flushes file data to disk
+    fn sync(&mut self) -> Result<()> {
+        #[cfg(feature = "fault-injection")]
+        if self.take_fault(|f| *f == Fault::SyncFailure).is_some() {
+            return Err(fault::injected("fsync failure").into());
+        }
+        Ok(self.file.sync_data()?)
+    }
+
+    fn update_metadata(&mut self) -> Result<()> {
+        if self.read_only {
+            self.discard_uncommitted()?;
+            return Err(UsfError::ReadOnly);
+        }
+        self.merge_pending_access();
+        self.metadata.generation += 1;
+        let metadata_bytes = bincode::serialize(&self.metadata)?;
+
+        let size = 16 + metadata_bytes.len() as u64;
+        if self.version == VERSION_1 && size > METADATA_CAPACITY {
+            // Undo the caller's changes, or every later commit would carry
+            // them and overflow too
+            self.discard_uncommitted()?;
+            return Err(UsfError::MetadataOverflow { size, capacity: METADATA_CAPACITY });
+        }
 
-        self.file.seek(SeekFrom::Start(5))?; // After magic bytes and version
-        self.file.write_all(&(metadata_bytes.len() as u64).to_le_bytes())?;
-        self.file.write_all(&metadata_bytes)?;
+        let mut bytes = Vec::with_capacity(size as usize);
+        bytes.extend_from_slice(&(metadata_bytes.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&metadata_bytes);
+        bytes.extend_from_slice(&xxh3_64(&metadata_bytes).to_le_bytes());
 
+        let end = self.file.seek(SeekFrom::End(0))?;
+        let (commit_offset, written) = match self.version {
+            VERSION_1 => (METADATA_OFFSET, bytes.clone()),
+            _ => (end, format::with_trailer(&bytes, end)),
+        };
+        let pointer = (self.version != VERSION_1).then(|| format::commit_pointer(commit_offset, metadata_bytes.len() as u64));
+        if self.metadata.write_ahead_log {
+            self.journal_commit(commit_offset, &written, pointer.as_deref())?;
+        }
+        self.file.seek(SeekFrom::Start(commit_offset))?;
+        #[cfg(feature = "fault-injection")]
+        if let Some(Fault::TornMetadata { written: torn }) = self.take_fault(|f| matches!(f, Fault::TornMetadata { .. })) {
+            self.file.write_all(&written[..torn.min(written.len())])?;
+            return Err(fault::injected("torn metadata commit").into());
+        }
+        self.file.write_all(&written)?;
+
+        // The commit takes effect once the pointer names it
+        if let Some(pointer) = pointer {
+            self.file.seek(SeekFrom::Start(METADATA_OFFSET))?;
+            self.file.write_all(&pointer)?;
+            self.commit = Some((commit_offset, written.len() as u64));
+        }
+        if self.metadata.write_ahead_log {
+            self.sync()?;
+            self.clear_journal()?;
+        }
+
+        if self.metadata.index_sidecar {
+            self.write_index_sidecar(&bytes)?;
+        }
         Ok(())
     }
+
+    // Where blocks start in this archive's format version
+    fn data_offset(&self) -> u64 {
+        format::data_offset(self.version)
+    }
+
+    // Bytes of the current commit outside the header, zero for version 1
+    fn commit_size(&self) -> u64 {
+        self.commit.map_or(0, |(_, size)| size)
+    }
 }
 
 #[derive(Debug)]
 struct Block {
     header: BlockHeader,
     data: Vec<u8>,
-    next_location: Option<BlockLocation>,
 }
 
 // Example usage and tests
@@ -376,4 +1077,156 @@ mod tests {
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_multi_block_values_survive_reopen() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test_extents.usf");
+        let mut storage = UniversalStorage::create(&file_path)?;
+        let value: Vec<u8> = (0..BLOCK_SIZE * 2 + 100).map(|i| (i % 241) as u8).collect();
+        storage.store("chained", &value, DataType::Binary)?;
+        storage.store("after", b"written past the chain", DataType::Text)?;
+        drop(storage);
+
+        // Every block of the value is listed in the committed index, in
+        // order and at distinct offsets, so it reassembles after a reopen
+        let mut storage = UniversalStorage::open(&file_path)?;
+        let blocks = storage.metadata.index["chained"].blocks.clone();
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks.windows(2).all(|pair| pair[0].offset + pair[0].disk_size() <= pair[1].offset));
+        assert_eq!(storage.retrieve("chained")?, value);
+        assert_eq!(storage.retrieve("after")?, b"written past the chain");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_if_changed_skips_identical_values() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("test_if_changed.usf"))?;
+
+        assert!(storage.store_if_changed("artifact", b"build 1", DataType::Binary)?);
+        let generation = storage.metadata.generation;
+        assert!(!storage.store_if_changed("artifact", b"build 1", DataType::Binary)?);
+        assert_eq!(storage.metadata.generation, generation);
+
+        assert!(storage.store_if_changed("artifact", b"build 1", DataType::Text)?);
+        assert!(storage.store_if_changed("artifact", b"build 2", DataType::Text)?);
+        assert_eq!(storage.retrieve("artifact")?, b"build 2");
+
+        // Entries without a recorded hash are compared by content
+        storage.metadata.index.get_mut("artifact").unwrap().content_hash = None;
+        assert!(!storage.store_if_changed("artifact", b"build 2", DataType::Text)?);
+        assert!(storage.store_if_changed("artifact", b"build 3", DataType::Text)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_with_prefix() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("test_prefix.usf"))?;
+        for key in ["images/a.png", "images/b/c.png", "images0", "imagesx", "image", "logs/1"] {
+            storage.store(key, key.as_bytes(), DataType::Binary)?;
+        }
+
+        assert_eq!(storage.keys_with_prefix("images/").collect::<Vec<_>>(), ["images/a.png", "images/b/c.png"]);
+        assert_eq!(storage.keys_with_prefix("images").count(), 4);
+        assert_eq!(storage.keys_with_prefix("").count(), 6);
+        assert_eq!(storage.keys_with_prefix("video/").count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_policy_canonicalization() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test_policy.usf");
+
+        let policy = KeyPolicy {
+            normalize_nfc: true,
+            case_insensitive: true,
+            ..KeyPolicy::default()
+        };
+        let mut storage = UniversalStorage::create_with_key_policy(&file_path, policy.clone())?;
+
+        // "Cafe\u{301}" is the decomposed form of "Café"
+        storage.store("Cafe\u{301}", b"espresso", DataType::Text)?;
+        assert_eq!(storage.retrieve("CAFÉ")?, b"espresso");
+
+        // The policy travels with the archive
+        let mut reopened = UniversalStorage::open(&file_path)?;
+        assert_eq!(reopened.key_policy(), &policy);
+        assert_eq!(reopened.retrieve("café")?, b"espresso");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_policy_rejects_invalid_keys() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test_policy_reject.usf");
+
+        let policy = KeyPolicy {
+            max_length: Some(8),
+            charset: KeyCharset::PathSafe,
+            ..KeyPolicy::default()
+        };
+        let mut storage = UniversalStorage::create_with_key_policy(&file_path, policy)?;
+
+        assert!(matches!(
+            storage.store("much/too/long", b"x", DataType::Text),
+            Err(UsfError::KeyTooLong { length: 13, limit: 8 })
+        ));
+        assert!(matches!(
+            storage.store("a b", b"x", DataType::Text),
+            Err(UsfError::InvalidKey { .. })
+        ));
+        storage.store("ok/key", b"x", DataType::Text)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_structured_round_trip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("structured.usf"))?;
+
+        let series: Vec<i64> = (0..1000).map(|i| 1_700_000_000 + i * 60).chain([i64::MIN, i64::MAX]).collect();
+        let encoded = bincode::serialize(&series).map_err(UsfError::from)?;
+        storage.store("series", &encoded, DataType::Structured)?;
+        assert_eq!(storage.retrieve("series")?, encoded);
+        assert_eq!(storage.retrieve_i64s("series")?, series);
+
+        // Spans several blocks, so no single block is a whole Vec<i64>
+        let long: Vec<i64> = (0..20_000).collect();
+        storage.store("long", &bincode::serialize(&long).map_err(UsfError::from)?, DataType::Structured)?;
+        assert_eq!(storage.retrieve_i64s("long")?, long);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_and_decompressed_data_are_checked() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("end_to_end.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("small", b"stored as is", DataType::Text)?;
+        let location = storage.metadata.index["small"].blocks[0].clone();
+        assert_eq!(storage.read_header(&location)?.original_checksum, Some(xxh3_64(b"stored as is")));
+
+        // Data that still matches the checksum of the stored bytes, as a
+        // faulty codec would produce
+        let data_offset = location.offset + BLOCK_HEADER_PREFIX_SIZE + location.header_size as u64;
+        platform::write_all_at(&storage.file, b"S", data_offset)?;
+        let forged = BlockHeader { checksum: xxh3_64(b"Stored as is"), ..storage.read_header(&location)? };
+        platform::write_all_at(&storage.file, &forged.encode(storage.version)?, location.offset + BLOCK_HEADER_PREFIX_SIZE)?;
+        assert!(matches!(storage.retrieve("small"), Err(UsfError::Corruption(message)) if message.contains("decompressed")));
+
+        // A flipped bit in the header itself
+        platform::write_all_at(&storage.file, b"\x7f", location.offset + BLOCK_HEADER_PREFIX_SIZE + 12)?;
+        assert!(matches!(storage.retrieve("small"), Err(UsfError::HeaderChecksumMismatch { offset }) if offset == location.offset));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::Read;
+use std::path::Path;
+use crate::{Access, DataType, Result, UniversalStorage, UsfError};
+
+/// Caps on sizes read from an archive before anything is allocated for
+/// them. Use with [`UniversalStorage::open_with_limits`] when the file may
+/// come from an untrusted source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLimits {
+    pub max_metadata_size: u64,
+    pub max_header_size: u64,
+    /// Maximum stored (compressed) size of a single block
+    pub max_block_size: u64,
+    /// Maximum size of a single block once decompressed; decompression
+    /// stops as soon as it passes this, whatever the header claims
+    pub max_decompressed_block_size: u64,
+    /// Maximum size of a value read whole, as by
+    /// [`UniversalStorage::retrieve`], [`UniversalStorage::retrieve_many`]
+    /// and [`UniversalStorage::retrieve_with`]. Streaming readers hold one
+    /// block at a time and are not bound by it.
+    pub max_entry_size: u64,
+    pub max_keys: u64,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_metadata_size: 1024 * 1024,
+            max_header_size: 4 * 1024,
+            max_block_size: 16 * 1024 * 1024,
+            max_decompressed_block_size: 16 * 1024 * 1024,
+            max_entry_size: 1024 * 1024 * 1024,
+            max_keys: 1_000_000,
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Opens an archive, rejecting any size field above `limits` with
+    /// [`UsfError::LimitExceeded`] instead of allocating for it.
+    pub fn open_with_limits<P: AsRef<Path>>(path: P, limits: ParseLimits) -> Result<Self> {
+        Self::open_inner(path.as_ref(), Some(limits), Access::Read)
+    }
+
+    pub fn parse_limits(&self) -> Option<&ParseLimits> {
+        self.limits.as_ref()
+    }
+
+    /// Caps the size of any single value stored in this archive; larger
+    /// stores fail with [`UsfError::ValueTooLarge`]. The limit is kept in
+    /// the archive metadata, so every writer enforces it.
+    ///
+    /// Payloads above the limit can still be kept by splitting them across
+    /// several keys with [`UniversalStorage::store_chunked`].
+    pub fn set_max_value_size(&mut self, limit: Option<u64>) -> Result<()> {
+        self.metadata.max_value_size = limit;
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn max_value_size(&self) -> Option<u64> {
+        self.metadata.max_value_size
+    }
+
+    /// Caps the length of keys, in bytes after normalization; longer keys
+    /// fail with [`UsfError::KeyTooLong`]. The limit is part of the
+    /// archive's [`crate::KeyPolicy`], so every writer enforces it. It
+    /// cannot be set below the longest key already stored, which would
+    /// then be out of reach.
+    pub fn set_max_key_length(&mut self, limit: Option<usize>) -> Result<()> {
+        let longest = self.metadata.index.keys().chain(self.metadata.trash.keys()).map(String::len).max();
+        if let (Some(limit), Some(length)) = (limit, longest) {
+            if length > limit {
+                return Err(UsfError::KeyTooLong { length, limit });
+            }
+        }
+        self.metadata.key_policy.max_length = limit;
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn max_key_length(&self) -> Option<usize> {
+        self.metadata.key_policy.max_length
+    }
+
+    /// Stores `data` as `<key>/part-0000`, `<key>/part-0001`, … each no
+    /// larger than the archive's maximum value size. Returns the number of
+    /// parts written.
+    pub fn store_chunked(&mut self, key: &str, data: &[u8], data_type: DataType) -> Result<usize> {
+        let part_size = self.metadata.max_value_size.unwrap_or(u64::MAX).max(1);
+        let parts: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(part_size.min(usize::MAX as u64) as usize).collect()
+        };
+
+        for (i, part) in parts.iter().enumerate() {
+            self.store(&chunk_key(key, i), part, data_type.clone())?;
+        }
+        Ok(parts.len())
+    }
+
+    /// Reassembles a value written by [`UniversalStorage::store_chunked`].
+    pub fn retrieve_chunked(&mut self, key: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for i in 0.. {
+            match self.retrieve(&chunk_key(key, i)) {
+                Ok(part) => data.extend_from_slice(&part),
+                Err(UsfError::KeyNotFound(_)) if i > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(data)
+    }
+}
+
+fn chunk_key(key: &str, index: usize) -> String {
+    format!("{}/part-{:04}", key, index)
+}
+
+pub(crate) fn check_value_size(key: &str, size: u64, limit: Option<u64>) -> Result<()> {
+    match limit {
+        Some(limit) if size > limit => Err(UsfError::ValueTooLarge { key: key.to_string(), size, limit }),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn check_limit(
+    limits: Option<&ParseLimits>,
+    what: &'static str,
+    size: u64,
+    pick: impl Fn(&ParseLimits) -> u64,
+) -> Result<()> {
+    match limits.map(pick) {
+        Some(limit) if size > limit => Err(UsfError::LimitExceeded { what, size, limit }),
+        _ => Ok(()),
+    }
+}
+
+// Reads decompressed block data to its end, failing as soon as it passes
+// the decompressed block limit rather than buffering all a bomb expands to
+pub(crate) fn read_capped(reader: impl Read, limits: Option<&ParseLimits>) -> Result<Vec<u8>> {
+    let limit = limits.map_or(u64::MAX, |l| l.max_decompressed_block_size);
+    let mut data = Vec::new();
+    reader.take(limit.saturating_add(1)).read_to_end(&mut data)?;
+    check_limit(limits, "decompressed block size", data.len() as u64, |l| l.max_decompressed_block_size)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, DATA_OFFSET, METADATA_OFFSET};
+    use std::fs::OpenOptions;
+    use std::io::{self, Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_limits_reject_oversized_fields() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("limits.usf");
+        let mut storage = UniversalStorage::create(&file_path)?;
+        storage.store("a", &[1u8; 2048], DataType::Binary)?;
+        storage.store("b", b"b", DataType::Binary)?;
+        drop(storage);
+
+        let tight = ParseLimits { max_keys: 1, ..ParseLimits::default() };
+        assert!(matches!(
+            UniversalStorage::open_with_limits(&file_path, tight),
+            Err(UsfError::LimitExceeded { what: "key count", .. })
+        ));
+
+        let small_blocks = ParseLimits { max_block_size: 16, ..ParseLimits::default() };
+        let mut storage = UniversalStorage::open_with_limits(&file_path, small_blocks)?;
+        assert_eq!(storage.retrieve("b")?, b"b");
+        assert!(matches!(storage.retrieve("a"), Err(UsfError::LimitExceeded { what: "block size", .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits_bound_decompression() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("bomb.usf");
+        let mut storage = UniversalStorage::create(&file_path)?;
+        storage.store("zeros", &[0u8; 60_000], DataType::Binary)?;
+        drop(storage);
+
+        let small = ParseLimits { max_decompressed_block_size: 4096, ..ParseLimits::default() };
+        let mut storage = UniversalStorage::open_with_limits(&file_path, small)?;
+        assert!(matches!(storage.retrieve("zeros"), Err(UsfError::LimitExceeded { what: "decompressed block size", .. })));
+
+        // Decompression stops at the limit even when the header lies
+        let bomb = zstd::encode_all(&[0u8; 1 << 20][..], 3)?;
+        let limits = ParseLimits { max_decompressed_block_size: 4096, ..ParseLimits::default() };
+        assert!(matches!(read_capped(zstd::stream::Decoder::new(&bomb[..])?, Some(&limits)), Err(UsfError::LimitExceeded { size: 4097, .. })));
+
+        // Whole values are capped, while streaming them is not
+        let small = ParseLimits { max_entry_size: 1024, ..ParseLimits::default() };
+        let mut storage = UniversalStorage::open_with_limits(&file_path, small)?;
+        assert!(matches!(storage.retrieve("zeros"), Err(UsfError::LimitExceeded { what: "entry size", .. })));
+        assert!(matches!(
+            &storage.retrieve_many(&["zeros"])[..],
+            [Err(UsfError::LimitExceeded { what: "entry size", .. })]
+        ));
+        assert!(matches!(
+            storage.retrieve_with("zeros", crate::RetrieveOptions::default()),
+            Err(UsfError::LimitExceeded { what: "entry size", .. })
+        ));
+        assert_eq!(storage.retrieve_to("zeros", &mut io::sink())?, 60_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostile_metadata_size_is_rejected() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("hostile.usf");
+        drop(UniversalStorage::create(&file_path)?);
+
+        // A well-formed commit pointer naming an impossible commit is passed
+        // over for the intact one, without allocating what it claims
+        let mut file = OpenOptions::new().write(true).open(&file_path)?;
+        file.seek(SeekFrom::Start(METADATA_OFFSET))?;
+        file.write_all(&format::commit_pointer(DATA_OFFSET, u64::MAX))?;
+        drop(file);
+
+        assert_eq!(UniversalStorage::open(&file_path)?.keys().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_key_length() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("keys.usf");
+        let mut storage = UniversalStorage::create(&file_path)?;
+        assert_eq!(storage.max_key_length(), Some(crate::MAX_KEY_LENGTH));
+
+        let huge = "k".repeat(crate::MAX_KEY_LENGTH + 1);
+        assert!(matches!(
+            storage.store(&huge, b"x", DataType::Text),
+            Err(UsfError::KeyTooLong { length, limit: crate::MAX_KEY_LENGTH }) if length == huge.len()
+        ));
+        assert_eq!(storage.keys().count(), 0);
+
+        // The limit can be lowered no further than the keys already stored
+        storage.store("sixsix", b"x", DataType::Text)?;
+        assert!(matches!(storage.set_max_key_length(Some(4)), Err(UsfError::KeyTooLong { length: 6, limit: 4 })));
+        storage.set_max_key_length(Some(6))?;
+        drop(storage);
+
+        let mut storage = UniversalStorage::open(&file_path)?;
+        assert_eq!(storage.max_key_length(), Some(6));
+        assert!(matches!(storage.store("seven77", b"x", DataType::Text), Err(UsfError::KeyTooLong { length: 7, limit: 6 })));
+        assert_eq!(storage.retrieve("sixsix")?, b"x");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_value_size_and_chunking() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("max_value.usf"))?;
+        storage.set_max_value_size(Some(100))?;
+
+        assert!(matches!(
+            storage.store("big", &[0u8; 101], DataType::Binary),
+            Err(UsfError::ValueTooLarge { size: 101, limit: 100, .. })
+        ));
+        let mut reader: &[u8] = &[0u8; 150];
+        assert!(matches!(
+            storage.store_from_reader("big", &mut reader, DataType::Binary),
+            Err(UsfError::ValueTooLarge { .. })
+        ));
+
+        let data: Vec<u8> = (0..250u8).collect();
+        assert_eq!(storage.store_chunked("big", &data, DataType::Binary)?, 3);
+        assert_eq!(storage.retrieve("big/part-0002")?.len(), 50);
+        assert_eq!(storage.retrieve_chunked("big")?, data);
+
+        Ok(())
+    }
+}
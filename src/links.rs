@@ -0,0 +1,157 @@
+use crate::{BlockLocation, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Adds `alias_key` as a second name for the value stored under
+    /// `existing_key`. Both keys share the same blocks; the blocks stay live
+    /// until every key referencing them is gone.
+    pub fn link(&mut self, existing_key: &str, alias_key: &str) -> Result<()> {
+        let existing_key = self.metadata.key_policy.canonicalize(existing_key)?;
+        let alias_key = self.metadata.key_policy.canonicalize(alias_key)?;
+        if existing_key == alias_key {
+            return Ok(());
+        }
+
+        let mut entry = self.metadata.index.get(&existing_key)
+            .ok_or_else(|| UsfError::KeyNotFound(existing_key.clone()))?
+            .clone();
+        entry.stored_at = self.now();
+
+        if let Some(first) = entry.blocks.first() {
+            *self.metadata.chain_refs.entry(first.offset).or_insert(1) += 1;
+        }
+        if let Some(previous) = self.metadata.index.insert(alias_key, entry) {
+            self.release_chain(&previous.blocks);
+        }
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Copies the value of `old_key` to `new_key`, replacing any value there.
+    /// No data is copied: the two keys share blocks as with
+    /// [`UniversalStorage::link`].
+    pub fn copy(&mut self, old_key: &str, new_key: &str) -> Result<()> {
+        self.link(old_key, new_key)
+    }
+
+    /// Moves the value of `old_key`, with its attributes and access
+    /// statistics, to `new_key`, replacing any value there. Only the index
+    /// changes.
+    pub fn rename(&mut self, old_key: &str, new_key: &str) -> Result<()> {
+        let old_key = self.metadata.key_policy.canonicalize(old_key)?;
+        let new_key = self.metadata.key_policy.canonicalize(new_key)?;
+        if !self.metadata.index.contains_key(&old_key) {
+            return Err(UsfError::KeyNotFound(old_key));
+        }
+        if old_key == new_key {
+            return Ok(());
+        }
+
+        let entry = self.metadata.index.remove(&old_key).expect("checked above");
+        if let Some(previous) = self.metadata.index.insert(new_key.clone(), entry) {
+            self.release_chain(&previous.blocks);
+        }
+        self.forget_access(&new_key);
+        if let Some(stats) = self.metadata.access.remove(&old_key) {
+            self.metadata.access.insert(new_key.clone(), stats);
+        }
+        if let Some(stats) = self.pending_access.remove(&old_key) {
+            self.pending_access.insert(new_key, stats);
+        }
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Number of keys sharing the blocks of `key`, including `key` itself.
+    pub fn ref_count(&self, key: &str) -> Result<u32> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or(UsfError::KeyNotFound(key))?;
+        Ok(entry.blocks.first()
+            .and_then(|first| self.metadata.chain_refs.get(&first.offset))
+            .copied()
+            .unwrap_or(1))
+    }
+
+    // Drops one reference to a block chain. Returns true when no key
+    // references the chain anymore.
+    pub(crate) fn release_chain(&mut self, locations: &[BlockLocation]) -> bool {
+        let Some(first) = locations.first() else {
+            return true;
+        };
+        match self.metadata.chain_refs.get_mut(&first.offset) {
+            Some(count) if *count > 2 => {
+                *count -= 1;
+                false
+            },
+            Some(_) => {
+                self.metadata.chain_refs.remove(&first.offset);
+                false
+            },
+            None => {
+                self.record_freed(locations);
+                true
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_link_shares_blocks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("links.usf"))?;
+
+        storage.store("models/v42", b"weights", DataType::Binary)?;
+        storage.link("models/v42", "models/latest")?;
+        assert_eq!(storage.retrieve("models/latest")?, b"weights");
+        assert_eq!(storage.ref_count("models/v42")?, 2);
+
+        assert_eq!(storage.stat()?.block_count, 1);
+        assert_eq!(storage.freed_bytes(), 0);
+
+        // Repointing the alias keeps the original value alive
+        storage.store("models/v43", b"new weights", DataType::Binary)?;
+        storage.link("models/v43", "models/latest")?;
+        assert_eq!(storage.ref_count("models/v42")?, 1);
+        assert_eq!(storage.ref_count("models/latest")?, 2);
+        assert_eq!(storage.retrieve("models/v42")?, b"weights");
+        assert_eq!(storage.freed_bytes(), 0);
+
+        assert!(matches!(storage.link("missing", "alias"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_and_copy_only_touch_the_index() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("rename.usf"))?;
+        storage.store("draft", b"report body", DataType::Text)?;
+        storage.store("stale", b"to be replaced", DataType::Text)?;
+        storage.set_attribute("draft", "author", "ops")?;
+        let offsets = |storage: &UniversalStorage, key: &str| -> Vec<u64> {
+            storage.metadata.index[key].blocks.iter().map(|loc| loc.offset).collect()
+        };
+        let blocks = offsets(&storage, "draft");
+
+        storage.rename("draft", "final")?;
+        assert!(!storage.contains_key("draft"));
+        assert_eq!(offsets(&storage, "final"), blocks);
+        assert_eq!(storage.attributes("final")?.get("author").map(String::as_str), Some("ops"));
+
+        storage.copy("final", "stale")?;
+        assert_eq!(storage.retrieve("stale")?, b"report body");
+        assert_eq!(storage.ref_count("final")?, 2);
+        assert_eq!(storage.stat()?.block_count, 1);
+
+        assert!(matches!(storage.rename("draft", "other"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+}
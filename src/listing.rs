@@ -0,0 +1,274 @@
+use std::ops::RangeBounds;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use crate::classify::glob_matches;
+use crate::format::{BlockHeader, CompressionMethod};
+use crate::{DataType, IndexEntry, Result, UniversalStorage, UsfError};
+
+/// One entry as listed by [`UniversalStorage::iter`], read from the index
+/// without touching the data region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntrySummary<'a> {
+    pub key: &'a str,
+    pub data_type: DataType,
+    /// Length of the value
+    pub size: u64,
+    /// Compressed bytes of the blocks holding the value. Values packed into
+    /// a solid group report the whole group.
+    pub stored_size: u64,
+    pub stored_at: DateTime<Utc>,
+    /// Last read, when access tracking recorded one
+    pub last_access: Option<DateTime<Utc>>,
+}
+
+/// How one value is stored, from its index entry and block headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub key: String,
+    pub data_type: DataType,
+    /// Length of the value
+    pub original_size: u64,
+    /// Compressed bytes across its blocks
+    pub compressed_size: u64,
+    pub stored_at: DateTime<Utc>,
+    /// Whether the value is packed into a solid group, whose blocks are
+    /// then all listed
+    pub solid: bool,
+    /// Uncompressed bytes held by every block of the chain but the last,
+    /// unless appends left it uneven
+    pub block_size: u64,
+    pub blocks: Vec<BlockInfo>,
+}
+
+impl EntryInfo {
+    /// The compression method shared by every block, if they agree.
+    pub fn compression_method(&self) -> Option<CompressionMethod> {
+        let first = self.blocks.first()?.compression_method;
+        self.blocks.iter().all(|block| block.compression_method == first).then_some(first)
+    }
+}
+
+/// Keys to look for with [`UniversalStorage::find_keys`].
+#[derive(Debug, Clone)]
+pub enum KeyPattern {
+    /// A glob as used by type rules: `*` and `?` stay within one path
+    /// segment, `**` spans segments, and a pattern without `/` is matched
+    /// against the last segment only
+    Glob(String),
+    /// A regular expression, found anywhere in the key unless anchored
+    Regex(Regex),
+}
+
+impl KeyPattern {
+    pub fn glob(pattern: &str) -> Self {
+        KeyPattern::Glob(pattern.to_string())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Regex::new(pattern)
+            .map(KeyPattern::Regex)
+            .map_err(|e| UsfError::InvalidKey { key: pattern.to_string(), reason: e.to_string() })
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Glob(pattern) => glob_matches(pattern, key),
+            KeyPattern::Regex(regex) => regex.is_match(key),
+        }
+    }
+
+    // Literal start every matching key shares, for narrowing the scan
+    fn literal_prefix(&self) -> &str {
+        match self {
+            KeyPattern::Glob(pattern) if pattern.contains('/') => {
+                &pattern[..pattern.find(['*', '?']).unwrap_or(pattern.len())]
+            },
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockInfo {
+    pub offset: u64,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub compression_method: CompressionMethod,
+    /// xxh3-64 of the stored (compressed) data
+    pub checksum: u64,
+    pub timestamp: DateTime<Utc>,
+    pub transforms: Vec<String>,
+}
+
+impl BlockInfo {
+    pub(crate) fn new(offset: u64, header: BlockHeader) -> Self {
+        Self {
+            offset,
+            original_size: header.original_size,
+            compressed_size: header.compressed_size,
+            compression_method: header.compression_method,
+            checksum: header.checksum,
+            timestamp: header.timestamp,
+            transforms: header.transforms,
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Summaries of every entry in key order.
+    pub fn iter(&self) -> impl Iterator<Item = EntrySummary<'_>> {
+        self.metadata.index.iter().map(|(key, entry)| self.summary(key, entry))
+    }
+
+    /// Summaries of the entries whose keys match `pattern`, in key order,
+    /// read from the index alone. A glob's literal leading directories
+    /// limit the scan to the keys under them.
+    pub fn find_keys<'a>(&'a self, pattern: &'a KeyPattern) -> impl Iterator<Item = EntrySummary<'a>> {
+        self.entries_with_prefix(pattern.literal_prefix())
+            .filter(|(key, _)| pattern.matches(key))
+            .map(|(key, entry)| self.summary(key, entry))
+    }
+
+    /// Summaries of the entries whose keys fall in `range`, in key order,
+    /// such as every time-prefixed key between two timestamps. Only that
+    /// part of the index is visited. Bounds are compared against canonical
+    /// keys as given.
+    pub fn range<'a, R: RangeBounds<&'a str>>(&self, range: R) -> impl Iterator<Item = EntrySummary<'_>> {
+        let bounds = (range.start_bound().map(|key| *key), range.end_bound().map(|key| *key));
+        self.metadata.index.range::<str, _>(bounds).map(|(key, entry)| self.summary(key, entry))
+    }
+
+    fn summary<'a>(&'a self, key: &'a str, entry: &IndexEntry) -> EntrySummary<'a> {
+        EntrySummary {
+            key,
+            data_type: entry.data_type.clone(),
+            size: entry.size,
+            stored_size: entry.blocks.iter().map(|loc| loc.data_size).sum(),
+            stored_at: entry.stored_at,
+            last_access: self.pending_access.get(key)
+                .and_then(|stats| stats.last_access)
+                .or_else(|| self.metadata.access.get(key).and_then(|stats| stats.last_access)),
+        }
+    }
+
+    /// Whether `key` is stored. Keys the key policy rejects are never
+    /// stored, so they report false.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.metadata.key_policy.canonicalize(key)
+            .is_ok_and(|key| self.metadata.index.contains_key(&key))
+    }
+
+    /// Describes how `key` is stored. Only block headers are read; the
+    /// value is neither read nor decompressed.
+    pub fn entry_info(&self, key: &str) -> Result<EntryInfo> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key).ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+
+        let blocks = entry.blocks.iter()
+            .map(|loc| Ok(BlockInfo::new(loc.offset, self.read_header(loc)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(EntryInfo {
+            data_type: entry.data_type.clone(),
+            original_size: entry.size,
+            compressed_size: blocks.iter().map(|block| block.compressed_size).sum(),
+            stored_at: entry.stored_at,
+            solid: entry.solid_offset.is_some(),
+            block_size: entry.block_size,
+            blocks,
+            key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BLOCK_SIZE;
+    use std::io;
+    use tempfile::tempdir;
+    use xxhash_rust::xxh3::xxh3_64;
+
+    #[test]
+    fn test_iter_lists_entries_without_reading_values() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("listing.usf"))?;
+        storage.store("b", &[0u8; 10_000], DataType::Binary)?;
+        storage.store("a", b"short", DataType::Text)?;
+        storage.set_access_tracking(true);
+        storage.retrieve("a")?;
+
+        let entries: Vec<EntrySummary> = storage.iter().collect();
+        assert_eq!(entries.iter().map(|e| e.key).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!((entries[0].data_type.clone(), entries[0].size, entries[0].stored_size), (DataType::Text, 5, 5));
+        assert!(entries[0].last_access.is_some());
+        assert_eq!(entries[1].size, 10_000);
+        assert!(entries[1].stored_size < 10_000);
+        assert!(entries[1].last_access.is_none());
+
+        assert!(storage.contains_key("a"));
+        assert!(!storage.contains_key("c"));
+        assert!(!storage.contains_key(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_keys_by_glob_and_regex() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("find.usf"))?;
+        for key in ["logs/2024-01/error.json", "logs/2024-02/error.json", "logs/2024-02/info.json", "logs/2023-12/error.json", "top/error.json"] {
+            storage.store(key, b"{}", DataType::Json)?;
+        }
+
+        let find = |pattern: &KeyPattern| storage.find_keys(pattern).map(|entry| entry.key.to_string()).collect::<Vec<_>>();
+        assert_eq!(find(&KeyPattern::glob("logs/2024-*/error.json")), ["logs/2024-01/error.json", "logs/2024-02/error.json"]);
+        assert_eq!(find(&KeyPattern::glob("error.json")).len(), 4);
+        assert_eq!(find(&KeyPattern::glob("logs/**/info.*")), ["logs/2024-02/info.json"]);
+        assert_eq!(find(&KeyPattern::regex(r"^logs/2023-\d+/")?), ["logs/2023-12/error.json"]);
+        assert_eq!(storage.find_keys(&KeyPattern::glob("logs/2024-01/*")).next().map(|entry| entry.size), Some(2));
+        assert!(matches!(KeyPattern::regex("("), Err(UsfError::InvalidKey { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_scans_between_keys() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("range_scan.usf"))?;
+        for hour in ["2024-05-01T08", "2024-05-01T09", "2024-05-01T10", "2024-05-02T00"] {
+            storage.store(&format!("events/{}", hour), hour.as_bytes(), DataType::Text)?;
+        }
+
+        let keys = |entries: Vec<EntrySummary>| entries.into_iter().map(|entry| entry.key.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            keys(storage.range("events/2024-05-01T09".."events/2024-05-02").collect()),
+            ["events/2024-05-01T09", "events/2024-05-01T10"]
+        );
+        assert_eq!(keys(storage.range("events/2024-05-01T10"..).collect()).len(), 2);
+        assert_eq!(keys(storage.range(.."events/2024-05-01T09").collect()), ["events/2024-05-01T08"]);
+        assert_eq!(storage.range(..).count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_info_reads_only_headers() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("entry_info.usf"))?;
+        let value = vec![3u8; BLOCK_SIZE + 100];
+        storage.store("big", &value, DataType::Binary)?;
+
+        let info = storage.entry_info("big")?;
+        assert_eq!((info.original_size, info.data_type.clone(), info.solid), (value.len() as u64, DataType::Binary, false));
+        assert_eq!(info.blocks.len(), 2);
+        assert_eq!(info.blocks[0].compression_method, CompressionMethod::Zstd);
+        // The 100-byte tail is too small to compress
+        assert_eq!(info.blocks[1].compression_method, CompressionMethod::None);
+        assert_eq!(info.compression_method(), None);
+        assert_eq!(info.blocks[1].checksum, xxh3_64(&value[BLOCK_SIZE..]));
+        assert_eq!(info.compressed_size, info.blocks.iter().map(|b| b.compressed_size).sum::<u64>());
+        assert!(matches!(storage.entry_info("missing"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+}
@@ -1,15 +1,255 @@
-use std::io::{self, Write};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
 use std::fs;
-use chrono::Utc;
 use log::{info, error};
+use regex::Regex;
 use simplelog::{Config, LevelFilter, SimpleLogger};
-use usf::{UniversalStorage, DataType};
+use usf::{ChecksumAlgorithm, UniversalStorage, DataType, StorageStats, Throttle, UsfOptions};
+
+mod conformance;
+mod serve;
+mod top;
+mod watch;
+
+const USAGE: &str = "Usage: usf [stat <archive> | grep <archive> <pattern> [key-prefix] | checksums <archive> [sha256|blake3] | catalog <archive> <sqlite-file> | gen-conformance <dir> | serve --readonly <archive> [addr] [--tenant <prefix>=[max-concurrent]:[bytes-per-sec]]... | top [addr] [interval-secs] | watch <dir> <archive> | migrate <archive> | recover <archive> | verify <archive> | salvage <archive> <dest> | merkle <archive> [key]]";
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:8080";
 
 fn main() -> io::Result<()> {
     // Initialize logging
     SimpleLogger::init(LevelFilter::Info, Config::default()).unwrap();
-    info!("Starting Universal Storage Feature Demonstration");
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => run_demo(),
+        Some("stat") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            stat(path)
+        },
+        Some("grep") => {
+            let (path, pattern) = match (args.get(1), args.get(2)) {
+                (Some(path), Some(pattern)) => (path, pattern),
+                _ => return Err(usage_error()),
+            };
+            grep(path, pattern, args.get(3).map(String::as_str).unwrap_or(""))
+        },
+        Some("checksums") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            let algorithm: ChecksumAlgorithm = args.get(2).map(String::as_str).unwrap_or("sha256").parse()
+                .map_err(|_| usage_error())?;
+            let mut storage = UniversalStorage::open(path)?;
+            storage.export_checksums(io::stdout().lock(), algorithm)?;
+            Ok(())
+        },
+        Some("catalog") => {
+            let (path, catalog) = match (args.get(1), args.get(2)) {
+                (Some(path), Some(catalog)) => (path, catalog),
+                _ => return Err(usage_error()),
+            };
+            let entries = UniversalStorage::open(path)?.export_catalog_sqlite(catalog)?;
+            println!("Wrote {} entries to {}", entries, catalog);
+            Ok(())
+        },
+        Some("gen-conformance") => {
+            let dir = args.get(1).ok_or_else(usage_error)?;
+            conformance::generate(dir.as_ref())
+        },
+        // Only the read-only mode exists; the flag keeps that explicit
+        Some("serve") => match (args.get(1).map(String::as_str), args.get(2)) {
+            (Some("--readonly"), Some(path)) => {
+                let throttle = Throttle::new();
+                let mut addr = DEFAULT_SERVE_ADDR;
+                let mut rest = args[3..].iter();
+                while let Some(arg) = rest.next() {
+                    match arg.as_str() {
+                        "--tenant" => {
+                            let (prefix, limits) = rest.next()
+                                .and_then(|spec| serve::parse_tenant(spec))
+                                .ok_or_else(usage_error)?;
+                            throttle.set_limits(&prefix, limits);
+                        },
+                        other => addr = other,
+                    }
+                }
+                serve::serve(path, addr, throttle)
+            },
+            _ => Err(usage_error()),
+        },
+        Some("top") => {
+            let addr = args.get(1).map_or(DEFAULT_SERVE_ADDR, String::as_str);
+            let interval = match args.get(2) {
+                Some(secs) => secs.parse().ok().filter(|secs| *secs > 0.0).ok_or_else(usage_error)?,
+                None => 1.0,
+            };
+            top::top(addr, std::time::Duration::from_secs_f64(interval))
+        },
+        Some("migrate") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            let report = UsfOptions::new().write(true).open(path)?.migrate()?;
+            match report.migrated() {
+                true => println!(
+                    "Migrated {} from version {} to {}: {} -> {}",
+                    path, report.from_version, report.to_version, format_bytes(report.bytes_before), format_bytes(report.bytes_after),
+                ),
+                false => println!("{} is already at version {}", path, report.to_version),
+            }
+            Ok(())
+        },
+        Some("recover") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            let (_, report) = UniversalStorage::recover(path)?;
+            match report.generation {
+                Some(generation) => println!("Kept the commit at generation {}", generation),
+                None => println!("No intact commit; rebuilt the index from blocks alone"),
+            }
+            println!(
+                "Recovered {} value(s) from {} block(s), skipping {}",
+                report.recovered_keys.len(), report.blocks, format_bytes(report.skipped_bytes),
+            );
+            for key in &report.recovered_keys {
+                println!("  {}", key);
+            }
+            Ok(())
+        },
+        Some("verify") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            let report = UniversalStorage::open(path)?.verify()?;
+            println!("Checked {} block(s), {}, in {} chain(s)", report.blocks, format_bytes(report.bytes), report.chains);
+            for issue in &report.issues {
+                println!("  {:?}", issue);
+            }
+            match report.is_clean() {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} problem(s) found", report.issues.len()))),
+            }
+        },
+        Some("salvage") => {
+            let (path, dest) = match (args.get(1), args.get(2)) {
+                (Some(path), Some(dest)) => (path, dest),
+                _ => return Err(usage_error()),
+            };
+            let report = UniversalStorage::open(path)?.salvage_to(dest)?;
+            println!("Copied {} key(s) to {} ({})", report.salvaged.len(), dest, format_bytes(report.bytes_after));
+            for dropped in &report.dropped {
+                let trashed = if dropped.trashed { " (trashed)" } else { "" };
+                println!("  dropped {}{}: {:?}", dropped.key, trashed, dropped.issue);
+            }
+            Ok(())
+        },
+        // Prints the root, then the proof for a key as JSON if one is given
+        Some("merkle") => {
+            let path = args.get(1).ok_or_else(usage_error)?;
+            let mut storage = UniversalStorage::open(path)?;
+            let root = storage.merkle_root()?;
+            println!("{}", root.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+            if let Some(key) = args.get(2) {
+                let proof = storage.merkle_proof(key)?;
+                println!("{}", serde_json::to_string_pretty(&proof)?);
+            }
+            Ok(())
+        },
+        Some("watch") => match (args.get(1), args.get(2)) {
+            (Some(dir), Some(path)) => watch::watch(dir, path),
+            _ => Err(usage_error()),
+        },
+        Some(_) => Err(usage_error()),
+    }
+}
+
+fn usage_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn stat(path: &str) -> io::Result<()> {
+    let mut storage = UniversalStorage::open(path)?;
+    let stats = storage.stat()?;
+    print_stats(path, &stats);
+    Ok(())
+}
+
+// Prints `key:line:text` for every line of a Text or Json value matching
+// `pattern`, streaming values so archives larger than memory work
+fn grep(path: &str, pattern: &str, key_prefix: &str) -> io::Result<()> {
+    let regex = Regex::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut storage = UniversalStorage::open(path)?;
+    let keys: Vec<String> = storage.keys_with_prefix(key_prefix)
+        .map(str::to_string)
+        .collect();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut line = Vec::new();
+    for key in keys {
+        if !matches!(storage.data_type_of(&key)?, DataType::Text | DataType::Json) {
+            continue;
+        }
+
+        let mut reader = BufReader::new(storage.retrieve_many_stream(&[&key])?);
+        let mut number = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            number += 1;
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            if regex.is_match(text) {
+                writeln!(out, "{}:{}:{}", key, number, text)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_stats(path: &str, stats: &StorageStats) {
+    println!("Archive:        {}", path);
+    println!("File size:      {}", format_bytes(stats.file_size));
+    println!("Keys:           {}", stats.key_count);
+    println!("Blocks:         {}", stats.block_count);
+    println!("Live bytes:     {}", format_bytes(stats.live_bytes));
+    println!("Dead bytes:     {}", format_bytes(stats.dead_bytes));
+    println!("Fragmentation:  {:.1}%", stats.fragmentation_percent());
+    println!("Index overhead: {}", format_bytes(stats.index_bytes));
+
+    println!();
+    println!("Compression by data type:");
+    let mut by_type: Vec<_> = stats.by_data_type.iter().collect();
+    by_type.sort_by_key(|(data_type, _)| format!("{:?}", data_type));
+    for (data_type, type_stats) in by_type {
+        println!(
+            "  {:<12} {:>6} keys  {:>10} -> {:>10}  ({:.2}x)",
+            format!("{:?}", data_type),
+            type_stats.entries,
+            format_bytes(type_stats.original_bytes),
+            format_bytes(type_stats.compressed_bytes),
+            type_stats.compression_ratio(),
+        );
+    }
+
+    println!();
+    println!("Largest keys:");
+    for (key, size) in &stats.largest_keys {
+        println!("  {:>10}  {}", format_bytes(*size), key);
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn run_demo() -> io::Result<()> {
+    info!("Starting Universal Storage Feature Demonstration");
     // File setup
     let path = "demo_storage.usf";
     if fs::metadata(path).is_ok() {
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::io;
+use serde::{Serialize, Deserialize};
+use crate::stream::ValueReader;
+use crate::{Result, UniversalStorage, UsfError};
+
+// Domain separation, so a leaf can never pass for an inner node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// The path from one entry to the root of an archive's Merkle tree, from
+/// [`UniversalStorage::merkle_proof`]. Together with the published root,
+/// it lets anyone holding just that entry's value check it, without the
+/// rest of the archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub key: String,
+    /// Position of the entry's leaf, in key order
+    pub index: u64,
+    /// Leaves in the tree, one per live entry
+    pub leaves: u64,
+    /// Hashes of the sibling nodes, from the leaf up
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Whether `value`, stored under this proof's key, belongs to the tree
+    /// with `root`.
+    pub fn verify(&self, value: &[u8], root: &[u8; 32]) -> bool {
+        if self.index >= self.leaves {
+            return false;
+        }
+        let mut hash = leaf_hash(&self.key, blake3::hash(value).as_bytes());
+        let mut siblings = self.siblings.iter();
+        let (mut index, mut width) = (self.index, self.leaves);
+        while width > 1 {
+            // The last node of an odd level moves up unpaired
+            if index % 2 == 1 || index + 1 < width {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                hash = match index % 2 {
+                    0 => node_hash(&hash, sibling),
+                    _ => node_hash(sibling, &hash),
+                };
+            }
+            index /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+impl UniversalStorage {
+    /// Root of a Merkle tree over every live entry, for publishing
+    /// alongside the archive. Each leaf, in key order, is the BLAKE3 of a
+    /// zero byte, the key's length (u64 LE), the key and the BLAKE3 of the
+    /// value as [`UniversalStorage::export_checksums`] reads it; each inner
+    /// node is the BLAKE3 of a one byte and its two children, and the last
+    /// node of an odd level moves up unpaired. An empty archive's root is
+    /// the BLAKE3 of nothing.
+    ///
+    /// Value digests are kept in the metadata, keyed by content hash, and
+    /// written with the next commit, so later roots only read values
+    /// stored since. Values whose content hash was dropped by an append
+    /// are read every time.
+    pub fn merkle_root(&mut self) -> Result<[u8; 32]> {
+        let leaves = self.merkle_leaves()?;
+        let mut level: Vec<[u8; 32]> = leaves.into_values().collect();
+        if level.is_empty() {
+            return Ok(*blake3::hash(&[]).as_bytes());
+        }
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        Ok(level[0])
+    }
+
+    /// The proof that the value under `key` belongs to the tree whose root
+    /// [`UniversalStorage::merkle_root`] returns.
+    pub fn merkle_proof(&mut self, key: &str) -> Result<MerkleProof> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let leaves = self.merkle_leaves()?;
+        let index = leaves.keys().position(|leaf_key| *leaf_key == key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+
+        let mut level: Vec<[u8; 32]> = leaves.into_values().collect();
+        let mut proof = MerkleProof { key, index: index as u64, leaves: level.len() as u64, siblings: Vec::new() };
+        let mut index = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(index ^ 1) {
+                proof.siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+        Ok(proof)
+    }
+
+    // Leaf hashes of every live entry, by key, refreshing the cached value
+    // digests
+    fn merkle_leaves(&mut self) -> Result<BTreeMap<String, [u8; 32]>> {
+        let entries: Vec<_> = self.metadata.index.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        let cached = std::mem::take(&mut self.metadata.merkle_digests);
+        let mut digests = BTreeMap::new();
+        let mut leaves = BTreeMap::new();
+        for (key, entry) in entries {
+            let known = entry.content_hash.and_then(|hash| digests.get(&hash).or_else(|| cached.get(&hash)).copied());
+            let digest = match known {
+                Some(digest) => digest,
+                None => {
+                    let mut hasher = blake3::Hasher::new();
+                    io::copy(&mut ValueReader::new(self, &entry), &mut hasher)?;
+                    *hasher.finalize().as_bytes()
+                },
+            };
+            if let Some(hash) = entry.content_hash {
+                digests.insert(hash, digest);
+            }
+            leaves.insert(key, digest);
+        }
+        self.metadata.merkle_digests = digests;
+        Ok(leaves.into_iter().map(|(key, digest)| {
+            let leaf = leaf_hash(&key, &digest);
+            (key, leaf)
+        }).collect())
+    }
+}
+
+fn leaf_hash(key: &str, digest: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update(digest);
+    *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [last] => *last,
+            _ => unreachable!("chunks of two"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_merkle_proofs() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("merkle.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        let empty = storage.merkle_root()?;
+        for i in 0..6 {
+            storage.store(&format!("key-{}", i), format!("value {}", i).as_bytes(), DataType::Text)?;
+        }
+        storage.copy("key-5", "key-6")?;
+        let root = storage.merkle_root()?;
+        assert_ne!(root, empty);
+        assert_eq!(storage.metadata.merkle_digests.len(), 6);
+
+        // Every entry of an odd-sized tree proves against the root, and
+        // only with its own value and key
+        for i in 0..7 {
+            let proof = storage.merkle_proof(&format!("key-{}", i))?;
+            let value = storage.retrieve(&proof.key)?;
+            assert!(proof.verify(&value, &root));
+            assert!(!proof.verify(b"forged", &root));
+            assert!(!MerkleProof { key: "other".to_string(), ..proof.clone() }.verify(&value, &root));
+        }
+        assert!(matches!(storage.merkle_proof("missing"), Err(UsfError::KeyNotFound(_))));
+
+        // Digests persist with the next commit, and the root follows writes
+        storage.store("key-1", b"rewritten", DataType::Text)?;
+        drop(storage);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.metadata.merkle_digests.len(), 6);
+        let rewritten = storage.merkle_root()?;
+        assert_ne!(rewritten, root);
+        let proof = storage.merkle_proof("key-1")?;
+        assert!(proof.verify(b"rewritten", &rewritten));
+        assert!(!proof.verify(b"value 1", &rewritten));
+
+        Ok(())
+    }
+}
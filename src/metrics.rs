@@ -0,0 +1,143 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use crate::UniversalStorage;
+
+// Number of slow retrievals remembered
+const SLOWEST_RETRIEVALS: usize = 10;
+
+/// Operation counters for one handle since it was opened (or since the
+/// last [`UniversalStorage::reset_metrics`]). Diff two snapshots to get
+/// rates.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OperationMetrics {
+    pub stores: u64,
+    pub retrieves: u64,
+    pub bytes_stored: u64,
+    pub bytes_retrieved: u64,
+    /// `get_or_store_with` calls answered from the archive
+    pub cache_hits: u64,
+    /// `get_or_store_with` calls that had to compute the value
+    pub cache_misses: u64,
+    pub compactions: u64,
+    /// Operations that ran past their [`crate::SlowOpThresholds`]
+    pub slow_operations: u64,
+    /// Slowest retrievals seen, slowest first
+    pub slowest_retrievals: Vec<(String, Duration)>,
+}
+
+impl OperationMetrics {
+    pub fn cache_hit_rate(&self) -> f64 {
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.cache_hits as f64 / lookups as f64
+    }
+
+    /// Stores plus retrieves per second since `earlier` was taken.
+    pub fn ops_per_sec(&self, earlier: &OperationMetrics, elapsed: Duration) -> f64 {
+        let ops = (self.stores + self.retrieves).saturating_sub(earlier.stores + earlier.retrieves);
+        per_sec(ops, elapsed)
+    }
+
+    /// Bytes stored plus retrieved per second since `earlier` was taken.
+    pub fn bytes_per_sec(&self, earlier: &OperationMetrics, elapsed: Duration) -> f64 {
+        let bytes = (self.bytes_stored + self.bytes_retrieved)
+            .saturating_sub(earlier.bytes_stored + earlier.bytes_retrieved);
+        per_sec(bytes, elapsed)
+    }
+
+    /// Adds the counts of `other`, such as another handle's, keeping the
+    /// slowest retrievals of both.
+    pub fn merge(&mut self, other: &OperationMetrics) {
+        self.stores += other.stores;
+        self.retrieves += other.retrieves;
+        self.bytes_stored += other.bytes_stored;
+        self.bytes_retrieved += other.bytes_retrieved;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.compactions += other.compactions;
+        self.slow_operations += other.slow_operations;
+        for (key, elapsed) in &other.slowest_retrievals {
+            self.note_slow(key, *elapsed);
+        }
+    }
+
+    fn record_retrieval(&mut self, key: &str, bytes: u64, elapsed: Duration) {
+        self.retrieves += 1;
+        self.bytes_retrieved += bytes;
+        self.note_slow(key, elapsed);
+    }
+
+    // Keeps `key` among the slowest retrievals if it is one, once per key
+    fn note_slow(&mut self, key: &str, elapsed: Duration) {
+        let slowest = &mut self.slowest_retrievals;
+        if slowest.iter().any(|(k, d)| k == key && *d >= elapsed) {
+            return;
+        }
+        if slowest.len() < SLOWEST_RETRIEVALS || slowest.last().is_some_and(|(_, d)| elapsed > *d) {
+            slowest.retain(|(k, _)| k != key);
+            let at = slowest.partition_point(|(_, d)| *d >= elapsed);
+            slowest.insert(at, (key.to_string(), elapsed));
+            slowest.truncate(SLOWEST_RETRIEVALS);
+        }
+    }
+}
+
+fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    count as f64 / elapsed.as_secs_f64()
+}
+
+impl UniversalStorage {
+    pub fn metrics(&self) -> &OperationMetrics {
+        &self.metrics
+    }
+
+    /// Clears these metrics and the [`crate::VerificationStats`].
+    pub fn reset_metrics(&mut self) {
+        self.metrics = OperationMetrics::default();
+        self.reset_verification_stats();
+    }
+
+    pub(crate) fn record_store(&mut self, bytes: u64) {
+        self.metrics.stores += 1;
+        self.metrics.bytes_stored += bytes;
+    }
+
+    pub(crate) fn record_retrieval(&mut self, key: &str, bytes: u64, elapsed: Duration) {
+        self.metrics.record_retrieval(key, bytes, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_operation_metrics() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("metrics.usf"))?;
+        let before = storage.metrics().clone();
+
+        storage.store("a", b"12345", DataType::Binary)?;
+        storage.retrieve("a")?;
+        storage.get_or_store_with("a", DataType::Binary, Vec::new)?;
+        storage.get_or_store_with("b", DataType::Binary, || b"xy".to_vec())?;
+
+        let metrics = storage.metrics();
+        assert_eq!(metrics.stores, 2);
+        assert_eq!(metrics.bytes_stored, 7);
+        assert_eq!(metrics.retrieves, 2);
+        assert_eq!(metrics.cache_hit_rate(), 0.5);
+        assert_eq!(metrics.slowest_retrievals.len(), 1);
+        assert_eq!(metrics.ops_per_sec(&before, Duration::from_secs(2)), 2.0);
+
+        Ok(())
+    }
+}
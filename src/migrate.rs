@@ -0,0 +1,105 @@
+use crate::format::VERSION;
+use crate::{Result, UniversalStorage};
+
+/// What [`UniversalStorage::migrate`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u8,
+    pub to_version: u8,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl MigrationReport {
+    pub fn migrated(&self) -> bool {
+        self.from_version != self.to_version
+    }
+}
+
+impl UniversalStorage {
+    /// Format version of the file this handle reads and writes. Archives
+    /// from earlier versions open and accept writes in their own version
+    /// until they are migrated.
+    pub fn format_version(&self) -> u8 {
+        self.version
+    }
+
+    /// Rewrites an archive from an earlier format version in the current
+    /// one, replacing the file atomically as
+    /// [`UniversalStorage::compact`] does. Unlike compaction, retention
+    /// rules are not applied and the trash is kept whole, so every key,
+    /// trashed entry and attribute comes through unchanged. A cold tier is
+    /// folded back in. Archives already in the current version are left
+    /// alone.
+    pub fn migrate(&mut self) -> Result<MigrationReport> {
+        let from_version = self.version;
+        if from_version == VERSION {
+            let size = self.file.metadata()?.len() + self.cold_tier_len()?;
+            return Ok(MigrationReport { from_version, to_version: VERSION, bytes_before: size, bytes_after: size });
+        }
+
+        let report = self.rewrite(None)?;
+        Ok(MigrationReport {
+            from_version,
+            to_version: self.version,
+            bytes_before: report.bytes_before,
+            bytes_after: report.bytes_after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, MAGIC_BYTES, V1_DATA_OFFSET, VERSION_1};
+    use crate::{DataType, MetaData, UsfError, UsfOptions};
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use xxhash_rust::xxh3::xxh3_64;
+
+    #[test]
+    fn test_migrate_version_1_archive() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("v1.usf");
+        let metadata = bincode::serialize(&MetaData::new(Default::default(), chrono::Utc::now())).map_err(io::Error::other)?;
+        let mut file = File::create(&path)?;
+        file.write_all(MAGIC_BYTES)?;
+        file.write_all(&[VERSION_1])?;
+        file.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        file.write_all(&metadata)?;
+        file.write_all(&xxh3_64(&metadata).to_le_bytes())?;
+        file.set_len(V1_DATA_OFFSET)?;
+        drop(file);
+
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        storage.set_trash_retention(Some(Duration::ZERO))?;
+        storage.store("kept", b"from version 1", DataType::Text)?;
+        storage.store("trashed", b"still recoverable", DataType::Text)?;
+        storage.delete("trashed")?;
+        assert_eq!(storage.format_version(), VERSION_1);
+
+        // The trash survives even with its retention lapsed
+        let report = storage.migrate()?;
+        assert!(report.migrated() && report.bytes_after < report.bytes_before);
+        assert_eq!((report.from_version, report.to_version), (VERSION_1, VERSION));
+        drop(storage);
+
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        assert_eq!(storage.format_version(), VERSION);
+        assert_eq!(storage.retrieve("kept")?, b"from version 1");
+        storage.undelete("trashed")?;
+        assert_eq!(storage.retrieve("trashed")?, b"still recoverable");
+        assert!(!storage.migrate()?.migrated());
+
+        // Versions newer than this build are refused by name
+        drop(storage);
+        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        crate::platform::write_all_at(&file, &[VERSION + 1], MAGIC_BYTES.len() as u64)?;
+        assert!(matches!(format::read_superblock(File::open(&path)?), Err(UsfError::UnsupportedVersion(v)) if v == VERSION + 1));
+        assert!(matches!(UniversalStorage::open(&path), Err(UsfError::UnsupportedVersion(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use chrono::Utc;
+use crate::{platform, readonly, Access, CheckLevel, KeyPolicy, MetaData, ParseLimits, Result, UniversalStorage};
+
+/// Opens an archive in a chosen mode, after [`std::fs::OpenOptions`].
+/// [`UniversalStorage::open`] is read-only and
+/// [`UniversalStorage::create`] always starts afresh; this also covers
+/// reopening an existing archive to keep storing into it.
+///
+/// ```no_run
+/// # use usf::{DataType, UsfOptions};
+/// let mut storage = UsfOptions::new().read(true).write(true).create(true).open("data.usf")?;
+/// storage.store("more", b"appended after reopening", DataType::Text)?;
+/// # Ok::<(), usf::UsfError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct UsfOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    truncate: bool,
+    key_policy: KeyPolicy,
+    limits: Option<ParseLimits>,
+    check: CheckLevel,
+}
+
+impl Default for UsfOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsfOptions {
+    /// Read-only, like [`UniversalStorage::open`].
+    pub fn new() -> Self {
+        Self {
+            read: true,
+            write: false,
+            create: false,
+            truncate: false,
+            key_policy: KeyPolicy::default(),
+            limits: None,
+            check: CheckLevel::None,
+        }
+    }
+
+    /// Handles always read, so opening with `read(false)` fails.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Creates the archive if nothing exists at the path. Needs `write`.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Replaces any existing archive with an empty one. Needs `write`.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Key policy of a newly created archive. Existing archives keep the
+    /// policy they were created with.
+    pub fn key_policy(&mut self, key_policy: KeyPolicy) -> &mut Self {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// Parse limits, as for [`UniversalStorage::open_with_limits`].
+    pub fn limits(&mut self, limits: ParseLimits) -> &mut Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// How much of an existing archive to verify before returning, as for
+    /// [`UniversalStorage::open_with_check`], so damage is reported on
+    /// open rather than by a read deep inside a pipeline. Defaults to
+    /// [`CheckLevel::None`].
+    pub fn check(&mut self, level: CheckLevel) -> &mut Self {
+        self.check = level;
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<UniversalStorage> {
+        let path = path.as_ref();
+        if !self.read {
+            return Err(invalid("archives cannot be opened without read access").into());
+        }
+        if (self.create || self.truncate) && !self.write {
+            return Err(invalid("creating or truncating an archive needs write access").into());
+        }
+
+        if self.truncate || (self.create && !path.exists()) {
+            // Not over an archive that read-only handles hold
+            if path.exists() {
+                readonly::lock(&File::open(platform::native_path(path))?, Access::Write)?;
+            }
+            let mut storage = UniversalStorage::initialize(path, MetaData::new(self.key_policy.clone(), Utc::now()))?;
+            readonly::lock(&storage.file, Access::Write)?;
+            storage.limits = self.limits.clone();
+            return Ok(storage);
+        }
+        let access = if self.write { Access::Write } else { Access::Read };
+        let mut storage = UniversalStorage::open_inner(path, self.limits.clone(), access)?;
+        storage.check_to(self.check)?;
+        Ok(storage)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfError};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reopen_for_writing() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("reopen.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.store("first", b"one", DataType::Text)?;
+        drop(storage);
+
+        // Read-only handles still refuse writes
+        let mut storage = UniversalStorage::open(&path)?;
+        assert!(matches!(storage.store("second", b"two", DataType::Text), Err(UsfError::Io(_))));
+
+        let mut storage = UsfOptions::new().read(true).write(true).create(true).open(&path)?;
+        storage.store("second", b"two", DataType::Text)?;
+        drop(storage);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.retrieve("first")?, b"one");
+        assert_eq!(storage.retrieve("second")?, b"two");
+
+        let storage = UsfOptions::new().write(true).truncate(true).open(&path)?;
+        assert!(storage.is_empty());
+        assert!(UsfOptions::new().create(true).open(dir.path().join("missing.usf")).is_err());
+        assert!(UsfOptions::new().open(dir.path().join("missing.usf")).is_err());
+
+        Ok(())
+    }
+}
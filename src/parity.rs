@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::io;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Serialize, Deserialize};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::format::{BlockHeader, CompressionMethod};
+use crate::{Block, BlockChecksum, BlockLocation, DataType, Result, UniversalStorage, UsfError};
+
+/// Most shards (data plus parity) a Reed-Solomon group over GF(2^8) can hold
+const MAX_GROUP_SHARDS: usize = 256;
+
+// Reed-Solomon parity over a run of blocks. Each member's on-disk bytes,
+// zero-padded to `shard_size`, form one data shard; each parity block
+// holds one parity shard as its data.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ParityGroup {
+    members: Vec<BlockLocation>,
+    parity: Vec<BlockLocation>,
+    shard_size: u64,
+}
+
+impl ParityGroup {
+    pub(crate) fn parity_blocks(&self) -> &[BlockLocation] {
+        &self.parity
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Offsets of damaged blocks rebuilt from parity
+    pub repaired: Vec<u64>,
+    /// Offsets of damaged blocks that could not be rebuilt, either because
+    /// they are not covered by parity or their group lost too many shards
+    pub unrecoverable: Vec<u64>,
+    /// Parity groups whose damaged parity blocks were rewritten
+    pub parity_rewritten: usize,
+}
+
+impl UniversalStorage {
+    /// Writes repair data for every live block not yet covered: blocks are
+    /// grouped `group_size` at a time in file order and each group gets
+    /// `parity_shards` Reed-Solomon parity blocks, so up to that many
+    /// damaged blocks per group can be rebuilt by
+    /// [`UniversalStorage::repair`]. Returns the number of groups written.
+    /// Compaction moves blocks, so it drops existing parity and writes it
+    /// afresh with the largest group size and parity count in use.
+    pub fn add_parity(&mut self, group_size: usize, parity_shards: usize) -> Result<usize> {
+        let written = self.write_parity(group_size, parity_shards)?;
+        if written > 0 {
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+        }
+        Ok(written)
+    }
+
+    // Writes the parity groups `add_parity` commits
+    pub(crate) fn write_parity(&mut self, group_size: usize, parity_shards: usize) -> Result<usize> {
+        if group_size == 0 || parity_shards == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "parity groups need data and parity shards").into());
+        }
+        let shards = group_size + parity_shards;
+        if shards > MAX_GROUP_SHARDS {
+            return Err(UsfError::LimitExceeded {
+                what: "parity group shard count",
+                size: shards as u64,
+                limit: MAX_GROUP_SHARDS as u64,
+            });
+        }
+
+        let covered: HashSet<u64> = self.metadata.parity.iter()
+            .flat_map(|group| group.members.iter().map(|loc| loc.offset))
+            .collect();
+        let mut uncovered: Vec<BlockLocation> = self.metadata.index.iter()
+            .map(|(key, entry)| (key, entry.blocks.as_slice()))
+            .chain(self.pinned_chains())
+            .flat_map(|(_, locations)| locations.iter().cloned())
+            .filter(|loc| !covered.contains(&loc.offset))
+            .collect();
+        uncovered.sort_by_key(|loc| loc.offset);
+        uncovered.dedup_by_key(|loc| loc.offset);
+
+        let mut written = 0;
+        for members in uncovered.chunks(group_size) {
+            let group = self.write_parity_group(members.to_vec(), parity_shards)?;
+            self.metadata.parity.push(group);
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Checks every block covered by parity and rebuilds damaged ones in
+    /// place. Blocks that fail their checksum outside any parity group are
+    /// reported as unrecoverable. Repair writes to the archive, so the
+    /// handle must be open for writing, e.g. through [`crate::UsfOptions`];
+    /// otherwise this fails with [`UsfError::ReadOnly`].
+    pub fn repair(&mut self) -> Result<RepairReport> {
+        self.check_opened_for_writing()?;
+        self.cold = self.open_cold_tier(true)?;
+        let mut report = RepairReport::default();
+
+        let mut groups = std::mem::take(&mut self.metadata.parity);
+        let result = groups.iter_mut()
+            .try_for_each(|group| self.repair_group(group, &mut report));
+        self.metadata.parity = groups;
+        result?;
+
+        let covered: HashSet<u64> = self.metadata.parity.iter()
+            .flat_map(|group| group.members.iter().map(|loc| loc.offset))
+            .collect();
+        let mut unprotected: Vec<BlockLocation> = self.metadata.index.values()
+            .flat_map(|entry| entry.blocks.iter().cloned())
+            .filter(|loc| !covered.contains(&loc.offset))
+            .collect();
+        unprotected.sort_by_key(|loc| loc.offset);
+        unprotected.dedup_by_key(|loc| loc.offset);
+        for location in unprotected {
+            if !self.block_intact(&location) {
+                report.unrecoverable.push(location.offset);
+            }
+        }
+
+        if report.parity_rewritten > 0 {
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+        }
+        self.sync()?;
+        report.unrecoverable.sort_unstable();
+        Ok(report)
+    }
+
+    fn write_parity_group(&mut self, members: Vec<BlockLocation>, parity_shards: usize) -> Result<ParityGroup> {
+        let shard_size = members.iter().map(|loc| loc.disk_size()).max().unwrap_or(0);
+        let mut shards = members.iter()
+            .map(|loc| self.read_shard(loc, shard_size))
+            .collect::<Result<Vec<_>>>()?;
+        shards.extend((0..parity_shards).map(|_| vec![0u8; shard_size as usize]));
+
+        codec(members.len(), parity_shards)?
+            .encode(&mut shards)
+            .map_err(|e| UsfError::Corruption(format!("parity encoding failed: {:?}", e)))?;
+
+        let parity = shards.split_off(members.len()).into_iter()
+            .map(|data| self.write_block(&parity_block(data)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ParityGroup { members, parity, shard_size })
+    }
+
+    fn repair_group(&mut self, group: &mut ParityGroup, report: &mut RepairReport) -> Result<()> {
+        let damaged: Vec<usize> = (0..group.members.len())
+            .filter(|&i| !self.block_intact(&group.members[i]))
+            .collect();
+        let parity_damaged = group.parity.iter().any(|loc| !self.block_intact(loc));
+        if damaged.is_empty() && !parity_damaged {
+            return Ok(());
+        }
+
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(group.members.len() + group.parity.len());
+        for (i, location) in group.members.iter().enumerate() {
+            let shard = match damaged.contains(&i) {
+                true => None,
+                false => Some(self.read_shard(location, group.shard_size)?),
+            };
+            shards.push(shard);
+        }
+        for location in &group.parity {
+            let shard = match self.block_intact(location) {
+                true => Some(self.read_block(location)?.data),
+                false => None,
+            };
+            shards.push(shard);
+        }
+
+        if codec(group.members.len(), group.parity.len())?.reconstruct_data(&mut shards).is_err() {
+            report.unrecoverable.extend(damaged.iter().map(|&i| group.members[i].offset));
+            return Ok(());
+        }
+
+        for &i in &damaged {
+            let location = &group.members[i];
+            let shard = shards[i].as_ref().expect("reconstructed data shard");
+            self.write_at(&shard[..location.disk_size() as usize], location.offset)?;
+            report.repaired.push(location.offset);
+        }
+
+        if parity_damaged {
+            // Data is whole again, so recompute parity into fresh blocks
+            let members = group.members.clone();
+            let replaced = std::mem::replace(group, self.write_parity_group(members, group.parity.len())?);
+            self.record_freed(&replaced.parity);
+            report.parity_rewritten += 1;
+        }
+        Ok(())
+    }
+
+    // Group size and parity count to cover blocks with again once they
+    // move, the largest in use
+    pub(crate) fn parity_geometry(&self) -> Option<(usize, usize)> {
+        let groups = &self.metadata.parity;
+        let group_size = groups.iter().map(|group| group.members.len()).max()?;
+        let parity_shards = groups.iter().map(|group| group.parity.len()).max()?;
+        Some((group_size, parity_shards))
+    }
+
+    // A block's on-disk bytes, zero-padded to `shard_size`
+    fn read_shard(&self, location: &BlockLocation, shard_size: u64) -> Result<Vec<u8>> {
+        let mut shard = vec![0u8; shard_size as usize];
+        self.read_at(&mut shard[..location.disk_size() as usize], location.offset)?;
+        Ok(shard)
+    }
+
+    fn block_intact(&self, location: &BlockLocation) -> bool {
+        self.read_block(location)
+            .map(|block| block.header.checksum_matches(&block.data))
+            .unwrap_or(false)
+    }
+}
+
+fn codec(data_shards: usize, parity_shards: usize) -> Result<ReedSolomon> {
+    ReedSolomon::new(data_shards, parity_shards)
+        .map_err(|e| UsfError::Corruption(format!("invalid parity group layout: {:?}", e)))
+}
+
+// Parity shards keep xxh3 checksums whatever the archive's algorithm;
+// they are only a means of repair, not data of record
+fn parity_block(data: Vec<u8>) -> Block {
+    let checksum = xxh3_64(&data);
+    Block {
+        header: BlockHeader {
+            data_type: DataType::Binary,
+            original_size: data.len() as u64,
+            compressed_size: data.len() as u64,
+            compression_method: CompressionMethod::None,
+            checksum,
+            timestamp: Default::default(),
+            transforms: Vec::new(),
+            original_checksum: Some(checksum),
+            checksum_algorithm: BlockChecksum::Xxh3,
+            digest: None,
+        },
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use crate::UsfOptions;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_repair_rebuilds_damaged_blocks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("parity.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let values: Vec<Vec<u8>> = (0..6u8)
+            .map(|i| (0..3000u32).map(|j| (j * 7 + i as u32) as u8).collect())
+            .collect();
+        for (i, value) in values.iter().enumerate() {
+            storage.store(&format!("value-{}", i), value, DataType::Binary)?;
+        }
+        assert!(matches!(storage.add_parity(0, 1), Err(UsfError::Io(e)) if e.kind() == io::ErrorKind::InvalidInput));
+        assert_eq!(storage.add_parity(3, 1)?, 2);
+        // Everything is already covered
+        assert_eq!(storage.add_parity(3, 1)?, 0);
+        // Compaction moves the blocks and covers them again
+        storage.compact()?;
+        assert_eq!(storage.metadata.parity.len(), 2);
+        assert_eq!(storage.add_parity(3, 1)?, 0);
+        storage.store("unprotected", b"no parity here", DataType::Text)?;
+
+        let targets: Vec<BlockLocation> = ["value-1", "value-4", "unprotected"].iter()
+            .map(|key| storage.metadata.index[*key].blocks[0].clone())
+            .collect();
+        drop(storage);
+
+        // Flip the last data byte of each target block
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        for location in &targets {
+            let at = location.offset + location.disk_size() - 1;
+            let mut byte = [0u8];
+            file.seek(SeekFrom::Start(at))?;
+            file.read_exact(&mut byte)?;
+            file.seek(SeekFrom::Start(at))?;
+            file.write_all(&[byte[0] ^ 0xff])?;
+        }
+        drop(file);
+
+        // Repair writes in place, so a handle from `open` refuses it
+        assert!(matches!(UniversalStorage::open(&path)?.repair(), Err(UsfError::ReadOnly)));
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        let report = storage.repair()?;
+        assert_eq!(report.repaired, vec![targets[0].offset, targets[1].offset]);
+        assert_eq!(report.unrecoverable, vec![targets[2].offset]);
+        assert!(storage.retrieve("unprotected").is_err());
+
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(&storage.retrieve(&format!("value-{}", i))?, value);
+        }
+        assert_eq!(storage.repair()?.repaired, Vec::<u64>::new());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Serialize, Deserialize};
+use crate::limits::check_value_size;
+use crate::{DataType, EntryOptions, Result, UniversalStorage, UsfError};
+
+/// Expected lifetime and access temperature of a value. Compaction lays
+/// values out grouped by hint: hot values first, then unhinted ones, then
+/// short-lived ones, so deleting them frees one contiguous run, and cold
+/// values last.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hint {
+    Hot,
+    Cold,
+    ShortLived,
+}
+
+impl UniversalStorage {
+    /// Like [`UniversalStorage::store`], recording a placement hint for
+    /// compaction.
+    pub fn store_with_hint(&mut self, key: &str, data: &[u8], data_type: DataType, hint: Hint) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        check_value_size(&key, data.len() as u64, self.metadata.max_value_size)?;
+        let encoding = self.encoding_for(&key, &data_type);
+        let blocks = Self::prepare_blocks(data, data_type.clone(), &encoding)?;
+        self.write_entry(key, blocks, data_type, EntryOptions { hint: Some(hint), ..EntryOptions::default() })
+    }
+
+    pub fn placement_hint(&self, key: &str) -> Result<Option<Hint>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.metadata.index.get(&key)
+            .map(|entry| entry.hint)
+            .ok_or(UsfError::KeyNotFound(key))
+    }
+}
+
+// Order in which compaction lays out values with each hint
+pub(crate) fn placement_rank(hint: Option<Hint>) -> u8 {
+    match hint {
+        Some(Hint::Hot) => 0,
+        None => 1,
+        Some(Hint::ShortLived) => 2,
+        Some(Hint::Cold) => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compaction_groups_by_hint() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("placement.usf"))?;
+        let hints = [Some(Hint::Cold), Some(Hint::Hot), None, Some(Hint::ShortLived)];
+        for i in 0..12 {
+            let key = format!("key-{:02}", i);
+            match hints[i % hints.len()] {
+                Some(hint) => storage.store_with_hint(&key, format!("value {}", i).as_bytes(), DataType::Text, hint)?,
+                None => storage.store(&key, format!("value {}", i).as_bytes(), DataType::Text)?,
+            }
+        }
+        storage.compact()?;
+
+        let mut placed: Vec<(u64, Option<Hint>)> = storage.metadata.index.values()
+            .map(|entry| (entry.blocks[0].offset, entry.hint))
+            .collect();
+        placed.sort_by_key(|(offset, _)| *offset);
+        let ranks: Vec<u8> = placed.iter().map(|(_, hint)| placement_rank(*hint)).collect();
+        assert!(ranks.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", ranks);
+        assert_eq!(storage.placement_hint("key-01")?, Some(Hint::Hot));
+        assert_eq!(storage.retrieve("key-03")?, b"value 3");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Positioned file I/O and path handling that differ between platforms.
+//!
+//! Positioned reads do not depend on a shared cursor, so block reads never
+//! race with one another over the file position. Unix has `pread`, while
+//! Windows' `seek_read` may return short reads and moves the cursor, so
+//! callers must seek before any cursor-based I/O.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Elsewhere fall back to the cursor; callers already seek before
+// cursor-based I/O
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn write_all_at(mut file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+/// The path to hand to the OS when opening an archive. On Windows,
+/// absolute paths are given the `\\?\` prefix so they may exceed
+/// `MAX_PATH`; elsewhere the path is returned unchanged.
+pub(crate) fn native_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use std::path::{Component, Prefix};
+        let absolute = match path.is_absolute() {
+            true => path.to_path_buf(),
+            false => match std::env::current_dir() {
+                Ok(dir) => dir.join(path),
+                Err(_) => return path.to_path_buf(),
+            },
+        };
+        // Verbatim paths skip normalization, so `.` and `..` must go first
+        let mut normalized = PathBuf::new();
+        for component in absolute.components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir => { normalized.pop(); },
+                other => normalized.push(other),
+            }
+        }
+        let verbatim = match normalized.components().next() {
+            Some(Component::Prefix(prefix)) => match prefix.kind() {
+                Prefix::Verbatim(_) | Prefix::VerbatimUNC(..) | Prefix::VerbatimDisk(_) | Prefix::DeviceNS(_) => return normalized,
+                Prefix::UNC(..) => {
+                    let rest = normalized.to_string_lossy().trim_start_matches('\\').to_string();
+                    format!(r"\\?\UNC\{}", rest)
+                },
+                Prefix::Disk(_) => format!(r"\\?\{}", normalized.display()),
+            },
+            _ => return normalized,
+        };
+        PathBuf::from(verbatim)
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_positioned_io() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+            .open(native_path(&dir.path().join("positioned.bin")))?;
+        write_all_at(&file, b"hello world", 0)?;
+        write_all_at(&file, b"W", 6)?;
+
+        let mut buf = [0u8; 5];
+        read_exact_at(&file, &mut buf, 6)?;
+        assert_eq!(&buf, b"World");
+        assert_eq!(read_exact_at(&file, &mut buf, 8).map_err(|e| e.kind()), Err(io::ErrorKind::UnexpectedEof));
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_long_paths() -> io::Result<()> {
+        use crate::{DataType, UniversalStorage};
+
+        let dir = tempdir()?;
+        let mut path = dir.path().to_path_buf();
+        for i in 0..12 {
+            path.push(format!("a-fairly-long-directory-name-{:02}", i));
+        }
+        std::fs::create_dir_all(native_path(&path))?;
+        let path = path.join("archive.usf");
+        assert!(path.as_os_str().len() > 260);
+
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("key", b"value", DataType::Text)?;
+        drop(storage);
+        assert_eq!(UniversalStorage::open(&path)?.retrieve("key")?, b"value");
+        assert!(native_path(&path).to_string_lossy().starts_with(r"\\?\"));
+
+        Ok(())
+    }
+}
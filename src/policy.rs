@@ -0,0 +1,88 @@
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+use crate::error::{Result, UsfError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyCharset {
+    /// Any UTF-8 string
+    #[default]
+    Any,
+    /// Any UTF-8 string without control characters
+    Printable,
+    /// Printable ASCII only
+    Ascii,
+    /// ASCII letters, digits and `.`, `_`, `-`, `/`
+    PathSafe,
+}
+
+impl KeyCharset {
+    fn allows(&self, c: char) -> bool {
+        match self {
+            KeyCharset::Any => true,
+            KeyCharset::Printable => !c.is_control(),
+            KeyCharset::Ascii => c.is_ascii_graphic() || c == ' ',
+            KeyCharset::PathSafe => c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'),
+        }
+    }
+}
+
+/// Key length cap of new archives' default policy. Every key is written
+/// into each commit, so one huge key bloats every metadata write after it.
+pub const MAX_KEY_LENGTH: usize = 64 * 1024;
+
+/// Rules applied to every key before it reaches the index. The policy is
+/// persisted in the archive metadata so every writer canonicalizes keys the
+/// same way.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyPolicy {
+    /// Maximum key length in bytes, measured after normalization; longer
+    /// keys fail with [`UsfError::KeyTooLong`]. Defaults to
+    /// [`MAX_KEY_LENGTH`]; archives written before the default keep none.
+    pub max_length: Option<usize>,
+    pub charset: KeyCharset,
+    /// Normalize keys to Unicode NFC
+    pub normalize_nfc: bool,
+    /// Fold keys to lowercase so lookups ignore case
+    pub case_insensitive: bool,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_length: Some(MAX_KEY_LENGTH),
+            charset: KeyCharset::default(),
+            normalize_nfc: false,
+            case_insensitive: false,
+        }
+    }
+}
+
+impl KeyPolicy {
+    /// Returns the canonical form of `key`, or an error if it violates the policy.
+    pub fn canonicalize(&self, key: &str) -> Result<String> {
+        let mut canonical: String = if self.normalize_nfc {
+            key.nfc().collect()
+        } else {
+            key.to_string()
+        };
+
+        if self.case_insensitive {
+            canonical = canonical.to_lowercase();
+        }
+
+        if let Some(limit) = self.max_length {
+            if canonical.len() > limit {
+                return Err(UsfError::KeyTooLong { length: canonical.len(), limit });
+            }
+        }
+
+        if let Some(c) = canonical.chars().find(|c| !self.charset.allows(*c)) {
+            return Err(UsfError::InvalidKey {
+                key: key.to_string(),
+                reason: format!("character {:?} not allowed by {:?} charset", c, self.charset),
+            });
+        }
+
+        Ok(canonical)
+    }
+}
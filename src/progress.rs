@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use crate::UniversalStorage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgressPhase {
+    Store,
+    Retrieve,
+    Verify,
+    Compact,
+    Export,
+    Import,
+    Migrate,
+}
+
+/// Receives progress for long-running operations. `total_bytes` is 0 when
+/// the total is not known up front, e.g. when ingesting from a reader.
+pub trait ProgressSink: Send + Sync {
+    fn on_progress(&self, done_bytes: u64, total_bytes: u64, phase: ProgressPhase);
+}
+
+impl UniversalStorage {
+    pub fn set_progress_sink(&mut self, sink: Option<Arc<dyn ProgressSink>>) {
+        self.progress = sink;
+    }
+
+    pub(crate) fn report_progress(&self, done_bytes: u64, total_bytes: u64, phase: ProgressPhase) {
+        if let Some(sink) = &self.progress {
+            sink.on_progress(done_bytes, total_bytes, phase);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, BLOCK_SIZE};
+    use std::io;
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<(u64, u64, ProgressPhase)>>,
+    }
+
+    impl ProgressSink for Recorder {
+        fn on_progress(&self, done_bytes: u64, total_bytes: u64, phase: ProgressPhase) {
+            self.events.lock().unwrap().push((done_bytes, total_bytes, phase));
+        }
+    }
+
+    #[test]
+    fn test_progress_reported_per_block() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("progress.usf"))?;
+        let recorder = Arc::new(Recorder::default());
+        storage.set_progress_sink(Some(recorder.clone()));
+
+        let data = vec![0u8; BLOCK_SIZE * 2 + 10];
+        storage.store("big", &data, DataType::Binary)?;
+        storage.retrieve("big")?;
+        storage.compact()?;
+
+        let events = recorder.events.lock().unwrap();
+        let of = |phase| events.iter().filter(|e| e.2 == phase).cloned().collect::<Vec<_>>();
+        let total = data.len() as u64;
+        assert_eq!(of(ProgressPhase::Store).last(), Some(&(total, total, ProgressPhase::Store)));
+        assert_eq!(of(ProgressPhase::Store).len(), 3);
+        assert_eq!(of(ProgressPhase::Retrieve).last(), Some(&(total, total, ProgressPhase::Retrieve)));
+        let compact = of(ProgressPhase::Compact);
+        assert_eq!(compact.len(), 3);
+        assert_eq!(compact.last().unwrap().0, compact.last().unwrap().1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::Range;
+use std::time::Instant;
+use crate::{clamp, BlockLocation, DataType, IndexEntry, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Retrieves up to `len` bytes of the value stored under `key`,
+    /// starting `offset` bytes in. Only the blocks covering the range are
+    /// read and decompressed. The range is cut short at the end of the
+    /// value, so a range starting past it yields no bytes.
+    pub fn retrieve_range(&mut self, key: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+
+        // The stored payload of a reference or a text delta log says
+        // nothing about where the value's bytes lie
+        if entry.data_type == DataType::Reference || entry.text_deltas.is_some() {
+            let value = self.retrieve(&key)?;
+            let start = (offset as usize).min(value.len());
+            let end = start.saturating_add(len as usize).min(value.len());
+            return Ok(value[start..end].to_vec());
+        }
+
+        let end = offset.saturating_add(len).min(entry.size);
+        let mut result = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        if offset < end {
+            let permits = self.admit([key.as_str()]);
+            for (location, range) in self.covering_blocks(&entry, offset, end)? {
+                let data = self.load_block(&location)?;
+                let piece = &data[clamp(&range, data.len())];
+                permits.charge(&key, piece.len() as u64);
+                result.extend_from_slice(piece);
+            }
+        }
+
+        if self.access_tracking {
+            self.record_access(&key);
+        }
+        self.record_retrieval(&key, result.len() as u64, started.elapsed());
+        Ok(result)
+    }
+
+    // The blocks holding bytes `start..end` of a value, each with the part
+    // of its decompressed data that falls in the range
+    fn covering_blocks(&self, entry: &IndexEntry, start: u64, end: u64) -> Result<Vec<(BlockLocation, Range<usize>)>> {
+        if !entry.ragged {
+            // Every block but the last holds exactly block_size bytes
+            let base = entry.solid_offset.unwrap_or(0);
+            let (start, end) = (base + start, base + end);
+            let block_size = entry.block_size;
+            let (first, last) = (start / block_size, (end - 1) / block_size);
+            return Ok((first..=last)
+                .filter_map(|i| {
+                    let from = if i == first { start % block_size } else { 0 };
+                    let to = if i == last { (end - 1) % block_size + 1 } else { block_size };
+                    let location = entry.blocks.get(i as usize)?;
+                    Some((location.clone(), from as usize..to as usize))
+                })
+                .collect());
+        }
+
+        let mut covering = Vec::new();
+        let mut position = 0;
+        for location in &entry.blocks {
+            let size = self.read_header(location)?.original_size;
+            if position + size > start {
+                let from = start.saturating_sub(position);
+                let to = (end - position).min(size);
+                covering.push((location.clone(), from as usize..to as usize));
+            }
+            position += size;
+            if position >= end {
+                break;
+            }
+        }
+        Ok(covering)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BLOCK_SIZE;
+    use std::fs::OpenOptions;
+    use std::io::{self, Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_retrieve_range_reads_only_covering_blocks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("range.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let value: Vec<u8> = (0..BLOCK_SIZE * 3 + 500).map(|i| (i % 251) as u8).collect();
+        storage.store("big", &value, DataType::Binary)?;
+        storage.add_solid_prefix("s/")?;
+        storage.store_many(&[("s/a", b"first member"), ("s/b", b"second member")], DataType::Text)?;
+        storage.store("log", b"line one\n", DataType::Text)?;
+        storage.append("log", b"line two\n")?;
+        storage.append("log", b"line three\n")?;
+
+        // Damage the first block: ranges past it still read
+        let first = storage.metadata.index["big"].blocks[0].clone();
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(first.offset + first.disk_size() - 1))?;
+        file.write_all(b"!")?;
+
+        let start = BLOCK_SIZE as u64 * 2 - 10;
+        assert_eq!(storage.retrieve_range("big", start, 20)?, &value[start as usize..start as usize + 20]);
+        assert_eq!(storage.retrieve_range("big", value.len() as u64 - 5, 100)?, &value[value.len() - 5..]);
+        assert!(storage.retrieve_range("big", value.len() as u64 + 1, 10)?.is_empty());
+        assert!(matches!(storage.retrieve_range("big", 0, 10), Err(UsfError::Corruption(_))));
+
+        assert_eq!(storage.retrieve_range("s/b", 7, 6)?, b"member");
+        assert_eq!(storage.retrieve_range("log", 5, 12)?, b"one\nline two");
+        assert!(matches!(storage.retrieve_range("missing", 0, 1), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use crate::{format, platform, recovery, Access, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Opens an existing archive for reading alone, under a shared lock.
+    /// Any number of read-only handles, in any process, can share an
+    /// archive; handles opened for writing through [`crate::UsfOptions`]
+    /// take an exclusive lock, so neither kind opens while the other is
+    /// held, and the open fails with [`UsfError::Locked`]. The locks are
+    /// advisory and [`UniversalStorage::open`] takes none.
+    ///
+    /// Unlike [`UniversalStorage::open`], this never writes: a pending
+    /// write-ahead journal is left for the next writable open, and every
+    /// method that would change the archive, trailing data included, fails
+    /// with [`UsfError::ReadOnly`] and leaves the handle as it was.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_inner(path.as_ref(), None, Access::ReadOnly)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        match self.read_only {
+            true => Err(UsfError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    // For changes made in place, which must not quietly turn a handle from
+    // `open` into a writer
+    pub(crate) fn check_opened_for_writing(&self) -> Result<()> {
+        match self.writable {
+            true => Ok(()),
+            false => Err(UsfError::ReadOnly),
+        }
+    }
+
+    // Puts back the committed metadata after a refused change, on a
+    // read-only handle or one that overflowed, so the handle keeps showing
+    // the archive as it is
+    pub(crate) fn discard_uncommitted(&mut self) -> Result<()> {
+        let superblock = format::read_superblock(&mut self.file)?;
+        let (_, metadata, _) = recovery::read_commit(&mut self.file, superblock, self.limits.as_ref())?;
+        self.metadata = metadata;
+        Ok(())
+    }
+
+    // Reopens the archive for writing, under an exclusive lock, as handles
+    // from `open` are read-only and compaction swaps in a new file. The new
+    // handle is locked before the old one is dropped, so on failure the
+    // handle keeps whatever lock it held. Locks held through different
+    // handles exclude each other, even within a process, so a writer must
+    // not reopen the file it already holds locked.
+    pub(crate) fn reopen_for_writing(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let file = OpenOptions::new().read(true).write(true).open(platform::native_path(&self.path))?;
+        lock(&file, Access::Write)?;
+        self.file = file;
+        self.writable = true;
+        Ok(())
+    }
+}
+
+// Takes the advisory lock for `access`: shared for read-only handles,
+// exclusive for writable ones, none for `open`
+pub(crate) fn lock(file: &File, access: Access) -> Result<()> {
+    let locked = match access {
+        Access::Read => return Ok(()),
+        Access::ReadOnly => file.try_lock_shared(),
+        Access::Write => file.try_lock(),
+    };
+    match locked {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(UsfError::Locked),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, TrailingDataAction, UsfOptions};
+    use std::io::{self, Write};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_only_handles() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shared.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.store("a", b"first", DataType::Text)?;
+        storage.set_write_ahead_log(true)?;
+
+        // Writers and read-only handles exclude each other
+        assert!(matches!(UniversalStorage::open_read_only(&path), Err(UsfError::Locked)));
+        drop(storage);
+        let mut first = UniversalStorage::open_read_only(&path)?;
+        let mut second = UniversalStorage::open_read_only(&path)?;
+        assert!(first.is_read_only());
+        assert!(matches!(UsfOptions::new().write(true).open(&path), Err(UsfError::Locked)));
+        assert_eq!(second.retrieve("a")?, b"first");
+
+        // Every change is refused, and leaves the handle as it was
+        let before = fs::read(&path)?;
+        assert!(matches!(first.store("b", b"second", DataType::Text), Err(UsfError::ReadOnly)));
+        assert!(matches!(first.delete("a"), Err(UsfError::ReadOnly)));
+        assert!(matches!(first.set_max_value_size(Some(1)), Err(UsfError::ReadOnly)));
+        assert!(matches!(first.compact(), Err(UsfError::ReadOnly)));
+        assert!(matches!(first.repair(), Err(UsfError::ReadOnly)));
+        assert_eq!(first.keys().collect::<Vec<_>>(), ["a"]);
+        assert_eq!(first.max_value_size(), None);
+        assert_eq!(first.retrieve("a")?, b"first");
+        assert_eq!(fs::read(&path)?, before);
+        assert_eq!(fs::read(first.journal_path())?, b"");
+        drop((first, second));
+
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        storage.store("b", b"second", DataType::Text)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_keeps_the_lock() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("reopen.usf");
+        let mut writer = UsfOptions::new().write(true).create(true).open(&path)?;
+        writer.store("a", b"first", DataType::Text)?;
+        fs::OpenOptions::new().append(true).open(&path)?.write_all(b"junk")?;
+
+        // A writer keeps its exclusive lock through changes that reopen
+        writer.discard_trailing_data(TrailingDataAction::Truncate)?;
+        assert!(matches!(UniversalStorage::open_read_only(&path), Err(UsfError::Locked)));
+        writer.compact()?;
+        assert!(matches!(UniversalStorage::open_read_only(&path), Err(UsfError::Locked)));
+        drop(writer);
+
+        // A handle that cannot take the lock is left as it was
+        let reader = UniversalStorage::open_read_only(&path)?;
+        let mut plain = UniversalStorage::open(&path)?;
+        assert!(matches!(plain.reopen_for_writing(), Err(UsfError::Locked)));
+        assert!(matches!(plain.repair(), Err(UsfError::ReadOnly)));
+        assert_eq!(plain.retrieve("a")?, b"first");
+        drop(reader);
+        plain.reopen_for_writing()?;
+        assert!(matches!(UniversalStorage::open_read_only(&path), Err(UsfError::Locked)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,534 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::path::Path;
+use chrono::Utc;
+use log::warn;
+use crate::format::{
+    self, BlockHeader, Superblock, BLOCK_HEADER_MAGIC, BLOCK_HEADER_PREFIX_SIZE, BLOCK_SIZE, COLD_TIER_BASE, MAGIC_BYTES, MAX_BLOCK_SIZE,
+    TRAILER_MAGIC_BYTES, TRAILER_SIZE, VERSION, VERSION_1, VERSION_2,
+};
+use crate::limits::check_limit;
+use crate::split::suffixed;
+use crate::{platform, readonly, Access, BlockLocation, EntryOptions, KeyPolicy, MetaData, ParseLimits, ReplayedJournal, Result, UniversalStorage, UsfError};
+
+/// Prefix of the keys [`UniversalStorage::recover`] gives the values it
+/// rebuilds from blocks, followed by the offset of the first block
+pub const RECOVERED_PREFIX: &str = "recovered/";
+
+/// Something found while opening an archive that did not stop it from
+/// opening. Listed by [`UniversalStorage::open_warnings`] and logged at
+/// warn level under the `usf::open` target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenWarning {
+    /// The file continues past everything its metadata accounts for
+    TrailingData(TrailingData),
+    /// The commit the header named was damaged, so the archive opened at
+    /// an earlier one
+    CommitRolledBack(RolledBackCommit),
+    /// A complete write-ahead journal was applied before opening
+    JournalReplayed(ReplayedJournal),
+}
+
+impl fmt::Display for OpenWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenWarning::TrailingData(trailing) => write!(
+                f,
+                "{} uncommitted bytes at offset {}: {} whole block(s), {} bytes of a partial block",
+                trailing.len, trailing.offset, trailing.complete_blocks, trailing.partial_bytes,
+            ),
+            OpenWarning::CommitRolledBack(rolled_back) => write!(
+                f,
+                "metadata commit at offset {} is damaged; opened the commit at offset {} (generation {}) instead",
+                rolled_back.damaged_offset, rolled_back.commit_offset, rolled_back.generation,
+            ),
+            OpenWarning::JournalReplayed(replayed) => write!(
+                f,
+                "replayed {} journaled write(s), {} bytes, left by an interrupted commit",
+                replayed.writes, replayed.bytes,
+            ),
+        }
+    }
+}
+
+/// Bytes at the end of the file that no committed metadata refers to,
+/// typically blocks written just before a crash and never committed.
+/// Another handle writing to the archive at the same time also leaves
+/// blocks here until it commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrailingData {
+    /// End of the last block or freed extent the metadata knows of
+    pub offset: u64,
+    pub len: u64,
+    /// Whole blocks with intact checksums, in file order from `offset`
+    pub complete_blocks: usize,
+    /// Bytes after them that do not form a whole block
+    pub partial_bytes: u64,
+}
+
+/// A damaged commit passed over on open. Changes committed after the
+/// commit opened are not visible; their blocks, and the damaged commit,
+/// show up as trailing data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RolledBackCommit {
+    pub damaged_offset: u64,
+    pub commit_offset: u64,
+    /// Generation of the metadata in the commit opened
+    pub generation: u64,
+}
+
+/// What [`UniversalStorage::recover`] rebuilt.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RecoveryReport {
+    /// Generation of the intact commit the index was rebuilt on, or `None`
+    /// if there was none and every value came from scanning blocks
+    pub generation: Option<u64>,
+    /// Keys given to the values rebuilt from blocks, in file order
+    pub recovered_keys: Vec<String>,
+    /// Whole blocks with intact checksums found by the scan
+    pub blocks: usize,
+    /// Bytes scanned that were neither whole blocks nor commits
+    pub skipped_bytes: u64,
+}
+
+/// What [`UniversalStorage::discard_trailing_data`] does with trailing
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingDataAction {
+    /// Cuts the file back to the committed data
+    #[default]
+    Truncate,
+    /// Copies the bytes to `<archive>.trailing` first, replacing any
+    /// earlier copy, then truncates
+    Quarantine,
+}
+
+impl UniversalStorage {
+    /// Warnings raised while this handle was opened.
+    pub fn open_warnings(&self) -> &[OpenWarning] {
+        &self.open_warnings
+    }
+
+    /// Looks for bytes past the committed end of the file, reading the
+    /// headers of any blocks found there.
+    pub fn trailing_data(&self) -> Result<Option<TrailingData>> {
+        let offset = self.committed_end();
+        let file_size = self.file.metadata()?.len();
+        if file_size <= offset {
+            return Ok(None);
+        }
+
+        let mut trailing = TrailingData { offset, len: file_size - offset, complete_blocks: 0, partial_bytes: 0 };
+        let mut position = offset;
+        while let Some((location, _)) = self.whole_block_at(position, file_size)? {
+            trailing.complete_blocks += 1;
+            position += location.disk_size();
+        }
+        trailing.partial_bytes = file_size - position;
+        Ok(Some(trailing))
+    }
+
+    /// Removes any trailing data (see [`UniversalStorage::trailing_data`])
+    /// so it cannot be mistaken for part of the archive, and returns what
+    /// was removed. Only call this while no other handle is writing.
+    pub fn discard_trailing_data(&mut self, action: TrailingDataAction) -> Result<Option<TrailingData>> {
+        self.check_writable()?;
+        let Some(trailing) = self.trailing_data()? else {
+            return Ok(None);
+        };
+        if action == TrailingDataAction::Quarantine {
+            let mut bytes = vec![0u8; trailing.len as usize];
+            self.read_at(&mut bytes, trailing.offset)?;
+            fs::write(platform::native_path(&suffixed(&self.path, ".trailing")), bytes)?;
+        }
+
+        if !self.writable {
+            self.reopen_for_writing()?;
+        }
+        self.file.set_len(trailing.offset)?;
+        self.file.sync_all()?;
+        self.open_warnings.retain(|warning| !matches!(warning, OpenWarning::TrailingData(_)));
+        Ok(Some(trailing))
+    }
+
+    // Records and logs anything worth warning about in a freshly opened
+    // archive
+    pub(crate) fn check_on_open(&mut self) -> Result<()> {
+        for warning in &self.open_warnings {
+            warn!(target: "usf::open", "{}: {}", self.path.display(), warning);
+        }
+        if let Some(trailing) = self.trailing_data()? {
+            let warning = OpenWarning::TrailingData(trailing);
+            warn!(target: "usf::open", "{}: {}", self.path.display(), warning);
+            self.open_warnings.push(warning);
+        }
+        Ok(())
+    }
+
+    // End of the current commit, or of the last block or freed extent in
+    // the main file that the metadata refers to if one lies beyond it
+    fn committed_end(&self) -> u64 {
+        let blocks = self.metadata.index.values()
+            .map(|entry| entry.blocks.as_slice())
+            .chain(self.pinned_chains().map(|(_, locations)| locations))
+            .chain(self.metadata.parity.iter().map(|group| group.parity_blocks()))
+            .flatten()
+            .map(|loc| (loc.offset, loc.disk_size()));
+        let freed = self.metadata.freed.iter().map(|(&offset, &len)| (offset, len));
+        blocks.chain(freed)
+            .chain(self.commit)
+            .filter(|(offset, _)| *offset < COLD_TIER_BASE)
+            .map(|(offset, len)| offset + len)
+            .fold(self.data_offset(), u64::max)
+    }
+
+    /// Rebuilds the index of the archive at `path` as far as its contents
+    /// allow, for an archive that no longer opens or that lost values
+    /// written just before a crash. The latest intact commit, if any, is
+    /// kept, and the file past it is scanned for whole blocks with matching
+    /// checksums. Each run of blocks laid out like a value's chain becomes
+    /// a value under [`RECOVERED_PREFIX`]; block headers hold no keys, so
+    /// these need renaming by hand. With no intact commit the whole file is
+    /// scanned, superseded values included, and values compressed with a
+    /// trained dictionary cannot be read. The rebuilt index is committed,
+    /// with a repaired header, and the archive returned open for writing,
+    /// under the exclusive lock; while another handle holds a lock this
+    /// fails with [`UsfError::Locked`] and leaves the archive untouched.
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<(Self, RecoveryReport)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).write(true).open(platform::native_path(path))?;
+        readonly::lock(&file, Access::Write)?;
+        Self::replay_journal(path, Some(&file))?;
+        let mut header = [0u8; 5];
+        file.read_exact(&mut header)?;
+        let version = match header[4] {
+            version @ (VERSION | VERSION_2 | VERSION_1) => version,
+            _ => VERSION,
+        };
+
+        let committed = format::read_superblock(&mut file)
+            .and_then(|superblock| read_commit(&mut file, superblock, None))
+            .ok()
+            .filter(|(superblock, ..)| superblock.version == version);
+        let mut report = RecoveryReport { generation: committed.as_ref().map(|(_, metadata, _)| metadata.generation), ..RecoveryReport::default() };
+        let (commit, metadata) = match committed {
+            Some((superblock, metadata, _)) => ((version != VERSION_1).then(|| (superblock.commit_offset, superblock.commit_size())), metadata),
+            None => (None, MetaData::new(KeyPolicy::default(), Utc::now())),
+        };
+        let mut storage = Self::from_parts(file, path, metadata);
+        storage.writable = true;
+        storage.version = version;
+        storage.commit = commit;
+        storage.cold = storage.open_cold_tier(true).ok().flatten();
+        storage.dictionaries = storage.load_dictionaries().unwrap_or_default();
+
+        let end = storage.file.metadata()?.len();
+        let mut position = storage.committed_end();
+        let mut chains: Vec<Vec<(BlockLocation, BlockHeader)>> = Vec::new();
+        while position < end {
+            if let Some((location, header)) = storage.whole_block_at(position, end)? {
+                position += location.disk_size();
+                report.blocks += 1;
+                match chains.last_mut() {
+                    Some(chain) if storage.continues_chain(chain, &location, &header) => chain.push((location, header)),
+                    _ => chains.push(vec![(location, header)]),
+                }
+            } else if let Some(size) = storage.commit_at(position, end)? {
+                position += size;
+            } else {
+                position += 1;
+                report.skipped_bytes += 1;
+            }
+        }
+
+        for chain in chains {
+            let key = format!("{}{}", RECOVERED_PREFIX, chain[0].0.offset);
+            let (first, last) = (&chain[0].1, &chain[chain.len() - 1].1);
+            let block_size = match chain.len() {
+                1 => first.original_size.max(BLOCK_SIZE as u64),
+                _ => first.original_size,
+            };
+            let (data_type, stored_at) = (first.data_type.clone(), last.timestamp);
+            let size = chain.iter().map(|(_, header)| header.original_size).sum();
+            let locations = chain.into_iter().map(|(location, _)| location).collect();
+            let options = EntryOptions { block_size: Some(block_size), ..EntryOptions::default() };
+            storage.index_entry(key.clone(), locations, size, data_type, options);
+            if let Some(entry) = storage.metadata.index.get_mut(&key) {
+                entry.stored_at = stored_at;
+            }
+            report.recovered_keys.push(key);
+        }
+
+        // The header may be what was damaged
+        platform::write_all_at(&storage.file, MAGIC_BYTES, 0)?;
+        platform::write_all_at(&storage.file, &[version], MAGIC_BYTES.len() as u64)?;
+        storage.metadata.modified = storage.now();
+        storage.update_metadata()?;
+        storage.file.sync_all()?;
+        Ok((storage, report))
+    }
+
+    // Whether a block found right after `chain` by a recovery scan carries
+    // on the same value: only whole blocks, all of one size no smaller
+    // than the minimum block size, come before a chain's last
+    fn continues_chain(&self, chain: &[(BlockLocation, BlockHeader)], location: &BlockLocation, header: &BlockHeader) -> bool {
+        let first = &chain[0].1;
+        let (last_location, last) = &chain[chain.len() - 1];
+        last_location.offset + last_location.disk_size() == location.offset
+            && header.data_type == first.data_type
+            && first.original_size >= self.block_size_bounds().0
+            && last.original_size == first.original_size
+            && header.original_size <= first.original_size
+    }
+
+    // Size of a commit, from version 2 on, starting at `position` and
+    // ending with its trailer before `end`, intact or not
+    fn commit_at(&self, position: u64, end: u64) -> Result<Option<u64>> {
+        if self.version == VERSION_1 || end - position < 16 + TRAILER_SIZE {
+            return Ok(None);
+        }
+        let mut length = [0u8; 8];
+        self.read_at(&mut length, position)?;
+        let Some(size) = u64::from_le_bytes(length).checked_add(16 + TRAILER_SIZE).filter(|size| *size <= end - position) else {
+            return Ok(None);
+        };
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        self.read_at(&mut trailer, position + size - TRAILER_SIZE)?;
+        let names_itself = trailer[..8] == position.to_le_bytes() && &trailer[8..] == TRAILER_MAGIC_BYTES;
+        Ok(names_itself.then_some(size))
+    }
+
+    // Location and header of the block at `position` if a whole one with a
+    // matching checksum lies there before `end`. The scan tries every
+    // offset of a damaged region, so anything read is bounded before it is
+    // allocated: headers by the default parse limit, and data by the
+    // largest a block can compress to.
+    fn whole_block_at(&self, position: u64, end: u64) -> Result<Option<(BlockLocation, BlockHeader)>> {
+        let remaining = end - position;
+        if remaining < BLOCK_HEADER_PREFIX_SIZE + BLOCK_HEADER_MAGIC.len() as u64 {
+            return Ok(None);
+        }
+        let mut prefix = [0u8; 8];
+        self.read_at(&mut prefix, position)?;
+        let header_size = u32::from_le_bytes(prefix[..4].try_into().expect("4-byte prefix")) as u64;
+        if header_size == 0 || header_size > ParseLimits::default().max_header_size || BLOCK_HEADER_PREFIX_SIZE + header_size > remaining {
+            return Ok(None);
+        }
+        if self.version == VERSION && prefix[4..] != BLOCK_HEADER_MAGIC[..] {
+            return Ok(None);
+        }
+
+        let mut header_bytes = vec![0u8; header_size as usize];
+        self.read_at(&mut header_bytes, position + BLOCK_HEADER_PREFIX_SIZE)?;
+        let Ok(header) = BlockHeader::decode(&header_bytes, position) else {
+            return Ok(None);
+        };
+        let size = header.disk_size(header_size as u32);
+        if size > remaining || header.compressed_size > zstd::zstd_safe::compress_bound(MAX_BLOCK_SIZE) as u64 {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; header.compressed_size as usize];
+        self.read_at(&mut data, position + BLOCK_HEADER_PREFIX_SIZE + header_size)?;
+        let location = BlockLocation { offset: position, header_size: header_size as u32, data_size: header.compressed_size };
+        Ok(header.checksum_matches(&data).then_some((location, header)))
+    }
+}
+
+// Reads and decodes the metadata of the commit `superblock` names. If it
+// fails its checksum or does not decode, the latest intact commit before it
+// is read instead, and reported.
+pub(crate) fn read_commit(
+    file: &mut File,
+    superblock: Superblock,
+    limits: Option<&ParseLimits>,
+) -> Result<(Superblock, MetaData, Option<RolledBackCommit>)> {
+    let read = |file: &mut File, superblock: &Superblock| -> Result<MetaData> {
+        check_limit(limits, "metadata size", superblock.metadata_size, |l| l.max_metadata_size)?;
+        Ok(bincode::deserialize(&format::read_metadata(file, superblock)?)?)
+    };
+    let damaged = match read(file, &superblock) {
+        Ok(metadata) => return Ok((superblock, metadata, None)),
+        Err(e @ (UsfError::Corruption(_) | UsfError::Serialization(_))) if superblock.version != VERSION_1 => e,
+        Err(e) => return Err(e),
+    };
+
+    let mut before = superblock.commit_offset;
+    while let Some(previous) = format::find_commit_before(&mut *file, superblock.version, before)? {
+        match read(file, &previous) {
+            Ok(metadata) => {
+                let rolled_back = RolledBackCommit {
+                    damaged_offset: superblock.commit_offset,
+                    commit_offset: previous.commit_offset,
+                    generation: metadata.generation,
+                };
+                return Ok((previous, metadata, Some(rolled_back)));
+            },
+            Err(UsfError::Serialization(_)) => before = previous.commit_offset,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(damaged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{COMMIT_POINTER_SIZE, METADATA_OFFSET};
+    use crate::{DataType, UsfOptions};
+    use std::io::{self, Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trailing_data_is_reported_and_discarded() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("trailing.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("kept", b"committed", DataType::Text)?;
+        let mut pointer = [0u8; COMMIT_POINTER_SIZE as usize];
+        storage.read_at(&mut pointer, METADATA_OFFSET)?;
+        storage.store("lost", &[5u8; 300], DataType::Binary)?;
+        let block = storage.metadata.index["lost"].blocks[0].clone();
+        let committed = block.offset;
+        let mut bytes = vec![0u8; block.disk_size() as usize];
+        storage.read_at(&mut bytes, block.offset)?;
+        drop(storage);
+        assert!(UniversalStorage::open(&path)?.open_warnings().is_empty());
+
+        // Point back at the commit from before "lost" and drop the one
+        // after its block, then cut a further block short, as a crash
+        // mid-write would
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        platform::write_all_at(&file, &pointer, METADATA_OFFSET)?;
+        file.set_len(committed + bytes.len() as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&bytes[..bytes.len() / 2])?;
+        drop(file);
+
+        let mut storage = UniversalStorage::open(&path)?;
+        let expected = TrailingData {
+            offset: committed,
+            len: bytes.len() as u64 + bytes.len() as u64 / 2,
+            complete_blocks: 1,
+            partial_bytes: bytes.len() as u64 / 2,
+        };
+        assert_eq!(storage.open_warnings(), [OpenWarning::TrailingData(expected)]);
+
+        assert_eq!(storage.discard_trailing_data(TrailingDataAction::Quarantine)?, Some(expected));
+        assert_eq!(fs::metadata(dir.path().join("trailing.usf.trailing"))?.len(), expected.len);
+        assert_eq!(fs::metadata(&path)?.len(), committed);
+        assert!(storage.open_warnings().is_empty());
+        assert_eq!(storage.retrieve("kept")?, b"committed");
+        assert!(UniversalStorage::open(&path)?.open_warnings().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_rebuilds_index_from_blocks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("recover.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        let large: Vec<u8> = (0..BLOCK_SIZE * 2 + 300).map(|i| (i * 31 % 251) as u8).collect();
+        storage.store("kept", b"committed", DataType::Text)?;
+
+        // Blocks written without a commit, as by a crash mid-store
+        let write_uncommitted = |storage: &mut UniversalStorage, data: &[u8], data_type: DataType| -> Result<u64> {
+            let encoding = storage.encoding_for("uncommitted", &data_type);
+            let blocks = UniversalStorage::prepare_blocks(data, data_type, &encoding)?;
+            Ok(storage.write_blocks(&blocks)?.0[0].offset)
+        };
+        let large_offset = write_uncommitted(&mut storage, &large, DataType::Binary)?;
+        let small_offset = write_uncommitted(&mut storage, b"small", DataType::Text)?;
+        drop(storage);
+
+        // Not while a writer holds the lock
+        let writer = UsfOptions::new().write(true).open(&path)?;
+        let untouched = fs::read(&path)?;
+        assert!(matches!(UniversalStorage::recover(&path), Err(UsfError::Locked)));
+        assert_eq!(fs::read(&path)?, untouched);
+        drop(writer);
+
+        let (mut storage, report) = UniversalStorage::recover(&path)?;
+        assert_eq!(report.generation, Some(2));
+        assert_eq!((report.blocks, report.skipped_bytes), (4, 0));
+        let large_key = format!("{}{}", RECOVERED_PREFIX, large_offset);
+        let small_key = format!("{}{}", RECOVERED_PREFIX, small_offset);
+        assert_eq!(report.recovered_keys, [large_key.clone(), small_key.clone()]);
+        assert_eq!(storage.retrieve(&large_key)?, large);
+        assert_eq!(storage.retrieve(&small_key)?, b"small");
+        assert_eq!(storage.retrieve("kept")?, b"committed");
+        storage.rename(&small_key, "small")?;
+        drop(storage);
+        assert!(UniversalStorage::open(&path)?.open_warnings().is_empty());
+
+        // With the header and every commit gone, the blocks alone remain
+        let file = OpenOptions::new().write(true).open(&path)?;
+        platform::write_all_at(&file, b"XXXXX", 0)?;
+        let mut before = file.metadata()?.len();
+        while let Some(commit) = format::find_commit_before(File::open(&path)?, format::VERSION, before)? {
+            platform::write_all_at(&file, b"torn", commit.commit_offset + 12)?;
+            before = commit.commit_offset;
+        }
+        platform::write_all_at(&file, b"garbage", file.metadata()?.len())?;
+        assert!(UniversalStorage::open(&path).is_err());
+
+        let (mut storage, report) = UniversalStorage::recover(&path)?;
+        assert_eq!((report.generation, report.blocks, report.skipped_bytes), (None, 5, 7));
+        assert_eq!(report.recovered_keys.len(), 3);
+        assert_eq!(storage.retrieve(&large_key)?, large);
+        assert_eq!(storage.retrieve(&report.recovered_keys[0])?, b"committed");
+        drop(storage);
+        assert_eq!(UniversalStorage::open(&path)?.keys().count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_commit_rolls_back() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("rollback.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("kept", b"committed", DataType::Text)?;
+        let (intact, _) = storage.commit.expect("version 3 commit");
+        let generation = storage.metadata.generation;
+        storage.store("lost", b"never reached the disk", DataType::Text)?;
+        let (damaged, _) = storage.commit.expect("version 3 commit");
+        drop(storage);
+
+        // The pointer names a commit whose metadata was torn
+        let file = OpenOptions::new().write(true).open(&path)?;
+        platform::write_all_at(&file, b"torn", damaged + 12)?;
+        drop(file);
+
+        let mut storage = UniversalStorage::open(&path)?;
+        let expected = RolledBackCommit { damaged_offset: damaged, commit_offset: intact, generation };
+        assert_eq!(storage.open_warnings()[0], OpenWarning::CommitRolledBack(expected));
+        assert!(matches!(storage.open_warnings()[1], OpenWarning::TrailingData(_)));
+        assert_eq!(storage.retrieve("kept")?, b"committed");
+        assert!(!storage.contains_key("lost"));
+        drop(storage);
+
+        // The next commit supersedes the damaged one
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        storage.store("after", b"recommitted", DataType::Text)?;
+        assert_eq!(storage.metadata.generation, generation + 1);
+        drop(storage);
+        let storage = UniversalStorage::open(&path)?;
+        assert!(!storage.open_warnings().iter().any(|warning| matches!(warning, OpenWarning::CommitRolledBack(_))));
+        assert_eq!(storage.keys().collect::<Vec<_>>(), ["after", "kept"]);
+
+        // With no intact commit to fall back to, the damage is reported
+        drop(storage);
+        let file = OpenOptions::new().write(true).open(&path)?;
+        let mut before = file.metadata()?.len();
+        while let Some(commit) = format::find_commit_before(File::open(&path)?, format::VERSION, before)? {
+            platform::write_all_at(&file, b"torn", commit.commit_offset + 12)?;
+            before = commit.commit_offset;
+        }
+        assert!(matches!(UniversalStorage::open(&path), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+}
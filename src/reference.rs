@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::{DataType, Result, UniversalStorage, UsfError};
+
+/// A pointer to data held outside this archive, stored as the payload of a
+/// [`DataType::Reference`] entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Path or URL of the archive or object holding the data
+    pub location: String,
+    /// Key within `location`, if it is an archive
+    pub key: String,
+    /// xxh3-64 of the referenced content, checked after resolving
+    pub content_hash: Option<u64>,
+}
+
+impl Reference {
+    pub fn new(location: impl Into<String>, key: impl Into<String>) -> Self {
+        Self { location: location.into(), key: key.into(), content_hash: None }
+    }
+
+    /// Pins the reference to `data`, so resolving to anything else fails.
+    pub fn with_content(mut self, data: &[u8]) -> Self {
+        self.content_hash = Some(xxh3_64(data));
+        self
+    }
+
+    fn verify(&self, data: &[u8]) -> Result<()> {
+        match self.content_hash {
+            Some(hash) if hash != xxh3_64(data) => Err(UsfError::Corruption(format!(
+                "content of {:?} in {:?} does not match the reference hash", self.key, self.location
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Fetches the data a [`Reference`] points at.
+pub trait ReferenceResolver: Send + Sync {
+    fn resolve(&self, reference: &Reference) -> Result<Vec<u8>>;
+}
+
+impl<F> ReferenceResolver for F
+where
+    F: Fn(&Reference) -> Result<Vec<u8>> + Send + Sync,
+{
+    fn resolve(&self, reference: &Reference) -> Result<Vec<u8>> {
+        self(reference)
+    }
+}
+
+/// Resolves references whose location is the path of another USF archive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchiveResolver;
+
+impl ReferenceResolver for ArchiveResolver {
+    fn resolve(&self, reference: &Reference) -> Result<Vec<u8>> {
+        UniversalStorage::open(&reference.location)?.retrieve(&reference.key)
+    }
+}
+
+impl UniversalStorage {
+    /// Stores `reference` under `key`. Without a resolver, retrieving the
+    /// key returns the encoded reference; see
+    /// [`UniversalStorage::reference`].
+    pub fn store_reference(&mut self, key: &str, reference: &Reference) -> Result<()> {
+        self.store(key, &bincode::serialize(reference)?, DataType::Reference)
+    }
+
+    /// The reference stored under `key`, or `None` if it holds plain data.
+    pub fn reference(&mut self, key: &str) -> Result<Option<Reference>> {
+        if self.data_type_of(key)? != DataType::Reference {
+            return Ok(None);
+        }
+        let resolver = self.resolver.take();
+        let payload = self.retrieve(key);
+        self.resolver = resolver;
+        Ok(Some(bincode::deserialize(&payload?)?))
+    }
+
+    /// With a resolver set, retrieving a reference entry returns the data
+    /// it points at, checked against its content hash.
+    pub fn set_reference_resolver(&mut self, resolver: Option<Arc<dyn ReferenceResolver>>) {
+        self.resolver = resolver;
+    }
+
+    // Swaps a reference payload for the data it points at, if resolving
+    pub(crate) fn resolve_reference(&self, payload: Vec<u8>) -> Result<Vec<u8>> {
+        let Some(resolver) = &self.resolver else {
+            return Ok(payload);
+        };
+        let reference: Reference = bincode::deserialize(&payload)?;
+        let data = resolver.resolve(&reference)?;
+        reference.verify(&data)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_reference_across_archives() -> io::Result<()> {
+        let dir = tempdir()?;
+        let shard_path = dir.path().join("shard-0.usf");
+        let mut shard = UniversalStorage::create(&shard_path)?;
+        shard.store("images/0001", b"pixels", DataType::Binary)?;
+        drop(shard);
+
+        let mut manifest = UniversalStorage::create(dir.path().join("manifest.usf"))?;
+        let location = shard_path.to_string_lossy().into_owned();
+        let reference = Reference::new(location.clone(), "images/0001").with_content(b"pixels");
+        manifest.store_reference("train/0001", &reference)?;
+        manifest.store_reference("train/0002", &Reference::new(location, "images/0001").with_content(b"other"))?;
+
+        // Unresolved, the entry reads back as the reference itself
+        assert_eq!(manifest.reference("train/0001")?, Some(reference));
+        assert_ne!(manifest.retrieve("train/0001")?, b"pixels");
+
+        manifest.set_reference_resolver(Some(Arc::new(ArchiveResolver)));
+        assert_eq!(manifest.retrieve("train/0001")?, b"pixels");
+        assert!(matches!(manifest.retrieve("train/0002"), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{Seek, SeekFrom, Write};
+use crate::{BlockLocation, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Copies the blocks of `key` back to back to the end of the file and
+    /// points the index at the copies, defragmenting one value (such as
+    /// one grown by many appends) without compacting the whole archive.
+    /// Blocks are copied as stored, without recompressing, and blocks in
+    /// a cold tier move back to the main file. Keys sharing the blocks
+    /// through a link or solid group move with it.
+    ///
+    /// Blocks are never overwritten in place, so the copies always go at
+    /// the end and the old blocks become freed space for compaction to
+    /// reclaim. Returns the bytes copied.
+    pub fn relocate(&mut self, key: &str) -> Result<u64> {
+        self.check_writable()?;
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let old = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .blocks.clone();
+        let Some(first) = old.first().map(|loc| loc.offset) else {
+            return Ok(0);
+        };
+
+        let mut bytes = Vec::with_capacity(old.iter().map(|loc| loc.disk_size() as usize).sum());
+        for loc in &old {
+            let start = bytes.len();
+            bytes.resize(start + loc.disk_size() as usize, 0);
+            self.read_at(&mut bytes[start..], loc.offset)?;
+        }
+        let start = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&bytes)?;
+
+        let mut offset = start;
+        let moved: Vec<BlockLocation> = old.iter()
+            .map(|loc| {
+                let location = BlockLocation { offset, ..loc.clone() };
+                offset += loc.disk_size();
+                location
+            })
+            .collect();
+        let shares_chain = |blocks: &[BlockLocation]| blocks.first().is_some_and(|loc| loc.offset == first);
+        let entries = self.metadata.index.values_mut()
+            .chain(self.metadata.trash.values_mut().map(|trashed| &mut trashed.entry));
+        for entry in entries.filter(|entry| shares_chain(&entry.blocks)) {
+            entry.blocks = moved.clone();
+        }
+        if let Some(count) = self.metadata.chain_refs.remove(&first) {
+            self.metadata.chain_refs.insert(start, count);
+        }
+        self.metadata.total_blocks += moved.len() as u64;
+        self.record_freed(&old);
+
+        self.metadata.modified = self.now();
+        self.update_metadata()?;
+        Ok(bytes.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_relocate_makes_chain_contiguous() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("relocate.usf"))?;
+        storage.store("log", b"entry 0\n", DataType::Text)?;
+        let mut expected = b"entry 0\n".to_vec();
+        for i in 1..5 {
+            storage.store(&format!("other-{}", i), &[i as u8; 300], DataType::Binary)?;
+            let line = format!("entry {}\n", i);
+            storage.append("log", line.as_bytes())?;
+            expected.extend_from_slice(line.as_bytes());
+        }
+        storage.link("log", "log-alias")?;
+        let old_bytes: u64 = storage.metadata.index["log"].blocks.iter().map(|loc| loc.disk_size()).sum();
+
+        let copied = storage.relocate("log")?;
+        assert_eq!(copied, old_bytes);
+        let blocks = &storage.metadata.index["log"].blocks;
+        assert!(blocks.windows(2).all(|pair| pair[0].offset + pair[0].disk_size() == pair[1].offset));
+        assert_eq!(storage.metadata.index["log-alias"].blocks[0].offset, blocks[0].offset);
+        assert_eq!(storage.ref_count("log")?, 2);
+        assert_eq!(storage.freed_bytes(), old_bytes);
+
+        assert_eq!(storage.retrieve("log")?, expected);
+        assert_eq!(storage.retrieve("log-alias")?, expected);
+        assert_eq!(storage.retrieve("other-3")?, [3u8; 300]);
+        assert!(matches!(storage.relocate("missing"), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+}
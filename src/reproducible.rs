@@ -0,0 +1,60 @@
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use crate::{KeyPolicy, MetaData, Result, UniversalStorage};
+
+impl UniversalStorage {
+    /// Creates an archive whose bytes depend only on the operations applied
+    /// to it: every recorded timestamp is `timestamp` and the index is
+    /// serialized in key order, so replaying the same stores produces an
+    /// identical file.
+    ///
+    /// Age-based rules (trash retention, `max_age`) compare against the
+    /// recorded timestamps, so they see every entry as written at
+    /// `timestamp`.
+    pub fn create_reproducible<P: AsRef<Path>>(path: P, key_policy: KeyPolicy, timestamp: DateTime<Utc>) -> Result<Self> {
+        let mut metadata = MetaData::new(key_policy, timestamp);
+        metadata.fixed_time = Some(timestamp);
+        Self::initialize(path.as_ref(), metadata)
+    }
+
+    /// The timestamp recorded in place of the clock, for reproducible
+    /// archives.
+    pub fn fixed_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.metadata.fixed_time
+    }
+
+    // Time to record for new entries, blocks and metadata changes
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        self.metadata.fixed_time.unwrap_or_else(Utc::now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    fn build(path: &Path) -> Result<()> {
+        let epoch = DateTime::from_timestamp(0, 0).unwrap_or_default();
+        let mut storage = UniversalStorage::create_reproducible(path, KeyPolicy::default(), epoch)?;
+        for i in 0..20 {
+            storage.store(&format!("key-{}", i), format!("value {}", i).as_bytes(), DataType::Text)?;
+        }
+        storage.store("doc", "repeat ".repeat(500).as_bytes(), DataType::Text)?;
+        storage.link("doc", "doc-alias")?;
+        storage.delete("key-3")
+    }
+
+    #[test]
+    fn test_reproducible_builds_are_identical() -> io::Result<()> {
+        let dir = tempdir()?;
+        build(&dir.path().join("a.usf"))?;
+        build(&dir.path().join("b.usf"))?;
+
+        assert_eq!(fs::read(dir.path().join("a.usf"))?, fs::read(dir.path().join("b.usf"))?);
+
+        Ok(())
+    }
+}
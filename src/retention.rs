@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use crate::{ExpiryEvent, ExpiryReason, Result, UniversalStorage};
+
+/// Declarative pruning rule for keys under a prefix. Keys under the prefix
+/// are treated as versions of one logical value, newest store first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RetentionRule {
+    pub prefix: String,
+    /// Keep only the most recently stored `n` keys under the prefix
+    pub keep_last: Option<usize>,
+    /// Delete keys stored longer ago than this
+    pub max_age: Option<Duration>,
+}
+
+impl UniversalStorage {
+    pub fn add_retention_rule(&mut self, rule: RetentionRule) -> Result<()> {
+        self.metadata.retention_rules.push(rule);
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn retention_rules(&self) -> &[RetentionRule] {
+        &self.metadata.retention_rules
+    }
+
+    pub fn clear_retention_rules(&mut self) -> Result<()> {
+        self.metadata.retention_rules.clear();
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Deletes every key violating a retention rule, in a single commit,
+    /// and returns the deleted keys in order. Ages are measured against the
+    /// archive's clock, so reproducible archives expire nothing by age.
+    /// Deletes go through the trash when one is configured, and each one is
+    /// reported to the expiry listener once committed.
+    pub fn apply_retention(&mut self) -> Result<Vec<String>> {
+        let now = self.now();
+        let mut doomed = BTreeMap::new();
+
+        for rule in &self.metadata.retention_rules {
+            let mut matching: Vec<_> = self.entries_with_prefix(&rule.prefix)
+                .map(|(key, entry)| (key, entry.stored_at))
+                .collect();
+            // Newest first, ties broken by key for a stable result
+            matching.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(a.0)));
+
+            for (position, (key, stored_at)) in matching.into_iter().enumerate() {
+                let over_count = rule.keep_last.is_some_and(|n| position >= n);
+                let too_old = rule.max_age.is_some_and(|age| {
+                    (now - stored_at).to_std().is_ok_and(|elapsed| elapsed > age)
+                });
+                let reason = match (too_old, over_count) {
+                    (true, _) => ExpiryReason::MaxAge,
+                    (false, true) => ExpiryReason::KeepLast,
+                    (false, false) => continue,
+                };
+                // An age-based expiry wins over a count-based one
+                let event = doomed.entry(key.clone())
+                    .or_insert(ExpiryEvent { key: key.clone(), reason, stored_at });
+                if reason == ExpiryReason::MaxAge {
+                    event.reason = reason;
+                }
+            }
+        }
+
+        if doomed.is_empty() {
+            return Ok(Vec::new());
+        }
+        for key in doomed.keys() {
+            self.remove_entry(key.clone())?;
+        }
+        self.metadata.modified = now;
+        self.update_metadata()?;
+        for event in doomed.values() {
+            self.notify_expired(event);
+        }
+        Ok(doomed.into_keys().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, KeyPolicy};
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_keep_last_versions() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("retention.usf"))?;
+        for version in 1..=4 {
+            storage.store(&format!("builds/v{}", version), b"artifact", DataType::Binary)?;
+        }
+        storage.store("other/v1", b"untouched", DataType::Binary)?;
+
+        storage.add_retention_rule(RetentionRule {
+            prefix: "builds/".to_string(),
+            keep_last: Some(2),
+            ..RetentionRule::default()
+        })?;
+
+        let report = storage.compact()?;
+        assert_eq!(report.retention_deleted, vec!["builds/v1".to_string(), "builds/v2".to_string()]);
+        assert!(storage.retrieve("builds/v3").is_ok());
+        assert!(storage.retrieve("builds/v4").is_ok());
+        assert!(storage.retrieve("other/v1").is_ok());
+
+        // Rules persist and find nothing further to prune
+        assert!(storage.apply_retention()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_age() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("age.usf"))?;
+        storage.store("logs/old", b"old", DataType::Text)?;
+        storage.add_retention_rule(RetentionRule {
+            prefix: "logs/".to_string(),
+            max_age: Some(Duration::ZERO),
+            ..RetentionRule::default()
+        })?;
+
+        let (events, expired) = std::sync::mpsc::channel();
+        storage.set_expiry_listener(Some(std::sync::Arc::new(events)));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.apply_retention()?, vec!["logs/old".to_string()]);
+
+        let event = expired.try_recv().expect("expiry reported");
+        assert_eq!(event.key, "logs/old");
+        assert_eq!(event.reason, ExpiryReason::MaxAge);
+        assert!(expired.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention_uses_the_archive_clock() -> io::Result<()> {
+        let dir = tempdir()?;
+        let epoch = chrono::DateTime::from_timestamp(0, 0).unwrap_or_default();
+        let mut storage = UniversalStorage::create_reproducible(dir.path().join("clock.usf"), KeyPolicy::default(), epoch)?;
+        for i in 0..3 {
+            storage.store(&format!("logs/{}", i), b"entry", DataType::Text)?;
+        }
+        storage.add_retention_rule(RetentionRule {
+            prefix: "logs/".to_string(),
+            max_age: Some(Duration::from_secs(1)),
+            ..RetentionRule::default()
+        })?;
+        // Nothing ages while the clock stands still
+        assert!(storage.apply_retention()?.is_empty());
+
+        storage.add_retention_rule(RetentionRule {
+            prefix: "logs/".to_string(),
+            keep_last: Some(1),
+            ..RetentionRule::default()
+        })?;
+        let generation = storage.metadata.generation;
+        assert_eq!(storage.apply_retention()?, ["logs/0", "logs/1"]);
+        assert_eq!(storage.metadata.generation, generation + 1);
+        assert_eq!(storage.keys().collect::<Vec<_>>(), ["logs/2"]);
+
+        Ok(())
+    }
+}
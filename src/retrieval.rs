@@ -0,0 +1,188 @@
+use std::time::Instant;
+use crate::limits::check_limit;
+use crate::listing::BlockInfo;
+use crate::{clamp, textdelta, DataType, Result, UniversalStorage, UsfError};
+
+/// How much of a value [`UniversalStorage::retrieve_with`] reads and
+/// returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetrieveMode {
+    /// The decompressed value, as [`UniversalStorage::retrieve`] returns it
+    #[default]
+    Normal,
+    /// Block headers only; no block data is read
+    HeaderOnly,
+    /// Each block's stored bytes as they are on disk, still compressed and
+    /// transformed, for copying blocks without decoding them
+    Raw,
+    /// Reads every block and checks it against its checksum, whatever the
+    /// [`ChecksumPolicy`](crate::ChecksumPolicy), without decompressing or
+    /// returning anything
+    VerifyOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RetrieveOptions {
+    pub mode: RetrieveMode,
+}
+
+impl RetrieveOptions {
+    pub fn new(mode: RetrieveMode) -> Self {
+        Self { mode }
+    }
+}
+
+/// What [`UniversalStorage::retrieve_with`] found. Values packed into a
+/// solid group report, and in raw mode return, the group's blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retrieved {
+    pub key: String,
+    pub data_type: DataType,
+    /// Length of the value
+    pub size: u64,
+    /// Headers of the blocks holding the value, in order
+    pub blocks: Vec<BlockInfo>,
+    /// The value, in [`RetrieveMode::Normal`]
+    pub value: Option<Vec<u8>>,
+    /// Stored bytes of each block in `blocks`, in [`RetrieveMode::Raw`]
+    pub raw: Vec<Vec<u8>>,
+}
+
+impl UniversalStorage {
+    /// Reads `key` as far as `options` asks. Checksum failures are
+    /// returned as [`UsfError::Corruption`] in every mode that reads
+    /// block data.
+    pub fn retrieve_with(&mut self, key: &str, options: RetrieveOptions) -> Result<Retrieved> {
+        let started = Instant::now();
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+        if options.mode == RetrieveMode::Normal {
+            check_limit(self.limits.as_ref(), "entry size", entry.size, |l| l.max_entry_size)?;
+        }
+        let mut retrieved = Retrieved {
+            key,
+            data_type: entry.data_type.clone(),
+            size: entry.size,
+            blocks: Vec::with_capacity(entry.blocks.len()),
+            value: None,
+            raw: Vec::new(),
+        };
+
+        if options.mode == RetrieveMode::HeaderOnly {
+            for loc in &entry.blocks {
+                retrieved.blocks.push(BlockInfo::new(loc.offset, self.read_header(loc)?));
+            }
+            return Ok(retrieved);
+        }
+
+        let permits = self.admit([retrieved.key.as_str()]);
+        let mut value = Vec::new();
+        for (loc, range) in entry.block_ranges() {
+            let block = self.read_block(&loc)?;
+            retrieved.blocks.push(BlockInfo::new(loc.offset, block.header.clone()));
+            match options.mode {
+                RetrieveMode::Normal => {
+                    let data = self.unpack_block(&loc, block)?;
+                    let piece = &data[clamp(&range, data.len())];
+                    permits.charge(&retrieved.key, piece.len() as u64);
+                    value.extend_from_slice(piece);
+                },
+                RetrieveMode::Raw => {
+                    self.check_block(&loc, &block)?;
+                    permits.charge(&retrieved.key, block.data.len() as u64);
+                    retrieved.raw.push(block.data);
+                },
+                RetrieveMode::VerifyOnly => {
+                    if !block.header.checksum_matches(&block.data) {
+                        return Err(UsfError::Corruption(format!("checksum mismatch in block at offset {}", loc.offset)));
+                    }
+                },
+                RetrieveMode::HeaderOnly => unreachable!("handled above"),
+            }
+        }
+
+        if options.mode == RetrieveMode::Normal {
+            if entry.text_deltas.is_some() {
+                value = textdelta::replay(&value)?;
+            }
+            if entry.data_type == DataType::Reference {
+                value = self.resolve_reference(value)?;
+                retrieved.size = value.len() as u64;
+            }
+            if self.access_tracking {
+                self.record_access(&retrieved.key);
+            }
+            self.record_retrieval(&retrieved.key, value.len() as u64, started.elapsed());
+            retrieved.value = Some(value);
+        }
+        Ok(retrieved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{CompressionMethod, BLOCK_SIZE};
+    use crate::platform;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_retrieve_with_modes() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("modes.usf"))?;
+        let text = "mirror me\n".repeat(BLOCK_SIZE / 8);
+        storage.store("doc", text.as_bytes(), DataType::Text)?;
+
+        let normal = storage.retrieve_with("doc", RetrieveOptions::default())?;
+        assert_eq!(normal.value.as_deref(), Some(text.as_bytes()));
+        assert_eq!(normal.blocks.len(), 2);
+
+        let headers = storage.retrieve_with("doc", RetrieveOptions::new(RetrieveMode::HeaderOnly))?;
+        assert_eq!((headers.value, headers.raw.len()), (None, 0));
+        assert_eq!(headers.blocks, normal.blocks);
+
+        // Raw blocks decode to the value without the archive's help
+        let raw = storage.retrieve_with("doc", RetrieveOptions::new(RetrieveMode::Raw))?;
+        assert_eq!(raw.blocks[0].compression_method, CompressionMethod::Zstd);
+        let decoded: Vec<u8> = raw.raw.iter()
+            .map(|data| zstd::decode_all(data.as_slice()))
+            .collect::<io::Result<Vec<_>>>()?
+            .concat();
+        assert_eq!(decoded, text.as_bytes());
+
+        let verified = storage.retrieve_with("doc", RetrieveOptions::new(RetrieveMode::VerifyOnly))?;
+        assert_eq!((verified.value, verified.raw.len()), (None, 0));
+
+        let location = storage.metadata.index["doc"].blocks[1].clone();
+        platform::write_all_at(&storage.file, b"!", location.offset + location.disk_size() - 1)?;
+        let damaged = storage.retrieve_with("doc", RetrieveOptions::new(RetrieveMode::VerifyOnly));
+        assert!(matches!(damaged, Err(UsfError::Corruption(_))));
+        assert!(storage.retrieve_with("doc", RetrieveOptions::new(RetrieveMode::HeaderOnly)).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieve_with_resolves_references() -> io::Result<()> {
+        let dir = tempdir()?;
+        let shard_path = dir.path().join("shard.usf");
+        let mut shard = UniversalStorage::create(&shard_path)?;
+        shard.store("images/0001", b"pixels", DataType::Binary)?;
+        drop(shard);
+
+        let mut manifest = UniversalStorage::create(dir.path().join("manifest.usf"))?;
+        let reference = crate::Reference::new(shard_path.to_string_lossy(), "images/0001").with_content(b"pixels");
+        manifest.store_reference("train/0001", &reference)?;
+        manifest.set_reference_resolver(Some(std::sync::Arc::new(crate::ArchiveResolver)));
+
+        let retrieved = manifest.retrieve_with("train/0001", RetrieveOptions::default())?;
+        assert_eq!(retrieved.value.as_deref(), Some(&b"pixels"[..]));
+        assert_eq!(retrieved.value, Some(manifest.retrieve("train/0001")?));
+        assert_eq!(retrieved.size, 6);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{BlockLocation, IndexEntry, Result, UniversalStorage, VerifyIssue};
+
+/// What [`UniversalStorage::salvage_to`] copied and what it left behind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Keys copied intact, in key order
+    pub salvaged: Vec<String>,
+    /// Keys left behind, live ones first, each with the first problem
+    /// found in its blocks
+    pub dropped: Vec<DroppedEntry>,
+    /// Size of the new archive
+    pub bytes_after: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedEntry {
+    pub key: String,
+    /// Whether the entry was in the trash rather than live
+    pub trashed: bool,
+    pub issue: VerifyIssue,
+}
+
+impl UniversalStorage {
+    /// Copies every entry whose blocks pass [`UniversalStorage::verify`]
+    /// into a fresh archive at `dest`, with this archive's settings, and
+    /// reports the entries left behind. Unlike
+    /// [`UniversalStorage::repair`], which rebuilds damaged blocks in place
+    /// from parity, this needs none and never writes to this archive, so
+    /// it is the way out once blocks are beyond repair. Trashed entries
+    /// and dictionaries come along when intact; interrupted ingests and
+    /// parity do not. Blocks are copied as stored, so a value compressed
+    /// with a dropped dictionary is copied but cannot be read.
+    pub fn salvage_to<P: AsRef<Path>>(&mut self, dest: P) -> Result<SalvageReport> {
+        let verified = self.verify()?;
+        let mut damaged_blocks: HashMap<u64, &VerifyIssue> = HashMap::new();
+        let mut damaged_keys: HashMap<&str, &VerifyIssue> = HashMap::new();
+        for issue in &verified.issues {
+            match issue {
+                VerifyIssue::MissingBlock { offset, .. } | VerifyIssue::UnreadableHeader { offset, .. }
+                | VerifyIssue::SizeMismatch { offset, .. } | VerifyIssue::ChecksumMismatch { offset, .. } => {
+                    damaged_blocks.insert(*offset, issue);
+                },
+                VerifyIssue::SizeAccounting { key, .. } => {
+                    damaged_keys.insert(key, issue);
+                },
+            }
+        }
+        let problem = |key: &str, blocks: &[BlockLocation]| -> Option<VerifyIssue> {
+            blocks.iter().find_map(|loc| damaged_blocks.get(&loc.offset))
+                .or_else(|| damaged_keys.get(key))
+                .map(|issue| (*issue).clone())
+        };
+
+        let mut report = SalvageReport::default();
+        let mut entries: Vec<(String, IndexEntry, bool)> = Vec::new();
+        let live = self.metadata.index.iter().map(|(key, entry)| (key, entry, false));
+        let trashed = self.metadata.trash.iter().map(|(key, trashed)| (key, &trashed.entry, true));
+        for (key, entry, trashed) in live.chain(trashed) {
+            match problem(key, &entry.blocks) {
+                Some(issue) => report.dropped.push(DroppedEntry { key: key.clone(), trashed, issue }),
+                None => entries.push((key.clone(), entry.clone(), trashed)),
+            }
+        }
+        let dictionaries: Vec<_> = self.metadata.dictionaries.iter()
+            .filter(|(name, stored)| problem(name, &stored.blocks).is_none())
+            .map(|(name, stored)| (name.clone(), stored.clone()))
+            .collect();
+
+        let mut metadata = self.metadata.clone();
+        metadata.index.clear();
+        metadata.trash.clear();
+        metadata.chain_refs.clear();
+        metadata.freed.clear();
+        metadata.ingests.clear();
+        metadata.dictionaries.clear();
+        metadata.parity.clear();
+        metadata.total_blocks = 0;
+        metadata.cold_tier = None;
+        metadata.index_sidecar = false;
+        metadata.write_ahead_log = false;
+        let mut target = Self::initialize(dest.as_ref(), metadata)?;
+        // As in compaction, the empty first commit is dropped rather than
+        // left as dead space
+        target.file.set_len(target.data_offset())?;
+        target.commit = None;
+
+        let mut moved: HashMap<u64, Vec<BlockLocation>> = HashMap::new();
+        let (mut copied, live_bytes) = (0, self.live_block_bytes());
+        for (name, mut stored) in dictionaries {
+            stored.blocks = self.copy_chain(&stored.blocks, &mut target, None, &mut moved, &mut copied, live_bytes)?;
+            target.metadata.dictionaries.insert(name, stored);
+        }
+        for (key, mut entry, trashed) in entries {
+            entry.blocks = self.copy_chain(&entry.blocks, &mut target, None, &mut moved, &mut copied, live_bytes)?;
+            match trashed {
+                true => {
+                    let mut trash_entry = self.metadata.trash[&key].clone();
+                    trash_entry.entry = entry;
+                    target.metadata.trash.insert(key, trash_entry);
+                },
+                false => {
+                    target.metadata.index.insert(key.clone(), entry);
+                    report.salvaged.push(key);
+                },
+            }
+        }
+        for (old_offset, count) in &self.metadata.chain_refs {
+            if let Some(first) = moved.get(old_offset).and_then(|chain| chain.first()) {
+                target.metadata.chain_refs.insert(first.offset, *count);
+            }
+        }
+
+        target.metadata.modified = target.now();
+        target.update_metadata()?;
+        target.file.sync_all()?;
+        report.bytes_after = target.file.metadata()?.len();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfOptions};
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_salvage_copies_intact_entries() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("damaged.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.set_trash_retention(Some(Duration::from_secs(3600)))?;
+        for key in ["a", "b", "c"] {
+            storage.store(key, format!("value of {}", key).as_bytes(), DataType::Text)?;
+        }
+        storage.copy("c", "linked")?;
+        storage.store("gone", b"trashed value", DataType::Text)?;
+        storage.delete("gone")?;
+        let damaged = storage.metadata.index["b"].blocks[0].clone();
+        drop(storage);
+
+        let file = OpenOptions::new().write(true).open(&path)?;
+        crate::platform::write_all_at(&file, b"!", damaged.offset + damaged.disk_size() - 1)?;
+        drop(file);
+
+        let mut storage = UniversalStorage::open(&path)?;
+        assert!(storage.retrieve("b").is_err());
+        let salvaged = dir.path().join("salvaged.usf");
+        let report = storage.salvage_to(&salvaged)?;
+        assert_eq!(report.salvaged, ["a", "c", "linked"]);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!((report.dropped[0].key.as_str(), report.dropped[0].trashed), ("b", false));
+        assert!(matches!(report.dropped[0].issue, VerifyIssue::ChecksumMismatch { offset, .. } if offset == damaged.offset));
+
+        let mut salvaged = UniversalStorage::open(&salvaged)?;
+        assert!(salvaged.verify()?.is_clean());
+        assert_eq!(salvaged.keys().collect::<Vec<_>>(), ["a", "c", "linked"]);
+        assert_eq!(salvaged.retrieve("linked")?, b"value of c");
+        assert_eq!(salvaged.ref_count("c")?, 2);
+        assert_eq!(salvaged.trash().len(), 1);
+        assert_eq!(salvaged.trash_retention(), Some(Duration::from_secs(3600)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+use crate::{Block, BlockLocation, Result, UniversalStorage, UsfError};
+
+/// When reads check a block's stored bytes against its checksum, and its
+/// decoded data against the checksum of the original. Skipping saves CPU
+/// on hot paths at the cost of possibly returning damaged data;
+/// [`UniversalStorage::open_with_check`] and other explicit checks always
+/// verify.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChecksumPolicy {
+    /// Every block read is verified
+    #[default]
+    Always,
+    /// Each block read is verified with this probability, from 0.0 to 1.0
+    Sampled(f64),
+    /// A block is verified the first time this process reads it, through
+    /// any handle, and trusted afterwards
+    FirstAccess,
+}
+
+/// Read-time checksum counts for one handle since it was opened (or since
+/// the last [`UniversalStorage::reset_metrics`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VerificationStats {
+    pub verified: u64,
+    /// Block reads the policy let through unchecked
+    pub skipped: u64,
+    /// Verified blocks whose checksum did not match
+    pub failures: u64,
+}
+
+// Counters kept behind `&self`, since blocks are read through shared
+// borrows
+#[derive(Debug, Default)]
+pub(crate) struct VerificationCounters {
+    verified: AtomicU64,
+    skipped: AtomicU64,
+    failures: AtomicU64,
+    // Feeds the sampling hash, so repeated reads of a block draw anew
+    draws: AtomicU64,
+}
+
+// Blocks verified by this process under FirstAccess: archive path, offset
+// and stored checksum, so a different block at the same offset after
+// compaction is verified again
+fn verified_blocks() -> &'static Mutex<HashSet<(PathBuf, u64, u64)>> {
+    static VERIFIED: OnceLock<Mutex<HashSet<(PathBuf, u64, u64)>>> = OnceLock::new();
+    VERIFIED.get_or_init(Default::default)
+}
+
+// Seed for the sampling hash, fixed per process
+fn sampling_seed() -> u64 {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    *SEED.get_or_init(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64))
+}
+
+impl UniversalStorage {
+    pub fn set_checksum_policy(&mut self, policy: ChecksumPolicy) {
+        self.checksum_policy = policy;
+    }
+
+    pub fn checksum_policy(&self) -> ChecksumPolicy {
+        self.checksum_policy
+    }
+
+    pub fn verification_stats(&self) -> VerificationStats {
+        let counters = &self.verification;
+        VerificationStats {
+            verified: counters.verified.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            failures: counters.failures.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset_verification_stats(&mut self) {
+        self.verification = VerificationCounters::default();
+    }
+
+    // Checks `block` against its checksum if the policy calls for it,
+    // returning whether it did
+    pub(crate) fn check_block(&self, location: &BlockLocation, block: &Block) -> Result<bool> {
+        let counters = &self.verification;
+        let first_access = || (self.path.clone(), location.offset, block.header.checksum);
+        let verify = match self.checksum_policy {
+            ChecksumPolicy::Always => true,
+            ChecksumPolicy::Sampled(probability) => {
+                let draw = counters.draws.fetch_add(1, Ordering::Relaxed);
+                let roll = xxh3_64_with_seed(&[location.offset.to_le_bytes(), draw.to_le_bytes()].concat(), sampling_seed());
+                (roll as f64) < probability.clamp(0.0, 1.0) * u64::MAX as f64
+            },
+            ChecksumPolicy::FirstAccess => {
+                !verified_blocks().lock().unwrap_or_else(PoisonError::into_inner).contains(&first_access())
+            },
+        };
+        if !verify {
+            counters.skipped.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+
+        counters.verified.fetch_add(1, Ordering::Relaxed);
+        if !block.header.checksum_matches(&block.data) {
+            counters.failures.fetch_add(1, Ordering::Relaxed);
+            return Err(UsfError::Corruption(format!("checksum mismatch in block at offset {}", location.offset)));
+        }
+        if self.checksum_policy == ChecksumPolicy::FirstAccess {
+            verified_blocks().lock().unwrap_or_else(PoisonError::into_inner).insert(first_access());
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{platform, DataType};
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checksum_policy_controls_verification() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sampling.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("hot", b"read often", DataType::Text)?;
+
+        for _ in 0..3 {
+            storage.retrieve("hot")?;
+        }
+        assert_eq!(storage.verification_stats(), VerificationStats { verified: 3, skipped: 0, failures: 0 });
+
+        // Another handle of this process trusts blocks the first one checked
+        storage.set_checksum_policy(ChecksumPolicy::FirstAccess);
+        storage.retrieve("hot")?;
+        let mut other = UniversalStorage::open(&path)?;
+        other.set_checksum_policy(ChecksumPolicy::FirstAccess);
+        other.retrieve("hot")?;
+        assert_eq!(other.verification_stats(), VerificationStats { verified: 0, skipped: 1, failures: 0 });
+
+        storage.reset_metrics();
+        storage.set_checksum_policy(ChecksumPolicy::Sampled(0.0));
+        storage.retrieve("hot")?;
+        storage.set_checksum_policy(ChecksumPolicy::Sampled(1.0));
+        storage.retrieve("hot")?;
+        assert_eq!(storage.verification_stats(), VerificationStats { verified: 1, skipped: 1, failures: 0 });
+
+        // Damage the block: skipped reads miss it, verified reads count it
+        let location = storage.metadata.index["hot"].blocks[0].clone();
+        platform::write_all_at(&storage.file, b"!", location.offset + location.disk_size() - 1)?;
+        storage.set_checksum_policy(ChecksumPolicy::Sampled(0.0));
+        assert!(storage.retrieve("hot").is_ok());
+        storage.set_checksum_policy(ChecksumPolicy::Always);
+        assert!(matches!(storage.retrieve("hot"), Err(UsfError::Corruption(_))));
+        assert_eq!(storage.verification_stats().failures, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{DataType, Result, UniversalStorage, UsfError};
+
+/// A view of the keys under one prefix, for handing a namespace to code
+/// that must not reach sibling data. Keys passed in and returned are
+/// relative to the prefix.
+pub struct ScopedStorage<'a> {
+    storage: &'a mut UniversalStorage,
+    // Canonical, and ends with '/'
+    prefix: String,
+}
+
+impl UniversalStorage {
+    /// Scopes to the keys under `prefix`, which is treated as a directory:
+    /// `scoped("tenant-a")` covers `tenant-a/...` but not `tenant-ab/...`.
+    pub fn scoped(&mut self, prefix: &str) -> Result<ScopedStorage<'_>> {
+        let prefix = scope_prefix(self, "", prefix)?;
+        Ok(ScopedStorage { storage: self, prefix })
+    }
+}
+
+impl ScopedStorage<'_> {
+    /// The absolute prefix this view is confined to.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// A narrower view under `prefix`, relative to this one.
+    pub fn scoped(&mut self, prefix: &str) -> Result<ScopedStorage<'_>> {
+        let prefix = scope_prefix(self.storage, &self.prefix, prefix)?;
+        Ok(ScopedStorage { storage: &mut *self.storage, prefix })
+    }
+
+    pub fn store(&mut self, key: &str, data: &[u8], data_type: DataType) -> Result<()> {
+        let full = self.resolve(key)?;
+        self.storage.store(&full, data, data_type).map_err(|e| self.relative(e))
+    }
+
+    pub fn retrieve(&mut self, key: &str) -> Result<Vec<u8>> {
+        let full = self.resolve(key)?;
+        self.storage.retrieve(&full).map_err(|e| self.relative(e))
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        let full = self.resolve(key)?;
+        self.storage.delete(&full).map_err(|e| self.relative(e))
+    }
+
+    pub fn data_type_of(&self, key: &str) -> Result<DataType> {
+        let full = self.resolve(key)?;
+        self.storage.data_type_of(&full).map_err(|e| self.relative(e))
+    }
+
+    /// Keys in this scope, relative to it and sorted.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.storage.keys_with_prefix(&self.prefix)
+            .map(|key| &key[self.prefix.len()..])
+    }
+
+    // The canonical absolute key, refusing anything that canonicalizes
+    // outside the scope
+    fn resolve(&self, key: &str) -> Result<String> {
+        let full = self.storage.metadata.key_policy.canonicalize(&format!("{}{}", self.prefix, key))?;
+        if !full.starts_with(self.prefix.as_str()) || full.len() == self.prefix.len() {
+            return Err(UsfError::InvalidKey { key: key.to_string(), reason: "outside the scope".to_string() });
+        }
+        Ok(full)
+    }
+
+    // Keeps the prefix out of errors returned to the scoped caller
+    fn relative(&self, error: UsfError) -> UsfError {
+        match error {
+            UsfError::KeyNotFound(key) => UsfError::KeyNotFound(key.strip_prefix(self.prefix.as_str()).unwrap_or(&key).to_string()),
+            other => other,
+        }
+    }
+}
+
+pub(crate) fn scope_prefix(storage: &UniversalStorage, parent: &str, prefix: &str) -> Result<String> {
+    let trimmed = prefix.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Err(UsfError::InvalidKey { key: prefix.to_string(), reason: "scope prefix is empty".to_string() });
+    }
+    let full = storage.metadata.key_policy.canonicalize(&format!("{}{}/", parent, trimmed))?;
+    if !full.starts_with(parent) {
+        return Err(UsfError::InvalidKey { key: prefix.to_string(), reason: "outside the scope".to_string() });
+    }
+    Ok(full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scoped_storage_isolation() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("scoped.usf"))?;
+        storage.store("tenant-ab/config", b"sibling", DataType::Text)?;
+
+        let mut tenant = storage.scoped("tenant-a")?;
+        tenant.store("config", b"mine", DataType::Text)?;
+        tenant.scoped("cache")?.store("entry", b"cached", DataType::Binary)?;
+        assert_eq!(tenant.keys().collect::<Vec<_>>(), ["cache/entry", "config"]);
+        assert_eq!(tenant.retrieve("config")?, b"mine");
+        assert!(matches!(tenant.retrieve("missing"), Err(UsfError::KeyNotFound(key)) if key == "missing"));
+        assert!(matches!(tenant.retrieve(""), Err(UsfError::InvalidKey { .. })));
+        tenant.delete("config")?;
+
+        assert_eq!(storage.retrieve("tenant-ab/config")?, b"sibling");
+        assert_eq!(storage.retrieve("tenant-a/cache/entry")?, b"cached");
+        assert!(storage.scoped("/").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+//! `usf serve --readonly`: a minimal HTTP/1.1 origin for archived assets.
+//!
+//! Only `GET` and `HEAD` exist; nothing in this module can modify the
+//! archive, which is opened with [`UniversalStorage::open_read_only`]. The
+//! workers' shared locks keep writers out while the server runs. Each
+//! connection carries one request. Responses carry strong ETags from the
+//! stored block checksums and honour `If-None-Match` and single `Range`
+//! requests; a range skips the blocks before it unread. References to
+//! other archives are served as the data they point at. A value's
+//! `content-type` attribute, when set, is its `Content-Type`.
+//!
+//! Connections are spread over a fixed set of workers, each with its own
+//! handle to the archive. Every handle shares one [`Throttle`], so the
+//! per-namespace limits given with `--tenant` hold across all workers and
+//! a tenant held back by them only ties up its own requests.
+//!
+//! `GET /metrics` returns the [`OperationMetrics`] of every worker summed,
+//! as JSON, for `usf top`; it shadows any key named `metrics`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use log::{error, info};
+use usf::{ArchiveResolver, DataType, OperationMetrics, TenantLimits, Throttle, UniversalStorage, UsfError};
+
+// Largest request line plus headers accepted
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+// Requests handled at once
+const WORKERS: usize = 8;
+// Path of the metrics endpoint, without its leading slash
+const METRICS_PATH: &str = "metrics";
+// Attribute overriding the content type derived from the data type
+const CONTENT_TYPE_ATTRIBUTE: &str = "content-type";
+
+// Each worker's metrics, copied from its handle after every request, so
+// any worker can answer for all of them
+type Board = Arc<Vec<Mutex<OperationMetrics>>>;
+
+pub fn serve(path: &str, addr: &str, throttle: Throttle) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (sender, receiver) = mpsc::channel::<TcpStream>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let board: Board = Arc::new((0..WORKERS).map(|_| Mutex::default()).collect());
+    for worker in 0..WORKERS {
+        let mut storage = UniversalStorage::open_read_only(path)?;
+        storage.set_throttle(Some(throttle.clone()));
+        storage.set_reference_resolver(Some(Arc::new(ArchiveResolver)));
+        let receiver = Arc::clone(&receiver);
+        let board = Arc::clone(&board);
+        thread::spawn(move || loop {
+            let next = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+            let Ok(stream) = next else {
+                return;
+            };
+            if let Err(e) = handle(&mut storage, stream, &board, worker) {
+                error!("Request failed: {}", e);
+            }
+            *board[worker].lock().unwrap_or_else(PoisonError::into_inner) = storage.metrics().clone();
+        });
+    }
+    info!("Serving {} read-only on http://{}", path, listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Accept failed: {}", e);
+                continue;
+            },
+        };
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        if sender.send(stream).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a `--tenant` value, `<prefix>=[max-concurrent]:[bytes-per-sec]`;
+/// a limit left empty is not applied.
+pub fn parse_tenant(spec: &str) -> Option<(String, TenantLimits)> {
+    let (prefix, limits) = spec.rsplit_once('=')?;
+    let (concurrent, rate) = limits.split_once(':')?;
+    let limit = |value: &str| match value {
+        "" => Some(None),
+        value => value.parse().ok().map(Some),
+    };
+    let limits = TenantLimits {
+        max_concurrent: limit(concurrent)?.map(|n: u64| n as usize),
+        max_bytes_per_sec: limit(rate)?,
+    };
+    Some((prefix.to_string(), limits))
+}
+
+struct Request {
+    method: String,
+    key: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn handle<S: Read + Write>(storage: &mut UniversalStorage, mut stream: S, board: &Board, worker: usize) -> io::Result<()> {
+    let request = match read_request(&mut stream)? {
+        Some(request) => request,
+        None => return respond_empty(&mut stream, "400 Bad Request", &[]),
+    };
+    if request.method != "GET" && request.method != "HEAD" {
+        return respond_empty(&mut stream, "405 Method Not Allowed", &[("Allow", "GET, HEAD".to_string())]);
+    }
+    if request.key == METRICS_PATH {
+        *board[worker].lock().unwrap_or_else(PoisonError::into_inner) = storage.metrics().clone();
+        return respond_metrics(&mut stream, &request, board);
+    }
+
+    let etag = match storage.etag(&request.key) {
+        Ok(etag) => etag,
+        Err(UsfError::KeyNotFound(_) | UsfError::InvalidKey { .. } | UsfError::KeyTooLong { .. }) => {
+            return respond_empty(&mut stream, "404 Not Found", &[]);
+        },
+        Err(e) => return Err(e.into()),
+    };
+    if request.header("If-None-Match").is_some_and(|tags| etag_matches(tags, &etag)) {
+        return respond_empty(&mut stream, "304 Not Modified", &[("ETag", etag)]);
+    }
+
+    let content_type = content_type(storage, &request.key)?;
+    let mut reader = storage.retrieve_many_stream(&[&request.key])?;
+    let size = reader.spans()[0].size;
+
+    let range = request.header("Range").and_then(|value| parse_range(value, size));
+    let (status, start, length, mut headers) = match range {
+        Some(Ok((start, end))) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, size);
+            ("206 Partial Content", start, end + 1 - start, vec![("Content-Range", content_range)])
+        },
+        Some(Err(())) => {
+            return respond_empty(&mut stream, "416 Range Not Satisfiable", &[("Content-Range", format!("bytes */{}", size))]);
+        },
+        None => ("200 OK", 0, size, Vec::new()),
+    };
+    headers.push(("ETag", etag));
+    headers.push(("Content-Type", content_type));
+    headers.push(("Accept-Ranges", "bytes".to_string()));
+    write_head(&mut stream, status, length, &headers)?;
+
+    if request.method == "GET" {
+        reader.skip(start)?;
+        io::copy(&mut reader.take(length), &mut stream)?;
+    }
+    stream.flush()
+}
+
+// Reads the request head; None if it is malformed or too large
+fn read_request<S: Read>(stream: &mut S) -> io::Result<Option<Request>> {
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE || stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        head.push(byte[0]);
+    }
+
+    let Ok(head) = std::str::from_utf8(&head) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (request_line.next(), request_line.next(), request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Ok(None);
+    }
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    let Some(key) = path.strip_prefix('/').and_then(percent_decode) else {
+        return Ok(None);
+    };
+
+    let mut headers = Vec::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(None);
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(Some(Request { method: method.to_string(), key, headers }))
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+// Parses a single `bytes=` range into inclusive bounds. None means the
+// header is ignored (unsupported or malformed); Err means it cannot be
+// satisfied.
+fn parse_range(value: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    let bounds = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        (suffix > 0 && size > 0).then(|| (size - suffix.min(size), size - 1))
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => u64::MAX,
+            last => last.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        (start < size).then(|| (start, end.min(size - 1)))
+    };
+    Some(bounds.ok_or(()))
+}
+
+// A `content-type` attribute wins; otherwise custom types named like a
+// MIME type are served as that type, and images are sniffed, since image
+// values may have been re-encoded on store
+fn content_type(storage: &mut UniversalStorage, key: &str) -> io::Result<String> {
+    if let Some(value) = storage.attributes(key)?.get(CONTENT_TYPE_ATTRIBUTE).filter(|value| is_header_value(value)) {
+        return Ok(value.clone());
+    }
+    let content_type = match storage.data_type_of(key)? {
+        DataType::Text => "text/plain; charset=utf-8",
+        DataType::Json => "application/json",
+        DataType::Image => {
+            let mut magic = Vec::new();
+            storage.open_reader(key)?.take(32).read_to_end(&mut magic)?;
+            image::guess_format(&magic).map(|format| format.to_mime_type()).unwrap_or("application/octet-stream")
+        },
+        DataType::Custom(id) => match storage.custom_type(id) {
+            Some(custom) if custom.name.contains('/') && is_header_value(&custom.name) => return Ok(custom.name.clone()),
+            _ => "application/octet-stream",
+        },
+        _ => "application/octet-stream",
+    };
+    Ok(content_type.to_string())
+}
+
+// Stored strings end up in response headers; anything but visible ASCII,
+// spaces and tabs could end the header early or inject another
+fn is_header_value(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte == b'\t' || (b' '..=b'~').contains(&byte))
+}
+
+fn write_head<S: Write>(stream: &mut S, status: &str, length: u64, headers: &[(&str, String)]) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, length)?;
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(stream, "X-Content-Type-Options: nosniff\r\nConnection: close\r\n\r\n")
+}
+
+// Sums every worker's metrics into one JSON document
+fn respond_metrics<S: Write>(stream: &mut S, request: &Request, board: &Board) -> io::Result<()> {
+    let mut total = OperationMetrics::default();
+    for metrics in board.iter() {
+        total.merge(&metrics.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+    let body = serde_json::to_vec(&total)?;
+    write_head(stream, "200 OK", body.len() as u64, &[("Content-Type", "application/json".to_string()), ("Cache-Control", "no-store".to_string())])?;
+    if request.method == "GET" {
+        stream.write_all(&body)?;
+    }
+    stream.flush()
+}
+
+fn respond_empty<S: Write>(stream: &mut S, status: &str, headers: &[(&str, String)]) -> io::Result<()> {
+    write_head(stream, status, 0, headers)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use usf::{CustomType, KeyPolicy};
+
+    // An in-memory connection: reads the request, collects the response
+    struct Connection {
+        request: io::Cursor<Vec<u8>>,
+        response: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.request.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.response.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(storage: &mut UniversalStorage, head: &str) -> io::Result<String> {
+        let board: Board = Arc::new(vec![Mutex::default()]);
+        request_on(storage, head, &board, 0)
+    }
+
+    fn request_on(storage: &mut UniversalStorage, head: &str, board: &Board, worker: usize) -> io::Result<String> {
+        let mut connection = Connection { request: io::Cursor::new(head.as_bytes().to_vec()), response: Vec::new() };
+        handle(storage, &mut connection, board, worker)?;
+        Ok(String::from_utf8_lossy(&connection.response).into_owned())
+    }
+
+    #[test]
+    fn test_readonly_requests() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("serve.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("docs/hello world.txt", b"hello, archive", DataType::Text)?;
+        drop(storage);
+        let mut storage = UniversalStorage::open_read_only(&path)?;
+        assert!(matches!(usf::UsfOptions::new().write(true).open(&path), Err(UsfError::Locked)));
+        let etag = storage.etag("docs/hello world.txt")?;
+
+        let full = request(&mut storage, "GET /docs/hello%20world.txt HTTP/1.1\r\nHost: x\r\n\r\n")?;
+        assert!(full.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(full.contains(&format!("ETag: {}\r\n", etag)));
+        assert!(full.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(full.ends_with("\r\n\r\nhello, archive"));
+
+        let partial = request(&mut storage, "GET /docs/hello%20world.txt HTTP/1.1\r\nRange: bytes=7-\r\n\r\n")?;
+        assert!(partial.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(partial.contains("Content-Range: bytes 7-13/14\r\n"));
+        assert!(partial.ends_with("\r\n\r\narchive"));
+
+        let cached = request(&mut storage, &format!("GET /docs/hello%20world.txt HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag))?;
+        assert!(cached.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        let head = request(&mut storage, "HEAD /docs/hello%20world.txt HTTP/1.1\r\n\r\n")?;
+        assert!(head.contains("Content-Length: 14\r\n") && head.ends_with("\r\n\r\n"));
+
+        assert!(request(&mut storage, "GET /missing HTTP/1.1\r\n\r\n")?.starts_with("HTTP/1.1 404"));
+        assert!(request(&mut storage, "PUT /docs/x HTTP/1.1\r\n\r\n")?.starts_with("HTTP/1.1 405"));
+        assert!(request(&mut storage, "GET /docs/hello%20world.txt HTTP/1.1\r\nRange: bytes=99-\r\n\r\n")?
+            .starts_with("HTTP/1.1 416"));
+
+        // Keys the policy rejects are simply not there
+        let policy_path = dir.path().join("short-keys.usf");
+        let policy = KeyPolicy { max_length: Some(16), ..KeyPolicy::default() };
+        drop(UniversalStorage::create_with_key_policy(&policy_path, policy)?);
+        let mut short_keys = UniversalStorage::open_read_only(&policy_path)?;
+        assert!(request(&mut short_keys, &format!("GET /{} HTTP/1.1\r\n\r\n", "k".repeat(17)))?.starts_with("HTTP/1.1 404"));
+
+        assert_eq!(parse_range("bytes=-4", 14), Some(Ok((10, 13))));
+        assert_eq!(parse_range("bytes=0-1,4-5", 14), None);
+        let tenant = TenantLimits { max_concurrent: None, max_bytes_per_sec: Some(65536) };
+        assert_eq!(parse_tenant("bulk/=:65536"), Some(("bulk/".to_string(), tenant)));
+        assert_eq!(parse_tenant("bulk/=two:"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_sum_workers() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("metrics.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("a", b"alpha", DataType::Text)?;
+        drop(storage);
+        let board: Board = Arc::new(vec![Mutex::default(), Mutex::default()]);
+        let mut first = UniversalStorage::open_read_only(&path)?;
+        let mut second = UniversalStorage::open_read_only(&path)?;
+
+        assert!(request_on(&mut first, "GET /a HTTP/1.1\r\n\r\n", &board, 0)?.ends_with("alpha"));
+        *board[0].lock().unwrap() = first.metrics().clone();
+        assert!(request_on(&mut second, "GET /a HTTP/1.1\r\nRange: bytes=1-2\r\n\r\n", &board, 1)?.ends_with("lp"));
+        request_on(&mut second, "HEAD /a HTTP/1.1\r\n\r\n", &board, 1)?;
+
+        let response = request_on(&mut second, "GET /metrics HTTP/1.1\r\n\r\n", &board, 1)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && response.contains("Content-Type: application/json\r\n"));
+        let (_, body) = response.split_once("\r\n\r\n").expect("head and body");
+        let metrics: OperationMetrics = serde_json::from_str(body)?;
+        assert_eq!((metrics.retrieves, metrics.bytes_retrieved), (2, 8));
+        assert_eq!(metrics.slowest_retrievals.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_type_from_attribute() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("types.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("index.html", b"<p>hi</p>", DataType::Text)?;
+        storage.set_attribute("index.html", CONTENT_TYPE_ATTRIBUTE, "text/html; charset=utf-8")?;
+        storage.store("forged", b"x", DataType::Text)?;
+        storage.set_attribute("forged", CONTENT_TYPE_ATTRIBUTE, "text/html\r\nSet-Cookie: a=b")?;
+        let injected = CustomType { name: "text/x\r\nSet-Cookie: a=b".to_string(), ..CustomType::default() };
+        storage.register_data_type(7, injected)?;
+        storage.store("custom", b"x", DataType::Custom(7))?;
+        drop(storage);
+        let mut storage = UniversalStorage::open_read_only(&path)?;
+
+        let html = request(&mut storage, "GET /index.html HTTP/1.1\r\n\r\n")?;
+        assert!(html.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        // Values that would split the header fall back to the data type
+        let forged = request(&mut storage, "GET /forged HTTP/1.1\r\n\r\n")?;
+        assert!(forged.contains("Content-Type: text/plain; charset=utf-8\r\n") && !forged.contains("Set-Cookie"));
+        let custom = request(&mut storage, "GET /custom HTTP/1.1\r\n\r\n")?;
+        assert!(custom.contains("Content-Type: application/octet-stream\r\n") && !custom.contains("Set-Cookie"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::format::{self, MAGIC_BYTES, METADATA_OFFSET, SIDECAR_MAGIC_BYTES, VERSION, VERSION_1, VERSION_2};
+use crate::split::suffixed;
+use crate::{platform, readonly, Access, MetaData, Result, UniversalStorage, UsfError};
+
+impl UniversalStorage {
+    /// Mirrors every metadata commit to a sidecar file next to the archive
+    /// (`<path>.idx`), replaced atomically each time. If the header or
+    /// metadata of the archive is later damaged,
+    /// [`UniversalStorage::restore_index_from_sidecar`] can put it back.
+    /// Disabling removes the sidecar.
+    pub fn set_index_sidecar(&mut self, enabled: bool) -> Result<()> {
+        self.metadata.index_sidecar = enabled;
+        self.metadata.modified = self.now();
+        self.update_metadata()?;
+        if !enabled {
+            match fs::remove_file(self.index_sidecar_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    pub fn index_sidecar(&self) -> bool {
+        self.metadata.index_sidecar
+    }
+
+    pub fn index_sidecar_path(&self) -> PathBuf {
+        suffixed(&self.path, ".idx")
+    }
+
+    /// Rewrites the header of the archive at `path` and commits the
+    /// metadata held in its index sidecar, then opens it. Blocks are not
+    /// touched, so values written after the sidecar's commit are lost, and
+    /// a commit that crashed between the archive and the sidecar leaves the
+    /// sidecar one commit behind. A complete write-ahead journal is
+    /// replayed first. Writing takes the exclusive lock; while another
+    /// handle holds a lock this fails with [`UsfError::Locked`] and leaves
+    /// the archive untouched.
+    pub fn restore_index_from_sidecar<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let sidecar = fs::read(suffixed(path, ".idx"))?;
+        let (version, commit) = match sidecar.strip_prefix(SIDECAR_MAGIC_BYTES.as_slice()) {
+            Some([version @ (VERSION | VERSION_2 | VERSION_1), commit @ ..]) => (*version, commit),
+            _ => return Err(UsfError::Corruption("not an index sidecar for a supported format version".to_string())),
+        };
+        let metadata_size = check_commit(commit)?;
+
+        let file = OpenOptions::new().read(true).write(true).open(platform::native_path(path))?;
+        readonly::lock(&file, Access::Write)?;
+        Self::replay_journal(path, Some(&file))?;
+        platform::write_all_at(&file, MAGIC_BYTES, 0)?;
+        platform::write_all_at(&file, &[version], MAGIC_BYTES.len() as u64)?;
+        if version == VERSION_1 {
+            platform::write_all_at(&file, commit, METADATA_OFFSET)?;
+        } else {
+            let end = file.metadata()?.len();
+            platform::write_all_at(&file, &format::with_trailer(commit, end), end)?;
+            file.sync_data()?;
+            platform::write_all_at(&file, &format::commit_pointer(end, metadata_size), METADATA_OFFSET)?;
+        }
+        file.sync_data()?;
+        drop(file);
+        Self::open(path)
+    }
+
+    // Atomically replaces the sidecar with `commit`, the length, metadata
+    // and checksum just committed
+    pub(crate) fn write_index_sidecar(&self, commit: &[u8]) -> Result<()> {
+        let path = self.index_sidecar_path();
+        let temporary = suffixed(&path, ".tmp");
+        let mut file = File::create(platform::native_path(&temporary))?;
+        file.write_all(SIDECAR_MAGIC_BYTES)?;
+        file.write_all(&[self.version])?;
+        file.write_all(commit)?;
+        file.sync_data()?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+}
+
+// Checks a commit image: length, metadata and checksum. Returns the
+// metadata length.
+fn check_commit(commit: &[u8]) -> Result<u64> {
+    let truncated = || UsfError::Corruption("index sidecar is truncated".to_string());
+    let length = commit.get(..8).ok_or_else(truncated)?;
+    let length = u64::from_le_bytes(length.try_into().expect("8 bytes")) as usize;
+    let metadata = commit.get(8..8 + length).ok_or_else(truncated)?;
+    let checksum = commit.get(8 + length..16 + length).ok_or_else(truncated)?;
+    if xxh3_64(metadata) != u64::from_le_bytes(checksum.try_into().expect("8 bytes")) {
+        return Err(UsfError::Corruption("index sidecar checksum mismatch".to_string()));
+    }
+    bincode::deserialize::<MetaData>(metadata)?;
+    Ok(length as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, UsfOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_restore_index_from_sidecar() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sidecar.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("before", b"not mirrored yet", DataType::Text)?;
+        storage.set_index_sidecar(true)?;
+        storage.store("doc", "mirrored ".repeat(300).as_bytes(), DataType::Text)?;
+        storage.store("dropped", b"overwritten", DataType::Text)?;
+        storage.delete("dropped")?;
+        storage.compact()?;
+        assert!(!dir.path().join("sidecar.usf.compact.idx").exists());
+        let sidecar = storage.index_sidecar_path();
+        assert_eq!(sidecar, dir.path().join("sidecar.usf.idx"));
+        assert!(sidecar.exists() && !suffixed(&sidecar, ".tmp").exists());
+        drop(storage);
+
+        // Wipe the header and the commit pointer
+        let file = OpenOptions::new().write(true).open(&path)?;
+        platform::write_all_at(&file, &[0xff; format::DATA_OFFSET as usize], 0)?;
+        drop(file);
+        assert!(UniversalStorage::open(&path).is_err());
+
+        let mut storage = UniversalStorage::restore_index_from_sidecar(&path)?;
+        storage.verify_metadata()?;
+        assert_eq!(storage.retrieve("before")?, b"not mirrored yet");
+        assert_eq!(storage.retrieve("doc")?, "mirrored ".repeat(300).as_bytes());
+        assert!(storage.index_sidecar());
+
+        // A damaged sidecar is refused rather than restored
+        let mut bytes = fs::read(&sidecar)?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&sidecar, bytes)?;
+        assert!(matches!(UniversalStorage::restore_index_from_sidecar(&path), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_waits_for_other_handles() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("locked.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.set_index_sidecar(true)?;
+        storage.store("doc", b"mirrored", DataType::Text)?;
+        drop(storage);
+        let before = fs::read(&path)?;
+
+        let writer = UsfOptions::new().write(true).open(&path)?;
+        assert!(matches!(UniversalStorage::restore_index_from_sidecar(&path), Err(UsfError::Locked)));
+        drop(writer);
+        let reader = UniversalStorage::open_read_only(&path)?;
+        assert!(matches!(UniversalStorage::restore_index_from_sidecar(&path), Err(UsfError::Locked)));
+        drop(reader);
+        assert_eq!(fs::read(&path)?, before);
+
+        let mut restored = UniversalStorage::restore_index_from_sidecar(&path)?;
+        assert_eq!(restored.retrieve("doc")?, b"mirrored");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use bytes::Bytes;
+use futures_sink::Sink;
+use crate::writer::{BackgroundWriter, WriteHandle};
+use crate::{DataType, Result, UsfError};
+
+/// An async [`Sink`] of `(key, value, data type)` items over a
+/// [`BackgroundWriter`], so streaming pipelines can push into an archive
+/// with backpressure: once `max_in_flight` stores are queued and not yet
+/// committed, the sink stops accepting items until one completes.
+///
+/// Values are compressed in [`Sink::start_send`], on the calling task, as
+/// with [`BackgroundWriter::store`]. Flushing waits for every accepted item
+/// to be committed; closing additionally makes them durable. The first
+/// failed store is returned from the next poll.
+pub struct IngestSink {
+    writer: BackgroundWriter,
+    max_in_flight: usize,
+    // Submitted stores, oldest first
+    in_flight: VecDeque<WriteHandle>,
+    // The fsync queued by poll_close
+    closing: Option<WriteHandle>,
+}
+
+impl IngestSink {
+    /// Wraps `writer`, accepting at most `max_in_flight` uncommitted items
+    /// (at least one).
+    pub fn new(writer: BackgroundWriter, max_in_flight: usize) -> Self {
+        Self { writer, max_in_flight: max_in_flight.max(1), in_flight: VecDeque::new(), closing: None }
+    }
+
+    /// Items accepted and not yet committed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn into_writer(self) -> BackgroundWriter {
+        self.writer
+    }
+
+    // Drops committed stores from the front of the queue. Stores all go
+    // through the bulk lane, so they finish in submission order.
+    fn reap(&mut self) -> Result<()> {
+        while let Some(handle) = self.in_flight.front_mut() {
+            if !handle.is_complete() {
+                break;
+            }
+            self.in_flight.pop_front().expect("front handle").wait()?;
+        }
+        Ok(())
+    }
+
+    // Ready once at most `limit` stores are in flight. The waker is
+    // registered before checking again, so a store finishing in between
+    // is not missed.
+    fn poll_in_flight(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<Result<()>> {
+        self.reap()?;
+        if self.in_flight.len() <= limit {
+            return Poll::Ready(Ok(()));
+        }
+        self.writer.wake_on_progress(cx.waker());
+        self.reap()?;
+        match self.in_flight.len() <= limit {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        }
+    }
+}
+
+impl Sink<(String, Bytes, DataType)> for IngestSink {
+    type Error = UsfError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let limit = self.max_in_flight - 1;
+        self.get_mut().poll_in_flight(cx, limit)
+    }
+
+    fn start_send(self: Pin<&mut Self>, (key, value, data_type): (String, Bytes, DataType)) -> Result<()> {
+        let sink = self.get_mut();
+        let handle = sink.writer.store(&key, &value, data_type)?;
+        sink.in_flight.push_back(handle);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_in_flight(cx, 0)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let sink = self.get_mut();
+        if sink.poll_in_flight(cx, 0)?.is_pending() {
+            return Poll::Pending;
+        }
+        if sink.closing.is_none() {
+            sink.closing = Some(sink.writer.flush()?);
+        }
+        let closing = sink.closing.as_mut().expect("flush queued");
+        if !closing.is_complete() {
+            sink.writer.wake_on_progress(cx.waker());
+            if !closing.is_complete() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(sink.closing.take().expect("flush queued").wait())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UniversalStorage;
+    use futures::executor::block_on;
+    use futures::SinkExt;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ingest_sink_limits_in_flight() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sink.usf");
+        let writer = UniversalStorage::create(&path)?.into_background_writer();
+        let mut sink = IngestSink::new(writer, 2);
+
+        block_on(async {
+            for i in 0..20 {
+                let value = Bytes::from(format!("event {}", i));
+                sink.feed((format!("events/{:02}", i), value, DataType::Text)).await?;
+                assert!(sink.in_flight() <= 2);
+            }
+            sink.close().await
+        })?;
+        assert_eq!(sink.in_flight(), 0);
+
+        let mut storage = sink.into_writer().finish()?;
+        assert_eq!(storage.len(), 20);
+        assert_eq!(storage.retrieve("events/19")?, b"event 19");
+
+        Ok(())
+    }
+}
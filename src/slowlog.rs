@@ -0,0 +1,135 @@
+use std::fmt;
+use std::time::Duration;
+use log::warn;
+use crate::format::CompressionMethod;
+use crate::UniversalStorage;
+
+/// How long [`UniversalStorage::store`] and [`UniversalStorage::retrieve`]
+/// may take before they log a [`SlowOperation`] warning. `None` never
+/// warns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SlowOpThresholds {
+    pub store: Option<Duration>,
+    pub retrieve: Option<Duration>,
+}
+
+/// One operation that ran past its threshold, with where its time went.
+/// Logged at warn level under the `usf::slow` target, as `key=value`
+/// pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowOperation {
+    /// `"store"` or `"retrieve"`
+    pub operation: &'static str,
+    pub key: String,
+    /// Length of the value
+    pub size: u64,
+    /// Compressed bytes across its blocks
+    pub stored_size: u64,
+    /// Compression methods of its blocks, each listed once
+    pub codecs: Vec<CompressionMethod>,
+    /// Compressing on store; checksumming and decompressing on retrieve
+    pub codec_time: Duration,
+    /// Reading or writing blocks
+    pub io_time: Duration,
+    /// Committing the metadata, on store
+    pub metadata_time: Duration,
+    pub total: Duration,
+}
+
+impl fmt::Display for SlowOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let codecs: Vec<String> = self.codecs.iter().map(|codec| format!("{:?}", codec)).collect();
+        write!(
+            f,
+            "slow {} key={:?} size={} stored_size={} codec={} codec_ms={:.3} io_ms={:.3} metadata_ms={:.3} total_ms={:.3}",
+            self.operation,
+            self.key,
+            self.size,
+            self.stored_size,
+            codecs.join("+"),
+            millis(self.codec_time),
+            millis(self.io_time),
+            millis(self.metadata_time),
+            millis(self.total),
+        )
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl UniversalStorage {
+    pub fn set_slow_op_thresholds(&mut self, thresholds: SlowOpThresholds) {
+        self.slow_ops = thresholds;
+    }
+
+    pub fn slow_op_thresholds(&self) -> SlowOpThresholds {
+        self.slow_ops
+    }
+
+    // Logs the operation `describe` builds if `total` passed `threshold`
+    pub(crate) fn report_if_slow<F>(&mut self, threshold: Option<Duration>, total: Duration, describe: F)
+    where
+        F: FnOnce() -> SlowOperation,
+    {
+        if threshold.is_some_and(|threshold| total > threshold) {
+            let operation = describe();
+            warn!(target: "usf::slow", "{}", operation);
+            self.metrics.slow_operations += 1;
+        }
+    }
+}
+
+// Each method once, in the order first seen
+pub(crate) fn distinct_codecs(methods: impl IntoIterator<Item = CompressionMethod>) -> Vec<CompressionMethod> {
+    let mut codecs = Vec::new();
+    for method in methods {
+        if !codecs.contains(&method) {
+            codecs.push(method);
+        }
+    }
+    codecs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_slow_operations_are_reported() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("slow.usf"))?;
+        storage.store("quiet", b"never reported", DataType::Text)?;
+        assert_eq!(storage.metrics().slow_operations, 0);
+
+        storage.set_slow_op_thresholds(SlowOpThresholds { store: Some(Duration::ZERO), retrieve: None });
+        storage.store("logged", &vec![b'x'; 5000], DataType::Text)?;
+        storage.retrieve("logged")?;
+        assert_eq!(storage.metrics().slow_operations, 1);
+        storage.set_slow_op_thresholds(SlowOpThresholds { store: None, retrieve: Some(Duration::ZERO) });
+        storage.retrieve("logged")?;
+        assert_eq!(storage.metrics().slow_operations, 2);
+
+        let operation = SlowOperation {
+            operation: "store",
+            key: "logs/a".to_string(),
+            size: 5000,
+            stored_size: 20,
+            codecs: distinct_codecs([CompressionMethod::Zstd, CompressionMethod::None, CompressionMethod::Zstd]),
+            codec_time: Duration::from_micros(1500),
+            io_time: Duration::from_millis(2),
+            metadata_time: Duration::ZERO,
+            total: Duration::from_micros(3500),
+        };
+        assert_eq!(
+            operation.to_string(),
+            "slow store key=\"logs/a\" size=5000 stored_size=20 codec=Zstd+None codec_ms=1.500 io_ms=2.000 metadata_ms=0.000 total_ms=3.500"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::sync::Arc;
+use crate::{platform, DataType, Result, UniversalStorage, UsfError};
+
+/// A read-only view of an archive as of one metadata generation.
+///
+/// Blocks are only ever appended, so a snapshot keeps reading the blocks
+/// its index points at while the archive takes new writes. While any
+/// snapshot is alive the archive refuses to compact, since that would
+/// discard blocks the snapshot still needs.
+pub struct Snapshot {
+    storage: UniversalStorage,
+    // Held for its reference count, which fences compaction
+    _fence: Arc<()>,
+}
+
+impl Snapshot {
+    /// Generation of the metadata the snapshot was taken from.
+    pub fn generation(&self) -> u64 {
+        self.storage.metadata.generation
+    }
+
+    /// Keys present at the time of the snapshot, in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.storage.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.metadata.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.metadata.index.is_empty()
+    }
+
+    pub fn data_type_of(&self, key: &str) -> Result<DataType> {
+        self.storage.data_type_of(key)
+    }
+
+    /// Reads `key` as it was when the snapshot was taken.
+    pub fn retrieve(&mut self, key: &str) -> Result<Vec<u8>> {
+        self.storage.retrieve(key)
+    }
+}
+
+impl UniversalStorage {
+    /// Takes a consistent read-only snapshot, e.g. for a hot backup, while
+    /// this handle keeps accepting writes. Release it with
+    /// [`UniversalStorage::thaw`] or by dropping it.
+    pub fn freeze(&mut self) -> Result<Snapshot> {
+        let file = File::open(platform::native_path(&self.path))?;
+        let mut storage = Self::from_parts(file, &self.path, self.metadata.clone());
+        storage.version = self.version;
+        storage.commit = self.commit;
+        storage.transforms = self.transforms.clone();
+        storage.dictionaries = self.dictionaries.clone();
+        storage.resolver = self.resolver.clone();
+        storage.limits = self.limits.clone();
+        storage.cold = storage.open_cold_tier(false)?;
+        Ok(Snapshot { storage, _fence: Arc::clone(&self.fence) })
+    }
+
+    /// Releases a snapshot taken with [`UniversalStorage::freeze`].
+    pub fn thaw(&mut self, snapshot: Snapshot) {
+        drop(snapshot);
+    }
+
+    /// Number of snapshots currently holding the compaction fence.
+    pub fn frozen_snapshots(&self) -> usize {
+        Arc::strong_count(&self.fence) - 1
+    }
+
+    pub(crate) fn check_not_frozen(&self) -> Result<()> {
+        match self.frozen_snapshots() {
+            0 => Ok(()),
+            count => Err(UsfError::Frozen(count)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_sees_frozen_state() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("snapshot.usf"))?;
+        storage.store("a", b"before", DataType::Text)?;
+        storage.store("b", b"kept", DataType::Text)?;
+
+        let mut snapshot = storage.freeze()?;
+        storage.store("a", b"after", DataType::Text)?;
+        storage.store("c", b"new", DataType::Text)?;
+        storage.delete("b")?;
+
+        assert_eq!(snapshot.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(snapshot.retrieve("a")?, b"before");
+        assert_eq!(snapshot.retrieve("b")?, b"kept");
+        assert!(snapshot.generation() < storage.metadata.generation);
+        assert_eq!(storage.retrieve("a")?, b"after");
+
+        assert!(matches!(storage.compact(), Err(UsfError::Frozen(1))));
+        storage.thaw(snapshot);
+        storage.compact()?;
+        assert_eq!(storage.retrieve("c")?, b"new");
+
+        Ok(())
+    }
+}
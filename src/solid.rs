@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use xxhash_rust::xxh3::xxh3_128;
+use crate::{chunk_size, BlockLocation, DataType, IndexEntry, Result, UniversalStorage};
+
+impl UniversalStorage {
+    /// Stores values under `prefix` in solid mode when written through
+    /// [`UniversalStorage::store_many`]: one batch's values are
+    /// concatenated and compressed together, which suits many small,
+    /// similar values. Reading one value decompresses the blocks it spans.
+    pub fn add_solid_prefix(&mut self, prefix: &str) -> Result<()> {
+        if !self.metadata.solid_prefixes.iter().any(|p| p == prefix) {
+            self.metadata.solid_prefixes.push(prefix.to_string());
+            self.metadata.modified = self.now();
+            self.update_metadata()?;
+        }
+        Ok(())
+    }
+
+    pub fn solid_prefixes(&self) -> &[String] {
+        &self.metadata.solid_prefixes
+    }
+
+    /// Stores several values of one type with a single metadata commit,
+    /// as [`UniversalStorage::store_batch`] does. Values under a solid
+    /// prefix are packed into one group per prefix.
+    pub fn store_many(&mut self, items: &[(&str, &[u8])], data_type: DataType) -> Result<()> {
+        let items: Vec<_> = items.iter().map(|(key, data)| (*key, *data, data_type.clone())).collect();
+        self.store_batch(&items)
+    }
+
+    // The solid prefix `key` falls under, if any
+    pub(crate) fn solid_prefix_of(&self, key: &str) -> Option<String> {
+        self.metadata.solid_prefixes.iter().find(|p| key.starts_with(p.as_str())).cloned()
+    }
+
+    // Writes the concatenated members of a solid group, returning the
+    // group's chain and its block size
+    pub(crate) fn write_solid_group(&mut self, prefix: &str, members: &[(String, &[u8])], data_type: &DataType) -> Result<(Vec<BlockLocation>, u64)> {
+        let combined: Vec<u8> = members.iter().flat_map(|(_, data)| data.iter().copied()).collect();
+        let encoding = self.encoding_for(prefix, data_type);
+        let blocks = Self::prepare_blocks(&combined, data_type.clone(), &encoding)?;
+        Ok((self.write_blocks(&blocks)?.0, chunk_size(&blocks)))
+    }
+
+    // Points every member of a written solid group at the group's chain,
+    // in memory only
+    pub(crate) fn index_solid_group(&mut self, members: Vec<(String, &[u8])>, locations: Vec<BlockLocation>, block_size: u64, data_type: DataType) {
+        self.metadata.total_blocks += locations.len() as u64;
+
+        // Every member shares the group's chain
+        if let (Some(first), true) = (locations.first(), members.len() > 1) {
+            self.metadata.chain_refs.insert(first.offset, members.len() as u32);
+        }
+
+        let now = self.now();
+        let mut offset = 0;
+        for (key, data) in members {
+            let entry = IndexEntry {
+                blocks: locations.clone(),
+                data_type: data_type.clone(),
+                size: data.len() as u64,
+                stored_at: now,
+                solid_offset: Some(offset),
+                hint: None,
+                attributes: BTreeMap::new(),
+                content_hash: Some(xxh3_128(data)),
+                ragged: false,
+                block_size,
+                text_deltas: None,
+            };
+            offset += data.len() as u64;
+            if let Some(previous) = self.metadata.index.insert(key, entry) {
+                self.release_chain(&previous.blocks);
+            }
+            self.record_store(data.len() as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BLOCK_SIZE;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_solid_group_round_trip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("solid.usf"))?;
+        storage.add_solid_prefix("configs/")?;
+
+        let values: Vec<(String, Vec<u8>)> = (0..40)
+            .map(|i| (format!("configs/{:02}", i), format!("{{\"replicas\": {}, \"region\": \"eu\"}}", i).into_bytes()))
+            .chain([("configs/big".to_string(), vec![b'x'; BLOCK_SIZE + 10])])
+            .chain([("plain".to_string(), b"not solid".to_vec())])
+            .collect();
+        let items: Vec<(&str, &[u8])> = values.iter().map(|(k, v)| (k.as_str(), v.as_slice())).collect();
+        storage.store_many(&items, DataType::Json)?;
+
+        for (key, value) in &values {
+            assert_eq!(&storage.retrieve(key)?, value);
+        }
+        assert_eq!(storage.ref_count("configs/07")?, 41);
+        assert_eq!(storage.ref_count("plain")?, 1);
+
+        // The group survives overwrites of some members and compaction
+        storage.store("configs/03", b"replaced", DataType::Json)?;
+        storage.compact()?;
+        assert_eq!(storage.retrieve("configs/04")?, values[4].1);
+        assert_eq!(storage.retrieve("configs/big")?, values[40].1);
+        assert_eq!(storage.stat()?.dead_bytes, 0);
+
+        let mut exported = Vec::new();
+        storage.export_stream(&mut exported)?;
+        let mut copy = UniversalStorage::create(dir.path().join("copy.usf"))?;
+        copy.import_stream(exported.as_slice())?;
+        assert_eq!(copy.retrieve("configs/39")?, values[39].1);
+
+        Ok(())
+    }
+}
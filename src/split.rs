@@ -0,0 +1,248 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+use crate::{Result, UniversalStorage, UsfError};
+
+const MANIFEST_HEADER: &str = "usf-split 1";
+const COPY_BUFFER_SIZE: usize = 1024 * 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPart {
+    /// File name of the part, relative to the manifest
+    pub file_name: String,
+    pub size: u64,
+    pub checksum: u64,
+}
+
+/// Describes how an archive was cut into parts. Written next to the parts
+/// as `<prefix>.manifest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitManifest {
+    pub total_size: u64,
+    pub checksum: u64,
+    pub parts: Vec<SplitPart>,
+}
+
+impl SplitManifest {
+    fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", MANIFEST_HEADER)?;
+        writeln!(writer, "size {}", self.total_size)?;
+        writeln!(writer, "checksum {:016x}", self.checksum)?;
+        for part in &self.parts {
+            writeln!(writer, "part {} {:016x} {}", part.size, part.checksum, part.file_name)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: BufRead>(reader: R) -> Result<Self> {
+        let invalid = |line: &str| UsfError::Corruption(format!("invalid split manifest line: {:?}", line));
+        let mut lines = reader.lines();
+
+        match lines.next().transpose()? {
+            Some(header) if header == MANIFEST_HEADER => {},
+            other => return Err(invalid(other.as_deref().unwrap_or(""))),
+        }
+
+        let mut manifest = SplitManifest { total_size: 0, checksum: 0, parts: Vec::new() };
+        for line in lines {
+            let line = line?;
+            let mut fields = line.splitn(4, ' ');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some("size"), Some(size), None, None) => {
+                    manifest.total_size = size.parse().map_err(|_| invalid(&line))?;
+                },
+                (Some("checksum"), Some(checksum), None, None) => {
+                    manifest.checksum = u64::from_str_radix(checksum, 16).map_err(|_| invalid(&line))?;
+                },
+                (Some("part"), _, _, Some(file_name)) if !is_part_name(file_name) => return Err(invalid(&line)),
+                (Some("part"), Some(size), Some(checksum), Some(file_name)) => manifest.parts.push(SplitPart {
+                    file_name: file_name.to_string(),
+                    size: size.parse().map_err(|_| invalid(&line))?,
+                    checksum: u64::from_str_radix(checksum, 16).map_err(|_| invalid(&line))?,
+                }),
+                (None, ..) | (Some(""), ..) => {},
+                _ => return Err(invalid(&line)),
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+impl UniversalStorage {
+    /// Cuts the archive file into parts of at most `part_size` bytes named
+    /// `<prefix>.part0000`, `<prefix>.part0001`, … plus a
+    /// `<prefix>.manifest` listing them with checksums.
+    pub fn split<P: AsRef<Path>>(&mut self, path_prefix: P, part_size: u64) -> Result<SplitManifest> {
+        if part_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "part size must be non-zero").into());
+        }
+
+        if self.metadata.cold_tier.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a tiered archive must be compacted into one file before splitting").into());
+        }
+
+        let prefix = path_prefix.as_ref();
+        let total_size = self.file.metadata()?.len();
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut whole = Xxh3::new();
+        let mut parts = Vec::new();
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut remaining = total_size;
+
+        while remaining > 0 || parts.is_empty() {
+            let path = suffixed(prefix, &format!(".part{:04}", parts.len()));
+            let mut part_file = File::create(&path)?;
+            let mut part_hash = Xxh3::new();
+            let size = remaining.min(part_size);
+
+            let mut left = size;
+            while left > 0 {
+                let n = (left as usize).min(buffer.len());
+                self.file.read_exact(&mut buffer[..n])?;
+                part_file.write_all(&buffer[..n])?;
+                part_hash.update(&buffer[..n]);
+                whole.update(&buffer[..n]);
+                left -= n as u64;
+            }
+            part_file.sync_all()?;
+
+            parts.push(SplitPart {
+                file_name: file_name(&path),
+                size,
+                checksum: part_hash.digest(),
+            });
+            remaining -= size;
+        }
+
+        let manifest = SplitManifest { total_size, checksum: whole.digest(), parts };
+        let mut manifest_file = File::create(suffixed(prefix, ".manifest"))?;
+        manifest.write_to(&mut manifest_file)?;
+        manifest_file.sync_all()?;
+
+        Ok(manifest)
+    }
+
+    /// Reassembles parts listed in a split manifest into `output`,
+    /// verifying every part, and opens the result. Parts are looked up next
+    /// to the manifest by bare file name. On any error `output` is removed
+    /// rather than left partly written.
+    pub fn join<P: AsRef<Path>, Q: AsRef<Path>>(manifest_path: P, output: Q) -> Result<Self> {
+        let manifest_path = manifest_path.as_ref();
+        let output = output.as_ref();
+        let manifest = SplitManifest::read_from(BufReader::new(File::open(manifest_path)?))?;
+        let dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+        let joined = join_parts(&manifest, dir, output).and_then(|()| Self::open(output));
+        if joined.is_err() {
+            let _ = fs::remove_file(output);
+        }
+        joined
+    }
+}
+
+// Copies every part into `output`, checking each against the manifest
+fn join_parts(manifest: &SplitManifest, dir: &Path, output: &Path) -> Result<()> {
+    let mut out = File::create(output)?;
+    let mut whole = Xxh3::new();
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+
+    for part in &manifest.parts {
+        let mut part_file = File::open(dir.join(&part.file_name))?;
+        let mut part_hash = Xxh3::new();
+        let mut copied = 0u64;
+        loop {
+            let n = part_file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            out.write_all(&buffer[..n])?;
+            part_hash.update(&buffer[..n]);
+            whole.update(&buffer[..n]);
+            copied += n as u64;
+        }
+
+        if copied != part.size || part_hash.digest() != part.checksum {
+            return Err(UsfError::Corruption(format!("split part {} is damaged", part.file_name)));
+        }
+    }
+
+    let size = out.metadata()?.len();
+    if size != manifest.total_size || whole.digest() != manifest.checksum {
+        return Err(UsfError::Corruption("joined archive does not match its manifest".to_string()));
+    }
+    out.sync_all()?;
+    Ok(())
+}
+
+// A single plain path component: parts sit next to the manifest, and
+// anything else could name a file outside its directory
+fn is_part_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+pub(crate) fn suffixed(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_split_and_join() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("whole.usf"))?;
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        storage.store("payload", &data, DataType::Binary)?;
+
+        fs::create_dir(dir.path().join("out"))?;
+        let manifest = storage.split(dir.path().join("out/archive"), 1_000)?;
+        assert!(manifest.parts.len() > 1);
+        assert!(manifest.parts.iter().all(|p| p.size <= 1_000));
+
+        let mut joined = UniversalStorage::join(dir.path().join("out/archive.manifest"), dir.path().join("joined.usf"))?;
+        assert_eq!(joined.retrieve("payload")?, data);
+
+        // A damaged part is detected
+        fs::write(dir.path().join("out/archive.part0001"), b"garbage")?;
+        assert!(matches!(
+            UniversalStorage::join(dir.path().join("out/archive.manifest"), dir.path().join("bad.usf")),
+            Err(UsfError::Corruption(_))
+        ));
+        assert!(!dir.path().join("bad.usf").exists());
+
+        // So is a missing one, which leaves no partial output behind
+        fs::remove_file(dir.path().join("out/archive.part0001"))?;
+        assert!(matches!(
+            UniversalStorage::join(dir.path().join("out/archive.manifest"), dir.path().join("partial.usf")),
+            Err(UsfError::Io(_))
+        ));
+        assert!(!dir.path().join("partial.usf").exists());
+
+        // Part names cannot leave the manifest's directory
+        for name in ["../whole.usf", "/etc/passwd", "nested/part", "..", "."] {
+            let manifest = format!("{}\nsize 1\nchecksum 0\npart 1 0 {}\n", MANIFEST_HEADER, name);
+            fs::write(dir.path().join("out/evil.manifest"), manifest)?;
+            assert!(matches!(
+                UniversalStorage::join(dir.path().join("out/evil.manifest"), dir.path().join("evil.usf")),
+                Err(UsfError::Corruption(_))
+            ), "{}", name);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use crate::format::COLD_DATA_OFFSET;
+use crate::{DataType, Result, UniversalStorage};
+
+const LARGEST_KEYS_REPORTED: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompressionStats {
+    pub entries: usize,
+    pub blocks: u64,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionStats {
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 1.0;
+        }
+        self.original_bytes as f64 / self.compressed_bytes as f64
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageStats {
+    /// Including the cold file of a tiered archive
+    pub file_size: u64,
+    pub key_count: usize,
+    pub block_count: u64,
+    /// Bytes occupied by blocks reachable from the index
+    pub live_bytes: u64,
+    /// Bytes in the data region no longer reachable from the index
+    pub dead_bytes: u64,
+    /// Serialized size of the metadata and key index
+    pub index_bytes: u64,
+    /// Keys with the largest original size, largest first
+    pub largest_keys: Vec<(String, u64)>,
+    pub by_data_type: HashMap<DataType, CompressionStats>,
+}
+
+impl StorageStats {
+    /// Share of the data region occupied by dead bytes, as a percentage.
+    pub fn fragmentation_percent(&self) -> f64 {
+        let total = self.live_bytes + self.dead_bytes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.dead_bytes as f64 * 100.0 / total as f64
+    }
+
+    pub fn original_bytes(&self) -> u64 {
+        self.by_data_type.values().map(|s| s.original_bytes).sum()
+    }
+
+    pub fn compressed_bytes(&self) -> u64 {
+        self.by_data_type.values().map(|s| s.compressed_bytes).sum()
+    }
+}
+
+impl UniversalStorage {
+    /// Number of stored keys.
+    pub fn len(&self) -> usize {
+        self.metadata.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metadata.index.is_empty()
+    }
+
+    /// Sum of the stored values' lengths, as they are read back.
+    pub fn total_logical_bytes(&self) -> u64 {
+        self.metadata.index.values().map(|entry| entry.size).sum()
+    }
+
+    /// Compressed bytes of the blocks the index references, excluding
+    /// block headers. Blocks shared by several keys count once.
+    pub fn total_physical_bytes(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.metadata.index.values()
+            .flat_map(|entry| &entry.blocks)
+            .filter(|loc| seen.insert(loc.offset))
+            .map(|loc| loc.data_size)
+            .sum()
+    }
+
+    /// Logical bytes per physical byte; 1.0 for an empty archive.
+    pub fn compression_ratio(&self) -> f64 {
+        let physical = self.total_physical_bytes();
+        if physical == 0 {
+            return 1.0;
+        }
+        self.total_logical_bytes() as f64 / physical as f64
+    }
+
+    /// Entries, blocks and bytes per data type, like
+    /// [`StorageStats::by_data_type`] but computed from the index alone,
+    /// without reading block headers. `original_bytes` sums value lengths.
+    pub fn data_type_breakdown(&self) -> HashMap<DataType, CompressionStats> {
+        let mut breakdown: HashMap<DataType, CompressionStats> = HashMap::new();
+        let mut seen = HashSet::new();
+        for entry in self.metadata.index.values() {
+            let type_stats = breakdown.entry(entry.data_type.clone()).or_default();
+            type_stats.entries += 1;
+            type_stats.original_bytes += entry.size;
+            for loc in entry.blocks.iter().filter(|loc| seen.insert(loc.offset)) {
+                type_stats.blocks += 1;
+                type_stats.compressed_bytes += loc.data_size;
+            }
+        }
+        breakdown
+    }
+
+    /// Walks the index and block headers to build a report of space usage.
+    pub fn stat(&mut self) -> Result<StorageStats> {
+        let mut stats = StorageStats {
+            file_size: self.file.metadata()?.len(),
+            key_count: self.metadata.index.len(),
+            index_bytes: bincode::serialized_size(&self.metadata)?,
+            ..StorageStats::default()
+        };
+
+        let mut seen_blocks = HashSet::new();
+        let mut key_sizes = Vec::with_capacity(self.metadata.index.len());
+        let index: Vec<_> = self.metadata.index.iter()
+            .map(|(key, entry)| (key.clone(), entry.blocks.clone()))
+            .collect();
+
+        for (key, locations) in index {
+            let mut original_size = 0;
+            let mut data_type = None;
+
+            for loc in &locations {
+                let header = self.read_header(loc)?;
+                original_size += header.original_size;
+
+                // Blocks shared between linked keys are counted once
+                if seen_blocks.insert(loc.offset) {
+                    let type_stats = stats.by_data_type.entry(header.data_type.clone()).or_default();
+                    type_stats.blocks += 1;
+                    type_stats.original_bytes += header.original_size;
+                    type_stats.compressed_bytes += header.compressed_size;
+
+                    stats.block_count += 1;
+                    stats.live_bytes += loc.disk_size();
+                }
+                data_type = Some(header.data_type);
+            }
+
+            if let Some(data_type) = data_type {
+                stats.by_data_type.entry(data_type).or_default().entries += 1;
+            }
+            key_sizes.push((key, original_size));
+        }
+
+        for (_, locations) in self.pinned_chains() {
+            for loc in locations {
+                if seen_blocks.insert(loc.offset) {
+                    stats.live_bytes += loc.disk_size();
+                }
+            }
+        }
+
+        // Both files of a tiered archive count towards the data region
+        let cold_size = self.cold_tier_len()?;
+        stats.file_size += cold_size;
+        let data_region = stats.file_size.saturating_sub(self.data_offset() + self.commit_size() + cold_size.min(COLD_DATA_OFFSET));
+        stats.dead_bytes = data_region.saturating_sub(stats.live_bytes);
+
+        key_sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        key_sizes.truncate(LARGEST_KEYS_REPORTED);
+        stats.largest_keys = key_sizes;
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stat_reports_dead_bytes_after_overwrite() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("stat.usf"))?;
+
+        let text = "stat me ".repeat(512);
+        storage.store("doc", text.as_bytes(), DataType::Text)?;
+        storage.store("bin", &[7u8; 100], DataType::Binary)?;
+
+        // Compaction leaves only the current commit behind the blocks
+        storage.compact()?;
+        let stats = storage.stat()?;
+        assert_eq!(stats.key_count, 2);
+        assert_eq!(stats.block_count, 2);
+        assert_eq!(stats.dead_bytes, 0);
+        assert_eq!(stats.largest_keys[0], ("doc".to_string(), text.len() as u64));
+        assert!(stats.by_data_type[&DataType::Text].compression_ratio() > 1.0);
+
+        // Overwriting leaves the previous blocks and commit behind
+        let superseded = storage.metadata.index["bin"].blocks[0].disk_size() + storage.commit_size();
+        storage.store("bin", &[8u8; 100], DataType::Binary)?;
+        let stats = storage.stat()?;
+        assert_eq!(stats.dead_bytes, superseded);
+        assert!(stats.fragmentation_percent() > 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_totals_from_metadata() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("totals.usf"))?;
+        assert!(storage.is_empty());
+        assert_eq!(storage.compression_ratio(), 1.0);
+
+        let text = "total me ".repeat(512);
+        storage.store("doc", text.as_bytes(), DataType::Text)?;
+        storage.store("bin", &[7u8; 100], DataType::Binary)?;
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.total_logical_bytes(), text.len() as u64 + 100);
+        assert!(storage.compression_ratio() > 1.0);
+
+        // Agrees with the header walk
+        let stats = storage.stat()?;
+        assert_eq!(storage.total_physical_bytes(), stats.compressed_bytes());
+        let breakdown = storage.data_type_breakdown();
+        assert_eq!(breakdown[&DataType::Text], stats.by_data_type[&DataType::Text]);
+        assert_eq!(breakdown[&DataType::Binary].compressed_bytes, 100);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::time::Instant;
+use crate::format::{BlockHeader, BLOCK_HEADER_PREFIX_SIZE};
+use crate::limits::check_limit;
+use crate::throttle::Permits;
+use crate::{clamp, textdelta, Block, BlockLocation, DataType, IndexEntry, Result, UniversalStorage, UsfError};
+
+// Upper bound on bytes fetched by one coalesced read
+const COALESCE_LIMIT: u64 = 1024 * 1024;
+
+/// Reads a stored value one block at a time, so only a single decompressed
+/// block is held in memory. Returned by [`UniversalStorage::open_reader`].
+pub struct ValueReader<'a> {
+    storage: &'a mut UniversalStorage,
+    blocks: Vec<(BlockLocation, Range<usize>)>,
+    next_block: usize,
+    buffer: Vec<u8>,
+    position: usize,
+    end: usize,
+    // Throttle slots for `key`, when read through open_reader
+    key: String,
+    permits: Permits,
+    // The blocks hold a text delta log, replayed on the first read
+    replay: bool,
+}
+
+impl<'a> ValueReader<'a> {
+    pub(crate) fn new(storage: &'a mut UniversalStorage, entry: &IndexEntry) -> Self {
+        Self {
+            storage,
+            blocks: entry.block_ranges(),
+            next_block: 0,
+            buffer: Vec::new(),
+            position: 0,
+            end: 0,
+            key: String::new(),
+            permits: Permits::default(),
+            replay: entry.text_deltas.is_some(),
+        }
+    }
+}
+
+impl Read for ValueReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.replay {
+            self.replay = false;
+            let mut log = Vec::new();
+            for (location, _) in std::mem::take(&mut self.blocks) {
+                log.extend(self.storage.load_block(&location)?);
+            }
+            self.buffer = textdelta::replay(&log)?;
+            (self.position, self.end) = (0, self.buffer.len());
+        }
+        while self.position == self.end {
+            let Some((location, range)) = self.blocks.get(self.next_block) else {
+                return Ok(0);
+            };
+            let (location, range) = (location.clone(), range.clone());
+            self.buffer = self.storage.load_block(&location)?;
+            let range = clamp(&range, self.buffer.len());
+            (self.position, self.end) = (range.start, range.end);
+            self.next_block += 1;
+        }
+
+        let n = out.len().min(self.end - self.position);
+        self.permits.charge(&self.key, n as u64);
+        out[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Where one key's value sits within a [`MultiValueReader`] stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySpan {
+    pub key: String,
+    /// Stream position of the value's first byte
+    pub start: u64,
+    pub size: u64,
+}
+
+/// Streams several values back to back. Blocks adjacent on disk are
+/// fetched with a single read. Once dropped, each value read from, wholly
+/// or in part, counts as a retrieval in [`UniversalStorage::metrics`].
+pub struct MultiValueReader<'a> {
+    storage: &'a mut UniversalStorage,
+    spans: Vec<KeySpan>,
+    blocks: Vec<(BlockLocation, Range<usize>)>,
+    next_block: usize,
+    // Values replayed from text delta logs up front, each with the index
+    // in `blocks` it comes before
+    replayed: VecDeque<(usize, Vec<u8>)>,
+    decoded: VecDeque<(Vec<u8>, Range<usize>)>,
+    buffer: Vec<u8>,
+    position: usize,
+    end: usize,
+    emitted: u64,
+    permits: Permits,
+    started: Instant,
+}
+
+impl MultiValueReader<'_> {
+    /// Every requested key with its place in the stream, in request order.
+    pub fn spans(&self) -> &[KeySpan] {
+        &self.spans
+    }
+
+    /// The key whose bytes the next read returns, or `None` at the end.
+    pub fn current_key(&self) -> Option<&str> {
+        self.spans.iter()
+            .find(|span| self.emitted < span.start + span.size)
+            .map(|span| span.key.as_str())
+    }
+
+    // Decodes the next run of contiguous blocks
+    fn fetch(&mut self) -> Result<()> {
+        let first = self.next_block;
+        let start = self.blocks[first].0.offset;
+        let mut end = start + self.blocks[first].0.disk_size();
+        let mut last = first + 1;
+        let stop = self.replayed.front().map_or(self.blocks.len(), |(before, _)| *before);
+        while let Some((next, _)) = self.blocks.get(last).filter(|_| last < stop) {
+            if next.offset != end || end + next.disk_size() - start > COALESCE_LIMIT {
+                break;
+            }
+            end += next.disk_size();
+            last += 1;
+        }
+
+        let limits = self.storage.limits.clone();
+        for (location, _) in &self.blocks[first..last] {
+            check_limit(limits.as_ref(), "block header size", location.header_size as u64, |l| l.max_header_size)?;
+            check_limit(limits.as_ref(), "block size", location.data_size, |l| l.max_block_size)?;
+        }
+
+        let mut raw = vec![0u8; (end - start) as usize];
+        self.storage.read_at(&mut raw, start)?;
+
+        for (location, range) in &self.blocks[first..last] {
+            let at = (location.offset - start) as usize;
+            let header_start = at + BLOCK_HEADER_PREFIX_SIZE as usize;
+            let data_start = header_start + location.header_size as usize;
+            let data_end = data_start + location.data_size as usize;
+
+            let header_size = u32::from_le_bytes(raw[at..header_start].try_into().expect("4-byte prefix"));
+            if header_size != location.header_size {
+                return Err(UsfError::Corruption(format!("block header size mismatch at offset {}", location.offset)));
+            }
+            let header = BlockHeader::decode(&raw[header_start..data_start], location.offset)?;
+            if header.compressed_size != location.data_size {
+                return Err(UsfError::Corruption(format!("block size mismatch at offset {}", location.offset)));
+            }
+
+            let block = Block { header, data: raw[data_start..data_end].to_vec() };
+            let data = self.storage.unpack_block(location, block)?;
+            let range = clamp(range, data.len());
+            self.decoded.push_back((data, range));
+        }
+
+        self.next_block = last;
+        Ok(())
+    }
+
+    // Makes bytes available in `buffer`; false at the end of the stream
+    fn fill(&mut self) -> io::Result<bool> {
+        while self.position == self.end {
+            if self.decoded.is_empty() {
+                if self.replayed.front().is_some_and(|(before, _)| *before == self.next_block) {
+                    let (_, value) = self.replayed.pop_front().expect("checked above");
+                    let len = value.len();
+                    self.decoded.push_back((value, 0..len));
+                } else if self.next_block == self.blocks.len() {
+                    return Ok(false);
+                } else {
+                    self.fetch()?;
+                }
+            }
+            let (buffer, range) = self.decoded.pop_front().unwrap_or_default();
+            self.buffer = buffer;
+            (self.position, self.end) = (range.start, range.end);
+        }
+        Ok(true)
+    }
+
+    /// Skips up to `n` bytes of the stream, returning the bytes skipped,
+    /// fewer only at its end. Blocks skipped whole are sized from their
+    /// headers and never read or decompressed, so seeking deep into a
+    /// value costs one header read per block passed over.
+    pub fn skip(&mut self, n: u64) -> Result<u64> {
+        let mut skipped = 0;
+        while skipped < n {
+            let at_block = self.position == self.end
+                && self.decoded.is_empty()
+                && self.next_block < self.blocks.len()
+                && self.replayed.front().is_none_or(|(before, _)| *before != self.next_block);
+            if at_block {
+                let (location, range) = &self.blocks[self.next_block];
+                let size = self.storage.read_header(location)?.original_size as usize;
+                let len = clamp(range, size).len() as u64;
+                if skipped + len <= n {
+                    self.next_block += 1;
+                    self.emitted += len;
+                    skipped += len;
+                    continue;
+                }
+            }
+            if !self.fill()? {
+                break;
+            }
+            let len = (self.end - self.position).min((n - skipped) as usize);
+            self.position += len;
+            self.emitted += len as u64;
+            skipped += len as u64;
+        }
+        Ok(skipped)
+    }
+}
+
+impl Read for MultiValueReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if !self.fill()? {
+            return Ok(0);
+        }
+        let n = out.len().min(self.end - self.position);
+        if let Some(key) = self.current_key() {
+            self.permits.charge(key, n as u64);
+        }
+        out[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        self.emitted += n as u64;
+        Ok(n)
+    }
+}
+
+impl Drop for MultiValueReader<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        for span in &self.spans {
+            let read = self.emitted.min(span.start + span.size).saturating_sub(span.start);
+            if read > 0 {
+                self.storage.record_retrieval(&span.key, read, elapsed);
+            }
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// A reader over the value stored under `key`, decompressing one block
+    /// at a time. A [`DataType::Reference`] is resolved up front, so its
+    /// target is buffered.
+    pub fn open_reader(&mut self, key: &str) -> Result<ValueReader<'_>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        let entry = self.metadata.index.get(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+            .clone();
+        if entry.data_type == DataType::Reference {
+            let data = self.retrieve(&key)?;
+            let end = data.len();
+            let permits = Permits::default();
+            return Ok(ValueReader { storage: self, blocks: Vec::new(), next_block: 0, buffer: data, position: 0, end, key, permits, replay: false });
+        }
+
+        if self.access_tracking {
+            self.record_access(&key);
+        }
+        let permits = self.admit([key.as_str()]);
+        Ok(ValueReader { key, permits, ..ValueReader::new(self, &entry) })
+    }
+
+    /// Streams the value stored under `key` into `writer`, holding one
+    /// decompressed block in memory at a time. Returns the bytes written.
+    pub fn retrieve_to<W: Write + ?Sized>(&mut self, key: &str, writer: &mut W) -> Result<u64> {
+        let started = Instant::now();
+        let written = io::copy(&mut self.open_reader(key)?, writer)?;
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.record_retrieval(&key, written, started.elapsed());
+        Ok(written)
+    }
+
+    /// Streams the values of `keys`, in the given order, as one logical
+    /// stream; [`MultiValueReader::spans`] reports where each value starts.
+    /// Suited to reassembling values sharded across `part-0000`,
+    /// `part-0001`, … keys. Fails up front if any key is missing. A
+    /// [`DataType::Reference`] is resolved up front and buffered.
+    pub fn retrieve_many_stream<S: AsRef<str>>(&mut self, keys: &[S]) -> Result<MultiValueReader<'_>> {
+        let mut spans = Vec::with_capacity(keys.len());
+        let mut blocks = Vec::new();
+        let mut replayed = VecDeque::new();
+        let mut start = 0;
+
+        for key in keys {
+            let key = self.metadata.key_policy.canonicalize(key.as_ref())?;
+            let entry = self.metadata.index.get(&key)
+                .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?
+                .clone();
+            // References are resolved up front, as by `open_reader`
+            let size = if entry.data_type == DataType::Reference {
+                let mut payload = Vec::new();
+                ValueReader::new(self, &entry).read_to_end(&mut payload)?;
+                let value = self.resolve_reference(payload)?;
+                let size = value.len() as u64;
+                replayed.push_back((blocks.len(), value));
+                size
+            } else if entry.text_deltas.is_some() {
+                check_limit(self.limits.as_ref(), "entry size", entry.size, |l| l.max_entry_size)?;
+                let mut value = Vec::with_capacity(entry.size as usize);
+                ValueReader::new(self, &entry).read_to_end(&mut value)?;
+                replayed.push_back((blocks.len(), value));
+                entry.size
+            } else {
+                blocks.extend(entry.block_ranges());
+                entry.size
+            };
+            spans.push(KeySpan { key, start, size });
+            start += size;
+        }
+
+        let permits = self.admit(spans.iter().map(|span| span.key.as_str()));
+        Ok(MultiValueReader {
+            storage: self,
+            spans,
+            blocks,
+            next_block: 0,
+            replayed,
+            decoded: VecDeque::new(),
+            buffer: Vec::new(),
+            position: 0,
+            end: 0,
+            emitted: 0,
+            permits,
+            started: Instant::now(),
+        })
+    }
+}
+
+// Fills `buf` as far as the reader allows, returning the bytes read
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{UsfOptions, BLOCK_SIZE};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_retrieve_to_streams_block_by_block() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("retrieve_to.usf"))?;
+        let big: Vec<u8> = (0..BLOCK_SIZE * 3 + 7).map(|i| (i % 253) as u8).collect();
+        storage.store("big", &big, DataType::Binary)?;
+
+        let mut out = Vec::new();
+        assert_eq!(storage.retrieve_to("big", &mut out)?, big.len() as u64);
+        assert_eq!(out, big);
+        assert_eq!(storage.metrics().bytes_retrieved, big.len() as u64);
+
+        let mut reader = storage.open_reader("big")?;
+        let mut head = vec![0u8; BLOCK_SIZE + 1];
+        reader.read_exact(&mut head)?;
+        assert_eq!(head, &big[..BLOCK_SIZE + 1]);
+        // Only the block being read is held
+        assert_eq!(reader.buffer.len(), BLOCK_SIZE);
+
+        assert!(matches!(storage.retrieve_to("missing", &mut io::sink()), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieve_many_stream() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("many.usf"))?;
+        let big: Vec<u8> = (0..BLOCK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        storage.store("model/part-0000", &big, DataType::Binary)?;
+        storage.store("model/part-0001", b"tail", DataType::Binary)?;
+        storage.store("other", b"unrelated", DataType::Binary)?;
+
+        let mut reader = storage.retrieve_many_stream(&["other", "model/part-0000", "model/part-0001"])?;
+        assert_eq!(reader.current_key(), Some("other"));
+        assert_eq!(reader.spans()[1], KeySpan { key: "model/part-0000".to_string(), start: 9, size: big.len() as u64 });
+
+        let mut head = [0u8; 9];
+        reader.read_exact(&mut head)?;
+        assert_eq!(&head, b"unrelated");
+        assert_eq!(reader.current_key(), Some("model/part-0000"));
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(&rest[..big.len()], big.as_slice());
+        assert_eq!(&rest[big.len()..], b"tail");
+        assert_eq!(reader.current_key(), None);
+        drop(reader);
+        assert_eq!((storage.metrics().retrieves, storage.metrics().bytes_retrieved), (3, rest.len() as u64 + 9));
+
+        assert!(matches!(storage.retrieve_many_stream(&["missing"]), Err(UsfError::KeyNotFound(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_leaves_skipped_blocks_undecoded() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("skip.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        let big: Vec<u8> = (0..BLOCK_SIZE * 3 + 7).map(|i| (i % 249) as u8).collect();
+        storage.store("big", &big, DataType::Binary)?;
+        storage.store("small", b"after", DataType::Binary)?;
+
+        // Damage the data of the first two blocks; skipping them must not
+        // notice
+        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        for location in &storage.metadata.index["big"].blocks[..2] {
+            let data = location.offset + BLOCK_HEADER_PREFIX_SIZE + location.header_size as u64;
+            crate::platform::write_all_at(&file, &[0xff; 16], data)?;
+        }
+
+        let mut reader = storage.retrieve_many_stream(&["big", "small"])?;
+        assert_eq!(reader.skip(BLOCK_SIZE as u64 * 2 + 5)?, BLOCK_SIZE as u64 * 2 + 5);
+        assert_eq!(reader.current_key(), Some("big"));
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(&rest[..BLOCK_SIZE + 2], &big[BLOCK_SIZE * 2 + 5..]);
+        assert_eq!(&rest[BLOCK_SIZE + 2..], b"after");
+        drop(reader);
+
+        let mut reader = storage.retrieve_many_stream(&["small"])?;
+        assert_eq!(reader.skip(100)?, 5);
+
+        Ok(())
+    }
+}
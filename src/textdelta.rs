@@ -0,0 +1,288 @@
+use std::io::Read;
+use std::time::{Duration, Instant};
+use bincode::Options;
+use serde::{Serialize, Deserialize};
+use similar::{Algorithm, DiffOp};
+use xxhash_rust::xxh3::xxh3_128;
+use crate::stream::ValueReader;
+use crate::{chunk_size, DataType, EntryOptions, Result, UniversalStorage, UsfError};
+
+// Versions between full snapshots unless a rule says otherwise
+const DEFAULT_SNAPSHOT_EVERY: u32 = 16;
+// Longest a diff may search before settling for a coarser one
+const DIFF_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Stores values under `prefix` as a log: a full snapshot followed by the
+/// line-based delta of each later version against the one before it.
+/// Overwriting such a value with [`UniversalStorage::store`] appends only
+/// what changed, and every `snapshot_every` versions a fresh snapshot
+/// replaces the log, bounding the work a read takes. Reads replay the log
+/// and return the value as stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TextDeltaRule {
+    pub prefix: String,
+    pub snapshot_every: u32,
+}
+
+impl TextDeltaRule {
+    pub fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string(), snapshot_every: DEFAULT_SNAPSHOT_EVERY }
+    }
+
+    pub fn snapshot_every(mut self, versions: u32) -> Self {
+        self.snapshot_every = versions;
+        self
+    }
+}
+
+// One record of a value's log, concatenated in bincode
+#[derive(Serialize, Deserialize, Debug)]
+enum LogRecord {
+    Snapshot(Vec<u8>),
+    // Edits turning the previous version into the next
+    Delta(Vec<LineEdit>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum LineEdit {
+    // `count` lines of the previous version, from line `start`
+    Copy { start: u64, count: u64 },
+    Insert(Vec<u8>),
+}
+
+impl UniversalStorage {
+    /// Stores values under the rule's prefix as text delta logs from now
+    /// on. Only [`UniversalStorage::store`] writes deltas; values stored
+    /// any other way are written whole, and the next store starts a new
+    /// log from them.
+    pub fn add_text_delta_rule(&mut self, rule: TextDeltaRule) -> Result<()> {
+        self.metadata.text_delta_rules.push(rule);
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn text_delta_rules(&self) -> &[TextDeltaRule] {
+        &self.metadata.text_delta_rules
+    }
+
+    pub fn clear_text_delta_rules(&mut self) -> Result<()> {
+        self.metadata.text_delta_rules.clear();
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Deltas recorded after the last snapshot of `key`, or `None` if it
+    /// is not stored as a text delta log.
+    pub fn text_deltas(&self, key: &str) -> Result<Option<u32>> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.metadata.index.get(&key)
+            .map(|entry| entry.text_deltas)
+            .ok_or(UsfError::KeyNotFound(key))
+    }
+
+    // Snapshot interval of the rule covering `key`, if any. References
+    // hold no text.
+    pub(crate) fn text_delta_rule_for(&self, key: &str, data_type: &DataType) -> Option<u32> {
+        if *data_type == DataType::Reference {
+            return None;
+        }
+        self.metadata.text_delta_rules.iter()
+            .find(|rule| key.starts_with(rule.prefix.as_str()))
+            .map(|rule| rule.snapshot_every)
+    }
+
+    // Stores `data` as the next version in the log of `key`, or as the
+    // snapshot starting a new one
+    pub(crate) fn store_text_delta(&mut self, key: String, data: &[u8], data_type: DataType, snapshot_every: u32) -> Result<()> {
+        let content_hash = xxh3_128(data);
+        let previous = self.metadata.index.get(&key)
+            .filter(|entry| entry.data_type == data_type && entry.text_deltas.is_some_and(|deltas| deltas + 1 < snapshot_every))
+            .cloned();
+        let encoding = self.encoding_for(&key, &data_type);
+
+        // A log shared through a link is left to the other keys
+        if let Some(entry) = previous.filter(|_| self.ref_count(&key).is_ok_and(|refs| refs == 1)) {
+            let mut old = Vec::with_capacity(entry.size as usize);
+            ValueReader::new(self, &entry).read_to_end(&mut old)?;
+            let record = log_options().serialize(&LogRecord::Delta(diff(&old, data)))?;
+            if record.len() < data.len() {
+                let blocks = Self::prepare_chunks(&record, entry.block_size as usize, data_type, &encoding)?;
+                let (locations, size) = self.write_blocks(&blocks)?;
+                self.metadata.total_blocks += locations.len() as u64;
+                let now = self.now();
+                let entry = self.metadata.index.get_mut(&key).expect("checked above");
+                entry.blocks.extend(locations);
+                entry.size = data.len() as u64;
+                entry.stored_at = now;
+                entry.content_hash = Some(content_hash);
+                entry.text_deltas = entry.text_deltas.map(|deltas| deltas + 1);
+                // As for any store, the new version starts without them
+                entry.hint = None;
+                entry.attributes.clear();
+
+                self.record_store(size);
+                self.metadata.modified = now;
+                return self.update_metadata();
+            }
+        }
+
+        let log = log_options().serialize(&LogRecord::Snapshot(data.to_vec()))?;
+        let blocks = Self::prepare_blocks(&log, data_type.clone(), &encoding)?;
+        let (locations, _) = self.write_blocks(&blocks)?;
+        let options = EntryOptions {
+            content_hash: Some(content_hash),
+            block_size: Some(chunk_size(&blocks)),
+            text_deltas: Some(0),
+            ..EntryOptions::default()
+        };
+        self.commit_entry(key, locations, data.len() as u64, data_type, options)
+    }
+}
+
+// Bincode as `bincode::serialize` writes it, with every read bounded by
+// the bytes left in the log
+fn log_options() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes()
+}
+
+// Lines of `data`, each with its terminating newline
+fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+fn diff(old: &[u8], new: &[u8]) -> Vec<LineEdit> {
+    let (old_lines, new_lines) = (lines(old), lines(new));
+    let deadline = Instant::now() + DIFF_TIMEOUT;
+    similar::capture_diff_slices_deadline(Algorithm::Myers, &old_lines, &new_lines, Some(deadline))
+        .into_iter()
+        .filter_map(|op| match op {
+            DiffOp::Equal { old_index, len, .. } => Some(LineEdit::Copy { start: old_index as u64, count: len as u64 }),
+            DiffOp::Delete { .. } => None,
+            DiffOp::Insert { new_index, new_len, .. } | DiffOp::Replace { new_index, new_len, .. } => {
+                Some(LineEdit::Insert(new_lines[new_index..new_index + new_len].concat()))
+            },
+        })
+        .collect()
+}
+
+fn apply(old: &[u8], edits: &[LineEdit]) -> Result<Vec<u8>> {
+    let old_lines = lines(old);
+    let mut value = Vec::with_capacity(old.len());
+    for edit in edits {
+        match edit {
+            LineEdit::Copy { start, count } => {
+                let copied = usize::try_from(*start).ok()
+                    .zip(usize::try_from(*count).ok())
+                    .and_then(|(start, count)| old_lines.get(start..start.checked_add(count)?))
+                    .ok_or_else(|| UsfError::Corruption(format!("text delta copies lines {}..{} of {}", start, start.saturating_add(*count), old_lines.len())))?;
+                copied.iter().for_each(|line| value.extend_from_slice(line));
+            },
+            LineEdit::Insert(bytes) => value.extend_from_slice(bytes),
+        }
+    }
+    Ok(value)
+}
+
+// The latest version in a value's log
+pub(crate) fn replay(mut log: &[u8]) -> Result<Vec<u8>> {
+    let mut value: Option<Vec<u8>> = None;
+    while !log.is_empty() {
+        let record = log_options().with_limit(log.len() as u64).deserialize_from(&mut log)?;
+        value = Some(match record {
+            LogRecord::Snapshot(data) => data,
+            LogRecord::Delta(edits) => {
+                let previous = value.ok_or_else(|| UsfError::Corruption("text delta log starts with a delta".to_string()))?;
+                apply(&previous, &edits)?
+            },
+        });
+    }
+    value.ok_or_else(|| UsfError::Corruption("empty text delta log".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UsfOptions;
+    use std::io;
+    use tempfile::tempdir;
+
+    fn config(version: usize) -> String {
+        (0..200).map(|line| match line {
+            17 => format!("version = {}\n", version),
+            _ => format!("setting_{} = {}\n", line, line * 7),
+        }).collect()
+    }
+
+    #[test]
+    fn test_diff_round_trip() -> io::Result<()> {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"a\nb\nc\n", b"a\nB\nc\nd\n"),
+            (b"", b"first\n"),
+            (b"no newline", b"no newline\nnow two"),
+            (b"drop\nall\n", b""),
+            (b"same\n", b"same\n"),
+        ];
+        for (old, new) in cases {
+            assert_eq!(apply(old, &diff(old, new))?, new);
+        }
+        assert_eq!(diff(b"a\nb\nc\n", b"a\nx\nc\n")[0], LineEdit::Copy { start: 0, count: 1 });
+        assert!(matches!(apply(b"one\n", &[LineEdit::Copy { start: 1, count: 1 }]), Err(UsfError::Corruption(_))));
+        assert!(replay(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_delta_versions() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("deltas.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.add_text_delta_rule(TextDeltaRule::new("configs/").snapshot_every(3))?;
+        storage.store("plain", config(0).as_bytes(), DataType::Text)?;
+
+        storage.store("configs/app.toml", config(1).as_bytes(), DataType::Text)?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, Some(0));
+        let snapshot_blocks = storage.metadata.index["configs/app.toml"].blocks.len();
+        storage.store("configs/app.toml", config(2).as_bytes(), DataType::Text)?;
+        storage.store("configs/app.toml", config(3).as_bytes(), DataType::Text)?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, Some(2));
+        assert_eq!(storage.metadata.index["configs/app.toml"].blocks.len(), snapshot_blocks + 2);
+        // Superseded versions cost nothing: the log was only appended to
+        assert_eq!(storage.freed_bytes(), 0);
+
+        // Every read path sees the latest version
+        let expected = config(3);
+        assert_eq!(storage.retrieve("configs/app.toml")?, expected.as_bytes());
+        assert_eq!(storage.retrieve_range("configs/app.toml", 100, 20)?, &expected.as_bytes()[100..120]);
+        let mut streamed = Vec::new();
+        storage.retrieve_to("configs/app.toml", &mut streamed)?;
+        assert_eq!(streamed, expected.as_bytes());
+        let many = storage.retrieve_many(&["plain", "configs/app.toml"]);
+        assert_eq!(many[1].as_deref().ok(), Some(expected.as_bytes()));
+        let mut joined = Vec::new();
+        storage.retrieve_many_stream(&["configs/app.toml", "plain", "configs/app.toml"])?.read_to_end(&mut joined)?;
+        assert_eq!(joined, [expected.as_bytes(), config(0).as_bytes(), expected.as_bytes()].concat());
+        assert_eq!(storage.entry_info("configs/app.toml")?.original_size, expected.len() as u64);
+        assert!(storage.holds_value("configs/app.toml", expected.as_bytes(), &DataType::Text)?);
+
+        // The interval starts a new log, and so does a rewrite
+        storage.store("configs/app.toml", config(4).as_bytes(), DataType::Text)?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, Some(0));
+        storage.store("configs/app.toml", b"short", DataType::Text)?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, Some(0));
+        storage.append("configs/app.toml", b" and appended")?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, None);
+        assert_eq!(storage.retrieve("configs/app.toml")?, b"short and appended");
+        assert_eq!(storage.text_deltas("plain")?, None);
+
+        storage.store("configs/app.toml", config(5).as_bytes(), DataType::Text)?;
+        storage.store("configs/app.toml", config(6).as_bytes(), DataType::Text)?;
+        storage.compact()?;
+        drop(storage);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.text_deltas("configs/app.toml")?, Some(1));
+        assert_eq!(storage.retrieve("configs/app.toml")?, config(6).as_bytes());
+
+        Ok(())
+    }
+}
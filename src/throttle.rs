@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::UniversalStorage;
+
+/// How hard the keys under one namespace may read an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TenantLimits {
+    /// Retrievals of the namespace's keys in progress at once, across
+    /// every handle sharing the [`Throttle`]; at least one is always let
+    /// through
+    pub max_concurrent: Option<usize>,
+    /// Value bytes returned per second, with bursts of up to one second's
+    /// worth
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+/// Per-namespace read limits, enforced inside retrieval itself rather than
+/// by whatever serves the data. A namespace is a key prefix; a key belongs
+/// to the longest configured prefix it starts with, and keys outside every
+/// namespace are not limited.
+///
+/// Clones share their state, so one `Throttle` set on several handles to
+/// the same archive (such as one per worker thread) limits each tenant
+/// across all of them. Limits can be changed while reads are in progress.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    inner: Arc<Tenants>,
+}
+
+#[derive(Default)]
+struct Tenants {
+    // By namespace prefix
+    tenants: Mutex<BTreeMap<String, Tenant>>,
+    released: Condvar,
+}
+
+struct Tenant {
+    limits: TenantLimits,
+    active: usize,
+    // Token bucket for max_bytes_per_sec; negative while in debt
+    allowance: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_limits(&self, prefix: &str, limits: TenantLimits) {
+        let mut tenants = self.tenants();
+        let tenant = tenants.entry(prefix.to_string()).or_insert_with(|| Tenant {
+            limits,
+            active: 0,
+            allowance: 0.0,
+            refilled: Instant::now(),
+        });
+        tenant.limits = limits;
+        tenant.allowance = limits.max_bytes_per_sec.unwrap_or(0) as f64;
+        tenant.refilled = Instant::now();
+        drop(tenants);
+        self.inner.released.notify_all();
+    }
+
+    pub fn remove_limits(&self, prefix: &str) {
+        self.tenants().remove(prefix);
+        self.inner.released.notify_all();
+    }
+
+    pub fn limits(&self, prefix: &str) -> Option<TenantLimits> {
+        self.tenants().get(prefix).map(|tenant| tenant.limits)
+    }
+
+    /// The namespace `key` is limited under, if any.
+    pub fn namespace_of(&self, key: &str) -> Option<String> {
+        namespace_of(&self.tenants(), key).map(str::to_string)
+    }
+
+    /// Retrievals of keys under `prefix` currently in progress.
+    pub fn active(&self, prefix: &str) -> usize {
+        self.tenants().get(prefix).map_or(0, |tenant| tenant.active)
+    }
+
+    // Waits until a retrieval of every key in `keys` may start. Namespaces
+    // are taken in sorted order, so readers of several never deadlock.
+    fn admit<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> Permits {
+        let mut tenants = self.tenants();
+        let mut namespaces: Vec<String> = keys.into_iter()
+            .filter_map(|key| namespace_of(&tenants, key).map(str::to_string))
+            .collect();
+        namespaces.sort();
+        namespaces.dedup();
+
+        let mut held = Vec::with_capacity(namespaces.len());
+        for namespace in namespaces {
+            while let Some(tenant) = tenants.get_mut(&namespace) {
+                if tenant.active < tenant.limits.max_concurrent.unwrap_or(usize::MAX).max(1) {
+                    tenant.active += 1;
+                    held.push(namespace);
+                    break;
+                }
+                tenants = self.inner.released.wait(tenants).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+        Permits { throttle: Some(self.clone()), held }
+    }
+
+    // Takes `bytes` from the allowance of `key`'s namespace, sleeping off
+    // any debt
+    fn charge(&self, key: &str, bytes: u64) {
+        let wait = {
+            let mut tenants = self.tenants();
+            let Some(namespace) = namespace_of(&tenants, key).map(str::to_string) else {
+                return;
+            };
+            let tenant = tenants.get_mut(&namespace).expect("namespace just found");
+            let Some(rate) = tenant.limits.max_bytes_per_sec else {
+                return;
+            };
+            let rate = rate.max(1) as f64;
+            let now = Instant::now();
+            let refill = now.duration_since(tenant.refilled).as_secs_f64() * rate;
+            tenant.allowance = (tenant.allowance + refill).min(rate) - bytes as f64;
+            tenant.refilled = now;
+            if tenant.allowance >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-tenant.allowance / rate)
+        };
+        thread::sleep(wait);
+    }
+
+    fn tenants(&self) -> MutexGuard<'_, BTreeMap<String, Tenant>> {
+        self.inner.tenants.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Longest configured prefix of `key`
+fn namespace_of<'t>(tenants: &'t BTreeMap<String, Tenant>, key: &str) -> Option<&'t str> {
+    tenants.keys()
+        .filter(|prefix| key.starts_with(prefix.as_str()))
+        .max_by_key(|prefix| prefix.len())
+        .map(String::as_str)
+}
+
+// Concurrency slots held by one retrieval, released on drop
+#[derive(Default)]
+pub(crate) struct Permits {
+    throttle: Option<Throttle>,
+    held: Vec<String>,
+}
+
+impl Permits {
+    // Counts `bytes` of `key`'s value against its namespace's bandwidth
+    pub(crate) fn charge(&self, key: &str, bytes: u64) {
+        if let Some(throttle) = &self.throttle {
+            throttle.charge(key, bytes);
+        }
+    }
+}
+
+impl Drop for Permits {
+    fn drop(&mut self) {
+        let Some(throttle) = &self.throttle else {
+            return;
+        };
+        let mut tenants = throttle.tenants();
+        for namespace in &self.held {
+            if let Some(tenant) = tenants.get_mut(namespace) {
+                tenant.active = tenant.active.saturating_sub(1);
+            }
+        }
+        drop(tenants);
+        throttle.inner.released.notify_all();
+    }
+}
+
+impl UniversalStorage {
+    /// Limits how fast and how many at once the keys of each namespace
+    /// are retrieved through this handle. See [`Throttle`].
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    pub fn throttle(&self) -> Option<&Throttle> {
+        self.throttle.as_ref()
+    }
+
+    // Waits for the throttle, if any, to let a retrieval of `keys` start
+    pub(crate) fn admit<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> Permits {
+        match &self.throttle {
+            Some(throttle) => throttle.admit(keys),
+            None => Permits::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io::{self, Read};
+    use std::sync::mpsc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_throttle_limits_each_namespace() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("throttle.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("bulk/video", &[7u8; 4000], DataType::Binary)?;
+        storage.store("bulk/fast/thumb", &[1u8; 4000], DataType::Binary)?;
+        storage.store("web/page", b"<html>", DataType::Text)?;
+        drop(storage);
+
+        let throttle = Throttle::new();
+        throttle.set_limits("bulk/", TenantLimits { max_concurrent: Some(1), max_bytes_per_sec: Some(10_000) });
+        throttle.set_limits("bulk/fast/", TenantLimits::default());
+        assert_eq!(throttle.namespace_of("bulk/fast/thumb").as_deref(), Some("bulk/fast/"));
+        assert_eq!(throttle.namespace_of("web/page"), None);
+
+        let mut storage = UniversalStorage::open(&path)?;
+        storage.set_throttle(Some(throttle.clone()));
+
+        // The first read spends the burst allowance; the second waits it off
+        let started = Instant::now();
+        assert_eq!(storage.retrieve("bulk/video")?.len(), 4000);
+        assert_eq!(storage.retrieve_range("bulk/video", 0, 4000)?.len(), 4000);
+        assert_eq!(storage.retrieve("bulk/video")?.len(), 4000);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        // A second handle waits while the namespace's only slot is held
+        let (sender, receiver) = mpsc::channel();
+        let mut reader = storage.open_reader("bulk/video")?;
+        assert_eq!(throttle.active("bulk/"), 1);
+        let other = thread::spawn({
+            let (path, throttle) = (path.clone(), throttle.clone());
+            move || {
+                let mut storage = UniversalStorage::open(&path).expect("open");
+                storage.set_throttle(Some(throttle));
+                sender.send(storage.retrieve("web/page").expect("web")).expect("send");
+                sender.send(storage.retrieve("bulk/video").expect("bulk")).expect("send");
+            }
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).expect("unlimited key"), b"<html>");
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        let mut value = Vec::new();
+        reader.read_to_end(&mut value)?;
+        drop(reader);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).expect("admitted"), value);
+        other.join().expect("join");
+        assert_eq!(throttle.active("bulk/"), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::format::{COLD_DATA_OFFSET, COLD_MAGIC_BYTES, COLD_TIER_BASE};
+use crate::placement::Hint;
+use crate::split::suffixed;
+use crate::{platform, CompactionReport, IndexEntry, Result, UniversalStorage, UsfError};
+
+/// Decides which entries [`UniversalStorage::compact_tiered`] moves to the
+/// cold file. An entry goes cold when any set criterion holds; placement
+/// hints override both.
+#[derive(Debug, Clone, Default)]
+pub struct TierPolicy {
+    /// Entries stored longer ago than this
+    pub cold_after: Option<Duration>,
+    /// Entries read fewer times than this. Reads are only counted while
+    /// access tracking is enabled.
+    pub min_hot_reads: Option<u64>,
+}
+
+impl TierPolicy {
+    fn is_cold(&self, entry: &IndexEntry, reads: u64, now: DateTime<Utc>) -> bool {
+        match entry.hint {
+            Some(Hint::Cold) => true,
+            Some(Hint::Hot) => false,
+            _ => self.cold_after.is_some_and(|age| now - entry.stored_at > age)
+                || self.min_hot_reads.is_some_and(|min| reads < min),
+        }
+    }
+}
+
+// The cold file of a tiered archive. `id` is also written at the start of
+// the file, so a file from another compaction is never read by mistake.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ColdTier {
+    // Relative paths are relative to the archive's directory
+    path: PathBuf,
+    id: u64,
+}
+
+impl UniversalStorage {
+    /// Like [`UniversalStorage::compact`], but writes the blocks of entries
+    /// `policy` selects to a separate cold file at `cold_path`, so the main
+    /// file stays small and the two can live on different disks. The index
+    /// stays in the main file and covers both; new writes always go to the
+    /// main file. A relative `cold_path` is taken relative to the archive's
+    /// directory. Trashed entries always go cold.
+    ///
+    /// A later plain [`UniversalStorage::compact`] folds the cold file
+    /// back into the main one and removes it.
+    pub fn compact_tiered<P: AsRef<Path>>(&mut self, cold_path: P, policy: &TierPolicy) -> Result<CompactionReport> {
+        self.compact_into(Some((cold_path.as_ref(), policy)))
+    }
+
+    /// Location of the cold file, for archives compacted with
+    /// [`UniversalStorage::compact_tiered`].
+    pub fn cold_tier_path(&self) -> Option<PathBuf> {
+        self.metadata.cold_tier.as_ref().map(|tier| self.resolve_cold_path(&tier.path))
+    }
+
+    // Whether compaction with `policy` places `entry` in the cold file
+    pub(crate) fn goes_cold(&self, key: &str, entry: &IndexEntry, policy: &TierPolicy, now: DateTime<Utc>) -> bool {
+        let reads = self.metadata.access.get(key).map_or(0, |stats| stats.read_count);
+        policy.is_cold(entry, reads, now)
+    }
+
+    // Starts a cold file for compaction at `<path>.compact`, returning the
+    // tier that will describe it once renamed into place
+    pub(crate) fn create_cold_tier(&self, path: &Path) -> Result<(ColdTier, File)> {
+        let stamp = format!("{:?}{}{}", Utc::now(), path.display(), self.metadata.generation);
+        let tier = ColdTier { path: path.to_path_buf(), id: xxh3_64(stamp.as_bytes()) };
+
+        let resolved = self.resolve_cold_path(path);
+        if let Some(parent) = resolved.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(platform::native_path(&suffixed(&resolved, ".compact")))?;
+        file.write_all(COLD_MAGIC_BYTES)?;
+        file.write_all(&tier.id.to_le_bytes())?;
+        Ok((tier, file))
+    }
+
+    // Moves a finished cold file into place once the archive referencing it
+    // has been committed, and removes a cold file no longer referenced
+    pub(crate) fn install_cold_tier(&mut self, previous: Option<ColdTier>) -> Result<()> {
+        let current = self.cold_tier_path();
+        if let Some(path) = &current {
+            fs::rename(suffixed(path, ".compact"), path)?;
+        }
+        if let Some(previous) = previous.map(|tier| self.resolve_cold_path(&tier.path)) {
+            if current.as_ref() != Some(&previous) {
+                fs::remove_file(previous)?;
+            }
+        }
+        self.cold = self.open_cold_tier(false)?;
+        Ok(())
+    }
+
+    // Opens the cold file the metadata names. If compaction stopped between
+    // committing the archive and renaming the new cold file, the rename is
+    // finished here.
+    pub(crate) fn open_cold_tier(&self, write: bool) -> Result<Option<File>> {
+        let Some(tier) = &self.metadata.cold_tier else {
+            return Ok(None);
+        };
+        let path = self.resolve_cold_path(&tier.path);
+        let open = |path: &Path| OpenOptions::new().read(true).write(write).open(platform::native_path(path));
+
+        if let Ok(file) = open(&path) {
+            if cold_tier_id(&file) == Some(tier.id) {
+                return Ok(Some(file));
+            }
+        }
+        let pending = suffixed(&path, ".compact");
+        if open(&pending).ok().and_then(|file| cold_tier_id(&file)) == Some(tier.id) {
+            fs::rename(&pending, &path)?;
+            return Ok(Some(open(&path)?));
+        }
+        Err(UsfError::Corruption(format!("cold tier {} is missing or does not belong to this archive", path.display())))
+    }
+
+    // Bytes in the cold file, or zero for an archive without one
+    pub(crate) fn cold_tier_len(&self) -> Result<u64> {
+        Ok(match &self.cold {
+            Some(file) => file.metadata()?.len(),
+            None => 0,
+        })
+    }
+
+    // Reads at a block offset, which may address the cold file
+    pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let (file, offset) = self.file_at(offset)?;
+        Ok(platform::read_exact_at(file, buf, offset)?)
+    }
+
+    pub(crate) fn write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        let (file, offset) = self.file_at(offset)?;
+        Ok(platform::write_all_at(file, buf, offset)?)
+    }
+
+    fn file_at(&self, offset: u64) -> Result<(&File, u64)> {
+        if offset < COLD_TIER_BASE {
+            return Ok((&self.file, offset));
+        }
+        match &self.cold {
+            Some(cold) => Ok((cold, offset - COLD_TIER_BASE)),
+            None => Err(UsfError::Corruption(format!("block offset {} is in a cold tier the archive does not have", offset))),
+        }
+    }
+
+    fn resolve_cold_path(&self, path: &Path) -> PathBuf {
+        self.path.parent().unwrap_or_else(|| Path::new("")).join(path)
+    }
+}
+
+fn cold_tier_id(file: &File) -> Option<u64> {
+    let mut header = [0u8; COLD_DATA_OFFSET as usize];
+    platform::read_exact_at(file, &mut header, 0).ok()?;
+    let id = header.strip_prefix(COLD_MAGIC_BYTES)?;
+    Some(u64::from_le_bytes(id.try_into().expect("8-byte id")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compact_tiered_splits_hot_and_cold() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("tiered.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.set_access_tracking(true);
+        let archive: Vec<u8> = (0..200_000u64).map(|i| xxh3_64(&i.to_le_bytes()) as u8).collect();
+        storage.store("logs/2019", &archive, DataType::Binary)?;
+        storage.store("config", b"{\"hot\":true}", DataType::Json)?;
+        storage.store_with_hint("index", b"pinned hot", DataType::Text, Hint::Hot)?;
+        storage.retrieve("config")?;
+
+        let policy = TierPolicy { min_hot_reads: Some(1), ..TierPolicy::default() };
+        let report = storage.compact_tiered("cold/tiered.cold", &policy)?;
+        let cold_path = dir.path().join("cold/tiered.cold");
+        assert_eq!(storage.cold_tier_path(), Some(cold_path.clone()));
+        assert!(report.cold_bytes_after > 100_000);
+        assert!(fs::metadata(&path)?.len() < crate::DATA_OFFSET + 1000);
+        assert!(storage.metadata.index["logs/2019"].blocks.iter().all(|loc| loc.offset >= COLD_TIER_BASE));
+
+        // Reads span both files, including after reopening
+        drop(storage);
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.retrieve("logs/2019")?, archive);
+        assert_eq!(storage.retrieve("index")?, b"pinned hot");
+        storage.verify_metadata()?;
+
+        // An interrupted compaction left the new cold file under its
+        // temporary name; opening finishes the rename
+        fs::rename(&cold_path, suffixed(&cold_path, ".compact"))?;
+        fs::write(&cold_path, b"USFCstale id")?;
+        let mut storage = UniversalStorage::open(&path)?;
+        assert_eq!(storage.retrieve("logs/2019")?, archive);
+
+        // A plain compaction folds the cold file back in
+        storage.compact()?;
+        assert_eq!(storage.cold_tier_path(), None);
+        assert!(!cold_path.exists());
+        assert_eq!(storage.retrieve("logs/2019")?, archive);
+
+        Ok(())
+    }
+}
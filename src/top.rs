@@ -0,0 +1,89 @@
+//! `usf top`: a live view of a running `usf serve`, redrawn from its
+//! `/metrics` endpoint every interval.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use usf::OperationMetrics;
+use crate::format_bytes;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn top(addr: &str, interval: Duration) -> io::Result<()> {
+    let mut previous = fetch(addr)?;
+    let mut taken = Instant::now();
+    loop {
+        thread::sleep(interval);
+        let current = fetch(addr)?;
+        let elapsed = taken.elapsed();
+        taken = Instant::now();
+        print!("\x1b[2J\x1b[H{}", render(addr, &current, &previous, elapsed));
+        io::stdout().flush()?;
+        previous = current;
+    }
+}
+
+// The server's metrics, summed over its workers
+fn fetch(addr: &str) -> io::Result<OperationMetrics> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", addr)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> io::Result<OperationMetrics> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let response = std::str::from_utf8(response).map_err(|_| invalid("response is not UTF-8"))?;
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(|| invalid("truncated response"))?;
+    let status = head.lines().next().unwrap_or("");
+    if !status.starts_with("HTTP/1.1 200 ") {
+        return Err(invalid(&format!("metrics request failed: {}", status)));
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+// Rates over the last interval, then totals since the server started
+fn render(addr: &str, current: &OperationMetrics, previous: &OperationMetrics, elapsed: Duration) -> String {
+    let mut view = format!("usf top: http://{}\n\n", addr);
+    view += &format!("ops/sec:         {:.1}\n", current.ops_per_sec(previous, elapsed));
+    view += &format!("bytes/sec:       {}\n", format_bytes(current.bytes_per_sec(previous, elapsed) as u64));
+    view += &format!("cache hit rate:  {:.1}%\n", current.cache_hit_rate() * 100.0);
+    view += &format!("compactions:     {}\n", current.compactions);
+    view += &format!("slow operations: {}\n", current.slow_operations);
+    view += &format!("retrieves:       {} ({})\n\nSlowest keys:\n", current.retrieves, format_bytes(current.bytes_retrieved));
+    for (key, took) in &current.slowest_retrievals {
+        view += &format!("  {:>10.3} ms  {}\n", took.as_secs_f64() * 1000.0, key);
+    }
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics_response() -> io::Result<()> {
+        let metrics = OperationMetrics {
+            retrieves: 30,
+            bytes_retrieved: 4096,
+            slowest_retrievals: vec![("docs/big".to_string(), Duration::from_millis(12))],
+            ..OperationMetrics::default()
+        };
+        let body = serde_json::to_string(&metrics)?;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let current = parse_response(response.as_bytes())?;
+        assert_eq!(current, metrics);
+        assert!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+
+        let previous = OperationMetrics { retrieves: 10, ..OperationMetrics::default() };
+        let view = render("127.0.0.1:8080", &current, &previous, Duration::from_secs(2));
+        assert!(view.contains("ops/sec:         10.0\n"));
+        assert!(view.contains("12.000 ms  docs/big\n"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::blocksize::BlockSizing;
+use crate::dictionary::Dictionaries;
+use crate::types::compression_for;
+use crate::{BlockChecksum, CompressionPolicy, CustomType, DataType, Result, UniversalStorage, UsfError};
+
+/// A reversible rewrite applied to every block stored under a key prefix,
+/// before compression. The names of the applied transforms are recorded in
+/// each block header, and retrieval reverses them in the opposite order.
+pub trait Transform: Send + Sync {
+    /// Name recorded in block headers; must identify the transform uniquely
+    fn name(&self) -> &str;
+    fn apply(&self, data: &[u8]) -> Result<Vec<u8>>;
+    /// Inverse of `apply`. Lossy transforms return their input unchanged.
+    fn reverse(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+// Registered transforms, in registration order
+#[derive(Clone, Default)]
+pub(crate) struct Transforms {
+    rules: Vec<(String, Arc<dyn Transform>)>,
+}
+
+// How the blocks of one value are encoded
+pub(crate) struct Encoding {
+    pub(crate) compression: CompressionPolicy,
+    pub(crate) transforms: Vec<Arc<dyn Transform>>,
+    // Dictionary id and bytes to compress with
+    pub(crate) dictionary: Option<(u32, Arc<Vec<u8>>)>,
+    pub(crate) block_sizing: BlockSizing,
+    pub(crate) block_checksum: BlockChecksum,
+}
+
+impl Encoding {
+    pub(crate) fn resolve(
+        custom_types: &BTreeMap<u16, CustomType>,
+        transforms: &Transforms,
+        dictionaries: &Dictionaries,
+        block_sizing: BlockSizing,
+        block_checksum: BlockChecksum,
+        key: &str,
+        data_type: &DataType,
+    ) -> Self {
+        Self {
+            compression: compression_for(custom_types, data_type),
+            transforms: transforms.rules.iter()
+                .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+                .map(|(_, transform)| Arc::clone(transform))
+                .collect(),
+            dictionary: dictionaries.for_key(key),
+            block_sizing,
+            block_checksum,
+        }
+    }
+
+    pub(crate) fn transform_names(&self) -> Vec<String> {
+        self.transforms.iter().map(|t| t.name().to_string()).collect()
+    }
+
+    pub(crate) fn apply<'a>(&self, chunk: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let mut data = Cow::Borrowed(chunk);
+        for transform in &self.transforms {
+            data = Cow::Owned(transform.apply(&data)?);
+        }
+        Ok(data)
+    }
+}
+
+impl UniversalStorage {
+    /// Applies `transform` to values stored under `prefix` from now on.
+    /// Several transforms matching a key run in registration order.
+    ///
+    /// Registrations are not persisted: register the same transforms after
+    /// opening an archive, or its transformed values cannot be read back.
+    pub fn add_transform(&mut self, prefix: &str, transform: Arc<dyn Transform>) {
+        self.transforms.rules.push((prefix.to_string(), transform));
+    }
+
+    pub fn clear_transforms(&mut self) {
+        self.transforms.rules.clear();
+    }
+
+    pub(crate) fn encoding_for(&self, key: &str, data_type: &DataType) -> Encoding {
+        Encoding::resolve(&self.metadata.custom_types, &self.transforms, &self.dictionaries, self.metadata.block_sizing, self.metadata.block_checksum, key, data_type)
+    }
+
+    // Undoes the transforms recorded in a block header
+    pub(crate) fn reverse_transforms(&self, names: &[String], mut data: Vec<u8>) -> Result<Vec<u8>> {
+        for name in names.iter().rev() {
+            let transform = self.transforms.rules.iter()
+                .find(|(_, t)| t.name() == name)
+                .map(|(_, t)| t)
+                .ok_or_else(|| UsfError::UnknownTransform(name.clone()))?;
+            data = transform.reverse(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io};
+    use tempfile::tempdir;
+
+    struct Xor(u8);
+
+    impl Transform for Xor {
+        fn name(&self) -> &str {
+            "xor"
+        }
+
+        fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn reverse(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.apply(data)
+        }
+    }
+
+    #[test]
+    fn test_prefix_transforms() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("transform.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.add_transform("secrets/", Arc::new(Xor(0x5a)));
+
+        storage.store("secrets/token", b"hunter2-hunter2", DataType::Binary)?;
+        storage.store("public/token", b"plain-as-day", DataType::Binary)?;
+        assert_eq!(storage.retrieve("secrets/token")?, b"hunter2-hunter2");
+
+        let raw = fs::read(&path)?;
+        let contains = |needle: &[u8]| raw.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(b"hunter2-hunter2"));
+        assert!(contains(b"plain-as-day"));
+
+        // Without the transform registered the value cannot be decoded
+        let mut reopened = UniversalStorage::open(&path)?;
+        assert!(matches!(reopened.retrieve("secrets/token"), Err(UsfError::UnknownTransform(_))));
+        reopened.add_transform("secrets/", Arc::new(Xor(0x5a)));
+        assert_eq!(reopened.retrieve("secrets/token")?, b"hunter2-hunter2");
+
+        Ok(())
+    }
+}
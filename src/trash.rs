@@ -0,0 +1,161 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::{IndexEntry, Result, UniversalStorage, UsfError};
+
+/// A deleted entry kept recoverable until its retention period lapses.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashEntry {
+    pub deleted_at: DateTime<Utc>,
+    pub(crate) entry: IndexEntry,
+}
+
+impl UniversalStorage {
+    /// Enables soft delete: deleted entries move to the trash and stay
+    /// recoverable for `retention`. `None` makes deletes permanent.
+    pub fn set_trash_retention(&mut self, retention: Option<Duration>) -> Result<()> {
+        self.metadata.trash_retention_secs = retention.map(|r| r.as_secs());
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn trash_retention(&self) -> Option<Duration> {
+        self.metadata.trash_retention_secs.map(Duration::from_secs)
+    }
+
+    /// Removes `key` from the index. With a trash retention configured the
+    /// entry can be restored with [`UniversalStorage::undelete`] until it
+    /// is purged by compaction.
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        self.remove_entry(key)?;
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    // Takes a canonical key out of the index, into the trash if one is
+    // configured, without committing
+    pub(crate) fn remove_entry(&mut self, key: String) -> Result<()> {
+        let entry = self.metadata.index.remove(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+
+        if self.metadata.trash_retention_secs.is_some() {
+            let trashed = TrashEntry { deleted_at: self.now(), entry };
+            if let Some(previous) = self.metadata.trash.insert(key, trashed) {
+                self.release_chain(&previous.entry.blocks);
+            }
+        } else {
+            self.release_chain(&entry.blocks);
+            self.forget_access(&key);
+        }
+        Ok(())
+    }
+
+    /// Restores a soft-deleted entry. Fails if the key has since been
+    /// stored again.
+    pub fn undelete(&mut self, key: &str) -> Result<()> {
+        let key = self.metadata.key_policy.canonicalize(key)?;
+        if self.metadata.index.contains_key(&key) {
+            return Err(UsfError::KeyExists(key));
+        }
+
+        let trashed = self.metadata.trash.remove(&key)
+            .ok_or_else(|| UsfError::KeyNotFound(key.clone()))?;
+        self.metadata.index.insert(key, trashed.entry);
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    /// Entries currently in the trash, oldest deletion first.
+    pub fn trash(&self) -> Vec<(String, TrashEntry)> {
+        let mut entries: Vec<_> = self.metadata.trash.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|a, b| a.1.deleted_at.cmp(&b.1.deleted_at).then_with(|| a.0.cmp(&b.0)));
+        entries
+    }
+
+    // Whether a trashed entry has outlived the configured retention
+    pub(crate) fn trash_expired(&self, entry: &TrashEntry, now: DateTime<Utc>) -> bool {
+        match self.metadata.trash_retention_secs {
+            Some(secs) => now - entry.deleted_at >= chrono::Duration::seconds(secs as i64),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_soft_delete_and_undelete() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("trash.usf"))?;
+        storage.set_trash_retention(Some(Duration::from_secs(3600)))?;
+
+        storage.store("report", b"quarterly numbers", DataType::Text)?;
+        storage.delete("report")?;
+        assert!(matches!(storage.retrieve("report"), Err(UsfError::KeyNotFound(_))));
+        assert_eq!(storage.trash()[0].0, "report");
+
+        // Still within retention, so compaction keeps it
+        storage.compact()?;
+        storage.undelete("report")?;
+        assert_eq!(storage.retrieve("report")?, b"quarterly numbers");
+        assert!(storage.trash().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_trash_purged_by_compaction() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("purge.usf"))?;
+        storage.set_trash_retention(Some(Duration::ZERO))?;
+
+        storage.store("scratch", &[1u8; 4096], DataType::Binary)?;
+        storage.store("keep", b"keep", DataType::Text)?;
+        storage.delete("scratch")?;
+
+        let report = storage.compact()?;
+        assert_eq!(report.purged_keys, vec!["scratch".to_string()]);
+        assert!(report.bytes_after < report.bytes_before);
+        assert!(matches!(storage.undelete("scratch"), Err(UsfError::KeyNotFound(_))));
+        assert_eq!(storage.retrieve("keep")?, b"keep");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_removed_keys_lose_access_stats() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("stats.usf"))?;
+        storage.set_access_tracking(true);
+        storage.store("gone", b"gone", DataType::Text)?;
+        storage.store("trashed", b"trashed", DataType::Text)?;
+        storage.retrieve("gone")?;
+        storage.retrieve("trashed")?;
+        storage.flush_access_stats()?;
+
+        // A permanent delete drops the stats, so a new value starts cold
+        storage.retrieve("gone")?;
+        storage.delete("gone")?;
+        assert!(!storage.access_stats().contains_key("gone"));
+        storage.store("gone", b"again", DataType::Text)?;
+        storage.retrieve("gone")?;
+        assert_eq!(storage.access_stats()["gone"].read_count, 1);
+
+        // Trashed entries keep theirs until they are purged
+        storage.set_trash_retention(Some(Duration::ZERO))?;
+        storage.delete("trashed")?;
+        assert_eq!(storage.access_stats()["trashed"].read_count, 1);
+        storage.compact()?;
+        assert!(!storage.access_stats().contains_key("trashed"));
+
+        Ok(())
+    }
+}
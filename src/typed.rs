@@ -0,0 +1,89 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::{DataType, Result, UniversalStorage, UsfError};
+
+/// How [`UniversalStorage::store_value_as`] serializes a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueFormat {
+    /// Compact bincode, stored as [`DataType::Structured`]
+    #[default]
+    Bincode,
+    /// Human-readable JSON, stored as [`DataType::Json`]
+    Json,
+}
+
+impl ValueFormat {
+    pub fn data_type(self) -> DataType {
+        match self {
+            ValueFormat::Bincode => DataType::Structured,
+            ValueFormat::Json => DataType::Json,
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            ValueFormat::Bincode => Ok(bincode::serialize(value)?),
+            ValueFormat::Json => Ok(serde_json::to_vec(value)?),
+        }
+    }
+}
+
+impl UniversalStorage {
+    /// Serializes `value` with bincode and stores it under `key`.
+    pub fn store_value<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.store_value_as(key, value, ValueFormat::default())
+    }
+
+    pub fn store_value_as<T: Serialize + ?Sized>(&mut self, key: &str, value: &T, format: ValueFormat) -> Result<()> {
+        self.store(key, &format.encode(value)?, format.data_type())
+    }
+
+    /// Retrieves a value written by [`UniversalStorage::store_value`] or
+    /// [`UniversalStorage::store_value_as`], decoding it by its stored
+    /// data type: JSON for [`DataType::Json`], bincode for
+    /// [`DataType::Structured`]. Values of any other type are refused.
+    pub fn retrieve_value<T: DeserializeOwned>(&mut self, key: &str) -> Result<T> {
+        let data_type = self.data_type_of(key)?;
+        let data = self.retrieve(key)?;
+        match data_type {
+            DataType::Json => Ok(serde_json::from_slice(&data)?),
+            DataType::Structured => Ok(bincode::deserialize(&data)?),
+            other => Err(UsfError::Serialization(format!("{:?} holds {} data, not a serialized value", key, other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        sensor: String,
+        samples: Vec<i64>,
+    }
+
+    #[test]
+    fn test_typed_values_round_trip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut storage = UniversalStorage::create(dir.path().join("typed.usf"))?;
+        let reading = Reading { sensor: "probe-1".to_string(), samples: vec![3, 5, 8] };
+
+        storage.store_value("compact", &reading)?;
+        storage.store_value_as("readable", &reading, ValueFormat::Json)?;
+        assert_eq!(storage.data_type_of("compact")?, DataType::Structured);
+        assert_eq!(storage.data_type_of("readable")?, DataType::Json);
+        assert_eq!(storage.retrieve("readable")?, br#"{"sensor":"probe-1","samples":[3,5,8]}"#);
+
+        assert_eq!(storage.retrieve_value::<Reading>("compact")?, reading);
+        assert_eq!(storage.retrieve_value::<Reading>("readable")?, reading);
+        storage.store("raw", b"plain", DataType::Text)?;
+        assert!(matches!(storage.retrieve_value::<Reading>("raw"), Err(UsfError::Serialization(_))));
+        assert!(matches!(storage.retrieve_value::<Vec<u32>>("readable"), Err(UsfError::Serialization(_))));
+
+        Ok(())
+    }
+}
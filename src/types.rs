@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::{DataType, Result, UniversalStorage};
+
+/// How blocks of a data type are compressed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionPolicy {
+    /// The built-in heuristics for the data type
+    #[default]
+    Auto,
+    /// Store blocks as-is, e.g. for already compressed media
+    None,
+    Zstd { level: i32 },
+}
+
+/// A user-defined data type, registered per archive and referenced by
+/// [`DataType::Custom`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CustomType {
+    pub name: String,
+    pub compression: CompressionPolicy,
+    /// Attribute names values of this type are expected to carry
+    pub attributes: Vec<String>,
+}
+
+impl UniversalStorage {
+    /// Registers (or replaces) the definition of `DataType::Custom(id)`.
+    /// Values of an unregistered custom type use [`CompressionPolicy::Auto`].
+    pub fn register_data_type(&mut self, id: u16, custom: CustomType) -> Result<()> {
+        self.metadata.custom_types.insert(id, custom);
+        self.metadata.modified = self.now();
+        self.update_metadata()
+    }
+
+    pub fn custom_type(&self, id: u16) -> Option<&CustomType> {
+        self.metadata.custom_types.get(&id)
+    }
+
+    /// Registered custom types, ordered by id.
+    pub fn custom_types(&self) -> Vec<(u16, &CustomType)> {
+        let mut types: Vec<_> = self.metadata.custom_types.iter()
+            .map(|(id, custom)| (*id, custom))
+            .collect();
+        types.sort_by_key(|(id, _)| *id);
+        types
+    }
+}
+
+pub(crate) fn compression_for(custom_types: &BTreeMap<u16, CustomType>, data_type: &DataType) -> CompressionPolicy {
+    match data_type {
+        DataType::Custom(id) => custom_types.get(id).map(|c| c.compression).unwrap_or_default(),
+        _ => CompressionPolicy::Auto,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_custom_data_type() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("custom.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.register_data_type(7, CustomType {
+            name: "audio/flac".to_string(),
+            compression: CompressionPolicy::None,
+            attributes: vec!["sample_rate".to_string()],
+        })?;
+
+        let samples = vec![3u8; 4096];
+        storage.store("clip", &samples, DataType::Custom(7))?;
+        assert_eq!(storage.retrieve("clip")?, samples);
+
+        // Stored uncompressed, as the policy asks
+        let stats = storage.stat()?;
+        assert_eq!(stats.by_data_type[&DataType::Custom(7)].compressed_bytes, 4096);
+
+        let reopened = UniversalStorage::open(&path)?;
+        assert_eq!(reopened.custom_type(7).map(|c| c.name.as_str()), Some("audio/flac"));
+        assert_eq!("Custom(7)".parse::<DataType>()?, DataType::Custom(7));
+
+        Ok(())
+    }
+}
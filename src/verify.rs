@@ -0,0 +1,451 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+use crate::format::{COLD_DATA_OFFSET, COLD_TIER_BASE, MAX_BLOCK_SIZE};
+use crate::{format, BlockLocation, IndexEntry, Result, UniversalStorage, UsfError};
+
+// Blocks checked by CheckLevel::QuickSample
+const QUICK_SAMPLE_BLOCKS: usize = 64;
+
+/// How much of an archive [`UniversalStorage::open_with_check`] verifies
+/// before returning, trading open time for assurance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CheckLevel {
+    /// Only what parsing the metadata requires, as [`UniversalStorage::open`]
+    #[default]
+    None,
+    /// [`UniversalStorage::verify_metadata`]
+    Metadata,
+    /// The metadata checks plus the checksums of a random sample of blocks
+    QuickSample,
+    /// The metadata checks plus the checksum of every block
+    Full,
+}
+
+/// What [`UniversalStorage::verify`] found.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VerifyReport {
+    /// Index entries and pinned chains walked
+    pub chains: usize,
+    /// Distinct blocks read
+    pub blocks: usize,
+    /// Stored bytes read, headers included
+    pub bytes: u64,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A problem found by [`UniversalStorage::verify`]. `key` names the
+/// first chain found using the block: a key, live or trashed, the key of
+/// an interrupted ingest, or a dictionary name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyIssue {
+    /// The block lies wholly or partly past the end of its file
+    MissingBlock { key: String, offset: u64 },
+    /// The header length or header does not parse or fails its checksum
+    UnreadableHeader { key: String, offset: u64, reason: String },
+    /// The header disagrees with the index about the stored size
+    SizeMismatch { key: String, offset: u64, indexed: u64, header: u64 },
+    /// The stored bytes do not match the header's checksum
+    ChecksumMismatch { key: String, offset: u64 },
+    /// The blocks of a value hold less data than its indexed size
+    SizeAccounting { key: String, indexed: u64, blocks: u64 },
+}
+
+impl UniversalStorage {
+    /// Opens the archive and runs [`UniversalStorage::verify_metadata`]
+    /// before returning. [`UniversalStorage::open`] skips those checks to
+    /// keep cold opens of large archives cheap.
+    pub fn open_verified<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_check(path, CheckLevel::Metadata)
+    }
+
+    /// Opens the archive and verifies it to `level` before returning.
+    /// Block checks compare the stored bytes against their checksums
+    /// without decompressing them, so they need no registered transforms.
+    pub fn open_with_check<P: AsRef<Path>>(path: P, level: CheckLevel) -> Result<Self> {
+        let mut storage = Self::open(path)?;
+        storage.check_to(level)?;
+        Ok(storage)
+    }
+
+    // Verifies an open archive to `level`
+    pub(crate) fn check_to(&mut self, level: CheckLevel) -> Result<()> {
+        match level {
+            CheckLevel::None => {},
+            CheckLevel::Metadata => self.verify_metadata()?,
+            CheckLevel::QuickSample => {
+                self.verify_metadata()?;
+                self.verify_block_checksums(Some(QUICK_SAMPLE_BLOCKS))?;
+            },
+            CheckLevel::Full => {
+                self.verify_metadata()?;
+                self.verify_block_checksums(None)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Checks the metadata checksum, that every indexed block lies inside
+    /// the data region, that no two distinct blocks, freed extents or the
+    /// current commit overlap, and that every value's size fits in its
+    /// blocks. Blocks themselves are checksummed whenever they are read.
+    pub fn verify_metadata(&mut self) -> Result<()> {
+        let superblock = format::read_superblock(&mut self.file)?;
+        self.file.seek(SeekFrom::Start(superblock.metadata_offset()))?;
+        let mut metadata_bytes = vec![0u8; superblock.metadata_size as usize];
+        self.file.read_exact(&mut metadata_bytes)?;
+        let mut checksum = [0u8; 8];
+        self.file.read_exact(&mut checksum)?;
+        if xxh3_64(&metadata_bytes) != u64::from_le_bytes(checksum) {
+            return Err(UsfError::Corruption("metadata checksum mismatch".to_string()));
+        }
+
+        let file_size = self.file.metadata()?.len();
+        let data_offset = self.data_offset();
+        let cold_end = match self.cold {
+            Some(_) => COLD_TIER_BASE + self.cold_tier_len()?,
+            None => COLD_TIER_BASE,
+        };
+        let out_of_bounds = self.metadata.index.iter()
+            .map(|(key, entry)| (key, entry.blocks.as_slice()))
+            .chain(self.pinned_chains())
+            .find(|(_, locations)| locations.iter().any(|loc| {
+                let Some(end) = loc.offset.checked_add(loc.disk_size()) else {
+                    return true;
+                };
+                match loc.offset < COLD_TIER_BASE {
+                    true => loc.offset < data_offset || end > file_size,
+                    false => loc.offset < COLD_TIER_BASE + COLD_DATA_OFFSET || end > cold_end,
+                }
+            }));
+        if let Some((key, _)) = out_of_bounds {
+            return Err(UsfError::Corruption(format!("entry {:?} points outside the data region", key)));
+        }
+
+        self.verify_extents()?;
+        self.verify_sizes()
+    }
+
+    // Blocks may be shared between chains, at the same offset and size,
+    // but distinct blocks, freed extents and the current commit never
+    // overlap
+    fn verify_extents(&self) -> Result<()> {
+        let mut extents: BTreeMap<u64, (u64, String)> = BTreeMap::new();
+        let chains = self.metadata.index.iter()
+            .map(|(key, entry)| (key.as_str(), entry.blocks.as_slice()))
+            .chain(self.pinned_chains().map(|(key, locations)| (key.as_str(), locations)))
+            .chain(self.metadata.parity.iter().map(|group| ("parity", group.parity_blocks())));
+        for (owner, locations) in chains {
+            for loc in locations {
+                let size = loc.disk_size();
+                match extents.insert(loc.offset, (size, owner.to_string())) {
+                    Some((other_size, other)) if other_size != size => {
+                        return Err(UsfError::Corruption(format!(
+                            "{:?} and {:?} index the block at offset {} with different sizes", other, owner, loc.offset,
+                        )));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        for (offset, len) in &self.metadata.freed {
+            if let Some((_, owner)) = extents.insert(*offset, (*len, "freed space".to_string())) {
+                return Err(UsfError::Corruption(format!("freed extent at offset {} is still used by {:?}", offset, owner)));
+            }
+        }
+        if let Some((offset, size)) = self.commit {
+            if let Some((_, owner)) = extents.insert(offset, (size, "the current commit".to_string())) {
+                return Err(UsfError::Corruption(format!("{:?} overlaps the current commit at offset {}", owner, offset)));
+            }
+        }
+
+        let mut previous: Option<(u64, &String)> = None;
+        for (offset, (size, owner)) in &extents {
+            if let Some((end, other)) = previous.filter(|(end, _)| *end > *offset) {
+                return Err(UsfError::Corruption(format!(
+                    "{:?} at offset {} overlaps {:?}, which runs to offset {}", owner, offset, other, end,
+                )));
+            }
+            previous = Some((offset.saturating_add(*size), owner));
+        }
+        Ok(())
+    }
+
+    // Every value's blocks have room for its size, in block sizes a write
+    // could have used
+    fn verify_sizes(&self) -> Result<()> {
+        let entries = self.metadata.index.iter()
+            .chain(self.metadata.trash.iter().map(|(key, trashed)| (key, &trashed.entry)));
+        for (key, entry) in entries {
+            let insane = |reason: &str| UsfError::Corruption(format!("entry {:?} {}", key, reason));
+            if entry.blocks.iter().any(|loc| loc.header_size == 0) {
+                return Err(insane("has a block with an empty header"));
+            }
+            if entry.blocks.is_empty() {
+                if entry.size > 0 && entry.text_deltas.is_none() {
+                    return Err(insane("has a size but no blocks"));
+                }
+                continue;
+            }
+            if entry.block_size == 0 || entry.block_size > MAX_BLOCK_SIZE as u64 {
+                return Err(insane(&format!("has a block size of {}", entry.block_size)));
+            }
+            // A text delta log's size is that of the replayed value
+            let capacity = (entry.blocks.len() as u64).saturating_mul(entry.block_size);
+            let needed = entry.solid_offset.unwrap_or(0).saturating_add(entry.size);
+            if entry.text_deltas.is_none() && needed > capacity {
+                return Err(insane(&format!("needs {} bytes but its blocks hold at most {}", needed, capacity)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads every block of every indexed entry and pinned chain, checking
+    /// that its header parses and agrees with the index, that its stored
+    /// bytes match their checksum, and that each value's blocks hold as
+    /// much data as the index says. Unlike the checks on open, nothing
+    /// stops at the first problem: every one found is listed in the
+    /// report, and an error is returned only when the file cannot be read.
+    /// Blocks are not decompressed.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let file_end = self.file.metadata()?.len();
+        let cold_end = COLD_TIER_BASE + self.cold_tier_len()?;
+        let mut report = VerifyReport::default();
+        // Uncompressed size of each block read intact, by offset
+        let mut checked: HashMap<u64, Option<u64>> = HashMap::new();
+
+        // Bytes a value's blocks must hold at least: solid groups hold more,
+        // and text delta logs are not compared
+        let accounted = |entry: &IndexEntry| entry.text_deltas.is_none().then(|| entry.solid_offset.unwrap_or(0) + entry.size);
+        let chains = self.metadata.index.iter()
+            .map(|(key, entry)| (key, entry.blocks.as_slice(), accounted(entry)))
+            .chain(self.metadata.trash.iter().map(|(key, trashed)| (key, trashed.entry.blocks.as_slice(), accounted(&trashed.entry))))
+            .chain(self.metadata.ingests.iter().map(|(key, checkpoint)| (key, checkpoint.blocks.as_slice(), None)))
+            .chain(self.metadata.dictionaries.iter().map(|(name, stored)| (name, stored.blocks.as_slice(), None)));
+        for (key, locations, accounted) in chains {
+            report.chains += 1;
+            let mut held = Some(0);
+            for location in locations {
+                let size = match checked.get(&location.offset) {
+                    Some(size) => *size,
+                    None => {
+                        let end = if location.offset < COLD_TIER_BASE { file_end } else { cold_end };
+                        let size = self.verify_block(key, location, end, &mut report)?;
+                        checked.insert(location.offset, size);
+                        size
+                    },
+                };
+                held = held.zip(size).map(|(held, size)| held + size);
+            }
+            if let (Some(indexed), Some(blocks)) = (accounted, held) {
+                if blocks < indexed {
+                    report.issues.push(VerifyIssue::SizeAccounting { key: key.clone(), indexed, blocks });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    // Checks one block for `verify`, returning its uncompressed size if
+    // it is intact
+    fn verify_block(&self, key: &str, location: &BlockLocation, end: u64, report: &mut VerifyReport) -> Result<Option<u64>> {
+        let (key, offset) = (key.to_string(), location.offset);
+        if location.offset.checked_add(location.disk_size()).is_none_or(|block_end| block_end > end) {
+            report.issues.push(VerifyIssue::MissingBlock { key, offset });
+            return Ok(None);
+        }
+        report.blocks += 1;
+        report.bytes += location.disk_size();
+
+        let header = match self.read_header(location) {
+            Ok(header) => header,
+            Err(UsfError::Corruption(reason) | UsfError::Serialization(reason)) => {
+                report.issues.push(VerifyIssue::UnreadableHeader { key, offset, reason });
+                return Ok(None);
+            },
+            Err(e @ UsfError::HeaderChecksumMismatch { .. }) => {
+                report.issues.push(VerifyIssue::UnreadableHeader { key, offset, reason: e.to_string() });
+                return Ok(None);
+            },
+            Err(e) => return Err(e),
+        };
+        if header.compressed_size != location.data_size {
+            report.issues.push(VerifyIssue::SizeMismatch { key, offset, indexed: location.data_size, header: header.compressed_size });
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; location.data_size as usize];
+        self.read_at(&mut data, location.offset + format::BLOCK_HEADER_PREFIX_SIZE + location.header_size as u64)?;
+        if !header.checksum_matches(&data) {
+            report.issues.push(VerifyIssue::ChecksumMismatch { key, offset });
+            return Ok(None);
+        }
+        Ok(Some(header.original_size))
+    }
+
+    // Checks the stored bytes of every live block, or of `sample` of them
+    // picked at random, against their checksums
+    fn verify_block_checksums(&self, sample: Option<usize>) -> Result<()> {
+        let mut blocks: BTreeMap<u64, (&String, &BlockLocation)> = BTreeMap::new();
+        let chains = self.metadata.index.iter()
+            .map(|(key, entry)| (key, entry.blocks.as_slice()))
+            .chain(self.pinned_chains());
+        for (key, locations) in chains {
+            for location in locations {
+                blocks.entry(location.offset).or_insert((key, location));
+            }
+        }
+
+        let mut blocks: Vec<_> = blocks.into_values().collect();
+        if let Some(sample) = sample.filter(|&sample| sample < blocks.len()) {
+            let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+            blocks.sort_by_key(|(_, location)| xxh3_64_with_seed(&location.offset.to_le_bytes(), seed));
+            blocks.truncate(sample);
+        }
+
+        for (key, location) in blocks {
+            let block = self.read_block(location)?;
+            if !block.header.checksum_matches(&block.data) {
+                return Err(UsfError::Corruption(format!(
+                    "checksum mismatch in block at offset {} of {:?}", location.offset, key
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataType;
+    use std::fs::OpenOptions;
+    use std::io::{self, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_verified_detects_damaged_metadata() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("verify.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        storage.store("key", b"value", DataType::Text)?;
+        drop(storage);
+        UniversalStorage::open_verified(&path)?;
+
+        // Damage a byte of the serialized key inside the metadata
+        let bytes = std::fs::read(&path)?;
+        let at = bytes.windows(3).position(|w| w == b"key").expect("key in metadata");
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(at as u64))?;
+        file.write_all(b"K")?;
+
+        // The lazy open still succeeds; the strict one refuses
+        assert!(UniversalStorage::open(&path).is_ok());
+        assert!(matches!(UniversalStorage::open_verified(&path), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_reports_every_problem() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fsck.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        for key in ["checksum", "header", "missing", "short", "intact"] {
+            storage.store(key, format!("value of {}", key).as_bytes(), DataType::Text)?;
+        }
+        storage.copy("intact", "linked")?;
+        let report = storage.verify()?;
+        assert!(report.is_clean());
+        // Linked blocks are read once
+        assert_eq!((report.chains, report.blocks), (6, 5));
+
+        let location = |storage: &UniversalStorage, key: &str| storage.metadata.index[key].blocks[0].clone();
+        let file = OpenOptions::new().write(true).open(&path)?;
+        let damaged = location(&storage, "checksum");
+        crate::platform::write_all_at(&file, b"!", damaged.offset + damaged.disk_size() - 1)?;
+        crate::platform::write_all_at(&file, b"XXXX", location(&storage, "header").offset + 4)?;
+        storage.metadata.index.get_mut("missing").expect("stored").blocks[0].offset = 1 << 40;
+        storage.metadata.index.get_mut("short").expect("stored").size += 1;
+
+        let report = storage.verify()?;
+        let key = |issue: &VerifyIssue| match issue {
+            VerifyIssue::MissingBlock { key, .. } | VerifyIssue::UnreadableHeader { key, .. } | VerifyIssue::SizeMismatch { key, .. }
+            | VerifyIssue::ChecksumMismatch { key, .. } | VerifyIssue::SizeAccounting { key, .. } => key.clone(),
+        };
+        assert_eq!(report.issues.iter().map(key).collect::<Vec<_>>(), ["checksum", "header", "missing", "short"]);
+        assert!(matches!(report.issues[0], VerifyIssue::ChecksumMismatch { offset, .. } if offset == damaged.offset));
+        assert!(matches!(report.issues[1], VerifyIssue::UnreadableHeader { .. }));
+        assert!(matches!(report.issues[3], VerifyIssue::SizeAccounting { indexed: 15, blocks: 14, .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_with_check_levels() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("check.usf");
+        let mut storage = UniversalStorage::create(&path)?;
+        for i in 0..10 {
+            storage.store(&format!("key-{}", i), format!("value {}", i).as_bytes(), DataType::Text)?;
+        }
+        let damaged = storage.metadata.index["key-7"].blocks[0].clone();
+        drop(storage);
+        for level in [CheckLevel::None, CheckLevel::Metadata, CheckLevel::QuickSample, CheckLevel::Full] {
+            UniversalStorage::open_with_check(&path, level)?;
+        }
+
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(damaged.offset + damaged.disk_size() - 1))?;
+        file.write_all(b"!")?;
+
+        assert!(UniversalStorage::open_with_check(&path, CheckLevel::None).is_ok());
+        assert!(UniversalStorage::open_with_check(&path, CheckLevel::Metadata).is_ok());
+        // Fewer blocks than the sample size, so every one is checked
+        assert!(matches!(UniversalStorage::open_with_check(&path, CheckLevel::QuickSample), Err(UsfError::Corruption(_))));
+        assert!(matches!(UniversalStorage::open_with_check(&path, CheckLevel::Full), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_checks_metadata_consistency() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("consistency.usf");
+        let mut storage = crate::UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.store("a", b"first value", DataType::Text)?;
+        storage.store("b", b"second value", DataType::Text)?;
+        drop(storage);
+        let checked = || crate::UsfOptions::new().check(CheckLevel::Metadata).open(&path);
+        assert!(checked().is_ok());
+
+        // A value larger than its blocks hold, under a valid checksum
+        let mut storage = crate::UsfOptions::new().write(true).open(&path)?;
+        storage.metadata.index.get_mut("a").unwrap().size = 1 << 40;
+        storage.update_metadata()?;
+        drop(storage);
+        assert!(UniversalStorage::open(&path).is_ok());
+        assert!(matches!(checked(), Err(UsfError::Corruption(_))));
+
+        // Blocks of two values that overlap
+        let mut storage = crate::UsfOptions::new().write(true).open(&path)?;
+        storage.metadata.index.get_mut("a").unwrap().size = 11;
+        let mut shifted = storage.metadata.index["a"].blocks[0].clone();
+        shifted.offset += 1;
+        storage.metadata.index.get_mut("b").unwrap().blocks[0] = shifted;
+        storage.update_metadata()?;
+        drop(storage);
+        assert!(UniversalStorage::open(&path).is_ok());
+        assert!(matches!(checked(), Err(UsfError::Corruption(_))));
+
+        Ok(())
+    }
+}
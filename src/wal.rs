@@ -0,0 +1,209 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::format::{JOURNAL_MAGIC_BYTES, METADATA_OFFSET};
+use crate::split::suffixed;
+use crate::{platform, readonly, Access, Result, UniversalStorage, UsfError};
+
+/// A write-ahead journal found complete on open and applied to the
+/// archive, finishing a commit that crashed before it reached the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayedJournal {
+    /// Writes applied: the commit and its pointer
+    pub writes: usize,
+    pub bytes: u64,
+}
+
+impl UniversalStorage {
+    /// Journals every metadata commit to `<path>.wal` before applying it.
+    /// Each commit first syncs the blocks written since the last commit to
+    /// the archive in place, then writes and syncs the journal with the
+    /// commit itself and the pointer naming it, applies those to the
+    /// archive, syncs it and empties the journal. Blocks are never copied
+    /// into the journal, so its size is bounded by the metadata. Once a
+    /// write returns, a crash can no longer lose it or leave the archive
+    /// naming data that never reached the disk: the next open able to take
+    /// the exclusive lock replays a complete journal, while
+    /// [`UniversalStorage::open_read_only`] and opens racing a locked
+    /// writer leave it for later. A journal cut short by the crash is
+    /// ignored, and the archive opens at its previous commit; blocks
+    /// written before it are left for [`UniversalStorage::recover`].
+    /// Disabling removes the journal.
+    pub fn set_write_ahead_log(&mut self, enabled: bool) -> Result<()> {
+        self.metadata.write_ahead_log = enabled;
+        self.metadata.modified = self.now();
+        self.update_metadata()?;
+        if !enabled {
+            match fs::remove_file(self.journal_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_ahead_log(&self) -> bool {
+        self.metadata.write_ahead_log
+    }
+
+    pub fn journal_path(&self) -> PathBuf {
+        suffixed(&self.path, ".wal")
+    }
+
+    // Journals a commit of `written` at `commit_offset`, with its pointer
+    // for version 2 on, once the blocks it names are synced in place
+    pub(crate) fn journal_commit(&mut self, commit_offset: u64, written: &[u8], pointer: Option<&[u8]>) -> Result<()> {
+        self.sync()?;
+        let mut writes = vec![(commit_offset, written)];
+        writes.extend(pointer.map(|pointer| (METADATA_OFFSET, pointer)));
+        self.write_journal(&writes)
+    }
+
+    // Replaces the journal with `writes` and syncs it
+    fn write_journal(&self, writes: &[(u64, &[u8])]) -> Result<()> {
+        let mut journal = JOURNAL_MAGIC_BYTES.to_vec();
+        for (offset, bytes) in writes.iter().filter(|(_, bytes)| !bytes.is_empty()) {
+            journal.extend_from_slice(&offset.to_le_bytes());
+            journal.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            journal.extend_from_slice(bytes);
+        }
+        journal.extend_from_slice(&xxh3_64(&journal).to_le_bytes());
+
+        let mut file = File::create(platform::native_path(&self.journal_path()))?;
+        file.write_all(&journal)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    // Empties the journal once its writes are synced to the archive
+    pub(crate) fn clear_journal(&self) -> Result<()> {
+        File::create(platform::native_path(&self.journal_path()))?;
+        Ok(())
+    }
+
+    // Applies the journal next to the archive at `path`, if it is
+    // complete, and empties it. Replay writes, so it runs only under the
+    // exclusive lock: `locked` is the archive already opened for writing
+    // and locked by the caller. Without it the lock is taken here, and if
+    // another handle holds one the journal is left alone, as a writer may
+    // be mid-commit and still need it.
+    pub(crate) fn replay_journal(path: &Path, locked: Option<&File>) -> Result<Option<ReplayedJournal>> {
+        let journal_path = suffixed(path, ".wal");
+        let journal = match fs::read(platform::native_path(&journal_path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            journal => journal?,
+        };
+        let Some(writes) = parse_journal(&journal) else {
+            return Ok(None);
+        };
+
+        let opened;
+        let file = match locked {
+            Some(file) => file,
+            None => {
+                opened = OpenOptions::new().write(true).open(platform::native_path(path))?;
+                match readonly::lock(&opened, Access::Write) {
+                    Err(UsfError::Locked) => return Ok(None),
+                    locked => locked?,
+                }
+                &opened
+            },
+        };
+        for (offset, bytes) in &writes {
+            platform::write_all_at(file, bytes, *offset)?;
+        }
+        file.sync_data()?;
+        File::create(platform::native_path(&journal_path))?;
+        Ok(Some(ReplayedJournal {
+            writes: writes.len(),
+            bytes: writes.iter().map(|(_, bytes)| bytes.len() as u64).sum(),
+        }))
+    }
+}
+
+// The writes in a complete journal. Empty, torn and damaged journals
+// have none to apply.
+fn parse_journal(journal: &[u8]) -> Option<Vec<(u64, &[u8])>> {
+    let (body, checksum) = journal.split_at_checked(journal.len().checked_sub(8)?)?;
+    if xxh3_64(body) != u64::from_le_bytes(checksum.try_into().ok()?) {
+        return None;
+    }
+    let mut rest = body.strip_prefix(JOURNAL_MAGIC_BYTES.as_slice())?;
+    let mut writes = Vec::new();
+    while !rest.is_empty() {
+        let (offset, tail) = rest.split_at_checked(8)?;
+        let (len, tail) = tail.split_at_checked(8)?;
+        let len = usize::try_from(u64::from_le_bytes(len.try_into().ok()?)).ok()?;
+        let (bytes, tail) = tail.split_at_checked(len)?;
+        writes.push((u64::from_le_bytes(offset.try_into().ok()?), bytes));
+        rest = tail;
+    }
+    Some(writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::DATA_OFFSET;
+    use crate::{DataType, OpenWarning, UsfOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_journal_replayed_after_crash() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("journaled.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&path)?;
+        storage.set_write_ahead_log(true)?;
+        storage.store("a", b"committed", DataType::Text)?;
+        assert_eq!(fs::read(storage.journal_path())?, b"");
+        let before = fs::read(&path)?;
+
+        // A crash once the journal is synced but before the archive is: the
+        // blocks are in place, but the commit and pointer never reached it
+        storage.store("b", b"journaled", DataType::Text)?;
+        let after = fs::read(&path)?;
+        let (commit_offset, _) = storage.commit.expect("version 3 commits are appended");
+        let commit = &after[commit_offset as usize..];
+        let pointer = &after[METADATA_OFFSET as usize..DATA_OFFSET as usize];
+        storage.write_journal(&[(commit_offset, commit), (METADATA_OFFSET, pointer)])?;
+        drop(storage);
+        let crashed = [&before, &after[before.len()..commit_offset as usize]].concat();
+        fs::write(&path, &crashed)?;
+
+        // While a writer holds the lock, its journal is left alone
+        let writer = File::open(&path)?;
+        writer.lock()?;
+        let journal = fs::read(suffixed(&path, ".wal"))?;
+        let mut storage = UniversalStorage::open(&path)?;
+        assert!(matches!(storage.open_warnings(), [OpenWarning::TrailingData(_)]));
+        assert!(matches!(storage.retrieve("b"), Err(crate::UsfError::KeyNotFound(_))));
+        assert_eq!(fs::read(storage.journal_path())?, journal);
+        assert_eq!(fs::read(&path)?, crashed);
+        drop((storage, writer));
+
+        let mut storage = UniversalStorage::open(&path)?;
+        let replayed = ReplayedJournal { writes: 2, bytes: (commit.len() + pointer.len()) as u64 };
+        assert_eq!(storage.open_warnings(), [OpenWarning::JournalReplayed(replayed)]);
+        assert_eq!(storage.retrieve("b")?, b"journaled");
+        assert_eq!(fs::read(&path)?, after);
+        drop(storage);
+
+        // A torn journal is ignored, leaving the previous commit in force
+        let storage = UsfOptions::new().write(true).open(&path)?;
+        assert!(storage.open_warnings().is_empty());
+        storage.write_journal(&[(METADATA_OFFSET, &[0u8; 24])])?;
+        let journal = fs::read(storage.journal_path())?;
+        fs::write(storage.journal_path(), &journal[..journal.len() - 1])?;
+        drop(storage);
+        let mut storage = UsfOptions::new().write(true).open(&path)?;
+        assert!(storage.open_warnings().is_empty());
+        assert_eq!(storage.retrieve("a")?, b"committed");
+
+        storage.set_write_ahead_log(false)?;
+        assert!(!storage.journal_path().exists());
+        assert!(!storage.write_ahead_log());
+
+        Ok(())
+    }
+}
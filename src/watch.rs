@@ -0,0 +1,240 @@
+//! `usf watch <dir> <archive>`: mirrors a working directory into an
+//! archive as it changes.
+//!
+//! The whole directory is synced once at start. After that, file system
+//! events are gathered until the directory has been quiet for [`DEBOUNCE`],
+//! or changes have been pending for [`MAX_DELAY`], and every path they
+//! touched is synced in a single [`UniversalStorage::apply_batch`] commit,
+//! so the archive never shows half of a burst of changes. Keys are paths
+//! relative to the directory with `/` separators. Files that vanish are
+//! deleted from the archive, along with everything under a removed
+//! directory; files whose contents did not change are not rewritten.
+//! Symbolic links are skipped, and so is the archive itself when it lives
+//! inside the watched directory.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
+use notify::{Event, RecursiveMode, Watcher};
+use usf::{DataType, UniversalStorage, UsfOptions};
+
+// Quiet period that ends a burst of changes
+const DEBOUNCE: Duration = Duration::from_millis(500);
+// Longest a change waits while the directory keeps changing
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub stored: usize,
+    pub deleted: usize,
+}
+
+pub fn watch(dir: &str, archive: &str) -> io::Result<()> {
+    let root = fs::canonicalize(dir)?;
+    let mut storage = UsfOptions::new().write(true).create(true).open(archive)?;
+    let archive = fs::canonicalize(archive)?;
+
+    // Watching starts before the first sync so nothing changed during it
+    // is missed
+    let (sender, events) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    watcher.watch(&root, RecursiveMode::Recursive).map_err(io::Error::other)?;
+    let report = sync(&mut storage, &root, &archive, &BTreeSet::from([root.clone()]))?;
+    info!("Mirrored {} into {}: {} stored, {} deleted", root.display(), archive.display(), report.stored, report.deleted);
+
+    while let Ok(first) = events.recv() {
+        let mut touched = BTreeSet::new();
+        collect(first, &root, &mut touched);
+        let started = Instant::now();
+        while started.elapsed() < MAX_DELAY {
+            match events.recv_timeout(DEBOUNCE) {
+                Ok(event) => collect(event, &root, &mut touched),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        match sync(&mut storage, &root, &archive, &touched) {
+            Ok(report) if report == SyncReport::default() => {},
+            Ok(report) => info!("Synced {} path(s): {} stored, {} deleted", touched.len(), report.stored, report.deleted),
+            Err(e) => error!("Sync failed, will retry on the next change: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// Adds the paths an event touched; a dropped or failed event rescans
+// everything
+fn collect(event: notify::Result<Event>, root: &Path, touched: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) if !event.need_rescan() => touched.extend(event.paths),
+        Ok(_) => {
+            touched.insert(root.to_path_buf());
+        },
+        Err(e) => {
+            warn!("Watch error, rescanning {}: {}", root.display(), e);
+            touched.insert(root.to_path_buf());
+        },
+    }
+}
+
+/// Brings the keys for `paths`, and everything under them, in line with
+/// what is on disk now, in one commit.
+pub fn sync(storage: &mut UniversalStorage, root: &Path, archive: &Path, paths: &BTreeSet<PathBuf>) -> io::Result<SyncReport> {
+    let mut files = BTreeMap::new();
+    let mut checked = Vec::new();
+    for path in paths {
+        let Some(key) = path.strip_prefix(root).ok().and_then(|relative| key_for(storage, relative)) else {
+            continue;
+        };
+        if is_archive_file(path, archive) {
+            continue;
+        }
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                files.insert(key.clone(), path.clone());
+            },
+            Ok(metadata) if metadata.is_dir() => walk(storage, path, &key, archive, &mut files)?,
+            _ => {},
+        }
+        checked.push(key);
+    }
+
+    // Keys at or under a checked path that no longer has a file behind it
+    let mut deletes = BTreeSet::new();
+    for key in &checked {
+        if !key.is_empty() && storage.contains_key(key) && !files.contains_key(key) {
+            deletes.insert(key.clone());
+        }
+        let prefix = match key.is_empty() {
+            true => String::new(),
+            false => format!("{}/", key),
+        };
+        deletes.extend(storage.keys_with_prefix(&prefix)
+            .filter(|key| !files.contains_key(*key))
+            .map(str::to_string));
+    }
+
+    let mut changed = Vec::new();
+    for (key, path) in files {
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            // Removed since it was listed; the next event deletes it
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let data_type = storage.classify(&key).unwrap_or(DataType::Binary);
+        if !storage.holds_value(&key, &data, &data_type)? {
+            changed.push((key, data, data_type));
+        }
+    }
+
+    let items: Vec<(&str, &[u8], DataType)> = changed.iter()
+        .map(|(key, data, data_type)| (key.as_str(), data.as_slice(), data_type.clone()))
+        .collect();
+    let deletes: Vec<&str> = deletes.iter().map(String::as_str).collect();
+    storage.apply_batch(&items, &deletes)?;
+    Ok(SyncReport { stored: items.len(), deleted: deletes.len() })
+}
+
+// Collects the regular files under `dir`, whose key is `key`
+fn walk(storage: &UniversalStorage, dir: &Path, key: &str, archive: &Path, files: &mut BTreeMap<String, PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let child = match key.is_empty() {
+            true => key_for(storage, Path::new(&entry.file_name())),
+            false => key_for(storage, &Path::new(key).join(entry.file_name())),
+        };
+        let Some(child) = child else {
+            continue;
+        };
+        if is_archive_file(&path, archive) {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(storage, &path, &child, archive, files)?;
+        } else if file_type.is_file() {
+            files.insert(child, path);
+        }
+    }
+    Ok(())
+}
+
+// The canonical key for a path relative to the root, or None with a
+// warning if it cannot be one
+fn key_for(storage: &UniversalStorage, relative: &Path) -> Option<String> {
+    let parts = relative.components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>();
+    let Some(parts) = parts else {
+        warn!("Skipping {}: path is not valid UTF-8", relative.display());
+        return None;
+    };
+    if parts.is_empty() {
+        return Some(String::new());
+    }
+    match storage.key_policy().canonicalize(&parts.join("/")) {
+        Ok(key) => Some(key),
+        Err(e) => {
+            warn!("Skipping {}: {}", relative.display(), e);
+            None
+        },
+    }
+}
+
+// The archive and the files kept beside it, such as its index sidecar
+fn is_archive_file(path: &Path, archive: &Path) -> bool {
+    path.to_string_lossy().starts_with(&*archive.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sync_mirrors_changes() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = fs::canonicalize(dir.path())?.join("work");
+        fs::create_dir_all(root.join("src/nested"))?;
+        fs::write(root.join("README.md"), "readme")?;
+        fs::write(root.join("src/main.rs"), "fn main() {}")?;
+        fs::write(root.join("src/nested/lib.rs"), "pub fn f() {}")?;
+        // An archive inside the watched directory is not mirrored into itself
+        let archive = root.join("mirror.usf");
+        let mut storage = UsfOptions::new().write(true).create(true).open(&archive)?;
+        storage.set_index_sidecar(true)?;
+
+        let everything = BTreeSet::from([root.clone()]);
+        assert_eq!(sync(&mut storage, &root, &archive, &everything)?, SyncReport { stored: 3, deleted: 0 });
+        assert_eq!(storage.keys().collect::<Vec<_>>(), ["README.md", "src/main.rs", "src/nested/lib.rs"]);
+        assert_eq!(sync(&mut storage, &root, &archive, &everything)?, SyncReport::default());
+
+        fs::write(root.join("README.md"), "changed")?;
+        fs::remove_dir_all(root.join("src/nested"))?;
+        fs::write(root.join("new.txt"), "new")?;
+        let touched = BTreeSet::from([root.join("README.md"), root.join("src/nested"), root.join("new.txt")]);
+        assert_eq!(sync(&mut storage, &root, &archive, &touched)?, SyncReport { stored: 2, deleted: 1 });
+        assert_eq!(storage.keys().collect::<Vec<_>>(), ["README.md", "new.txt", "src/main.rs"]);
+        assert_eq!(storage.retrieve("README.md")?, b"changed");
+
+        // A moved directory surfaces as its old and new paths
+        fs::rename(root.join("src"), root.join("lib"))?;
+        let touched = BTreeSet::from([root.join("src"), root.join("lib")]);
+        assert_eq!(sync(&mut storage, &root, &archive, &touched)?, SyncReport { stored: 1, deleted: 1 });
+        assert_eq!(storage.retrieve("lib/main.rs")?, b"fn main() {}");
+        assert!(!storage.contains_key("src/main.rs"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::Waker;
+use std::thread::{self, JoinHandle};
+use crate::blocksize::BlockSizing;
+use crate::limits::check_value_size;
+use crate::dictionary::Dictionaries;
+use crate::transform::{Encoding, Transforms};
+use crate::{Block, BlockChecksum, CustomType, DataType, EntryOptions, Hint, KeyPolicy, Result, Snapshot, UniversalStorage, UsfError};
+
+enum Job {
+    Store { key: String, blocks: Vec<Block>, data_type: DataType, options: EntryOptions, done: Sender<Result<()>> },
+    Flush { done: Sender<Result<()>> },
+}
+
+/// Completion handle for work submitted to a [`BackgroundWriter`].
+pub struct WriteHandle {
+    done: Receiver<Result<()>>,
+    result: Option<Result<()>>,
+}
+
+impl WriteHandle {
+    /// Returns true once the writer thread has finished the submitted work.
+    pub fn is_complete(&mut self) -> bool {
+        if self.result.is_none() {
+            self.result = match self.done.try_recv() {
+                Ok(result) => Some(result),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => Some(Err(UsfError::WriterClosed)),
+            };
+        }
+        self.result.is_some()
+    }
+
+    /// Blocks until the submitted work has been committed.
+    pub fn wait(self) -> Result<()> {
+        match self.result {
+            Some(result) => result,
+            None => self.done.recv().unwrap_or(Err(UsfError::WriterClosed)),
+        }
+    }
+}
+
+/// Queue lane for a [`BackgroundWriter`] store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Small latency-sensitive writes, applied ahead of queued bulk work
+    Interactive,
+    #[default]
+    Bulk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StoreOptions {
+    pub priority: Priority,
+    /// Placement hint recorded for compaction
+    pub hint: Option<Hint>,
+}
+
+/// Marks a point in a [`BackgroundWriter`]'s queue. Reads that require a
+/// token see every write submitted before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BarrierToken(u64);
+
+/// Owns a storage handle and performs all writes on a dedicated thread, in
+/// submission order. Compression happens on the submitting thread, so
+/// several threads sharing the writer compress in parallel.
+///
+/// Interactive stores overtake queued bulk stores, but never a queued
+/// store of the same key, so each key's writes still land in submission
+/// order. Flushes wait for everything submitted before them in both lanes.
+///
+/// Consistency: a write becomes visible to [`BackgroundWriter::retrieve`]
+/// once the writer thread has committed it, which is when its
+/// [`WriteHandle`] completes. Reads do not wait for queued writes unless
+/// given a [`BarrierToken`], in which case they see every write submitted
+/// before the token was taken.
+pub struct BackgroundWriter {
+    queue: Arc<Queue>,
+    thread: Option<JoinHandle<()>>,
+    storage: Arc<Mutex<UniversalStorage>>,
+    progress: Arc<Applied>,
+    key_policy: KeyPolicy,
+    max_value_size: Option<u64>,
+    block_sizing: BlockSizing,
+    block_checksum: BlockChecksum,
+    custom_types: BTreeMap<u16, CustomType>,
+    transforms: Transforms,
+    dictionaries: Dictionaries,
+}
+
+#[derive(Default)]
+struct Queue {
+    lanes: Mutex<Lanes>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Lanes {
+    interactive: VecDeque<(u64, Job)>,
+    bulk: VecDeque<(u64, Job)>,
+    next_seq: u64,
+    closed: bool,
+}
+
+// Jobs the writer thread has finished. Lanes complete out of order, so
+// finished sequence numbers above the first gap are kept aside.
+#[derive(Default)]
+struct Applied {
+    state: Mutex<AppliedState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct AppliedState {
+    // Every job up to and including this one has finished
+    through: u64,
+    ahead: BTreeSet<u64>,
+    closed: bool,
+    // Async callers to wake when the next job finishes
+    wakers: Vec<Waker>,
+}
+
+impl BackgroundWriter {
+    fn spawn(storage: UniversalStorage) -> Self {
+        let key_policy = storage.metadata.key_policy.clone();
+        let max_value_size = storage.metadata.max_value_size;
+        let block_sizing = storage.metadata.block_sizing;
+        let block_checksum = storage.metadata.block_checksum;
+        let custom_types = storage.metadata.custom_types.clone();
+        let transforms = storage.transforms.clone();
+        let dictionaries = storage.dictionaries.clone();
+        let storage = Arc::new(Mutex::new(storage));
+        let progress = Arc::new(Applied::default());
+        let queue = Arc::new(Queue::default());
+
+        let thread = {
+            let storage = Arc::clone(&storage);
+            let progress = Arc::clone(&progress);
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                while let Some((seq, job)) = queue.next() {
+                    let mut storage = lock(&storage);
+                    match job {
+                        Job::Store { key, blocks, data_type, options, done } => {
+                            let _ = done.send(storage.write_entry(key, blocks, data_type, options));
+                        },
+                        Job::Flush { done } => {
+                            let _ = done.send(storage.sync());
+                        },
+                    }
+                    drop(storage);
+                    progress.finished(seq);
+                }
+                progress.close();
+            })
+        };
+
+        Self {
+            queue,
+            thread: Some(thread),
+            storage,
+            progress,
+            key_policy,
+            max_value_size,
+            block_sizing,
+            block_checksum,
+            custom_types,
+            transforms,
+            dictionaries,
+        }
+    }
+
+    /// Compresses `data` and queues it for writing. The handle completes
+    /// once the entry is written and the index committed.
+    pub fn store(&self, key: &str, data: &[u8], data_type: DataType) -> Result<WriteHandle> {
+        self.store_with_options(key, data, data_type, StoreOptions::default())
+    }
+
+    /// Like [`BackgroundWriter::store`], queued in the lane `options` picks.
+    pub fn store_with_options(&self, key: &str, data: &[u8], data_type: DataType, options: StoreOptions) -> Result<WriteHandle> {
+        let key = self.key_policy.canonicalize(key)?;
+        check_value_size(&key, data.len() as u64, self.max_value_size)?;
+        let encoding = Encoding::resolve(&self.custom_types, &self.transforms, &self.dictionaries, self.block_sizing, self.block_checksum, &key, &data_type);
+        let blocks = UniversalStorage::prepare_blocks(data, data_type.clone(), &encoding)?;
+        let entry = EntryOptions { hint: options.hint, ..EntryOptions::default() };
+        self.submit(options.priority, |done| Job::Store { key, blocks, data_type, options: entry, done }).map(|(handle, _)| handle)
+    }
+
+    /// Queues an fsync behind all previously submitted stores. The handle
+    /// completes once everything before it is durable.
+    pub fn flush(&self) -> Result<WriteHandle> {
+        self.submit(Priority::Bulk, |done| Job::Flush { done }).map(|(handle, _)| handle)
+    }
+
+    /// Like [`BackgroundWriter::flush`], but also returns a token that
+    /// [`BackgroundWriter::retrieve`] can wait on without holding the handle.
+    pub fn flush_barrier(&self) -> Result<(WriteHandle, BarrierToken)> {
+        self.submit(Priority::Bulk, |done| Job::Flush { done })
+    }
+
+    /// Reads `key` from the committed state. With `after`, first waits
+    /// until every write submitted before the token is visible.
+    pub fn retrieve(&self, key: &str, after: Option<BarrierToken>) -> Result<Vec<u8>> {
+        if let Some(token) = after {
+            self.progress.wait_for(token.0)?;
+        }
+        lock(&self.storage).retrieve(key)
+    }
+
+    /// Snapshots the committed state without pausing the writer thread;
+    /// see [`UniversalStorage::freeze`].
+    pub fn freeze(&self) -> Result<Snapshot> {
+        lock(&self.storage).freeze()
+    }
+
+    /// Drains the queue, stops the writer thread and returns the storage.
+    pub fn finish(mut self) -> Result<UniversalStorage> {
+        self.queue.close();
+        self.thread.take()
+            .expect("writer thread is joined only once")
+            .join()
+            .map_err(|_| UsfError::WriterClosed)?;
+        let storage = Arc::clone(&self.storage);
+        drop(self);
+        Arc::try_unwrap(storage)
+            .map(|storage| storage.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .map_err(|_| UsfError::WriterClosed)
+    }
+
+    // Wakes `waker` once the writer thread next finishes a job
+    pub(crate) fn wake_on_progress(&self, waker: &Waker) {
+        let mut state = lock(&self.progress.state);
+        if !state.wakers.iter().any(|registered| registered.will_wake(waker)) {
+            state.wakers.push(waker.clone());
+        }
+    }
+
+    fn submit(&self, priority: Priority, job: impl FnOnce(Sender<Result<()>>) -> Job) -> Result<(WriteHandle, BarrierToken)> {
+        let (done, result) = mpsc::channel();
+        let seq = self.queue.push(priority, job(done))?;
+        Ok((WriteHandle { done: result, result: None }, BarrierToken(seq)))
+    }
+}
+
+impl Queue {
+    fn push(&self, priority: Priority, job: Job) -> Result<u64> {
+        let mut lanes = lock(&self.lanes);
+        if lanes.closed {
+            return Err(UsfError::WriterClosed);
+        }
+        lanes.next_seq += 1;
+        let seq = lanes.next_seq;
+
+        // An interactive store may not overtake a queued store of its key
+        let overtakes = |queued: &(u64, Job)| matches!(
+            (&queued.1, &job),
+            (Job::Store { key: queued, .. }, Job::Store { key, .. }) if queued == key
+        );
+        if priority == Priority::Interactive && !lanes.bulk.iter().any(overtakes) {
+            lanes.interactive.push_back((seq, job));
+        } else {
+            lanes.bulk.push_back((seq, job));
+        }
+        self.ready.notify_one();
+        Ok(seq)
+    }
+
+    // Blocks for the next job, or returns None once closed and drained
+    fn next(&self) -> Option<(u64, Job)> {
+        let mut lanes = lock(&self.lanes);
+        loop {
+            if let Some(job) = lanes.interactive.pop_front().or_else(|| lanes.bulk.pop_front()) {
+                return Some(job);
+            }
+            if lanes.closed {
+                return None;
+            }
+            lanes = self.ready.wait(lanes).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn close(&self) {
+        lock(&self.lanes).closed = true;
+        self.ready.notify_all();
+    }
+}
+
+impl Applied {
+    fn finished(&self, seq: u64) {
+        let mut state = lock(&self.state);
+        let state = &mut *state;
+        state.ahead.insert(seq);
+        while state.ahead.remove(&(state.through + 1)) {
+            state.through += 1;
+        }
+        state.wakers.drain(..).for_each(Waker::wake);
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        let mut state = lock(&self.state);
+        state.closed = true;
+        state.wakers.drain(..).for_each(Waker::wake);
+        self.changed.notify_all();
+    }
+
+    fn wait_for(&self, seq: u64) -> Result<()> {
+        let mut state = lock(&self.state);
+        while state.through < seq {
+            if state.closed {
+                return Err(UsfError::WriterClosed);
+            }
+            state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        // Let queued writes land before the file is closed
+        self.queue.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl UniversalStorage {
+    /// Moves this handle onto a dedicated writer thread.
+    pub fn into_background_writer(self) -> BackgroundWriter {
+        BackgroundWriter::spawn(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_background_writer_preserves_order() -> io::Result<()> {
+        let dir = tempdir()?;
+        let storage = UniversalStorage::create(dir.path().join("writer.usf"))?;
+        let writer = storage.into_background_writer();
+
+        let handles = (0..8)
+            .map(|i| writer.store("counter", format!("value {}", i).as_bytes(), DataType::Text))
+            .collect::<Result<Vec<_>>>()?;
+        writer.store("other", b"other", DataType::Text)?;
+
+        let mut flushed = writer.flush()?;
+        for handle in handles {
+            handle.wait()?;
+        }
+        while !flushed.is_complete() {
+            thread::yield_now();
+        }
+        flushed.wait()?;
+
+        let mut storage = writer.finish()?;
+        assert_eq!(storage.retrieve("counter")?, b"value 7");
+        assert_eq!(storage.retrieve("other")?, b"other");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_barrier_read_your_writes() -> io::Result<()> {
+        let dir = tempdir()?;
+        let writer = UniversalStorage::create(dir.path().join("barrier.usf"))?.into_background_writer();
+
+        for i in 0..5 {
+            writer.store("log", format!("entry {}", i).as_bytes(), DataType::Text)?;
+        }
+        let (_, token) = writer.flush_barrier()?;
+        assert_eq!(writer.retrieve("log", Some(token))?, b"entry 4");
+
+        let mut storage = writer.finish()?;
+        assert_eq!(storage.retrieve("log")?, b"entry 4");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interactive_stores_overtake_bulk() -> io::Result<()> {
+        let dir = tempdir()?;
+        let writer = UniversalStorage::create(dir.path().join("lanes.usf"))?.into_background_writer();
+        let interactive = StoreOptions { priority: Priority::Interactive, ..StoreOptions::default() };
+
+        // Hold the writer thread up while both lanes fill
+        let paused = lock(&writer.storage);
+        for i in 0..4 {
+            writer.store(&format!("bulk/{}", i), &vec![i as u8; 4096], DataType::Binary)?;
+        }
+        writer.store("shared", b"old", DataType::Text)?;
+        let urgent = writer.store_with_options("urgent", b"now", DataType::Text, interactive)?;
+        writer.store_with_options("shared", b"new", DataType::Text, interactive)?;
+        let (_, token) = writer.flush_barrier()?;
+        drop(paused);
+
+        urgent.wait()?;
+        assert_eq!(writer.retrieve("shared", Some(token))?, b"new");
+
+        let storage = writer.finish()?;
+        let offset = |key: &str| storage.metadata.index[key].blocks[0].offset;
+        assert!(offset("urgent") < offset("bulk/3"));
+        assert!(offset("shared") > offset("bulk/3"));
+
+        Ok(())
+    }
+}